@@ -0,0 +1,31 @@
+//! Runs transformers under a simulated clock instead of the system clock.
+
+use std::time::{Duration, SystemTime};
+
+use ioc_core::{
+    clock::{Clock, ClockDriver},
+    error::IocBuildError,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+///Runs transformers under a simulated clock that starts at the current time and advances in steps of step_ms.
+/// It runs speed times faster than real time, or as fast as possible if no speed is given. Modules keep running in real time.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ClockConfig {
+    pub step_ms: u64,
+    pub speed: Option<f64>,
+}
+
+impl ClockConfig {
+    ///Returns the clock and the driver that advances it.
+    pub fn try_build(&self) -> Result<(Clock, ClockDriver), IocBuildError> {
+        if self.step_ms == 0 {
+            return Err(IocBuildError::message("clock step_ms must be greater than 0"));
+        }
+        if let Some(speed) = self.speed.filter(|speed| !(speed.is_finite() && *speed > 0.0)) {
+            return Err(IocBuildError::from_string(format!("clock speed must be greater than 0, got {}", speed)));
+        }
+        Ok(Clock::simulated(SystemTime::now(), Duration::from_millis(self.step_ms)))
+    }
+}
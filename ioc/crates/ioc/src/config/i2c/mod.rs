@@ -0,0 +1,182 @@
+use std::future::Future;
+
+use ioc_core::error::IocBuildError;
+use serde::Deserialize;
+use schemars::JsonSchema;
+
+#[cfg(feature = "devices")]
+use std::sync::{Mutex, MutexGuard};
+#[cfg(feature = "devices")]
+use ioc_devices::{
+    bus::{I2cBusManager, SharedI2c},
+    mock::{I2cBus, MockI2c},
+};
+
+///Where devices find their I2C buses.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum I2cBackend {
+    ///The Raspberry Pi's buses. Needs the rpi feature.
+    Rpi,
+    ///Simulated buses with a model of each supported chip at its default address, to run configs with devices without the hardware.
+    Mock,
+}
+
+///Configuration for the I2C buses of devices.
+/// Without a backend, devices use the Raspberry Pi's buses in builds with the rpi feature. Other builds have no buses
+/// unless the Mock backend is configured, so a config meant for the hardware doesn't quietly run against simulated chips.
+#[derive(Deserialize, JsonSchema, Debug, Default)]
+pub struct I2cConfig {
+    pub backend: Option<I2cBackend>,
+}
+
+impl I2cConfig {
+    ///Returns the backend to use, None if there is none, or an error if this build doesn't support it.
+    pub fn try_build(&self) -> Result<Option<I2cBackend>, IocBuildError> {
+        match self.backend {
+            #[cfg(not(feature = "rpi"))]
+            Some(I2cBackend::Rpi) => Err(IocBuildError::message(
+                "the Rpi i2c backend needs the rpi feature. Use the Mock backend to run without the hardware",
+            )),
+            Some(backend) => Ok(Some(backend)),
+            None if cfg!(feature = "rpi") => Ok(Some(I2cBackend::Rpi)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(all(feature = "devices", feature = "rpi"))]
+type Hardware = ioc_rpi_gpio::I2c;
+#[cfg(all(feature = "devices", not(feature = "rpi")))]
+type Hardware = ioc_devices::mock::NoHardware;
+
+///The bus type devices are built with.
+#[cfg(feature = "devices")]
+pub type Bus = SharedI2c<I2cBus<Hardware>>;
+
+#[cfg(feature = "devices")]
+tokio::task_local! {
+    //the buses of the config that is running. see `scope`
+    static BUSES: Mutex<I2cBusManager<I2cBus<Hardware>>>;
+}
+
+#[cfg(feature = "devices")]
+fn bus_manager(backend: Option<I2cBackend>) -> I2cBusManager<I2cBus<Hardware>> {
+    match backend {
+        Some(I2cBackend::Mock) => I2cBusManager::new(|_| Ok(I2cBus::Mock(MockI2c::with_all_chips()))),
+        #[cfg(feature = "rpi")]
+        Some(I2cBackend::Rpi) => I2cBusManager::new(|bus| {
+            ioc_rpi_gpio::get_bus(bus)
+                .map(I2cBus::Hardware)
+                .map_err(|err| err.message)
+        }),
+        #[cfg(not(feature = "rpi"))]
+        Some(I2cBackend::Rpi) => I2cBusManager::new(|bus| Err(format!("unable to open i2c bus {}: this build has no rpi feature", bus))),
+        None => I2cBusManager::new(|bus| {
+            Err(format!(
+                "unable to open i2c bus {}: this build has no rpi feature. Configure the Mock i2c backend to simulate the devices",
+                bus
+            ))
+        }),
+    }
+}
+
+///Runs a config, i.e. builds and runs its nodes, with devices getting their buses from the given backend.
+/// Each run opens its own buses, which devices on the same bus share, also when they are restarted.
+pub async fn scope<F: Future>(backend: Option<I2cBackend>, run: F) -> F::Output {
+    #[cfg(feature = "devices")]
+    {
+        scope_with(bus_manager(backend), run).await
+    }
+    #[cfg(not(feature = "devices"))]
+    {
+        let _ = backend;
+        run.await
+    }
+}
+
+#[cfg(feature = "devices")]
+async fn scope_with<F: Future>(manager: I2cBusManager<I2cBus<Hardware>>, run: F) -> F::Output {
+    BUSES.scope(Mutex::new(manager), run).await
+}
+
+#[cfg(feature = "devices")]
+fn lock(manager: &Mutex<I2cBusManager<I2cBus<Hardware>>>) -> MutexGuard<'_, I2cBusManager<I2cBus<Hardware>>> {
+    match manager.lock() {
+        Ok(manager) => manager,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+///Gets a handle to the I2C bus with the given number from the running config, opening it if no device did yet.
+/// Devices on the same bus share it. Fails if the bus isn't available, or outside of a config.
+#[cfg(feature = "devices")]
+pub fn bus(bus: u8) -> Result<Bus, IocBuildError> {
+    BUSES
+        .try_with(|manager| Ok(lock(manager).bus(bus)?))
+        .unwrap_or_else(|_| Err(IocBuildError::message("i2c devices can only be built while a config runs")))
+}
+
+#[cfg(all(test, feature = "devices", not(feature = "rpi")))]
+mod tests {
+    use super::{scope, scope_with, I2cBackend, I2cConfig};
+    use crate::config::registry;
+    use ioc_core::registry::NodeConfig;
+    use ioc_devices::{
+        bus::I2cBusManager,
+        mock::{I2cBus, MockI2c},
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    fn bmp180() -> NodeConfig {
+        serde_json::from_str(r#"{"Bmp180": {"pressure_precision": "Standard", "period_ms": 10}}"#).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_backend() {
+        let registry = registry();
+        let cancel_token = CancellationToken::new();
+
+        //without the rpi feature, devices only get buses from an explicit Mock backend
+        let backend = I2cConfig::default().try_build().unwrap();
+        assert_eq!(backend, None);
+        let err = scope(backend, registry.build_module(bmp180(), cancel_token.clone())).await.err().unwrap();
+        assert!(format!("{:?}", err).contains("Mock"), "{:?}", err);
+        let backend = I2cConfig { backend: Some(I2cBackend::Mock) }.try_build().unwrap();
+        assert!(scope(backend, registry.build_module(bmp180(), cancel_token.clone())).await.is_ok());
+        assert!(I2cConfig { backend: Some(I2cBackend::Rpi) }.try_build().is_err());
+
+        //there are no buses outside of a config
+        assert!(registry.build_module(bmp180(), cancel_token.clone()).await.is_err());
+        cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_shared_bus() {
+        let registry = registry();
+        let cancel_token = CancellationToken::new();
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let manager = I2cBusManager::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(I2cBus::Mock(MockI2c::with_all_chips()))
+        });
+        let on_bus_3: NodeConfig =
+            serde_json::from_str(r#"{"Bmp180": {"i2c_bus": 3, "pressure_precision": "Standard", "period_ms": 10}}"#).unwrap();
+
+        //both devices on the default bus use the one handle it was opened with
+        let build = |node: NodeConfig| registry.build_module(node, cancel_token.clone());
+        scope_with(manager, async {
+            build(bmp180()).await.unwrap();
+            build(bmp180()).await.unwrap();
+            assert_eq!(opened.load(Ordering::SeqCst), 1);
+            build(on_bus_3).await.unwrap();
+            assert_eq!(opened.load(Ordering::SeqCst), 2);
+        })
+        .await;
+        cancel_token.cancel();
+    }
+}
@@ -1,57 +1,194 @@
+pub mod clock;
+pub mod i2c;
 pub mod module;
 pub mod pipe;
+pub mod record;
+pub mod safe_state;
+pub mod supervision;
 pub mod transformer;
 
 use std::collections::{HashMap, HashSet};
 
-use module::IocModuleConfig;
+use clock::ClockConfig;
+use i2c::I2cConfig;
 use pipe::PipeConfig;
+use record::RecordConfig;
+use safe_state::{SafeState, SafeStateConfig};
+use supervision::{SupervisionConfig, Supervisor};
+use tokio::sync::oneshot;
 use tokio_util::sync::CancellationToken;
-use transformer::IocTransformerConfig;
+use crate::systemd;
 
+use config_rs::{Config, ConfigError, File, FileFormat};
 use futures_util::future::join_all;
+use ioc_core::clock::Clock;
 use ioc_core::error::IocBuildError;
+use ioc_core::registry::{NodeConfig, Registry};
 use serde::Deserialize;
+use schemars::{gen::SchemaGenerator, schema::{RootSchema, Schema}, JsonSchema};
 use tracing::{debug, trace};
 
 ///Metadata fields for an IOC configuration
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct IocMetadataConfig {
     pub name: Option<String>,
     pub description: Option<String>,
 }
 
-///This is the struc corresponding to the root of the config file
+///Returns a `Registry` with all modules and transformers this binary was compiled with.
+pub fn registry() -> Registry {
+    let mut registry = Registry::default();
+    module::register(&mut registry);
+    transformer::register(&mut registry);
+    registry
+}
+
+///Like `registry`, with the modules and transformers added by the given functions, e.g. a crate's `register`.
+pub fn registry_with(register: &[fn(&mut Registry)]) -> Registry {
+    let mut registry = registry();
+    for register in register {
+        register(&mut registry);
+    }
+    registry
+}
+
+///An entry below the `modules` section in the config file. Built by the module factory registered for its type.
+#[derive(Deserialize, Debug)]
+#[serde(transparent)]
+pub struct IocModuleConfig(pub NodeConfig);
+
+impl JsonSchema for IocModuleConfig {
+    fn schema_name() -> String {
+        "IocModuleConfig".to_string()
+    }
+    //the real schema depends on the registry. see `IocConfig::json_schema`
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        Schema::Bool(true)
+    }
+}
+
+///An entry below the `transformers` section in the config file. Built by the transformer factory registered for its type.
 #[derive(Deserialize, Debug)]
+#[serde(transparent)]
+pub struct IocTransformerConfig(pub NodeConfig);
+
+impl JsonSchema for IocTransformerConfig {
+    fn schema_name() -> String {
+        "IocTransformerConfig".to_string()
+    }
+    //the real schema depends on the registry. see `IocConfig::json_schema`
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        Schema::Bool(true)
+    }
+}
+
+///This is the struc corresponding to the root of the config file
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct IocConfig {
     pub metadata: IocMetadataConfig,
     pub modules: config_rs::Map<String, IocModuleConfig>,
     pub transformers: Option<config_rs::Map<String, IocTransformerConfig>>,
     pub pipes: Vec<PipeConfig>,
+    pub supervision: Option<SupervisionConfig>,
+    pub safe_state: Option<SafeStateConfig>,
+    pub record: Option<RecordConfig>,
+    pub clock: Option<ClockConfig>,
+    pub i2c: Option<I2cConfig>,
 }
 
 impl IocConfig {
+    ///Reads a config file. The format is guessed from the extension, which may be left out.
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        Config::builder()
+            .add_source(File::with_name(path))
+            .build()
+            .and_then(|config| config.try_deserialize())
+    }
+
+    ///Reads a config from YAML text.
+    pub fn from_yaml(yaml: &str) -> Result<Self, ConfigError> {
+        Config::builder()
+            .add_source(File::from_str(yaml, FileFormat::Yaml))
+            .build()
+            .and_then(|config| config.try_deserialize())
+    }
+
+    ///Generates a JSON Schema describing the config file. Only modules and transformers in the registry are included.
+    pub fn json_schema(registry: &Registry) -> RootSchema {
+        let mut gen = SchemaGenerator::default();
+        let modules = registry.module_schema(&mut gen);
+        let transformers = registry.transformer_schema(&mut gen);
+        let mut root = gen.into_root_schema_for::<IocConfig>();
+        root.definitions.insert(IocModuleConfig::schema_name(), modules);
+        root.definitions.insert(IocTransformerConfig::schema_name(), transformers);
+        root
+    }
+
     ///Builds and runs the application, waiting for it to finish.
-    /// Returns an error if the application can't be started.
-    pub async fn start(self, cancel_token: CancellationToken) -> Result<(), IocBuildError> {
-        let mut handles = Vec::with_capacity(128);
+    /// Returns an error if the application can't be started, or if a critical module or transformer stopped.
+    ///
+    /// Once the cancel token is cancelled, pipes are stopped, outputs are driven to their safe values, and then modules are stopped.
+    pub async fn start(self, registry: &Registry, cancel_token: CancellationToken) -> Result<(), IocBuildError> {
+        let Some(clock_config) = &self.clock else {
+            return self.start_with(registry, Clock::real(), None, cancel_token).await;
+        };
+        let (clock, driver) = clock_config.try_build()?;
+        let speed = clock_config.speed;
+        debug!("running under a simulated clock");
+        //the clock runs until everything stopped
+        let clock_token = CancellationToken::new();
+        let run = async {
+            let result = self.start_with(registry, clock, None, cancel_token).await;
+            clock_token.cancel();
+            result
+        };
+        let (result, _) = tokio::join!(run, driver.run(speed, clock_token.clone()));
+        result
+    }
+
+    ///Like `start`, but transformers keep time with the given clock instead of the one in the config.
+    /// If ready is given, it's sent once all modules, transformers and pipes are running.
+    pub async fn start_with(
+        self,
+        registry: &Registry,
+        clock: Clock,
+        ready: Option<oneshot::Sender<()>>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), IocBuildError> {
+        //devices built while it runs use its i2c buses
+        let backend = match &self.i2c {
+            Some(i2c) => i2c.try_build()?,
+            None => I2cConfig::default().try_build()?,
+        };
+        i2c::scope(backend, self.run(registry, clock, ready, cancel_token)).await
+    }
+
+    async fn run(
+        self,
+        registry: &Registry,
+        clock: Clock,
+        ready: Option<oneshot::Sender<()>>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), IocBuildError> {
+        //modules and pipes are stopped in order on shutdown, so they get their own tokens
+        let modules_token = CancellationToken::new();
+        let pipes_token = CancellationToken::new();
+        let mut supervisor = Supervisor::new(self.supervision.unwrap_or_default(), modules_token.clone(), clock.clone());
+        let mut pipe_handles = Vec::with_capacity(128);
         let mut inputs = HashMap::with_capacity(128);
         let mut outputs = HashMap::with_capacity(128);
 
         //build modules, which are collections of inputs and outputs
         debug!("building modules ...");
+        systemd::status("building modules");
         for (module_key, module_config) in self.modules {
             trace!("building module {} ...", module_key);
-            match module_config.build(cancel_token.clone()).await {
+            //each module gets its own token, so it can be stopped if it needs to be restarted
+            let module_token = modules_token.child_token();
+            match registry.build_module(module_config.0.clone(), module_token.clone()).await {
                 Ok(module) => {
-                    handles.push(module.join_handle);
                     //inputs and outputs are created, prefixed with the module's key
-                    for (input_key, input) in module.inputs {
-                        inputs.insert(format!("{}.{}", module_key, input_key), input);
-                    }
-                    for (output_key, output) in module.outputs {
-                        outputs.insert(format!("{}.{}", module_key, output_key), output);
-                    }
+                    supervisor.add_module(module_key, module_config.0, module, module_token, &mut inputs, &mut outputs);
                 }
                 Err(err) => {
                     return Err(IocBuildError::from_string(format!(
@@ -68,23 +205,35 @@ impl IocConfig {
         set of all inputs. We iterate along all remaining transformers until they are all built.
         */
         debug!("done bulding modules. building transformers ...");
-        let mut remaining_xformers = self.transformers.unwrap_or_default();
+        systemd::status("building transformers");
+        let mut xformer_kinds = HashMap::new();
+        let mut xformer_readers = HashMap::new();
+        let mut remaining_xformers = HashMap::new();
+        for (xformer_key, xformer_config) in self.transformers.unwrap_or_default() {
+            xformer_kinds.insert(xformer_key.clone(), xformer_config.0.kind.clone());
+            match registry.transformer_config(xformer_config.0) {
+                Ok(xformer_config) => {
+                    remaining_xformers.insert(xformer_key, xformer_config);
+                }
+                Err(err) => {
+                    return Err(IocBuildError::from_string(format!(
+                        "failed to build transformer {}: {:?}",
+                        xformer_key, err
+                    )));
+                }
+            }
+        }
         while !remaining_xformers.is_empty() {
-            let mut processed_xformers = HashSet::new();
+            let mut processed_xformers = Vec::new();
 
             for (xformer_key, xformer_config) in &remaining_xformers {
                 let input_keys: HashSet<&String> = inputs.keys().collect();
                 let needs_inputs = xformer_config.needs_inputs();
                 if needs_inputs.is_subset(&input_keys) {
                     trace!("building transformer {} ...", xformer_key);
-                    match xformer_config.try_build(&inputs).await {
+                    match xformer_config.try_build(&inputs, &clock).await {
                         Ok(xformer) => {
-                            processed_xformers.insert(xformer_key.clone());
-                            handles.push(xformer.join_handle);
-                            //new inputs are prefixed with the transformer's key
-                            for (input_key, input) in xformer.inputs {
-                                inputs.insert(format!("{}.{}", xformer_key, input_key), input);
-                            }
+                            processed_xformers.push((xformer_key.clone(), xformer));
                         }
                         Err(err) => {
                             return Err(IocBuildError::from_string(format!(
@@ -103,8 +252,14 @@ impl IocConfig {
                 break;
             } else {
                 //remove those transformer configs that we processed in this pass
-                for k in processed_xformers {
-                    remaining_xformers.remove(&k);
+                for (xformer_key, xformer) in processed_xformers {
+                    if let Some(xformer_config) = remaining_xformers.remove(&xformer_key) {
+                        let needs_inputs: HashSet<String> = xformer_config.needs_inputs().into_iter().cloned().collect();
+                        xformer_readers.insert(xformer_key.clone(), needs_inputs);
+                        let kind = xformer_kinds.get(&xformer_key).cloned().unwrap_or_default();
+                        //new inputs are prefixed with the transformer's key
+                        supervisor.add_transformer(xformer_key, kind, xformer_config, xformer, &mut inputs);
+                    }
                 }
             }
         }
@@ -119,7 +274,11 @@ impl IocConfig {
                     let trans_inputs = trans.needs_inputs();
                     let missing_inputs: Vec<_> =
                         trans_inputs.difference(&known_keys).collect();
-                    format!("- {}: unable to find inputs: {:?}", k, missing_inputs)
+                    let hints: Vec<String> = missing_inputs
+                        .iter()
+                        .filter_map(|missing| port_hint(registry, &xformer_kinds, missing))
+                        .collect();
+                    format!("- {}: unable to find inputs: {:?}{}", k, missing_inputs, hints.concat())
                 })
                 .collect();
             return Err(
@@ -129,18 +288,165 @@ impl IocConfig {
             );
         }
 
+        supervisor.check_nodes()?;
+
+        //the recorder runs until modules stop, so it sees outputs driven to safe values on shutdown
+        let recorder = match &self.record {
+            Some(record_config) => Some(record_config.try_build(&inputs, modules_token.clone())?),
+            None => None,
+        };
+
+        //outputs with safe values are guarded before pipes write to them
+        let safe_state = SafeState::try_build(self.safe_state.unwrap_or_default(), &mut outputs)?;
+        supervisor.guard_outputs(|node| safe_state.downstream(node, &self.pipes, &xformer_readers));
+
         //build pipes, which read from a single input and write to a single output
         debug!("done building transformers. building pipes ...");
-        for pipe_config in self.pipes {
+        systemd::status("building pipes");
+        for pipe_config in &self.pipes {
             trace!("building pipe {:?}", pipe_config);
-            let pipe = pipe_config.try_build(&inputs, &outputs, cancel_token.clone())?;
-            handles.push(pipe.handle);
+            let pipe = pipe_config.try_build(&inputs, &outputs, pipes_token.clone())?;
+            pipe_handles.push(pipe.handle);
         }
+        //outputs close once the pipes writing to them stop
+        drop(outputs);
         debug!("done bulding pipes. done starting up.");
+        systemd::ready("running");
+        if let Some(ready) = ready {
+            let _ = ready.send(());
+        }
+        systemd::spawn_watchdog(supervisor.liveness(), cancel_token.clone());
+
+        let shutdown = async {
+            cancel_token.cancelled().await;
+            debug!("stopping pipes ...");
+            systemd::stopping("stopping pipes");
+            pipes_token.cancel();
+            join_all(pipe_handles).await;
+            debug!("driving outputs to safe values ...");
+            systemd::status("driving outputs to safe values");
+            safe_state.apply().await;
+            debug!("stopping modules ...");
+            systemd::status("stopping modules");
+            modules_token.cancel();
+        };
 
-        //wait for server to exit and all tasks to stop
-        join_all(handles).await;
+        //wait for shutdown and all tasks to stop, restarting modules and transformers as configured
+        let (result, _) = tokio::join!(supervisor.run(registry, &inputs, cancel_token.clone()), shutdown);
+        if let Some(recorder) = recorder {
+            let _ = recorder.await;
+        }
+        result
+    }
+}
 
-        Ok(())
+//if a missing input refers to another transformer, lists the inputs that transformer provides
+fn port_hint(registry: &Registry, xformer_kinds: &HashMap<String, String>, missing: &str) -> Option<String> {
+    let (xformer_key, port) = missing.split_once('.')?;
+    let kind = xformer_kinds.get(xformer_key)?;
+    let ports = registry.transformer_ports(kind)?;
+    if ports.is_empty() || ports.iter().any(|p| p == port) {
+        None
+    } else {
+        Some(format!(" ({} provides {:?})", xformer_key, ports))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{registry, registry_with, IocConfig};
+    use ioc_core::{clock::Clock, registry::Registry, Input, InputKind, ModuleIO};
+    #[cfg(feature = "sims")]
+    use ioc_core::registry::NodeConfig;
+    use std::collections::HashMap;
+    use tokio::sync::oneshot;
+    use tokio_util::sync::CancellationToken;
+
+    //a module registered outside this crate, with a constant input x
+    fn register_constant(registry: &mut Registry) {
+        registry.register_module("Constant", |value: f64, cancel_token: CancellationToken| async move {
+            let (x, tx) = Input::new(value);
+            let join_handle = tokio::spawn(async move {
+                cancel_token.cancelled().await;
+                drop(tx);
+            });
+            Ok(ModuleIO {
+                join_handle,
+                inputs: HashMap::from([("x".to_string(), InputKind::Float(x))]),
+                outputs: HashMap::new(),
+            })
+        });
+    }
+
+    #[tokio::test]
+    async fn test_registry_with() {
+        let yaml = "
+metadata:
+  name: extra
+modules:
+  constant:
+    Constant: 1.5
+pipes: []
+";
+        assert!(!registry().module_kinds().any(|kind| kind == "Constant"));
+        let config = IocConfig::from_yaml(yaml).unwrap();
+        let registry = registry_with(&[register_constant]);
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let cancel_token = CancellationToken::new();
+        let shutdown = cancel_token.clone();
+        let (result, _) = tokio::join!(config.start_with(&registry, Clock::real(), Some(ready_tx), cancel_token), async move {
+            ready_rx.await.unwrap();
+            shutdown.cancel();
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn test_keys_lowercase() {
+        //the config crate lowercases keys, so node names, types and config fields all end up lowercase
+        let yaml = "metadata: { name: a, description: b }\nmodules:\n  Local_Server:\n    Server: { Port: 1 }\npipes: []\n";
+        let config = IocConfig::from_yaml(yaml).unwrap();
+        let (key, module) = config.modules.iter().next().unwrap();
+        assert_eq!((key.as_str(), module.0.kind.as_str()), ("local_server", "server"));
+        assert_eq!(module.0.config, serde_json::json!({ "port": 1 }));
+    }
+
+    //the nodes of a config by name, as their type and config
+    #[cfg(feature = "sims")]
+    fn nodes<'a>(nodes: impl Iterator<Item = (&'a String, &'a NodeConfig)>) -> Vec<(String, String, serde_json::Value)> {
+        let mut nodes: Vec<_> = nodes
+            .map(|(name, node)| (name.clone(), node.kind.clone(), node.config.clone()))
+            .collect();
+        nodes.sort_by(|a, b| a.0.cmp(&b.0));
+        nodes
+    }
+
+    //the sim demo's transformers are registered by the sims feature
+    #[cfg(feature = "sims")]
+    #[test]
+    fn test_config_formats() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../example-configs");
+        let yaml = IocConfig::load(&format!("{}/sim_demo.yml", dir)).unwrap();
+        let registry = registry();
+        for format in ["toml", "json"] {
+            let config = IocConfig::load(&format!("{}/sim_demo.{}", dir, format)).unwrap();
+            assert_eq!(config.metadata.name, yaml.metadata.name, "{}", format);
+
+            //nodes have the same types and configs, so integers are still integers
+            let modules = nodes(config.modules.iter().map(|(name, module)| (name, &module.0)));
+            assert_eq!(modules, nodes(yaml.modules.iter().map(|(name, module)| (name, &module.0))), "{}", format);
+            let (transformers, yaml_transformers) = (config.transformers.unwrap(), yaml.transformers.as_ref().unwrap());
+            let transformers = nodes(transformers.iter().map(|(name, xformer)| (name, &xformer.0)));
+            assert_eq!(transformers, nodes(yaml_transformers.iter().map(|(name, xformer)| (name, &xformer.0))), "{}", format);
+            assert!(modules[0].2["port"].is_u64(), "{}", format);
+            assert!(transformers[0].2["period_ms"].is_u64(), "{}", format);
+            for (_, kind, config) in transformers {
+                registry.transformer_config(NodeConfig { kind, config }).unwrap();
+            }
+
+            let pipes: Vec<_> = config.pipes.iter().map(|pipe| (&pipe.from, &pipe.to)).collect();
+            let yaml_pipes: Vec<_> = yaml.pipes.iter().map(|pipe| (&pipe.from, &pipe.to)).collect();
+            assert_eq!(pipes, yaml_pipes, "{}", format);
+        }
     }
 }
@@ -1,5 +1,9 @@
-use ioc_core::{error::IocBuildError, feedback::{Feedback, FeedbackConfig}, Module, ModuleIO};
-use serde::Deserialize;
+use ioc_core::{
+    feedback::{Feedback, FeedbackConfig},
+    registry::Registry,
+    replay::{Replay, ReplayConfig},
+    Module,
+};
 
 //ioc_server
 #[cfg(feature = "server")]
@@ -19,90 +23,73 @@ use ioc_devices::devices::{
 };
 #[cfg(feature = "devices")]
 use ioc_core::ModuleBuilder;
+#[cfg(feature = "devices")]
+use crate::config::i2c::bus as i2c_bus_provider;
 
-
-//rpi (for i2c source)
-#[cfg(feature = "rpi")]
-use ioc_rpi_gpio::{I2c, gpio::{Gpio, GpioConfig}};
-use tokio_util::sync::CancellationToken;
-
+//rpi
 #[cfg(feature = "rpi")]
-fn i2c_bus_provider(bus: u8) -> I2c {
-    ioc_rpi_gpio::get_bus(bus)
-}
+use ioc_rpi_gpio::gpio::{Gpio, GpioConfig};
 
+///Registers all modules that may appear below the `modules` section in the config file.
 /// Modules are collections of Inputs and/or Outputs provided by some black-box system.
-#[derive(Deserialize, Debug)]
-pub enum IocModuleConfig {
-    //core 
-    Feedback(FeedbackConfig),
+pub fn register(registry: &mut Registry) {
+    //core
+    registry.register_module("Feedback", |feedback_config: FeedbackConfig, cancel_token| async move {
+        Feedback::try_build(&feedback_config, cancel_token)
+            .await
+            .map(|feedback| feedback.into())
+    });
+    registry.register_module("Replay", |replay_config: ReplayConfig, cancel_token| async move {
+        Replay::try_build(&replay_config, cancel_token)
+            .await
+            .map(|replay| replay.into())
+    });
 
-    //ioc_server
+    //server
     #[cfg(feature = "server")]
-    Server(ServerConfig),
+    registry.register_module("Server", |server_config: ServerConfig, cancel_token| async move {
+        Server::try_build(&server_config, cancel_token)
+            .await
+            .map(|server| server.into())
+    });
 
-    //ioc_extra
+    //extra
     #[cfg(feature = "extra")]
-    RaspiCam(CameraConfig),
+    registry.register_module("RaspiCam", |cam_config: CameraConfig, cancel_token| async move {
+        Camera::try_build(&cam_config, cancel_token).await.map(|cam| cam.into())
+    });
 
-    //ioc_devices
-    #[cfg(feature = "devices")]
-    Pca9685(Pca9685DeviceConfig),
-    #[cfg(feature = "devices")]
-    Bmp180(Bmp180DeviceConfig),
-    #[cfg(feature = "devices")]
-    L3dg20(L3gd20DeviceConfig),
+    //devices
     #[cfg(feature = "devices")]
-    Lsm303dlhc(Lsm303dlhcDeviceConfig),
-
-    //rpi 
-    #[cfg(feature = "rpi")]
-    Gpio(GpioConfig),
-}
-
-impl IocModuleConfig {
-    pub async fn build(&self, cancel_token: CancellationToken) -> Result<ModuleIO, IocBuildError> {
-        match self {
-            //core
-            Self::Feedback(feedback_config) => Feedback::try_build(feedback_config, cancel_token)
-            .await
-            .map(|feedback| feedback.into()),
-
-            //server
-            #[cfg(feature = "server")]
-            Self::Server(server_config) => Server::try_build(server_config, cancel_token)
+    registry
+        .register_module("Pca9685", |pca9685_config: Pca9685DeviceConfig, cancel_token| async move {
+            Pca9685DeviceBuilder::new(i2c_bus_provider)
+                .try_build(&pca9685_config, cancel_token)
                 .await
-                .map(|server| server.into()),
-
-            //extra
-            #[cfg(feature = "extra")]
-            Self::RaspiCam(cam_config) => Camera::try_build(cam_config, cancel_token).await.map(|cam| cam.into()),
-
-            //devices
-            #[cfg(feature = "devices")]
-            Self::Pca9685(pca9685_config) => Pca9685DeviceBuilder::new(i2c_bus_provider)
-                .try_build(pca9685_config, cancel_token)
-                .await
-                .map(|outputs| outputs.into()),
-            #[cfg(feature = "devices")]
-            Self::Bmp180(bmp180_config) => Bmp180DeviceBuilder::new(i2c_bus_provider)
-                .try_build(bmp180_config, cancel_token)
+                .map(|outputs| outputs.into())
+        })
+        .register_module("Bmp180", |bmp180_config: Bmp180DeviceConfig, cancel_token| async move {
+            Bmp180DeviceBuilder::new(i2c_bus_provider)
+                .try_build(&bmp180_config, cancel_token)
                 .await
-                .map(|sensor| sensor.into()),
-            #[cfg(feature = "devices")]
-            Self::L3dg20(l3dg20_cfg) => L3gd20DeviceBuilder::new(i2c_bus_provider)
-                .try_build(l3dg20_cfg, cancel_token)
+                .map(|sensor| sensor.into())
+        })
+        .register_module("L3dg20", |l3dg20_cfg: L3gd20DeviceConfig, cancel_token| async move {
+            L3gd20DeviceBuilder::new(i2c_bus_provider)
+                .try_build(&l3dg20_cfg, cancel_token)
                 .await
-                .map(|sensor| sensor.into()),
-            #[cfg(feature = "devices")]
-            Self::Lsm303dlhc(lsm303dlhc_cfg) => Lsm303dlhcDeviceBuilder::new(i2c_bus_provider)
-                .try_build(lsm303dlhc_cfg, cancel_token)
+                .map(|sensor| sensor.into())
+        })
+        .register_module("Lsm303dlhc", |lsm303dlhc_cfg: Lsm303dlhcDeviceConfig, cancel_token| async move {
+            Lsm303dlhcDeviceBuilder::new(i2c_bus_provider)
+                .try_build(&lsm303dlhc_cfg, cancel_token)
                 .await
-                .map(|sensor| sensor.into()),
-            
-            //rpi
-            #[cfg(feature = "rpi")]
-            Self::Gpio(gpio_config) => Gpio::try_build(gpio_config, cancel_token).await.map(|gpio| gpio.into()),
-        }
-    }
+                .map(|sensor| sensor.into())
+        });
+
+    //rpi
+    #[cfg(feature = "rpi")]
+    registry.register_module("Gpio", |gpio_config: GpioConfig, cancel_token| async move {
+        Gpio::try_build(&gpio_config, cancel_token).await.map(|gpio| gpio.into())
+    });
 }
@@ -2,10 +2,11 @@ use std::collections::HashMap;
 
 use ioc_core::{error::IocBuildError, pipe::Pipe, InputKind, OutputKind};
 use serde::Deserialize;
+use schemars::JsonSchema;
 use tokio_util::sync::CancellationToken;
 
 //A `Pipe`` is a simple object that reads values from an `Input` and writes values received to an `Output`
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct PipeConfig {
     pub from: String,
     pub to: String,
@@ -31,6 +32,12 @@ impl PipeConfig {
             (Some(InputKind::Binary(input)), Some(OutputKind::Binary(output))) => Ok(Pipe::new(input, output, cancel_token)),
             // If both input and output are of type Float, create a new Pipe with the input and output as references.
             (Some(InputKind::Float(input)), Some(OutputKind::Float(output))) => Ok(Pipe::new(input, output, cancel_token)),
+            // If both input and output are of type Int, create a new Pipe with the input and output as references.
+            (Some(InputKind::Int(input)), Some(OutputKind::Int(output))) => Ok(Pipe::new(input, output, cancel_token)),
+            // An Int input may be piped to a Float output. Values are converted to floats.
+            (Some(InputKind::Int(input)), Some(OutputKind::Float(output))) => Ok(Pipe::map(input, output, |value| value as f64, cancel_token)),
+            // If both input and output are of type Timestamp, create a new Pipe with the input and output as references.
+            (Some(InputKind::Timestamp(input)), Some(OutputKind::Timestamp(output))) => Ok(Pipe::new(input, output, cancel_token)),
             // If both input and output are of type Bool, create a new Pipe with the input and output as references.
             (Some(InputKind::Bool(input)), Some(OutputKind::Bool(output))) => Ok(Pipe::new(input, output, cancel_token)),
             // If both input and output are of type Array, create a new Pipe with the input and output as references.
@@ -0,0 +1,59 @@
+//! Records inputs to a log file that can be played back with the `Replay` module.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use ioc_core::{
+    error::IocBuildError,
+    record::{Recorder, Rotation},
+    InputKind,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+///Records inputs to the file at path. Each input is named by its node's key and its own, e.g. imu.accel_x.
+/// Records every input unless inputs are given.
+/// Once the file grows past max_file_bytes (default 64 MiB) it is rotated to path.1, path.1 to path.2 and so on, keeping max_files old files (default 5).
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct RecordConfig {
+    pub path: String,
+    pub inputs: Option<Vec<String>>,
+    pub max_file_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+}
+
+impl RecordConfig {
+    ///Starts recording. Returns an error if an input to record doesn't exist.
+    pub fn try_build(
+        &self,
+        inputs: &HashMap<String, InputKind>,
+        cancel_token: CancellationToken,
+    ) -> Result<JoinHandle<()>, IocBuildError> {
+        let recorded = match &self.inputs {
+            Some(keys) => {
+                let mut recorded = Vec::with_capacity(keys.len());
+                let mut errors = Vec::new();
+                for key in keys {
+                    match inputs.get(key) {
+                        Some(input) => recorded.push((key.clone(), input)),
+                        None => errors.push(IocBuildError::from_string(format!(
+                            "input {} is to be recorded but doesn't exist",
+                            key
+                        ))),
+                    }
+                }
+                if !errors.is_empty() {
+                    return Err(IocBuildError::from_errs(errors));
+                }
+                recorded
+            }
+            None => inputs.iter().map(|(key, input)| (key.clone(), input)).collect(),
+        };
+        let rotation = Rotation {
+            max_bytes: self.max_file_bytes.unwrap_or(64 << 20),
+            max_files: self.max_files.unwrap_or(5),
+        };
+        Recorder::spawn(PathBuf::from(&self.path), recorded, rotation, cancel_token)
+    }
+}
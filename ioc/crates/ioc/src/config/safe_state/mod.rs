@@ -0,0 +1,113 @@
+//! Safe values for outputs, applied when the IOC shuts down and while a module or transformer upstream of an output is down.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use futures_util::future::join_all;
+use ioc_core::{error::IocBuildError, safe::SafeOutput, OutputKind, Value};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::time::{sleep, timeout};
+use tracing::{info, warn};
+
+use super::pipe::PipeConfig;
+
+///The value an output is driven to when it isn't safe to write anything else to it.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct SafeOutputConfig {
+    pub output: String,
+    pub safe_value: Value,
+}
+
+///Safe values for outputs. Each output is named by its module's key and its own, e.g. motors.left.
+/// On shutdown, pipes are stopped first, then outputs are driven to their safe values, and modules are stopped once every output took its safe value, or timeout_ms passed, plus settle_ms.
+/// While a module or transformer that feeds an output is down, the output is held at its safe value.
+#[derive(Deserialize, JsonSchema, Debug, Default)]
+pub struct SafeStateConfig {
+    #[serde(default)]
+    pub settle_ms: u64,
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub outputs: Vec<SafeOutputConfig>,
+}
+
+///Guards for the outputs with safe values.
+pub struct SafeState {
+    settle: Duration,
+    timeout: Duration,
+    outputs: HashMap<String, SafeOutput>,
+}
+
+impl SafeState {
+    ///Guards the configured outputs, replacing them in `outputs` with the guarded outputs.
+    pub fn try_build(config: SafeStateConfig, outputs: &mut HashMap<String, OutputKind>) -> Result<Self, IocBuildError> {
+        let mut guards = HashMap::with_capacity(config.outputs.len());
+        let mut errors = Vec::new();
+        for output_config in config.outputs {
+            let guarded = match outputs.remove(&output_config.output) {
+                Some(output) => SafeOutput::for_kind(output, &output_config.safe_value),
+                None => Err(IocBuildError::from_string(format!(
+                    "output {} has a safe value but doesn't exist",
+                    output_config.output
+                ))),
+            };
+            match guarded {
+                Ok((guard, output)) => {
+                    outputs.insert(output_config.output.clone(), output);
+                    guards.insert(output_config.output, guard);
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(IocBuildError::from_errs(errors));
+        }
+        Ok(Self {
+            settle: Duration::from_millis(config.settle_ms),
+            timeout: Duration::from_millis(config.timeout_ms.unwrap_or(1000)),
+            outputs: guards,
+        })
+    }
+
+    ///Returns the guards of the outputs fed by a node, through pipes from its inputs or from inputs of transformers downstream of it.
+    /// `readers` are the inputs each transformer reads.
+    pub fn downstream(&self, node: &str, pipes: &[PipeConfig], readers: &HashMap<String, HashSet<String>>) -> Vec<SafeOutput> {
+        let mut nodes = HashSet::from([node.to_string()]);
+        let mut queue = vec![node.to_string()];
+        while let Some(upstream) = queue.pop() {
+            for (xformer_key, needs_inputs) in readers {
+                if !nodes.contains(xformer_key) && needs_inputs.iter().any(|input| provided_by(input, &upstream)) {
+                    nodes.insert(xformer_key.clone());
+                    queue.push(xformer_key.clone());
+                }
+            }
+        }
+        pipes
+            .iter()
+            .filter(|pipe| nodes.iter().any(|node| provided_by(&pipe.from, node)))
+            .filter_map(|pipe| self.outputs.get(&pipe.to).cloned())
+            .collect()
+    }
+
+    ///Drives every guarded output to its safe value and waits for them to take it, then waits settle_ms.
+    pub async fn apply(self) {
+        let guard_timeout = self.timeout;
+        join_all(self.outputs.iter().map(|(key, guard)| async move {
+            if timeout(guard_timeout, guard.shutdown()).await.is_err() {
+                warn!(output = %key, "timed out driving output to its safe value");
+            }
+        }))
+        .await;
+        if !self.outputs.is_empty() {
+            info!("outputs driven to safe values");
+        }
+        sleep(self.settle).await;
+    }
+}
+
+//inputs are prefixed with the key of the node that provides them
+fn provided_by(input: &str, node: &str) -> bool {
+    input.split_once('.').is_some_and(|(prefix, _)| prefix == node)
+}
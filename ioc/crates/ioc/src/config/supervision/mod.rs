@@ -0,0 +1,689 @@
+//! Restart policies for modules and transformers, and the `Supervisor` that applies them while the IOC runs.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use futures_util::{future::LocalBoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use ioc_core::{
+    clock::Clock,
+    error::IocBuildError,
+    registry::{DynTransformerConfig, NodeConfig, Registry},
+    relay::{InputKindRelay, OutputKindRelay},
+    safe::SafeOutput,
+    InputKind, ModuleIO, OutputKind, TransformerI,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::{AbortHandle, JoinError, JoinHandle},
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+///When a module or transformer is restarted after it stops, e.g. because it panicked.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+pub enum RestartPolicy {
+    ///The node is not restarted.
+    #[default]
+    Never,
+    ///The node is restarted delay_ms after it stops.
+    Always { delay_ms: u64 },
+    ///The node is restarted after a delay that starts at initial_ms and doubles with each restart, up to max_ms.
+    /// The delay starts over once the node has run for max_ms.
+    Backoff { initial_ms: u64, max_ms: u64 },
+}
+
+///How a single module or transformer is supervised.
+/// max_restarts is the number of times the node may be restarted. There is no limit if it's not given.
+/// If critical is true, the IOC shuts down when the node stops and won't be restarted.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct NodeSupervisionConfig {
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    pub max_restarts: Option<u32>,
+    #[serde(default)]
+    pub critical: bool,
+}
+
+///Supervision of modules and transformers.
+/// default applies to nodes that are not listed in nodes, which is keyed by the module or transformer key.
+///
+/// The inputs and outputs of nodes that may be restarted are relayed, so they stay connected while the node restarts.
+/// While a node is down its inputs keep their last value, with `Stale` quality, and the last value written to each output is written again once it's back.
+/// Outputs with a safe value that the node feeds are held at it until the node is back.
+#[derive(Deserialize, JsonSchema, Debug, Default)]
+pub struct SupervisionConfig {
+    #[serde(default)]
+    pub default: NodeSupervisionConfig,
+    #[serde(default)]
+    pub nodes: config_rs::Map<String, NodeSupervisionConfig>,
+}
+
+//how to build a node again
+enum NodeSource {
+    Module(NodeConfig),
+    Transformer(Box<dyn DynTransformerConfig>),
+}
+
+struct Node {
+    key: String,
+    kind: String,
+    source: NodeSource,
+    policy: NodeSupervisionConfig,
+    restarts: u32,
+    delay: Option<Duration>,
+    started: Instant,
+    //cancelled when a module stops, to stop anything it left running
+    cancel_token: CancellationToken,
+    inputs: HashMap<String, InputKindRelay>,
+    outputs: HashMap<String, OutputKindRelay>,
+    //outputs downstream of the node, held at their safe values while it's down
+    safe_outputs: Vec<SafeOutput>,
+    engaged: bool,
+    //set while the node is running
+    abort_handle: Option<AbortHandle>,
+    //set once the node stopped and won't be restarted
+    failed: bool,
+}
+
+impl Node {
+    fn restartable(policy: &NodeSupervisionConfig) -> bool {
+        !matches!(policy.restart, RestartPolicy::Never) && policy.max_restarts != Some(0)
+    }
+
+    //returns how long to wait before restarting, or None if the node shouldn't be restarted
+    fn next_delay(&mut self) -> Option<Duration> {
+        if self.policy.max_restarts.is_some_and(|max_restarts| self.restarts >= max_restarts) {
+            return None;
+        }
+        let delay = match self.policy.restart {
+            RestartPolicy::Never => return None,
+            RestartPolicy::Always { delay_ms } => Duration::from_millis(delay_ms),
+            RestartPolicy::Backoff { initial_ms, max_ms } => {
+                let max = Duration::from_millis(max_ms);
+                match self.delay {
+                    Some(delay) if self.started.elapsed() < max => (delay * 2).min(max),
+                    _ => Duration::from_millis(initial_ms).min(max),
+                }
+            }
+        };
+        self.restarts += 1;
+        self.delay = Some(delay);
+        Some(delay)
+    }
+
+    //connects the inputs and outputs of a new instance to the relays
+    fn connect(&self, inputs: HashMap<String, InputKind>, outputs: HashMap<String, OutputKind>) -> Result<(), IocBuildError> {
+        let mut errors = Vec::new();
+        for (input_key, input) in inputs {
+            match self.inputs.get(&input_key).map(|relay| relay.connect(input)) {
+                Some(Ok(())) => {}
+                Some(Err(err)) => errors.push(err),
+                None => warn!(node = %self.key, input = %input_key, "restarted node has a new input that is not connected"),
+            }
+        }
+        for (output_key, output) in outputs {
+            match self.outputs.get(&output_key).map(|relay| relay.connect(output)) {
+                Some(Ok(())) => {}
+                Some(Err(err)) => errors.push(err),
+                None => warn!(node = %self.key, output = %output_key, "restarted node has a new output that is not connected"),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(IocBuildError::from_errs(errors))
+        }
+    }
+
+    async fn rebuild(
+        &mut self,
+        registry: &Registry,
+        inputs: &HashMap<String, InputKind>,
+        modules_token: &CancellationToken,
+        clock: &Clock,
+    ) -> Result<JoinHandle<()>, IocBuildError> {
+        let (join_handle, new_inputs, new_outputs) = match &self.source {
+            NodeSource::Module(config) => {
+                self.cancel_token = modules_token.child_token();
+                let module = registry.build_module(config.clone(), self.cancel_token.clone()).await?;
+                (module.join_handle, module.inputs, module.outputs)
+            }
+            NodeSource::Transformer(config) => {
+                let xformer = config.try_build(inputs, clock).await?;
+                (xformer.join_handle, xformer.inputs, HashMap::new())
+            }
+        };
+        self.started = Instant::now();
+        if let Err(err) = self.connect(new_inputs, new_outputs) {
+            join_handle.abort();
+            return Err(err);
+        }
+        Ok(join_handle)
+    }
+
+    //drops the relays, so consumers see the node's inputs close like they would without supervision
+    fn disconnect(&mut self) {
+        self.inputs.clear();
+        self.outputs.clear();
+    }
+
+    fn engage(&mut self) {
+        if !self.engaged {
+            self.safe_outputs.iter().for_each(SafeOutput::engage);
+            self.engaged = true;
+        }
+    }
+
+    fn release(&mut self) {
+        if self.engaged {
+            self.safe_outputs.iter().for_each(SafeOutput::release);
+            self.engaged = false;
+        }
+    }
+
+    fn is_module(&self) -> bool {
+        matches!(self.source, NodeSource::Module(_))
+    }
+}
+
+///How many modules and transformers are running, which are down until they restart, and which failed for good.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphStatus {
+    pub running: usize,
+    pub down: Vec<String>,
+    ///nodes that stopped and won't be restarted
+    pub failed: Vec<String>,
+}
+
+impl fmt::Display for GraphStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.running + self.down.len() + self.failed.len();
+        write!(f, "{}/{} nodes running", self.running, total)?;
+        if !self.down.is_empty() {
+            write!(f, ", down: {}", self.down.join(", "))?;
+        }
+        if !self.failed.is_empty() {
+            write!(f, ", failed: {}", self.failed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+///Asks a running `Supervisor` for the `GraphStatus`.
+/// The supervisor answers in between handling nodes that stop and restart, so an answer also shows that it isn't stuck.
+#[derive(Clone)]
+pub struct Liveness {
+    probes: mpsc::Sender<oneshot::Sender<GraphStatus>>,
+}
+
+impl Liveness {
+    pub(crate) fn new(probes: mpsc::Sender<oneshot::Sender<GraphStatus>>) -> Self {
+        Self { probes }
+    }
+
+    ///Returns `None` if the supervisor isn't running anymore.
+    pub async fn check(&self) -> Option<GraphStatus> {
+        let (reply, status) = oneshot::channel();
+        self.probes.send(reply).await.ok()?;
+        status.await.ok()
+    }
+}
+
+type Running = FuturesUnordered<LocalBoxFuture<'static, (usize, Result<(), JoinError>)>>;
+type Restarts = FuturesUnordered<LocalBoxFuture<'static, (usize, bool)>>;
+
+///Waits for modules and transformers to stop, and restarts them according to their `RestartPolicy`.
+pub struct Supervisor {
+    config: SupervisionConfig,
+    //restarted modules get a child of this token
+    modules_token: CancellationToken,
+    //restarted transformers keep time with this clock
+    clock: Clock,
+    nodes: Vec<Node>,
+    handles: Vec<(usize, JoinHandle<()>)>,
+    probes_tx: mpsc::Sender<oneshot::Sender<GraphStatus>>,
+    probes: mpsc::Receiver<oneshot::Sender<GraphStatus>>,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisionConfig, modules_token: CancellationToken, clock: Clock) -> Self {
+        let (probes_tx, probes) = mpsc::channel(1);
+        Self {
+            config,
+            modules_token,
+            clock,
+            nodes: Vec::new(),
+            handles: Vec::new(),
+            probes_tx,
+            probes,
+        }
+    }
+
+    ///Returns a `Liveness` to check on the supervisor once it runs.
+    pub fn liveness(&self) -> Liveness {
+        Liveness::new(self.probes_tx.clone())
+    }
+
+    fn status(&self) -> GraphStatus {
+        let keys = |filter: fn(&Node) -> bool| self.nodes.iter().filter(|node| filter(node)).map(|node| node.key.clone()).collect();
+        GraphStatus {
+            running: self.nodes.iter().filter(|node| node.abort_handle.is_some()).count(),
+            down: keys(|node| node.abort_handle.is_none() && !node.failed),
+            failed: keys(|node| node.failed),
+        }
+    }
+
+    ///Sets the outputs to hold at their safe values while each node is down.
+    pub fn guard_outputs(&mut self, downstream: impl Fn(&str) -> Vec<SafeOutput>) {
+        for node in self.nodes.iter_mut() {
+            node.safe_outputs = downstream(&node.key);
+        }
+    }
+
+    fn policy(&self, key: &str) -> NodeSupervisionConfig {
+        self.config.nodes.get(key).unwrap_or(&self.config.default).clone()
+    }
+
+    ///Returns an error if the supervision config names nodes that don't exist.
+    pub fn check_nodes(&self) -> Result<(), IocBuildError> {
+        let unknown: Vec<&String> = self
+            .config
+            .nodes
+            .keys()
+            .filter(|key| !self.nodes.iter().any(|node| node.key == **key))
+            .collect();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(IocBuildError::from_string(format!(
+                "supervision config refers to modules or transformers that don't exist: {:?}",
+                unknown
+            )))
+        }
+    }
+
+    ///Supervises a module, and adds its inputs and outputs prefixed with the module's key.
+    pub fn add_module(
+        &mut self,
+        key: String,
+        config: NodeConfig,
+        module: ModuleIO,
+        cancel_token: CancellationToken,
+        inputs: &mut HashMap<String, InputKind>,
+        outputs: &mut HashMap<String, OutputKind>,
+    ) {
+        let kind = config.kind.clone();
+        self.add(key, kind, NodeSource::Module(config), module.join_handle, cancel_token, module.inputs, module.outputs, inputs, outputs);
+    }
+
+    ///Supervises a transformer, and adds its inputs prefixed with the transformer's key.
+    pub fn add_transformer(
+        &mut self,
+        key: String,
+        kind: String,
+        config: Box<dyn DynTransformerConfig>,
+        xformer: TransformerI,
+        inputs: &mut HashMap<String, InputKind>,
+    ) {
+        self.add(
+            key,
+            kind,
+            NodeSource::Transformer(config),
+            xformer.join_handle,
+            CancellationToken::new(),
+            xformer.inputs,
+            HashMap::new(),
+            inputs,
+            &mut HashMap::new(),
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add(
+        &mut self,
+        key: String,
+        kind: String,
+        source: NodeSource,
+        join_handle: JoinHandle<()>,
+        cancel_token: CancellationToken,
+        node_inputs: HashMap<String, InputKind>,
+        node_outputs: HashMap<String, OutputKind>,
+        inputs: &mut HashMap<String, InputKind>,
+        outputs: &mut HashMap<String, OutputKind>,
+    ) {
+        let policy = self.policy(&key);
+        let restartable = Node::restartable(&policy);
+        let mut node = Node {
+            key,
+            kind,
+            source,
+            policy,
+            restarts: 0,
+            delay: None,
+            started: Instant::now(),
+            cancel_token,
+            inputs: HashMap::new(),
+            outputs: HashMap::new(),
+            safe_outputs: Vec::new(),
+            engaged: false,
+            abort_handle: None,
+            failed: false,
+        };
+        //only nodes that may restart need relays
+        for (input_key, input) in node_inputs {
+            let input = if restartable {
+                let (relay, input) = InputKindRelay::new(input);
+                node.inputs.insert(input_key.clone(), relay);
+                input
+            } else {
+                input
+            };
+            inputs.insert(format!("{}.{}", node.key, input_key), input);
+        }
+        for (output_key, output) in node_outputs {
+            let output = if restartable {
+                let (relay, output) = OutputKindRelay::new(output);
+                node.outputs.insert(output_key.clone(), relay);
+                output
+            } else {
+                output
+            };
+            outputs.insert(format!("{}.{}", node.key, output_key), output);
+        }
+        self.handles.push((self.nodes.len(), join_handle));
+        self.nodes.push(node);
+    }
+
+    ///Waits for all nodes to stop, restarting them according to their policies until the cancel token is cancelled.
+    /// Returns an error if a critical node stopped, in which case the cancel token is cancelled to shut down everything else.
+    ///
+    /// Once the cancel token is cancelled and all modules stopped, transformers that are still running are aborted.
+    pub async fn run(
+        mut self,
+        registry: &Registry,
+        inputs: &HashMap<String, InputKind>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), IocBuildError> {
+        let mut running: Running = FuturesUnordered::new();
+        for (index, join_handle) in std::mem::take(&mut self.handles) {
+            running.push(wait(&mut self.nodes[index], index, join_handle));
+        }
+        let mut restarts: Restarts = FuturesUnordered::new();
+        let mut failed = Vec::new();
+        let mut shutting_down = false;
+
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled(), if !shutting_down => {
+                    shutting_down = true;
+                    self.stop_transformers();
+                }
+                Some(reply) = self.probes.recv(), if !shutting_down || !running.is_empty() || !restarts.is_empty() => {
+                    let _ = reply.send(self.status());
+                }
+                Some((index, result)) = running.next() => {
+                    let node = &mut self.nodes[index];
+                    node.cancel_token.cancel();
+                    node.abort_handle = None;
+                    if cancel_token.is_cancelled() {
+                        node.disconnect();
+                        self.stop_transformers();
+                        continue;
+                    }
+                    match result {
+                        Err(err) if err.is_panic() => error!(
+                            node = %node.key,
+                            kind = %node.kind,
+                            restarts = node.restarts,
+                            panic = %panic_message(err),
+                            "node panicked"
+                        ),
+                        Err(err) => error!(node = %node.key, kind = %node.kind, restarts = node.restarts, "node failed: {}", err),
+                        Ok(()) => warn!(node = %node.key, kind = %node.kind, restarts = node.restarts, "node stopped"),
+                    }
+                    node.engage();
+                    if let Some(key) = stopped(index, node, &mut restarts, &cancel_token) {
+                        failed.push(key);
+                    }
+                }
+                Some((index, due)) = restarts.next() => {
+                    let node = &mut self.nodes[index];
+                    if !due {
+                        node.disconnect();
+                        continue;
+                    }
+                    match node.rebuild(registry, inputs, &self.modules_token, &self.clock).await {
+                        Ok(join_handle) => {
+                            info!(node = %node.key, kind = %node.kind, restarts = node.restarts, "node restarted");
+                            running.push(wait(node, index, join_handle));
+                            node.release();
+                        }
+                        Err(err) => {
+                            error!(node = %node.key, kind = %node.kind, restarts = node.restarts, "failed to restart node: {:?}", err);
+                            if let Some(key) = stopped(index, node, &mut restarts, &cancel_token) {
+                                failed.push(key);
+                            }
+                        }
+                    }
+                }
+                else => break,
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(IocBuildError::from_string(format!("critical nodes stopped: {:?}", failed)))
+        }
+    }
+
+    //transformers stop once their inputs close, but some also run on a timer. abort those once no module is left to feed them
+    fn stop_transformers(&self) {
+        if self.nodes.iter().any(|node| node.is_module() && node.abort_handle.is_some()) {
+            return;
+        }
+        for node in self.nodes.iter().filter(|node| !node.is_module()) {
+            if let Some(abort_handle) = &node.abort_handle {
+                abort_handle.abort();
+            }
+        }
+    }
+}
+
+//schedules a restart, or gives up on the node. returns the node's key if it was critical
+fn stopped(index: usize, node: &mut Node, restarts: &mut Restarts, cancel_token: &CancellationToken) -> Option<String> {
+    match node.next_delay() {
+        Some(delay) => {
+            info!(node = %node.key, delay_ms = delay.as_millis() as u64, "restarting node");
+            restarts.push(restart_after(index, delay, cancel_token.clone()));
+            None
+        }
+        None => {
+            node.disconnect();
+            node.failed = true;
+            if node.policy.critical {
+                error!(node = %node.key, kind = %node.kind, "critical node stopped. shutting down");
+                cancel_token.cancel();
+                Some(node.key.clone())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn wait(node: &mut Node, index: usize, join_handle: JoinHandle<()>) -> LocalBoxFuture<'static, (usize, Result<(), JoinError>)> {
+    node.abort_handle = Some(join_handle.abort_handle());
+    join_handle.map(move |result| (index, result)).boxed_local()
+}
+
+//resolves to false if the IOC is shut down before the restart is due
+fn restart_after(index: usize, delay: Duration, cancel_token: CancellationToken) -> LocalBoxFuture<'static, (usize, bool)> {
+    async move {
+        tokio::select! {
+            _ = sleep(delay) => (index, true),
+            _ = cancel_token.cancelled() => (index, false),
+        }
+    }
+    .boxed_local()
+}
+
+fn panic_message(err: JoinError) -> String {
+    let panic = err.into_panic();
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NodeSupervisionConfig, RestartPolicy, SupervisionConfig, Supervisor};
+    use ioc_core::{clock::Clock, registry::{NodeConfig, Registry}, safe::SafeOutput, Input, InputKind, ModuleIO, Output};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio_util::sync::CancellationToken;
+
+    //a module that panics the first time it's built. its input x is the number of times it was built before
+    fn flaky_registry() -> Registry {
+        let builds = Arc::new(AtomicUsize::new(0));
+        let mut registry = Registry::default();
+        registry.register_module("Flaky", move |_: (), cancel_token: CancellationToken| {
+            let build = builds.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let (x, tx) = Input::new(build as f64);
+                let join_handle = tokio::spawn(async move {
+                    if build == 0 {
+                        panic!("flaky module failed");
+                    }
+                    cancel_token.cancelled().await;
+                    drop(tx);
+                });
+                Ok(ModuleIO {
+                    join_handle,
+                    inputs: HashMap::from([("x".to_string(), InputKind::Float(x))]),
+                    outputs: HashMap::new(),
+                })
+            }
+        });
+        registry
+    }
+
+    async fn add_flaky(registry: &Registry, supervisor: &mut Supervisor, cancel_token: &CancellationToken) -> HashMap<String, InputKind> {
+        let (mut inputs, mut outputs) = (HashMap::new(), HashMap::new());
+        let node: NodeConfig = serde_json::from_str(r#"{"Flaky": null}"#).unwrap();
+        let module_token = cancel_token.child_token();
+        let module = registry.build_module(node.clone(), module_token.clone()).await.unwrap();
+        supervisor.add_module("flaky".to_string(), node, module, module_token, &mut inputs, &mut outputs);
+        inputs
+    }
+
+    async fn run_flaky(policy: NodeSupervisionConfig) -> (Result<(), ioc_core::error::IocBuildError>, f64) {
+        let registry = flaky_registry();
+        let cancel_token = CancellationToken::new();
+        let mut supervisor = Supervisor::new(
+            SupervisionConfig {
+                default: policy,
+                nodes: Default::default(),
+            },
+            cancel_token.clone(),
+            Clock::real(),
+        );
+        let inputs = add_flaky(&registry, &mut supervisor, &cancel_token).await;
+        let mut x = match inputs.get("flaky.x") {
+            Some(InputKind::Float(x)) => x.source(),
+            _ => panic!("expected a float input"),
+        };
+
+        let shutdown = cancel_token.clone();
+        let (result, last) = tokio::join!(supervisor.run(&registry, &inputs, cancel_token), async move {
+            //stop once the module was restarted. if its input closes, the supervisor shuts down by itself
+            while x.changed().await.is_ok() {
+                if *x.borrow_and_update() >= 1.0 {
+                    shutdown.cancel();
+                }
+            }
+            *x.borrow()
+        });
+        (result, last)
+    }
+
+    #[tokio::test]
+    async fn test_restart() {
+        let policy = NodeSupervisionConfig {
+            restart: RestartPolicy::Always { delay_ms: 10 },
+            max_restarts: Some(1),
+            critical: true,
+        };
+        //the relayed input is connected to the restarted module
+        let (result, x) = run_flaky(policy).await;
+        assert!(result.is_ok());
+        assert_eq!(x, 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_critical() {
+        let policy = NodeSupervisionConfig {
+            restart: RestartPolicy::Never,
+            max_restarts: None,
+            critical: true,
+        };
+        let (result, x) = run_flaky(policy).await;
+        assert!(result.is_err());
+        assert_eq!(x, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_safe_outputs() {
+        let registry = flaky_registry();
+        let cancel_token = CancellationToken::new();
+        let mut supervisor = Supervisor::new(SupervisionConfig::default(), cancel_token.clone(), Clock::real());
+        let inputs = add_flaky(&registry, &mut supervisor, &cancel_token).await;
+        let (output, mut rx) = Output::new();
+        let (guard, _guarded) = SafeOutput::new(output, -1.0);
+        supervisor.guard_outputs(|_| vec![guard.clone()]);
+
+        //outputs downstream of the node are driven to their safe values when it stops
+        let shutdown = cancel_token.clone();
+        let (result, value) = tokio::join!(supervisor.run(&registry, &inputs, cancel_token), async move {
+            let value = rx.recv().await;
+            shutdown.cancel();
+            value
+        });
+        assert!(result.is_ok());
+        assert_eq!(value, Some(-1.0));
+    }
+
+    #[tokio::test]
+    async fn test_liveness() {
+        let registry = flaky_registry();
+        let cancel_token = CancellationToken::new();
+        let mut supervisor = Supervisor::new(SupervisionConfig::default(), cancel_token.clone(), Clock::real());
+        let inputs = add_flaky(&registry, &mut supervisor, &cancel_token).await;
+        let liveness = supervisor.liveness();
+
+        let shutdown = cancel_token.clone();
+        let (result, status) = tokio::join!(supervisor.run(&registry, &inputs, cancel_token), async move {
+            //the module panics right away and isn't restarted
+            let mut status = liveness.check().await;
+            while status.as_ref().is_some_and(|status| status.running > 0) {
+                tokio::task::yield_now().await;
+                status = liveness.check().await;
+            }
+            shutdown.cancel();
+            status
+        });
+        assert!(result.is_ok());
+        let status = status.unwrap();
+        assert_eq!(status.failed, vec!["flaky".to_string()]);
+        assert_eq!(status.to_string(), "0/1 nodes running, failed: flaky");
+    }
+}
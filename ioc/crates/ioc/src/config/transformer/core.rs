@@ -2,15 +2,17 @@ use std::collections::{HashMap, HashSet};
 
 use super::TransformerConfig;
 use ioc_core::{
+    clock::Clock,
     error::IocBuildError,
     transformer::{Sum, SumConfig},
     InputKind, Transformer, TransformerI,
 };
 
 use serde::Deserialize;
+use schemars::JsonSchema;
 
 ///Creates a transformer that reads Float values from any number of inputs, emits an input named 'value' which is their sum. 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct SumTransformerConfig {
     pub inputs: Vec<String>,
 }
@@ -19,6 +21,7 @@ impl TransformerConfig for SumTransformerConfig {
     async fn try_build(
         &self,
         upstream_inputs: &HashMap<String, InputKind>,
+        _clock: &Clock,
     ) -> Result<TransformerI, IocBuildError> {
         let mut inputs = Vec::with_capacity(self.inputs.len());
         let mut errors = Vec::with_capacity(inputs.len());
@@ -0,0 +1,89 @@
+use std::collections::{HashMap, HashSet};
+
+use ioc_core::{clock::Clock, error::IocBuildError, Input, InputKind, Transformer, TransformerI, Value};
+use ioc_devices::devices::imu_calibration::{ImuCalibration, ImuCalibrationConfig, SensorInputs, DEFAULT_GYRO_SAMPLES, DEFAULT_MAG_SAMPLES};
+use serde::Deserialize;
+use schemars::JsonSchema;
+
+use super::TransformerConfig;
+
+///Calibrates the gyro and magnetometer of an IMU, and saves the results to `file` for the devices to load with their `calibration_file`.
+/// gyro and magnetometer name the devices' raw Array inputs, like `gyro.raw` or `mag_accel.magnetometer_raw`.
+/// Readings are collected once start_gyro or start_mag, Bool inputs, turn true: gyro_samples (200 by default) while the gyro is held still,
+/// or mag_samples (500 by default) while the magnetometer is turned through every orientation.
+/// Emits a String input named 'state' telling what it's doing, and a Float input named 'progress' from 0 to 1.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ImuCalibrationTransformerConfig {
+    file: String,
+    gyro: Option<String>,
+    start_gyro: Option<String>,
+    gyro_samples: Option<usize>,
+    magnetometer: Option<String>,
+    start_mag: Option<String>,
+    mag_samples: Option<usize>,
+}
+
+fn array_input<'a>(upstream_inputs: &'a HashMap<String, InputKind>, name: &String) -> Result<&'a Input<Vec<Value>>, IocBuildError> {
+    match upstream_inputs.get(name) {
+        Some(InputKind::Array(array)) => Ok(array),
+        Some(x) => Err(IocBuildError::from_string(format!(
+            "unable to build imu calibration from non-array input of type {:?}",
+            x
+        ))),
+        None => Err(IocBuildError::from_string(format!("no input with name {}", name))),
+    }
+}
+
+fn bool_input<'a>(upstream_inputs: &'a HashMap<String, InputKind>, name: &String) -> Result<&'a Input<bool>, IocBuildError> {
+    match upstream_inputs.get(name) {
+        Some(InputKind::Bool(start)) => Ok(start),
+        Some(x) => Err(IocBuildError::from_string(format!(
+            "unable to start imu calibration from non-bool input of type {:?}",
+            x
+        ))),
+        None => Err(IocBuildError::from_string(format!("no input with name {}", name))),
+    }
+}
+
+//a sensor's readings and the input that starts calibrating it, which go together
+fn sensor<'a>(
+    upstream_inputs: &'a HashMap<String, InputKind>,
+    readings: &Option<String>,
+    start: &Option<String>,
+) -> Result<Option<SensorInputs<'a>>, IocBuildError> {
+    match (readings, start) {
+        (Some(readings), Some(start)) => Ok(Some((
+            array_input(upstream_inputs, readings)?,
+            bool_input(upstream_inputs, start)?,
+        ))),
+        (None, None) => Ok(None),
+        _ => Err(IocBuildError::message(
+            "imu calibration needs gyro with start_gyro, and magnetometer with start_mag",
+        )),
+    }
+}
+
+impl TransformerConfig for ImuCalibrationTransformerConfig {
+    async fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        _clock: &Clock,
+    ) -> Result<TransformerI, IocBuildError> {
+        let calibration = ImuCalibration::try_build(&ImuCalibrationConfig {
+            file: self.file.clone().into(),
+            gyro: sensor(upstream_inputs, &self.gyro, &self.start_gyro)?,
+            gyro_samples: self.gyro_samples.unwrap_or(DEFAULT_GYRO_SAMPLES),
+            magnetometer: sensor(upstream_inputs, &self.magnetometer, &self.start_mag)?,
+            mag_samples: self.mag_samples.unwrap_or(DEFAULT_MAG_SAMPLES),
+        })
+        .await?;
+        Ok(calibration.into())
+    }
+
+    fn needs_inputs(&self) -> HashSet<&String> {
+        [&self.gyro, &self.start_gyro, &self.magnetometer, &self.start_mag]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
@@ -1,23 +1,31 @@
 use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 use super::TransformerConfig;
-use ioc_core::{error::IocBuildError, InputKind, Transformer, TransformerI, Value};
+use ioc_core::{clock::Clock, error::IocBuildError, Input, InputKind, Transformer, TransformerI, Value};
 use ioc_extra::transform::{
     hbridge::{HBridge, HBridgeConfig},
     linear::{LinearTransform, LinearTransformConfig},
     function::FunctionTransformer,
-    pid::{Pid, PidConfig},
+    pid::{AntiWindup, Pid, PidAutotune, PidConfig, PidLimits, TuningRule},
+    cascade::{Cascade, CascadeConfig, CascadeLoop},
+    mixer::{Mixer, MixerConfig},
     limiter::{LimiterParams, LimiterFilterConfig, Limiter},
     average::{WindowAverageFilterConfig, WindowAverage},
+    expression::{Expression, ExpressionConfig, ExpressionInput},
+    ahrs::{Ahrs, AhrsConfig, AhrsFilter},
+    filter::{Filter, FilterConfig, FilterKind},
+    kalman::{Kalman, KalmanConfig, KalmanControl, KalmanInput, KalmanMeasurement},
 };
 
 use serde::Deserialize;
+use schemars::JsonSchema;
 
 ///Creates a transformer that consumes a Float input that is positive or negative. Emits three inputs:
 /// - forward - this is the input when it is positive, zero otherwise
 /// - reverse - this is -input when it is negative, zero otherwise
 /// - enable - this is 1.0 when input is nonzero, zero otherwize
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct HBridgeTransformerConfig {
     input: String,
 }
@@ -26,6 +34,7 @@ impl TransformerConfig for HBridgeTransformerConfig {
     async fn try_build(
         &self,
         upstream_inputs: &HashMap<String, InputKind>,
+        _clock: &Clock,
     ) -> Result<TransformerI, IocBuildError> {
         let input = match upstream_inputs.get(&self.input) {
             Some(InputKind::Float(float)) => float,
@@ -63,7 +72,7 @@ impl TransformerConfig for HBridgeTransformerConfig {
 /// from must contain two values for the domain. from[0] must be less than from[1]
 /// to must contain two values for range. if to[0] > to[1] then there will be an inverse relationship. 
 /// If the input supplied is beyond the domain, the output emitted will be beyond the range.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct LinearTransformerConfig {
     input: String,
     from: Vec<f64>,
@@ -74,6 +83,7 @@ impl TransformerConfig for LinearTransformerConfig {
     async fn try_build(
         &self,
         upstream_inputs: &HashMap<String, InputKind>,
+        _clock: &Clock,
     ) -> Result<TransformerI, IocBuildError> {
         if self.from.len() != 2 || self.to.len() != 2 {
             return Err(IocBuildError::message(
@@ -113,7 +123,7 @@ impl TransformerConfig for LinearTransformerConfig {
 
 
 ///Clamps the input Float to the min and max, inclusive. Emits an inputs called 'value'
-#[derive(Debug,Deserialize)]
+#[derive(Debug,Deserialize,JsonSchema)]
 pub struct ClampConfig {
     pub input: String,
     pub min: f64,
@@ -124,6 +134,7 @@ impl TransformerConfig for ClampConfig {
     async fn try_build(
         &self,
         upstream_inputs: &HashMap<String, InputKind>,
+        _clock: &Clock,
     ) -> Result<TransformerI, IocBuildError> {
         if self.min > self.max {
             return Err(IocBuildError::message(
@@ -163,8 +174,8 @@ impl TransformerConfig for ClampConfig {
     }
 }
 
-///Consumes an Array input, expected to be a 3-vector. Drops the z axis and computes atan2(y,x) and emits an input named 'value'
-#[derive(Debug,Deserialize)]
+///Consumes an Array input, expected to be a 3-vector of Floats or Ints. Drops the z axis, which may be left out, and computes atan2(y,x) and emits an input named 'value'
+#[derive(Debug,Deserialize,JsonSchema)]
 pub struct HeadingConfig {
     pub input: String,
 }
@@ -173,6 +184,7 @@ impl TransformerConfig for HeadingConfig {
     async fn try_build(
         &self,
         upstream_inputs: &HashMap<String, InputKind>,
+        _clock: &Clock,
     ) -> Result<TransformerI, IocBuildError> {
 
         let input = match upstream_inputs.get(&self.input) {
@@ -192,10 +204,10 @@ impl TransformerConfig for HeadingConfig {
         };
 
         let output = FunctionTransformer::new(input, move |vec: Vec<Value>| {
-            if vec.len() > 2 {
-                match (&vec[0], &vec[1]) {
-                    (Value::Float(x), Value::Float(y)) => {
-                        y.atan2(*x)
+            if vec.len() >= 2 {
+                match (vec[0].as_f64(), vec[1].as_f64()) {
+                    (Some(x), Some(y)) => {
+                        y.atan2(x)
                     },
                     _ => f64::NAN
                 }
@@ -216,13 +228,170 @@ impl TransformerConfig for HeadingConfig {
     }
 }
 
+///Fuses a gyro, accelerometer and magnetometer, Array inputs like the `L3dg20`'s and `Lsm303dlhc`'s, into the orientation of the sensor.
+/// The gyro reads radians per second around x, y and z. The accelerometer and magnetometer can be in any unit, and the magnetometer can be left out,
+/// but then yaw drifts and there is no heading. filter is `{ Madgwick: { beta: 0.1 } }` by default, or `{ Mahony: { kp: 1.0, ki: 0.0 } }`.
+/// Every period_ms milliseconds it emits:
+/// - quaternion - the orientation as an Array [w, x, y, z], rotating the sensor's axes onto north, west and up
+/// - roll, pitch and yaw - in radians, with yaw counterclockwise from magnetic north
+/// - heading - the angle `Heading` emits when the sensor is level, corrected for tilt
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AhrsTransformerConfig {
+    gyro: String,
+    accelerometer: String,
+    magnetometer: Option<String>,
+    #[serde(default)]
+    filter: AhrsFilter,
+    period_ms: u64,
+}
+
+fn array_input<'a>(upstream_inputs: &'a HashMap<String, InputKind>, name: &String) -> Result<&'a Input<Vec<Value>>, IocBuildError> {
+    match upstream_inputs.get(name) {
+        Some(InputKind::Array(arr)) => Ok(arr),
+        Some(x) => Err(IocBuildError::from_string(format!(
+            "unable to build ahrs from non-array input of type {:?}",
+            x
+        ))),
+        None => Err(IocBuildError::from_string(format!("no input with name {}", name))),
+    }
+}
+
+impl TransformerConfig for AhrsTransformerConfig {
+    async fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
+    ) -> Result<TransformerI, IocBuildError> {
+        let ahrs = Ahrs::try_build(&AhrsConfig {
+            gyro: array_input(upstream_inputs, &self.gyro)?,
+            accelerometer: array_input(upstream_inputs, &self.accelerometer)?,
+            magnetometer: match &self.magnetometer {
+                Some(magnetometer) => Some(array_input(upstream_inputs, magnetometer)?),
+                None => None,
+            },
+            filter: self.filter,
+            period_ms: self.period_ms,
+            clock,
+        })
+        .await?;
+        Ok(ahrs.into())
+    }
+
+    fn needs_inputs(&self) -> HashSet<&String> {
+        [Some(&self.gyro), Some(&self.accelerometer), self.magnetometer.as_ref()]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+///A measurement for a Kalman filter, from a Float or Array input. h maps the state to what it measures, with a row for each of its values,
+/// and r is the covariance of its noise.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct KalmanMeasurementConfig {
+    input: String,
+    h: Vec<Vec<f64>>,
+    r: Vec<Vec<f64>>,
+}
+
+///A control input for a Kalman filter, a Float or an Array. b maps it to its effect on the state over a period, with a column for each of its values.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct KalmanControlConfig {
+    input: String,
+    b: Vec<Vec<f64>>,
+}
+
+///A linear Kalman filter. Every period_ms milliseconds it moves the state ahead with the matrix f, adding the process noise q and
+/// the effect of the optional control, and it corrects the state whenever one of its measurements changes.
+/// The state has as many values as f has rows. It starts at initial_state, all zeros by default, with initial_covariance, the identity by default.
+/// Emits Arrays named 'state', the estimate, and 'variance', the diagonal of its covariance. With names, e.g. `[position, velocity]`,
+/// it also emits each value of the state as a Float input with that name.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct KalmanTransformerConfig {
+    f: Vec<Vec<f64>>,
+    q: Vec<Vec<f64>>,
+    control: Option<KalmanControlConfig>,
+    measurements: Vec<KalmanMeasurementConfig>,
+    initial_state: Option<Vec<f64>>,
+    initial_covariance: Option<Vec<Vec<f64>>>,
+    #[serde(default)]
+    names: Vec<String>,
+    period_ms: u64,
+}
+
+fn kalman_input<'a>(upstream_inputs: &'a HashMap<String, InputKind>, name: &String) -> Result<KalmanInput<'a>, IocBuildError> {
+    match upstream_inputs.get(name) {
+        Some(InputKind::Float(float)) => Ok(KalmanInput::Float(float)),
+        Some(InputKind::Array(arr)) => Ok(KalmanInput::Array(arr)),
+        Some(x) => Err(IocBuildError::from_string(format!(
+            "unable to build kalman filter from input of type {:?}. must be a Float or an Array",
+            x
+        ))),
+        None => Err(IocBuildError::from_string(format!("no input with name {}", name))),
+    }
+}
+
+impl TransformerConfig for KalmanTransformerConfig {
+    async fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
+    ) -> Result<TransformerI, IocBuildError> {
+        let control = match &self.control {
+            Some(control) => Some(KalmanControl {
+                input: kalman_input(upstream_inputs, &control.input)?,
+                b: control.b.clone(),
+            }),
+            None => None,
+        };
+        let measurements = self
+            .measurements
+            .iter()
+            .map(|measurement| {
+                Ok(KalmanMeasurement {
+                    input: kalman_input(upstream_inputs, &measurement.input)?,
+                    h: measurement.h.clone(),
+                    r: measurement.r.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, IocBuildError>>()?;
+        let kalman = Kalman::try_build(&KalmanConfig {
+            f: self.f.clone(),
+            q: self.q.clone(),
+            control,
+            measurements,
+            initial_state: self.initial_state.clone(),
+            initial_covariance: self.initial_covariance.clone(),
+            names: self.names.clone(),
+            period_ms: self.period_ms,
+            clock,
+        })
+        .await?;
+        Ok(kalman.into())
+    }
+
+    fn needs_inputs(&self) -> HashSet<&String> {
+        self.measurements
+            .iter()
+            .map(|measurement| &measurement.input)
+            .chain(self.control.as_ref().map(|control| &control.input))
+            .collect()
+    }
+}
+
 ///Configuration for a tunable PID controller. 
 /// p, i, and d are inputs for the P, I, and D coefficients respectively. Integrals and derivatives are calculated numerically.
 /// set_point is the desired state
 /// process_var is the observed state
 /// period_ms is the number of milliseconds between frames
-/// emits an input named 'value' which is the control signal.
-#[derive(Debug,Deserialize)]
+/// min/max optionally limit the output, and anti_windup picks how the integral is kept from winding up meanwhile: `Clamp` (the default) or `{ BackCalculation: { tracking_ms: 100 } }`
+/// the derivative is taken from the process_var, so set point changes don't kick it, and derivative_filter_ms is the time constant of a low-pass filter on it
+/// feed_forward is an optional Float input added to the output
+/// enable is an optional Bool input. while it's false the output is the optional manual input, or 0, and the controller picks up from there when it's enabled again
+/// autotune optionally finds gains with a relay experiment, see PidAutotuneConfig
+/// emits an input named 'value' which is the control signal, and 'p_term', 'i_term' and 'd_term', its parts, for tuning.
+/// with autotune it also emits 'tuning', 'ultimate_gain', 'ultimate_period' and the gains it suggests, 'suggested_p', 'suggested_i' and 'suggested_d'
+#[derive(Debug,Deserialize,JsonSchema)]
 pub struct PidCtrlConfig {
     p: String,
     i: String,
@@ -230,116 +399,284 @@ pub struct PidCtrlConfig {
     set_point: String,
     process_var: String,
     period_ms: u64,
+    min: Option<f64>,
+    max: Option<f64>,
+    #[serde(default)]
+    anti_windup: AntiWindup,
+    #[serde(default)]
+    derivative_filter_ms: f64,
+    feed_forward: Option<String>,
+    enable: Option<String>,
+    manual: Option<String>,
+    autotune: Option<PidAutotuneConfig>,
+}
+
+///Autotuning for a PID controller. When the Bool input start turns true, the output switches between amplitude above and below what it was
+/// as the process_var crosses the set point, until it measured cycles oscillations after the first. The oscillations give the ultimate gain and period,
+/// and rule (ZieglerNichols, ZieglerNicholsPi, TyreusLuyben, SomeOvershoot or NoOvershoot, ZieglerNichols by default) turns them into suggested gains.
+/// the process_var has to cross the set point by hysteresis, 0 by default, to switch the output. turning start false stops the experiment
+/// experiments that don't finish within max_duration_ms, 5 minutes by default, or whose oscillation doesn't exceed hysteresis, stop without results
+#[derive(Debug,Deserialize,JsonSchema)]
+pub struct PidAutotuneConfig {
+    start: String,
+    amplitude: f64,
+    #[serde(default)]
+    hysteresis: f64,
+    #[serde(default = "default_autotune_cycles")]
+    cycles: u32,
+    #[serde(default)]
+    rule: TuningRule,
+    #[serde(default = "default_autotune_max_duration_ms")]
+    max_duration_ms: u64,
+}
+
+fn default_autotune_cycles() -> u32 {
+    4
+}
+
+fn default_autotune_max_duration_ms() -> u64 {
+    300_000
+}
+
+fn pid_float_input<'a>(upstream_inputs: &'a HashMap<String, InputKind>, name: &String) -> Result<&'a Input<f64>, IocBuildError> {
+    match upstream_inputs.get(name) {
+        Some(InputKind::Float(float)) => Ok(float),
+        Some(x) => Err(IocBuildError::from_string(format!(
+            "unable to build pid controller from non-float input of type {:?}",
+            x
+        ))),
+        None => Err(IocBuildError::from_string(format!("no input with name {}", name))),
+    }
+}
+
+fn pid_bool_input<'a>(upstream_inputs: &'a HashMap<String, InputKind>, name: &String) -> Result<&'a Input<bool>, IocBuildError> {
+    match upstream_inputs.get(name) {
+        Some(InputKind::Bool(input)) => Ok(input),
+        Some(x) => Err(IocBuildError::from_string(format!(
+            "unable to build pid controller from non-bool input of type {:?}",
+            x
+        ))),
+        None => Err(IocBuildError::from_string(format!("no input with name {}", name))),
+    }
 }
 
 impl TransformerConfig for PidCtrlConfig {
     async fn try_build(
         &self,
         upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
     ) -> Result<TransformerI, IocBuildError> {
-        let p = match upstream_inputs.get(&self.p) {
-            Some(InputKind::Float(float)) => float,
-            Some(x) => {
-                return Err(IocBuildError::from_string(format!(
-                    "unable to build pid controller from non-float input of type {:?}",
-                    x
-                )));
-            }
-            None => {
-                return Err(IocBuildError::from_string(format!(
-                    "no input with name {}",
-                    self.p
-                )));
-            }
-        };
-        let i = match upstream_inputs.get(&self.i) {
-            Some(InputKind::Float(float)) => float,
-            Some(x) => {
-                return Err(IocBuildError::from_string(format!(
-                    "unable to build pid controller from non-float input of type {:?}",
-                    x
-                )));
-            }
-            None => {
-                return Err(IocBuildError::from_string(format!(
-                    "no input with name {}",
-                    self.i
-                )));
-            }
-        };
-        let d = match upstream_inputs.get(&self.d) {
-            Some(InputKind::Float(float)) => float,
-            Some(x) => {
-                return Err(IocBuildError::from_string(format!(
-                    "unable to build pid controller from non-float input of type {:?}",
-                    x
-                )));
-            }
-            None => {
-                return Err(IocBuildError::from_string(format!(
-                    "no input with name {}",
-                    self.d
-                )));
-            }
+        let enable = match &self.enable {
+            Some(enable) => Some(pid_bool_input(upstream_inputs, enable)?),
+            None => None,
         };
-        let sp = match upstream_inputs.get(&self.set_point) {
-            Some(InputKind::Float(float)) => float,
-            Some(x) => {
-                return Err(IocBuildError::from_string(format!(
-                    "unable to build pid controller from non-float input of type {:?}",
-                    x
-                )));
-            }
-            None => {
-                return Err(IocBuildError::from_string(format!(
-                    "no input with name {}",
-                    self.set_point
-                )));
-            }
+        let autotune = match &self.autotune {
+            Some(autotune) => Some(PidAutotune {
+                start: pid_bool_input(upstream_inputs, &autotune.start)?,
+                amplitude: autotune.amplitude,
+                hysteresis: autotune.hysteresis,
+                cycles: autotune.cycles,
+                rule: autotune.rule,
+                max_duration: Duration::from_millis(autotune.max_duration_ms),
+            }),
+            None => None,
         };
-        let pv = match upstream_inputs.get(&self.process_var) {
-            Some(InputKind::Float(float)) => float,
-            Some(x) => {
-                return Err(IocBuildError::from_string(format!(
-                    "unable to build pid controller from non-float input of type {:?}",
-                    x
-                )));
-            }
-            None => {
-                return Err(IocBuildError::from_string(format!(
-                    "no input with name {}",
-                    self.process_var
-                )));
-            }
+        let optional_input = |name: &Option<String>| match name {
+            Some(name) => pid_float_input(upstream_inputs, name).map(Some),
+            None => Ok(None),
         };
 
         let pid = Pid::try_build(
             &PidConfig{
-                set_point: sp,
-                process_var: pv,
-                p,
-                i,
-                d,
+                set_point: pid_float_input(upstream_inputs, &self.set_point)?,
+                process_var: pid_float_input(upstream_inputs, &self.process_var)?,
+                p: pid_float_input(upstream_inputs, &self.p)?,
+                i: pid_float_input(upstream_inputs, &self.i)?,
+                d: pid_float_input(upstream_inputs, &self.d)?,
+                feed_forward: optional_input(&self.feed_forward)?,
+                enable,
+                manual: optional_input(&self.manual)?,
+                limits: PidLimits {
+                    min: self.min.unwrap_or(f64::NEG_INFINITY),
+                    max: self.max.unwrap_or(f64::INFINITY),
+                    anti_windup: self.anti_windup,
+                },
+                derivative_filter_ms: self.derivative_filter_ms,
+                autotune,
                 period_ms: self.period_ms,
+                clock,
             }
         ).await?;
 
-        Ok(TransformerI{
-            join_handle: pid.join_handle,
-            inputs: HashMap::from([
-                ("value".to_string(), InputKind::Float(pid.value)),
-            ])
-        })
+        Ok(pid.into())
     }
 
     fn needs_inputs(&self) -> HashSet<&String> {
-        HashSet::from([
+        let mut needs = HashSet::from([
             &self.p,
             &self.i,
             &self.d,
             &self.process_var,
             &self.set_point,
-        ])
+        ]);
+        needs.extend(self.feed_forward.iter());
+        needs.extend(self.enable.iter());
+        needs.extend(self.manual.iter());
+        needs.extend(self.autotune.iter().map(|autotune| &autotune.start));
+        needs
+    }
+}
+
+///One of the loops of a Cascade. p, i, d and process_var are Float inputs, and the optional min, max, anti_windup, derivative_filter_ms
+/// and feed_forward are the same as for a PID controller.
+#[derive(Debug,Deserialize,JsonSchema)]
+pub struct CascadeLoopConfig {
+    p: String,
+    i: String,
+    d: String,
+    process_var: String,
+    min: Option<f64>,
+    max: Option<f64>,
+    #[serde(default)]
+    anti_windup: AntiWindup,
+    #[serde(default)]
+    derivative_filter_ms: f64,
+    feed_forward: Option<String>,
+}
+
+impl CascadeLoopConfig {
+    fn build<'a>(&self, upstream_inputs: &'a HashMap<String, InputKind>) -> Result<CascadeLoop<'a>, IocBuildError> {
+        Ok(CascadeLoop {
+            process_var: pid_float_input(upstream_inputs, &self.process_var)?,
+            p: pid_float_input(upstream_inputs, &self.p)?,
+            i: pid_float_input(upstream_inputs, &self.i)?,
+            d: pid_float_input(upstream_inputs, &self.d)?,
+            feed_forward: match &self.feed_forward {
+                Some(feed_forward) => Some(pid_float_input(upstream_inputs, feed_forward)?),
+                None => None,
+            },
+            limits: PidLimits {
+                min: self.min.unwrap_or(f64::NEG_INFINITY),
+                max: self.max.unwrap_or(f64::INFINITY),
+                anti_windup: self.anti_windup,
+            },
+            derivative_filter_ms: self.derivative_filter_ms,
+        })
+    }
+
+    fn needs_inputs(&self) -> impl Iterator<Item = &String> {
+        [&self.p, &self.i, &self.d, &self.process_var].into_iter().chain(self.feed_forward.iter())
+    }
+}
+
+///Two PID controllers in a cascade, e.g. a velocity loop inside a position loop. The outer loop's output is the inner loop's set point.
+/// set_point is the outer loop's set point, and outer and inner are the loops
+/// period_ms is the outer loop's period, and the inner loop runs inner_rate times each period, from the same clock
+/// enable is an optional Bool input. while it's false the output is the optional manual input, or 0, and the outer loop follows the inner loop's process_var
+/// so both pick up from where they are when it's enabled again
+/// emits an input named 'value' which is the inner loop's output, and 'inner_set_point', the outer loop's.
+#[derive(Debug,Deserialize,JsonSchema)]
+pub struct CascadeTransformerConfig {
+    set_point: String,
+    outer: CascadeLoopConfig,
+    inner: CascadeLoopConfig,
+    period_ms: u64,
+    inner_rate: u32,
+    enable: Option<String>,
+    manual: Option<String>,
+}
+
+impl TransformerConfig for CascadeTransformerConfig {
+    async fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
+    ) -> Result<TransformerI, IocBuildError> {
+        let cascade = Cascade::try_build(&CascadeConfig {
+            set_point: pid_float_input(upstream_inputs, &self.set_point)?,
+            outer: self.outer.build(upstream_inputs)?,
+            inner: self.inner.build(upstream_inputs)?,
+            enable: match &self.enable {
+                Some(enable) => Some(pid_bool_input(upstream_inputs, enable)?),
+                None => None,
+            },
+            manual: match &self.manual {
+                Some(manual) => Some(pid_float_input(upstream_inputs, manual)?),
+                None => None,
+            },
+            period_ms: self.period_ms,
+            inner_rate: self.inner_rate,
+            clock,
+        })
+        .await?;
+        Ok(cascade.into())
+    }
+
+    fn needs_inputs(&self) -> HashSet<&String> {
+        let mut needs: HashSet<&String> = self.outer.needs_inputs().chain(self.inner.needs_inputs()).collect();
+        needs.insert(&self.set_point);
+        needs.extend(self.enable.iter());
+        needs.extend(self.manual.iter());
+        needs
+    }
+}
+
+///One of the inputs of a Mixer, and its weight, 1 by default.
+#[derive(Debug,Deserialize,JsonSchema)]
+pub struct MixerInputConfig {
+    input: String,
+    #[serde(default = "default_mixer_weight")]
+    weight: f64,
+}
+
+fn default_mixer_weight() -> f64 {
+    1.0
+}
+
+///Adds up Float inputs, e.g. the outputs of several controllers, each times its weight, and clamps the sum to the optional min and max.
+/// emits an input named 'value' which is the clamped sum, and a Bool input named 'saturated', which is true while it's clamped.
+#[derive(Debug,Deserialize,JsonSchema)]
+pub struct MixerTransformerConfig {
+    inputs: Vec<MixerInputConfig>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl TransformerConfig for MixerTransformerConfig {
+    async fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        _clock: &Clock,
+    ) -> Result<TransformerI, IocBuildError> {
+        let mut inputs = Vec::new();
+        for input in self.inputs.iter() {
+            match upstream_inputs.get(&input.input) {
+                Some(InputKind::Float(float)) => inputs.push((float, input.weight)),
+                Some(x) => {
+                    return Err(IocBuildError::from_string(format!(
+                        "unable to build mixer from non-float input of type {:?}",
+                        x
+                    )));
+                }
+                None => {
+                    return Err(IocBuildError::from_string(format!(
+                        "no input with name {}",
+                        input.input
+                    )));
+                }
+            }
+        }
+        let mixer = Mixer::try_build(&MixerConfig {
+            inputs,
+            min: self.min.unwrap_or(f64::NEG_INFINITY),
+            max: self.max.unwrap_or(f64::INFINITY),
+        })
+        .await?;
+        Ok(mixer.into())
+    }
+
+    fn needs_inputs(&self) -> HashSet<&String> {
+        self.inputs.iter().map(|input| &input.input).collect()
     }
 }
 
@@ -348,7 +685,7 @@ impl TransformerConfig for PidCtrlConfig {
 /// vmin/vmax are limits on the velocity in units/sec. Must have vmin < 0 and vmax > 0
 /// amin/amax are the acceleration values used to move. Must have amin < 0 and amax > 0
 /// emits an input named 'value' every period_ms milliseconds
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct LimiterConfig {
     input: String,
     amin: f64,
@@ -364,6 +701,7 @@ impl TransformerConfig for LimiterConfig {
     async fn try_build(
         &self,
         upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
     ) -> Result<TransformerI, IocBuildError> {
         let input = match upstream_inputs.get(&self.input) {
             Some(InputKind::Float(float)) => float,
@@ -387,7 +725,8 @@ impl TransformerConfig for LimiterConfig {
                 dmin: self.vmin, dmax: self.vmax,
                 ddmin: self.amin, ddmax: self.amax,
                 period_ms: self.period_ms as u64,
-            }
+            },
+            clock,
         };
         let limiter = Limiter::try_build(&cfg).await?;
 
@@ -407,7 +746,7 @@ impl TransformerConfig for LimiterConfig {
 }
 
 ///This takes fast-changing input Float value, calculates its average using reimann sums over windows period_ms milliseconds and emits those average values.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct WindowAverageConfig {
     input: String,
     period_ms: u64,
@@ -417,6 +756,7 @@ impl TransformerConfig for WindowAverageConfig {
     async fn try_build(
         &self,
         upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
     ) -> Result<TransformerI, IocBuildError> {
         let input = match upstream_inputs.get(&self.input) {
             Some(InputKind::Float(float)) => float,
@@ -436,6 +776,7 @@ impl TransformerConfig for WindowAverageConfig {
         let cfg = WindowAverageFilterConfig{
             input,
             period_ms: self.period_ms,
+            clock,
         };
         let avg = WindowAverage::try_build(&cfg).await?;
 
@@ -452,4 +793,111 @@ impl TransformerConfig for WindowAverageConfig {
             &self.input
         ])
     }
-}
\ No newline at end of file
+}
+
+///Filters a Float input and emits the result as an input named 'value'. filter is one of:
+/// - `{ Ema: { time_constant_ms: 200 } }` - exponential moving average, emitting every period_ms, a tenth of the time constant by default
+/// - `{ LowPass: { cutoff_hz: 5, sample_rate_hz: 100 } }` and `HighPass` - second order Butterworth filters
+/// - `{ BandPass: { low_hz: 1, high_hz: 5, sample_rate_hz: 100 } }` - second order band-pass filter
+/// - `{ Median: { window_ms: 100 } }` - median of the values of the last window_ms milliseconds, for rejecting spikes
+/// - `{ Deadband: { center: 0, width: 0.05 } }` - values within width of center become center
+///
+/// Butterworth filters sample the input, holding its last value, sample_rate_hz times a second, and the moving average
+/// weighs values by how long they were held, so both work with inputs that update unevenly or rarely.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct FilterTransformerConfig {
+    input: String,
+    filter: FilterKind,
+}
+
+impl TransformerConfig for FilterTransformerConfig {
+    async fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
+    ) -> Result<TransformerI, IocBuildError> {
+        let input = match upstream_inputs.get(&self.input) {
+            Some(InputKind::Float(float)) => float,
+            Some(x) => {
+                return Err(IocBuildError::from_string(format!(
+                    "unable to build filter from non-float input of type {:?}",
+                    x
+                )));
+            }
+            None => {
+                return Err(IocBuildError::from_string(format!(
+                    "no input with name {}",
+                    self.input
+                )));
+            }
+        };
+        let filter = Filter::try_build(&FilterConfig {
+            input,
+            kind: self.filter.clone(),
+            clock,
+        })
+        .await?;
+        Ok(filter.into())
+    }
+
+    fn needs_inputs(&self) -> HashSet<&String> {
+        HashSet::from([&self.input])
+    }
+}
+
+///Evaluates an expression whenever one of its inputs changes, and emits the result as an input named 'value'.
+/// inputs maps the names used in the expression to upstream Float, Int or Bool inputs. Ints are converted to Floats.
+/// Names should be lowercase, since the config crate lowercases keys read from config files.
+/// The expression is type checked when it's built, and 'value' is a Float or a Bool depending on its type, e.g.
+/// `clamp((a - b) * 0.5, -1, 1)` emits a Float and `a > 0.2 && enabled` emits a Bool.
+/// Supports `+ - * / % ^`, `< <= > >= == !=`, `&& || !`, `condition ? a : b`, `pi`, and the functions
+/// abs, sign, sqrt, exp, ln, log10, floor, ceil, round, sin, cos, tan, asin, acos, atan, atan2, pow, min, max and clamp.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ExpressionTransformerConfig {
+    inputs: HashMap<String, String>,
+    expression: String,
+}
+
+impl TransformerConfig for ExpressionTransformerConfig {
+    async fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        _clock: &Clock,
+    ) -> Result<TransformerI, IocBuildError> {
+        let mut inputs = HashMap::with_capacity(self.inputs.len());
+        let mut errors = Vec::new();
+        for (name, key) in &self.inputs {
+            match upstream_inputs.get(key) {
+                Some(InputKind::Float(input)) => {
+                    inputs.insert(name.clone(), ExpressionInput::Float(input));
+                }
+                Some(InputKind::Int(input)) => {
+                    inputs.insert(name.clone(), ExpressionInput::Int(input));
+                }
+                Some(InputKind::Bool(input)) => {
+                    inputs.insert(name.clone(), ExpressionInput::Bool(input));
+                }
+                Some(other) => errors.push(IocBuildError::from_string(format!(
+                    "Expression input '{name}' must be a Float, Int or Bool but '{key}' is {other:?}"
+                ))),
+                None => errors.push(IocBuildError::from_string(format!(
+                    "No input named '{key}'"
+                ))),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(IocBuildError::from_errs(errors));
+        }
+
+        let expression = Expression::try_build(&ExpressionConfig {
+            expression: &self.expression,
+            inputs,
+        })
+        .await?;
+        Ok(expression.into())
+    }
+
+    fn needs_inputs(&self) -> HashSet<&String> {
+        self.inputs.values().collect()
+    }
+}
@@ -4,7 +4,7 @@ pub mod core;
 pub mod extra;
 
 #[cfg(feature = "extra")]
-use extra::{HBridgeTransformerConfig, LinearTransformerConfig, ClampConfig, HeadingConfig, PidCtrlConfig, LimiterConfig, WindowAverageConfig};
+use extra::{HBridgeTransformerConfig, LinearTransformerConfig, ClampConfig, HeadingConfig, PidCtrlConfig, LimiterConfig, WindowAverageConfig, ExpressionTransformerConfig, AhrsTransformerConfig, KalmanTransformerConfig, FilterTransformerConfig, CascadeTransformerConfig, MixerTransformerConfig};
 
 #[cfg(feature = "sims")]
 pub mod sims;
@@ -12,105 +12,64 @@ pub mod sims;
 #[cfg(feature = "sims")]
 use sims::DampedOscillatorSimConfig;
 
+#[cfg(feature = "script")]
+pub mod script;
+
+#[cfg(feature = "script")]
+use script::ScriptTransformerConfig;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "wasm")]
+use wasm::WasmTransformerConfig;
+
+#[cfg(feature = "devices")]
+pub mod devices;
+
+#[cfg(feature = "devices")]
+use devices::ImuCalibrationTransformerConfig;
+
 use core::SumTransformerConfig;
-use ioc_core::{error::IocBuildError, InputKind, TransformerI};
-use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
-use std::future::Future;
-
-///This trait should be implemented by all config objects
-pub trait TransformerConfig {
-    fn try_build(
-        &self,
-        upstream_inputs: &HashMap<String, InputKind>,
-    ) -> impl Future<Output = Result<TransformerI, IocBuildError>>;
-    fn needs_inputs(&self) -> HashSet<&String>;
-}
+use ioc_core::registry::Registry;
 
-///All possible objects that could appear below the `transformers` secion in the config file.
-#[derive(Deserialize, Debug)]
-pub enum IocTransformerConfig {
+pub use ioc_core::registry::TransformerConfig;
+
+///Registers all transformers that may appear below the `transformers` section in the config file.
+pub fn register(registry: &mut Registry) {
     //core
-    Sum(SumTransformerConfig),
+    registry.register_transformer::<SumTransformerConfig>("Sum", &["value"]);
 
     //extra
     #[cfg(feature = "extra")]
-    HBridge(HBridgeTransformerConfig),
-    #[cfg(feature = "extra")]
-    LinearTransform(LinearTransformerConfig),
-    #[cfg(feature = "extra")]
-    Clamp(ClampConfig),
-    #[cfg(feature = "extra")]
-    Heading(HeadingConfig),
-    #[cfg(feature = "extra")]
-    PID(PidCtrlConfig),
-    #[cfg(feature = "extra")]
-    Limiter(LimiterConfig),
-    #[cfg(feature = "extra")]
-    WindowAverage(WindowAverageConfig),
+    registry
+        .register_transformer::<HBridgeTransformerConfig>("HBridge", &["forward", "reverse", "enable"])
+        .register_transformer::<LinearTransformerConfig>("LinearTransform", &["value"])
+        .register_transformer::<ClampConfig>("Clamp", &["value"])
+        .register_transformer::<HeadingConfig>("Heading", &["value"])
+        .register_transformer::<AhrsTransformerConfig>("Ahrs", &["quaternion", "roll", "pitch", "yaw", "heading"])
+        .register_transformer::<KalmanTransformerConfig>("Kalman", &[])
+        .register_transformer::<PidCtrlConfig>("PID", &["value", "p_term", "i_term", "d_term", "tuning", "ultimate_gain", "ultimate_period", "suggested_p", "suggested_i", "suggested_d"])
+        .register_transformer::<CascadeTransformerConfig>("Cascade", &["value", "inner_set_point"])
+        .register_transformer::<MixerTransformerConfig>("Mixer", &["value", "saturated"])
+        .register_transformer::<LimiterConfig>("Limiter", &["value"])
+        .register_transformer::<WindowAverageConfig>("WindowAverage", &["value"])
+        .register_transformer::<FilterTransformerConfig>("Filter", &["value"])
+        .register_transformer::<ExpressionTransformerConfig>("Expression", &["value"]);
 
-    //sim 
+    //sims
     #[cfg(feature = "sims")]
-    DampedOscillator(DampedOscillatorSimConfig),
-}
+    registry.register_transformer::<DampedOscillatorSimConfig>("DampedOscillator", &["x", "v"]);
+
+    //script
+    #[cfg(feature = "script")]
+    registry.register_transformer::<ScriptTransformerConfig>("Script", &[]);
+
+    //wasm
+    #[cfg(feature = "wasm")]
+    registry.register_transformer::<WasmTransformerConfig>("Wasm", &[]);
 
-impl IocTransformerConfig {
-    //Attempts to build this transformer given a map of all named upstream inputs.
-    pub async fn try_build(
-        &self,
-        upstream_inputs: &HashMap<String, InputKind>,
-    ) -> Result<TransformerI, IocBuildError> {
-        match self {
-            //core
-            Self::Sum(sum) => sum.try_build(upstream_inputs).await,
-
-            //extra
-            #[cfg(feature = "extra")]
-            Self::HBridge(hbridge) => hbridge.try_build(upstream_inputs).await,
-            #[cfg(feature = "extra")]
-            Self::LinearTransform(lxform) => lxform.try_build(upstream_inputs).await,
-            #[cfg(feature = "extra")]
-            Self::Clamp(clampcfg) => clampcfg.try_build(upstream_inputs).await,
-            #[cfg(feature = "extra")]
-            Self::Heading(hdgcfg) => hdgcfg.try_build(upstream_inputs).await,
-            #[cfg(feature = "extra")]
-            Self::PID(pidcfg) => pidcfg.try_build(upstream_inputs).await,
-            #[cfg(feature = "extra")]
-            Self::Limiter(limcfg) => limcfg.try_build(upstream_inputs).await,
-            #[cfg(feature = "extra")]
-            Self::WindowAverage(avgcfg) => avgcfg.try_build(upstream_inputs).await,
-
-            //sims
-            #[cfg(feature = "sims")]
-            Self::DampedOscillator(simcfg) => simcfg.try_build(upstream_inputs).await,
-        }
-    }
-
-    //Returns the names of upstream inputs this Transformer will require to be built.
-    pub fn needs_inputs(&self) -> HashSet<&String> {
-        match self {
-            //core
-            Self::Sum(sum) => sum.needs_inputs(),
-
-            //extra
-            #[cfg(feature = "extra")]
-            Self::HBridge(hbridge) => hbridge.needs_inputs(),
-            #[cfg(feature = "extra")]
-            Self::LinearTransform(lxform) => lxform.needs_inputs(),
-            #[cfg(feature = "extra")]
-            Self::Clamp(clampcfg) => clampcfg.needs_inputs(),
-            #[cfg(feature = "extra")]
-            Self::Heading(hdgcfg) => hdgcfg.needs_inputs(),
-            #[cfg(feature = "extra")]
-            Self::PID(pidcfg) => pidcfg.needs_inputs(),
-            #[cfg(feature = "extra")]
-            Self::Limiter(limcfg) => limcfg.needs_inputs(),
-            #[cfg(feature = "extra")]
-            Self::WindowAverage(avgcfg) => avgcfg.needs_inputs(),
-
-            //sims
-            #[cfg(feature = "sims")]
-            Self::DampedOscillator(simcfg) => simcfg.needs_inputs(),
-        }
-    }
+    //devices
+    #[cfg(feature = "devices")]
+    registry.register_transformer::<ImuCalibrationTransformerConfig>("ImuCalibration", &["state", "progress"]);
 }
@@ -0,0 +1,127 @@
+use std::collections::{HashMap, HashSet};
+
+use ioc_core::{clock::Clock, error::IocBuildError, InputKind, Transformer, TransformerI};
+use ioc_extra::transform::script::{Script, ScriptConfig, ScriptInput, ScriptLimits, ScriptOutput};
+use serde::Deserialize;
+use schemars::JsonSchema;
+
+use super::TransformerConfig;
+
+///An input port of a script, naming the upstream input it reads from.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub enum ScriptInputConfig {
+    Float(String),
+    Int(String),
+    Bool(String),
+    String(String),
+}
+
+impl ScriptInputConfig {
+    fn key(&self) -> &String {
+        match self {
+            Self::Float(key) | Self::Int(key) | Self::Bool(key) | Self::String(key) => key,
+        }
+    }
+}
+
+///An output port of a script, with its start value.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub enum ScriptOutputConfig {
+    Float { start: f64 },
+    Int { start: i64 },
+    Bool { start: bool },
+    String { start: String },
+}
+
+///Runs a [Rhai](https://rhai.rs) script.
+/// inputs are typed ports read by the script as variables with the same name
+/// outputs are typed ports written by the script by assigning to variables with the same name. Each one is emitted as an input.
+/// `state` is a map that is kept between evaluations. init is an optional script that runs once, e.g. to set up `state`.
+/// The script is evaluated whenever an input changes, or every period_ms if it's given.
+/// max_operations and max_time_ms limit each evaluation. Evaluations that exceed them are stopped and their outputs are discarded.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ScriptTransformerConfig {
+    #[serde(default)]
+    inputs: HashMap<String, ScriptInputConfig>,
+    outputs: HashMap<String, ScriptOutputConfig>,
+    init: Option<String>,
+    script: String,
+    period_ms: Option<u64>,
+    max_operations: Option<u64>,
+    max_time_ms: Option<u64>,
+}
+
+impl TransformerConfig for ScriptTransformerConfig {
+    async fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
+    ) -> Result<TransformerI, IocBuildError> {
+        let mut inputs = HashMap::with_capacity(self.inputs.len());
+        let mut errors = Vec::new();
+        for (name, input_config) in &self.inputs {
+            let key = input_config.key();
+            match (input_config, upstream_inputs.get(key)) {
+                (ScriptInputConfig::Float(_), Some(InputKind::Float(input))) => {
+                    inputs.insert(name.clone(), ScriptInput::Float(input));
+                }
+                (ScriptInputConfig::Int(_), Some(InputKind::Int(input))) => {
+                    inputs.insert(name.clone(), ScriptInput::Int(input));
+                }
+                (ScriptInputConfig::Bool(_), Some(InputKind::Bool(input))) => {
+                    inputs.insert(name.clone(), ScriptInput::Bool(input));
+                }
+                (ScriptInputConfig::String(_), Some(InputKind::String(input))) => {
+                    inputs.insert(name.clone(), ScriptInput::String(input));
+                }
+                (_, Some(other)) => errors.push(IocBuildError::from_string(format!(
+                    "Script input '{name}' expected '{key}' to be {input_config:?} but got {other:?}"
+                ))),
+                (_, None) => errors.push(IocBuildError::from_string(format!(
+                    "No input named '{key}'"
+                ))),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(IocBuildError::from_errs(errors));
+        }
+
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|(name, output)| {
+                let output = match output {
+                    ScriptOutputConfig::Float { start } => ScriptOutput::Float(*start),
+                    ScriptOutputConfig::Int { start } => ScriptOutput::Int(*start),
+                    ScriptOutputConfig::Bool { start } => ScriptOutput::Bool(*start),
+                    ScriptOutputConfig::String { start } => ScriptOutput::String(start.clone()),
+                };
+                (name.clone(), output)
+            })
+            .collect();
+
+        let mut limits = ScriptLimits::default();
+        if let Some(max_operations) = self.max_operations {
+            limits.max_operations = max_operations;
+        }
+        if let Some(max_time_ms) = self.max_time_ms {
+            limits.max_time_ms = max_time_ms;
+        }
+
+        let script = Script::try_build(&ScriptConfig {
+            inputs,
+            outputs,
+            init: self.init.as_deref(),
+            script: &self.script,
+            period_ms: self.period_ms,
+            limits,
+            clock,
+        })
+        .await?;
+        Ok(script.into())
+    }
+
+    fn needs_inputs(&self) -> HashSet<&String> {
+        self.inputs.values().map(|input| input.key()).collect()
+    }
+}
@@ -1,15 +1,16 @@
 use std::collections::{HashMap, HashSet};
 
 use ioc_sims::damped_oscillator::{DampedOscillatorConfig, DampedOscillator};
-use ioc_core::{error::IocBuildError, InputKind, Transformer, TransformerI};
+use ioc_core::{clock::Clock, error::IocBuildError, InputKind, Transformer, TransformerI};
 use serde::Deserialize;
+use schemars::JsonSchema;
 
 use super::TransformerConfig;
 
 
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct DampedOscillatorSimConfig{
     m: String,
     k: String,
@@ -23,6 +24,7 @@ impl TransformerConfig for DampedOscillatorSimConfig {
     async fn try_build(
         &self,
         upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
     ) -> Result<TransformerI, IocBuildError> {
         let m = match upstream_inputs.get(&self.m) {
             Some(InputKind::Float(float)) => float,
@@ -57,7 +59,7 @@ impl TransformerConfig for DampedOscillatorSimConfig {
         };
 
         let config = DampedOscillatorConfig {
-            m, k, c, f, period_ms: self.period_ms, steps_per_frame: self.steps_per_frame
+            m, k, c, f, period_ms: self.period_ms, steps_per_frame: self.steps_per_frame, clock
         };
 
         let oscillator = DampedOscillator::try_build(&config).await?;
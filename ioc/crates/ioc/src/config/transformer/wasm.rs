@@ -0,0 +1,73 @@
+use std::collections::{HashMap, HashSet};
+
+use ioc_core::{clock::Clock, error::IocBuildError, InputKind, Transformer, TransformerI};
+use ioc_extra::transform::wasm::{Wasm, WasmConfig, WasmLimits};
+use serde::Deserialize;
+use schemars::JsonSchema;
+
+use super::TransformerConfig;
+
+///Loads a transformer from a WebAssembly module. See `ioc_extra::transform::wasm` for the ABI it must implement.
+/// path is the .wasm file, relative to the working directory
+/// inputs maps the plugin's input ports to upstream inputs. The plugin declares the type of each port, and they are checked when it's built.
+/// Each of the plugin's output ports is emitted as an input with the port's name.
+/// The plugin steps whenever an input changes, or every period_ms if it's given.
+/// max_fuel and max_time_ms limit each step. Steps that exceed them are stopped or discarded.
+/// max_memory_bytes limits the plugin's memory, 16MiB by default.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct WasmTransformerConfig {
+    path: String,
+    #[serde(default)]
+    inputs: HashMap<String, String>,
+    period_ms: Option<u64>,
+    max_fuel: Option<u64>,
+    max_time_ms: Option<u64>,
+    max_memory_bytes: Option<usize>,
+}
+
+impl TransformerConfig for WasmTransformerConfig {
+    async fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
+    ) -> Result<TransformerI, IocBuildError> {
+        let wasm = tokio::fs::read(&self.path).await.map_err(|err| {
+            IocBuildError::from_string(format!("unable to read wasm plugin {}: {}", self.path, err))
+        })?;
+
+        let mut inputs = HashMap::with_capacity(self.inputs.len());
+        for (name, key) in &self.inputs {
+            match upstream_inputs.get(key) {
+                Some(input) => {
+                    inputs.insert(name.clone(), input);
+                }
+                None => return Err(IocBuildError::from_string(format!("No input named '{key}'"))),
+            }
+        }
+
+        let mut limits = WasmLimits::default();
+        if let Some(max_fuel) = self.max_fuel {
+            limits.max_fuel = max_fuel;
+        }
+        if let Some(max_time_ms) = self.max_time_ms {
+            limits.max_time_ms = max_time_ms;
+        }
+        if let Some(max_memory_bytes) = self.max_memory_bytes {
+            limits.max_memory_bytes = max_memory_bytes;
+        }
+
+        let plugin = Wasm::try_build(&WasmConfig {
+            wasm: &wasm,
+            inputs,
+            period_ms: self.period_ms,
+            limits,
+            clock,
+        })
+        .await?;
+        Ok(plugin.into())
+    }
+
+    fn needs_inputs(&self) -> HashSet<&String> {
+        self.inputs.values().collect()
+    }
+}
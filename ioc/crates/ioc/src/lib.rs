@@ -0,0 +1,136 @@
+//! The IOC application: builds and runs the modules, transformers and pipes described by a config file.
+//! The `ioc` binary is a thin wrapper around this library, so configs can also be built and run in-process, e.g. by tests.
+
+pub mod config;
+pub mod systemd;
+
+pub use config::{registry, registry_with, IocConfig};
+pub use ioc_core::registry::Registry;
+
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+///Runs the IOC like the `ioc` binary does: the only argument is the config file to run, or `schema` to print the config's JSON schema.
+/// Besides the modules and transformers this crate was built with, the given functions can register more, so a binary can add its own:
+///
+/// ```ignore
+/// #[tokio::main]
+/// async fn main() {
+///     ioc::run(&[my_nodes::register]).await
+/// }
+/// ```
+pub async fn run(register: &[fn(&mut Registry)]) {
+    //set up logging 
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "ioc=info,tower_http=info".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    //log panics with the rest of the logs. the supervisor logs which module or transformer panicked
+    std::panic::set_hook(Box::new(|info| {
+        let location = info.location().map(|location| location.to_string()).unwrap_or_default();
+        let message = match (info.payload().downcast_ref::<&str>(), info.payload().downcast_ref::<String>()) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            _ => "unknown panic".to_string(),
+        };
+        error!(%location, "panicked: {}", message);
+    }));
+
+    //all modules and transformers this binary was built with
+    let registry = registry_with(register);
+
+    //get config file name from arguments
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        warn!("only one parameter, the config file, is expected.");
+        warn!("got {}", args.join(","));
+    } else if args[1] == "schema" {
+        //print the json schema for the config file instead of starting up
+        print_schema(&registry);
+    } else if let Some(cfg_name) = args.get(1).map(|o| o.as_str()) {
+
+        //try to parse that config file
+        let config_res = IocConfig::load(cfg_name);
+
+        match config_res {
+            Ok(config) => {
+                //try to start up if we parsed the config
+                info!("IOC starting up!");
+                if let Some(ref name) = config.metadata.name {
+                    info!("name: {name}")
+                }
+                if let Some(ref descrip) = config.metadata.description {
+                    info!("description: {descrip}")
+                }
+
+                let cancel_token = get_cancellation_token();
+
+                //this starts the application and waits for it to finish
+                match config.start(&registry, cancel_token).await {
+                    Ok(_) => info!("IOC shut down!"),
+                    Err(err) => {
+                        error!("IOC exited with an error: {:?}", err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            Err(err) => {
+                error!(
+                    "Error starting IOC server. Failed to parse config: {:?}",
+                    err
+                );
+            }
+        }
+    }
+}
+
+///prints the json schema for the config file to stdout
+fn print_schema(registry: &Registry) {
+    match serde_json::to_string_pretty(&IocConfig::json_schema(registry)) {
+        Ok(json) => println!("{json}"),
+        Err(err) => error!("Error serializing config schema: {:?}", err),
+    }
+}
+
+///returns a token that is cancelled on ctrl-c (SIGINT) or SIGTERM. a second signal exits right away
+fn get_cancellation_token() -> CancellationToken {
+    let token = CancellationToken::new();
+    let task_token = token.clone();
+    tokio::spawn(async move {
+        loop {
+            let signal = tokio::select! {
+                res = tokio::signal::ctrl_c() => {
+                    res.expect("failed to listen for ctrl-c");
+                    "ctrl-c"
+                }
+                _ = terminate() => "SIGTERM",
+            };
+            if task_token.is_cancelled() {
+                warn!("{} received while shutting down, exiting now", signal);
+                std::process::exit(1);
+            }
+            info!("{} received, shutting down", signal);
+            task_token.cancel();
+        }
+    });
+    token
+}
+
+#[cfg(unix)]
+async fn terminate() {
+    use tokio::signal::unix::{signal, SignalKind};
+    signal(SignalKind::terminate())
+        .expect("failed to listen for SIGTERM")
+        .recv()
+        .await;
+}
+
+#[cfg(not(unix))]
+async fn terminate() {
+    std::future::pending::<()>().await
+}
\ No newline at end of file
@@ -0,0 +1,179 @@
+//! Notifies systemd of the IOC's state when it runs as a `Type=notify` service. Does nothing otherwise, or without the systemd feature.
+
+use std::time::Duration;
+
+use tokio::{task::JoinHandle, time::timeout};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::config::supervision::{GraphStatus, Liveness};
+
+//how often the status is updated if the watchdog isn't enabled
+const STATUS_PERIOD: Duration = Duration::from_secs(5);
+
+#[cfg_attr(not(feature = "systemd"), allow(dead_code))]
+enum State<'a> {
+    Ready,
+    Stopping,
+    Status(&'a str),
+    Watchdog,
+}
+
+///Tells systemd that the IOC started up.
+pub fn ready(status: &str) {
+    notify(&[State::Ready, State::Status(status)]);
+}
+
+///Updates the status shown by `systemctl status`.
+pub fn status(status: &str) {
+    notify(&[State::Status(status)]);
+}
+
+///Tells systemd that the IOC is shutting down.
+pub fn stopping(status: &str) {
+    notify(&[State::Stopping, State::Status(status)]);
+}
+
+#[cfg(feature = "systemd")]
+fn notify(states: &[State]) {
+    use sd_notify::NotifyState;
+    let states: Vec<NotifyState> = states
+        .iter()
+        .map(|state| match state {
+            State::Ready => NotifyState::Ready,
+            State::Stopping => NotifyState::Stopping,
+            State::Status(status) => NotifyState::Status(status),
+            State::Watchdog => NotifyState::Watchdog,
+        })
+        .collect();
+    if let Err(err) = sd_notify::notify(false, &states) {
+        debug!("failed to notify systemd: {}", err);
+    }
+}
+
+#[cfg(not(feature = "systemd"))]
+fn notify(_: &[State]) {}
+
+//returns how often to ping the watchdog, if systemd enabled it for this process
+#[cfg(feature = "systemd")]
+fn watchdog_period() -> Option<Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec) / 2)
+}
+
+#[cfg(not(feature = "systemd"))]
+fn watchdog_period() -> Option<Duration> {
+    None
+}
+
+//the outcome of a liveness check
+#[derive(Debug, PartialEq)]
+enum Check {
+    //answered in time, and no node failed for good
+    Alive(GraphStatus),
+    //answered in time, but some nodes failed and won't be restarted
+    Failed(GraphStatus),
+    TimedOut,
+    //the supervisor isn't running anymore
+    Stopped,
+}
+
+impl Check {
+    fn ping(&self) -> bool {
+        matches!(self, Check::Alive(_))
+    }
+}
+
+async fn check(liveness: &Liveness, wait: Duration) -> Check {
+    match timeout(wait, liveness.check()).await {
+        Ok(Some(graph_status)) if graph_status.failed.is_empty() => Check::Alive(graph_status),
+        Ok(Some(graph_status)) => Check::Failed(graph_status),
+        Ok(None) => Check::Stopped,
+        Err(_) => Check::TimedOut,
+    }
+}
+
+///Periodically checks that the supervisor and the runtime respond, and updates the status with the `GraphStatus`.
+/// If systemd enabled the watchdog, it is pinged after each check that was answered in time while no node has failed for good,
+/// so systemd restarts the IOC if it's wedged or lost a node it can't restart.
+/// Returns `None` if the IOC isn't running under systemd. Stops when the cancel token is cancelled.
+pub fn spawn_watchdog(liveness: Liveness, cancel_token: CancellationToken) -> Option<JoinHandle<()>> {
+    std::env::var_os("NOTIFY_SOCKET")?;
+    let watchdog = watchdog_period();
+    let period = watchdog.unwrap_or(STATUS_PERIOD);
+    debug!("checking liveness every {}ms, watchdog enabled: {}", period.as_millis(), watchdog.is_some());
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let check = check(&liveness, period / 2).await;
+            let ping = watchdog.is_some() && check.ping();
+            match check {
+                Check::Alive(graph_status) if ping => notify(&[State::Watchdog, State::Status(&graph_status.to_string())]),
+                Check::Alive(graph_status) => status(&graph_status.to_string()),
+                Check::Failed(graph_status) => {
+                    if watchdog.is_some() {
+                        warn!("{}. not pinging the watchdog", graph_status);
+                    }
+                    status(&graph_status.to_string());
+                }
+                Check::Stopped => break,
+                Check::TimedOut => warn!("liveness check timed out. not pinging the watchdog"),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Check};
+    use crate::config::supervision::{GraphStatus, Liveness};
+    use std::time::Duration;
+    use tokio::sync::{mpsc, oneshot};
+
+    //a liveness answered by a fake supervisor, which answers with the given status or not at all
+    fn fake_liveness(answer: Option<GraphStatus>) -> (Liveness, tokio::task::JoinHandle<()>) {
+        let (probes_tx, mut probes) = mpsc::channel::<oneshot::Sender<GraphStatus>>(1);
+        let supervisor = tokio::spawn(async move {
+            while let Some(reply) = probes.recv().await {
+                match &answer {
+                    Some(graph_status) => {
+                        let _ = reply.send(graph_status.clone());
+                    }
+                    //hold on to the reply, like a supervisor that's stuck
+                    None => std::future::pending().await,
+                }
+            }
+        });
+        (Liveness::new(probes_tx), supervisor)
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_check() {
+        let wait = Duration::from_millis(20);
+        let restarting = GraphStatus { running: 1, down: vec!["a".to_string()], failed: vec![] };
+        let (liveness, _supervisor) = fake_liveness(Some(restarting.clone()));
+        let alive = check(&liveness, wait).await;
+        assert_eq!(alive, Check::Alive(restarting));
+        assert!(alive.ping());
+
+        let failed = GraphStatus { running: 1, down: vec![], failed: vec!["a".to_string()] };
+        let (liveness, _supervisor) = fake_liveness(Some(failed.clone()));
+        let check_failed = check(&liveness, wait).await;
+        assert_eq!(check_failed, Check::Failed(failed));
+        assert!(!check_failed.ping());
+
+        let (liveness, _supervisor) = fake_liveness(None);
+        let timed_out = check(&liveness, wait).await;
+        assert_eq!(timed_out, Check::TimedOut);
+        assert!(!timed_out.ping());
+
+        let (liveness, supervisor) = fake_liveness(None);
+        supervisor.abort();
+        let _ = supervisor.await;
+        assert_eq!(check(&liveness, wait).await, Check::Stopped);
+    }
+}
@@ -0,0 +1,177 @@
+//! A `Clock` tells the time and sleeps, either in real time or in simulated time that advances in fixed steps.
+//!
+//! Transformers that depend on time are given a `Clock` when they are built. Running a graph under a simulated clock
+//! makes it reproducible: time only advances when the `ClockDriver` ticks, and each tick lets woken tasks run before the next.
+
+use std::{
+    future::Future,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{sync::watch, time::MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+//how often the driver yields after each tick, so values can propagate through chains of tasks
+const SETTLE_YIELDS: usize = 16;
+
+//real clocks count elapsed time from the first time it's read, so it's the same for all of them
+static REAL_START: OnceLock<Instant> = OnceLock::new();
+
+///Tells the time, in real time by default. Cloning a simulated clock shares its time.
+#[derive(Clone, Default)]
+pub struct Clock {
+    simulated: Option<Arc<Simulated>>,
+}
+
+struct Simulated {
+    origin: SystemTime,
+    elapsed: watch::Sender<Duration>,
+}
+
+impl Clock {
+    ///A clock that follows the system time.
+    pub fn real() -> Self {
+        Self::default()
+    }
+
+    ///A clock that starts at origin and only advances when the returned `ClockDriver` ticks, by step each time.
+    pub fn simulated(origin: SystemTime, step: Duration) -> (Self, ClockDriver) {
+        let simulated = Arc::new(Simulated {
+            origin,
+            elapsed: watch::Sender::new(Duration::ZERO),
+        });
+        let driver = ClockDriver {
+            simulated: simulated.clone(),
+            step,
+        };
+        (Self { simulated: Some(simulated) }, driver)
+    }
+
+    pub fn is_simulated(&self) -> bool {
+        self.simulated.is_some()
+    }
+
+    ///The current time. Use it for timestamps, and `elapsed` to measure time: the system time can jump, e.g. when it's set over NTP.
+    pub fn now(&self) -> SystemTime {
+        match &self.simulated {
+            Some(simulated) => simulated.origin + *simulated.elapsed.borrow(),
+            None => SystemTime::now(),
+        }
+    }
+
+    ///Monotonic time since the clock started, which never goes backwards. Real clocks take it from `Instant`.
+    pub fn elapsed(&self) -> Duration {
+        match &self.simulated {
+            Some(simulated) => *simulated.elapsed.borrow(),
+            None => REAL_START.get_or_init(Instant::now).elapsed(),
+        }
+    }
+
+    ///Waits until the given duration passed on this clock, counting from when this is called, like `tokio::time::sleep`.
+    /// A simulated clock's sleep only finishes once its driver ticked past the deadline, or every copy of the clock was dropped.
+    pub fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send + 'static {
+        let simulated = self.simulated.as_ref().map(|simulated| {
+            let elapsed = simulated.elapsed.subscribe();
+            let deadline = *elapsed.borrow() + duration;
+            (elapsed, deadline)
+        });
+        let real = simulated.is_none().then(|| tokio::time::sleep(duration));
+        async move {
+            if let Some((mut elapsed, deadline)) = simulated {
+                let _ = elapsed.wait_for(|elapsed| *elapsed >= deadline).await;
+            } else if let Some(real) = real {
+                real.await;
+            }
+        }
+    }
+}
+
+///Advances a simulated `Clock`.
+pub struct ClockDriver {
+    simulated: Arc<Simulated>,
+    step: Duration,
+}
+
+impl ClockDriver {
+    ///How far each tick advances the clock.
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    ///How far the clock advanced since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.simulated.elapsed.borrow()
+    }
+
+    ///Advances the clock by one step, then yields so that tasks woken by it can run.
+    /// On a current thread runtime, values propagate through up to 16 tasks before this returns.
+    pub async fn tick(&self) {
+        self.simulated.elapsed.send_modify(|elapsed| *elapsed += self.step);
+        for _ in 0..SETTLE_YIELDS {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    ///Ticks until the clock advanced by at least the given duration.
+    pub async fn advance(&self, duration: Duration) {
+        let until = self.elapsed() + duration;
+        while self.elapsed() < until {
+            self.tick().await;
+        }
+    }
+
+    ///Ticks until the cancel token is cancelled. Runs speed times faster than real time, or as fast as possible if no speed is given.
+    pub async fn run(self, speed: Option<f64>, cancel_token: CancellationToken) {
+        match speed {
+            Some(speed) => {
+                let mut interval = tokio::time::interval(self.step.div_f64(speed));
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    tokio::select! {
+                        _ = cancel_token.cancelled() => break,
+                        _ = interval.tick() => self.tick().await,
+                    }
+                }
+            }
+            None => {
+                while !cancel_token.is_cancelled() {
+                    self.tick().await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Clock;
+    use std::time::{Duration, SystemTime};
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_simulated_clock() {
+        let origin = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let (clock, driver) = Clock::simulated(origin, Duration::from_millis(10));
+        assert_eq!(clock.now(), origin);
+
+        //a sleeping task wakes once the clock passed its deadline, not before
+        let sleeper = clock.clone();
+        let sleep = clock.sleep(Duration::from_millis(25));
+        let task = tokio::spawn(async move {
+            sleep.await;
+            sleeper.now()
+        });
+        driver.advance(Duration::from_millis(20)).await;
+        assert!(!task.is_finished());
+        driver.tick().await;
+        assert_eq!(task.await.unwrap(), origin + Duration::from_millis(30));
+        assert_eq!(driver.elapsed(), Duration::from_millis(30));
+        assert_eq!(clock.elapsed(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_real_elapsed() {
+        //real clocks share where they count from
+        let earlier = Clock::real().elapsed();
+        assert!(Clock::real().elapsed() >= earlier);
+    }
+}
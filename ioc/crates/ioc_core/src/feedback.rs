@@ -1,18 +1,26 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::SystemTime};
 
-use crate::{error::IocBuildError, Input, InputKind, Module, ModuleIO, Output, OutputKind, Value};
+use crate::{error::IocBuildError, timestamp, Input, InputKind, Module, ModuleIO, Output, OutputKind, Value};
 use serde::Deserialize;
+use schemars::JsonSchema;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, warn};
 
 /// Configuration for a FeedbackItem. Contains a start value.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub enum FeedbackItemConfig {
     String{ start: String },
     Binary{ start: Vec<u8> },
     Bool{ start: bool },
     Float{ start: f64} ,
+    Int{ start: i64 },
+    ///start is given in seconds since the unix epoch
+    Timestamp{
+        #[serde(deserialize_with = "timestamp::deserialize_secs")]
+        #[schemars(with = "f64")]
+        start: SystemTime
+    },
     Array{ start: Vec<Value> },
     Object{ start: HashMap<String, Value> },
 }
@@ -20,7 +28,7 @@ pub enum FeedbackItemConfig {
 /// Configuration for a Feedback module. Contains a map of FeedbackItemConfigs.
 /// 
 /// Each FeedbackItemConfig contains a start value and will create one Input and one corresponding Output.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct FeedbackConfig {
     items: HashMap<String, FeedbackItemConfig>,
 }
@@ -100,13 +108,25 @@ impl Module for Feedback {
                     join_handles.push(join_handle);
                 },
                 FeedbackItemConfig::Float{ start } => {
-                    let (input, output, join_handle) = spawn_feedback_pipe(start.clone(), cancel_token.clone());
+                    let (input, output, join_handle) = spawn_feedback_pipe(*start, cancel_token.clone());
                     inputs.insert(name.clone(), InputKind::Float(input));
                     outputs.insert(name.clone(), OutputKind::Float(output));
                     join_handles.push(join_handle);
                 },
+                FeedbackItemConfig::Int{ start } => {
+                    let (input, output, join_handle) = spawn_feedback_pipe(*start, cancel_token.clone());
+                    inputs.insert(name.clone(), InputKind::Int(input));
+                    outputs.insert(name.clone(), OutputKind::Int(output));
+                    join_handles.push(join_handle);
+                },
+                FeedbackItemConfig::Timestamp{ start } => {
+                    let (input, output, join_handle) = spawn_feedback_pipe(*start, cancel_token.clone());
+                    inputs.insert(name.clone(), InputKind::Timestamp(input));
+                    outputs.insert(name.clone(), OutputKind::Timestamp(output));
+                    join_handles.push(join_handle);
+                },
                 FeedbackItemConfig::Bool{ start } => {
-                    let (input, output, join_handle) = spawn_feedback_pipe(start.clone(), cancel_token.clone());
+                    let (input, output, join_handle) = spawn_feedback_pipe(*start, cancel_token.clone());
                     inputs.insert(name.clone(), InputKind::Bool(input));
                     outputs.insert(name.clone(), OutputKind::Bool(output));
                     join_handles.push(join_handle);
@@ -1,8 +1,10 @@
 //!This is the core library for the IOC project. All other IOC libraries depend on this one. This includes all fundamental data types required for a running IOC instance.
 
 use error::IocBuildError;
+use sample::{Sample, SampleSender, SampleSource};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{de::Visitor, ser::SerializeSeq, ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
-use std::{collections::HashMap, fmt, future::Future};
+use std::{collections::HashMap, fmt, future::Future, time::SystemTime};
 use tokio::{
     sync::{mpsc, watch},
     task::JoinHandle,
@@ -13,33 +15,73 @@ pub mod error;
 pub mod pipe;
 pub mod transformer;
 pub mod feedback;
+pub mod timestamp;
+pub mod clock;
+pub mod sample;
+pub mod registry;
+pub mod record;
+pub mod relay;
+pub mod replay;
+pub mod safe;
 
 pub struct Input<T>{
-    rx: watch::Receiver<T>
+    rx: watch::Receiver<T>,
+    samples: Option<watch::Receiver<Sample<T>>>,
 }
 
 impl<T> Input<T> {
     pub fn new(start: T) -> (Self, watch::Sender<T>) {
         let (tx, rx) = watch::channel(start);
-        (Self { rx }, tx)
+        (Self { rx, samples: None }, tx)
     }
     pub fn source(&self) -> watch::Receiver<T> {
         self.rx.clone()
     }
 }
 
+impl<T: Clone> Input<T> {
+    ///Create an `Input` that also provides `Sample`s, i.e. values with their sample time and `Quality`.
+    pub fn new_sampled(start: Sample<T>) -> (Self, SampleSender<T>) {
+        let (value_tx, rx) = watch::channel(start.value.clone());
+        let (sample_tx, samples) = watch::channel(start);
+        (Self { rx, samples: Some(samples) }, SampleSender { value_tx, sample_tx })
+    }
+    ///Returns a receiver for `Sample`s if this `Input` provides them.
+    pub fn samples(&self) -> Option<watch::Receiver<Sample<T>>> {
+        self.samples.clone()
+    }
+    ///Returns a `SampleSource` for this `Input`. Values of inputs without samples are stamped when they are received.
+    pub fn sample_source(&self) -> SampleSource<T> {
+        match &self.samples {
+            Some(samples) => SampleSource::sampled(samples.clone()),
+            None => SampleSource::plain(self.rx.clone()),
+        }
+    }
+}
+
 pub struct Output<T>{
-    pub tx: mpsc::Sender<T>
+    pub tx: mpsc::Sender<T>,
+    pub samples_tx: Option<mpsc::Sender<Sample<T>>>,
 }
 
 impl<T> Output<T> {
     pub fn new() -> (Self, mpsc::Receiver<T>) {
         let (tx, rx) = mpsc::channel(1);
-        (Self { tx }, rx)
+        (Self { tx, samples_tx: None }, rx)
+    }
+    ///Create an `Output` that also accepts `Sample`s. Plain values and samples are received on separate channels.
+    pub fn new_sampled() -> (Self, mpsc::Receiver<T>, mpsc::Receiver<Sample<T>>) {
+        let (tx, rx) = mpsc::channel(1);
+        let (samples_tx, samples_rx) = mpsc::channel(1);
+        (Self { tx, samples_tx: Some(samples_tx) }, rx, samples_rx)
     }
     pub fn sink(&self) -> mpsc::Sender<T> {
         self.tx.clone()
     }
+    ///Returns a sender for `Sample`s if this `Output` accepts them.
+    pub fn sample_sink(&self) -> Option<mpsc::Sender<Sample<T>>> {
+        self.samples_tx.clone()
+    }
 }
 
 ///Enum to hold fundamental data type values.
@@ -48,11 +90,24 @@ pub enum Value {
     String(String),
     Binary(Vec<u8>),
     Float(f64),
+    Int(i64),
     Bool(bool),
+    Timestamp(SystemTime),
     Array(Vec<Value>),
     Object(HashMap<String, Value>),
 }
 
+impl Value {
+    ///The number in a Float or an Int. Config files write whole numbers as Ints, so this accepts both where a float is expected.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(value) => Some(*value),
+            Value::Int(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for Value {
     fn deserialize<D>(deserializer: D) -> Result<Value, D::Error>
     where
@@ -68,7 +123,7 @@ impl <'a> Visitor<'a> for ValueVisitor {
     type Value = Value;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("")
+        formatter.write_str("a string, binary, number, bool, array or object")
     }
 
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
@@ -83,6 +138,19 @@ impl <'a> Visitor<'a> for ValueVisitor {
         Ok(Value::Float(v))
     }
 
+    //integers are produced by toml, json and yaml for numbers without a decimal point
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Value::Int(v))
+    }
+
+    //unsigned integers too large for an Int are kept as a Float
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+        match i64::try_from(v) {
+            Ok(v) => Ok(Value::Int(v)),
+            Err(_) => Ok(Value::Float(v as f64)),
+        }
+    }
+
     fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
         Ok(Value::Bool(v))
     }
@@ -110,6 +178,17 @@ impl <'a> Visitor<'a> for ValueVisitor {
     }
 }
 
+///`Value` is untyped in configuration, so any JSON value is accepted.
+impl JsonSchema for Value {
+    fn schema_name() -> String {
+        "Value".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        Schema::Bool(true)
+    }
+}
+
 impl Serialize for Value {
     fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
     where
@@ -119,7 +198,9 @@ impl Serialize for Value {
             Value::String(s) => ser.serialize_str(s),
             Value::Binary(b) => ser.serialize_bytes(b),
             Value::Float(f) => ser.serialize_f64(*f),
+            Value::Int(i) => ser.serialize_i64(*i),
             Value::Bool(b) => ser.serialize_bool(*b),
+            Value::Timestamp(t) => ser.serialize_f64(timestamp::to_secs(t)),
             Value::Array(a) => {
                 let mut seq = ser.serialize_seq(Some(a.len()))?;
                 for v in a {
@@ -143,7 +224,9 @@ pub enum InputKind {
     String(Input<String>),
     Binary(Input<Vec<u8>>),
     Float(Input<f64>),
+    Int(Input<i64>),
     Bool(Input<bool>),
+    Timestamp(Input<SystemTime>),
     Array(Input<Vec<Value>>),
     Object(Input<HashMap<String, Value>>),
 }
@@ -154,7 +237,9 @@ impl fmt::Debug for InputKind {
             Self::String(_) => f.write_str("String"),
             Self::Binary(_) => f.write_str("Binary"),
             Self::Float(_) => f.write_str("Float"),
+            Self::Int(_) => f.write_str("Int"),
             Self::Bool(_) => f.write_str("Bool"),
+            Self::Timestamp(_) => f.write_str("Timestamp"),
             Self::Array(_) => f.write_str("Array"),
             Self::Object(_) => f.write_str("Object")
         }
@@ -166,7 +251,9 @@ pub enum OutputKind {
     String(Output<String>),
     Binary(Output<Vec<u8>>),
     Float(Output<f64>),
+    Int(Output<i64>),
     Bool(Output<bool>),
+    Timestamp(Output<SystemTime>),
     Array(Output<Vec<Value>>),
     Object(Output<HashMap<String, Value>>),
 }
@@ -177,7 +264,9 @@ impl fmt::Debug for OutputKind {
             Self::String(_) => f.write_str("String"),
             Self::Binary(_) => f.write_str("Binary"),
             Self::Float(_) => f.write_str("Float"),
+            Self::Int(_) => f.write_str("Int"),
             Self::Bool(_) => f.write_str("Bool"),
+            Self::Timestamp(_) => f.write_str("Timestamp"),
             Self::Array(_) => f.write_str("Array"),
             Self::Object(_) => f.write_str("Object")
         }
@@ -236,3 +325,30 @@ pub trait Transformer<'a>: Into<TransformerI> {
 
     fn try_build(cfg: &Self::Config) -> impl Future<Output = Result<Self, IocBuildError>>;
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::Value;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_deserialize_value() {
+        //integers are read as Ints, unless they are too large
+        let value: Value = serde_json::from_str("[1, -2, 3.5, 18446744073709551615]").unwrap();
+        assert_eq!(value, Value::Array(vec![Value::Int(1), Value::Int(-2), Value::Float(3.5), Value::Float(u64::MAX as f64)]));
+
+        let value: Value = serde_json::from_str(r#"{"a": true, "b": "x"}"#).unwrap();
+        assert_eq!(value, Value::Object(HashMap::from([
+            ("a".to_string(), Value::Bool(true)),
+            ("b".to_string(), Value::String("x".to_string())),
+        ])));
+    }
+
+    #[test]
+    fn test_as_f64() {
+        assert_eq!(Value::Float(1.5).as_f64(), Some(1.5));
+        assert_eq!(Value::Int(-2).as_f64(), Some(-2.0));
+        assert_eq!(Value::Bool(true).as_f64(), None);
+    }
+}
@@ -13,23 +13,51 @@ pub struct Pipe {
 impl Pipe {
     ///Create a new `Pipe`. Spawns a task that reads from the input and writes to the output.
     pub fn new<T: Send + Sync + Clone + 'static>(input: &Input<T>, output: &Output<T>, cancel_token: CancellationToken) -> Pipe {
-        let mut source = input.source();
-        let sink = output.sink();
+        Self::map(input, output, |value| value, cancel_token)
+    }
 
-        let task = tokio::spawn(async move {
-            loop {
-                let value: T = source.borrow_and_update().clone();
-                if let Err(err) = sink.send(value).await {
-                    error!("Pipe error sending to sink: {}", err);
-                    return;
-                }
-                if let Err(err) = source.changed().await {
-                    error!("Pipe error receiving from source: {}", err);
-                    break;
+    ///Create a new `Pipe` that converts each value read from the input with the given function before writing it to the output.
+    pub fn map<I, O, F>(input: &Input<I>, output: &Output<O>, function: F, cancel_token: CancellationToken) -> Pipe
+    where
+        I: Send + Sync + Clone + 'static,
+        O: Send + Sync + 'static,
+        F: Fn(I) -> O + Send + Sync + 'static,
+    {
+        let task = match (input.samples(), output.sample_sink()) {
+            //forward sample time and quality if both ends support them
+            (Some(mut source), Some(sink)) => tokio::spawn(async move {
+                loop {
+                    let sample = source.borrow_and_update().clone();
+                    if let Err(err) = sink.send(sample.map(&function)).await {
+                        error!("Pipe error sending to sink: {}", err);
+                        return;
+                    }
+                    if let Err(err) = source.changed().await {
+                        error!("Pipe error receiving from source: {}", err);
+                        break;
+                    }
                 }
+                debug!("Pipe shutting down!")
+            }),
+            _ => {
+                let mut source = input.source();
+                let sink = output.sink();
+                tokio::spawn(async move {
+                    loop {
+                        let value: I = source.borrow_and_update().clone();
+                        if let Err(err) = sink.send(function(value)).await {
+                            error!("Pipe error sending to sink: {}", err);
+                            return;
+                        }
+                        if let Err(err) = source.changed().await {
+                            error!("Pipe error receiving from source: {}", err);
+                            break;
+                        }
+                    }
+                    debug!("Pipe shutting down!")
+                })
             }
-            debug!("Pipe shutting down!")
-        });
+        };
 
         let handle = tokio::spawn(async move {
             cancel_token.cancelled().await;
@@ -0,0 +1,586 @@
+//! Records samples of `Input`s to a compact binary log, see `Recorder`. Recordings are played back by the `Replay` module in `replay`.
+//!
+//! A recording starts with a magic number, followed by entries. Each entry starts with a tag byte:
+//! - `0` declares an input: `u16` id, `u16` name length, name.
+//! - `1` is a sample: `u16` id, `i64` microseconds since the unix epoch, `u8` quality, value.
+//!
+//! Values start with a tag byte for their type, followed by the value. Numbers are little endian, strings and binary values are prefixed with their `u32` length.
+//! Every file starts with the declarations of all inputs, followed by the last sample of each of them, so a rotated file can be replayed on its own.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::IocBuildError,
+    sample::{Quality, Sample, SampleInfo},
+    Input, InputKind, Output, OutputKind, Value,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+
+///Identifies a recording. The last byte is the format version.
+pub const MAGIC: &[u8; 8] = b"IOCREC\x00\x01";
+
+///An entry in a recording.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Declare { id: u16, name: String },
+    Sample { id: u16, sample: Sample<Value> },
+}
+
+//value type tags
+const STRING: u8 = 0;
+const BINARY: u8 = 1;
+const FLOAT: u8 = 2;
+const INT: u8 = 3;
+const BOOL: u8 = 4;
+const TIMESTAMP: u8 = 5;
+const ARRAY: u8 = 6;
+const OBJECT: u8 = 7;
+
+///How deeply arrays and objects may nest. Deeper values are rejected, so a corrupt recording can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+impl Entry {
+    ///Appends the encoded entry to the buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Entry::Declare { id, name } => {
+                buf.push(0);
+                buf.extend_from_slice(&id.to_le_bytes());
+                let name = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+                buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                buf.extend_from_slice(name);
+            }
+            Entry::Sample { id, sample } => encode_sample(*id, sample, buf),
+        }
+    }
+
+    ///Reads the next entry. Returns `None` at the end of the recording, including when the last entry was cut off, e.g. by a crash.
+    pub fn decode<R: Read>(reader: &mut R) -> io::Result<Option<Entry>> {
+        let mut tag = [0u8; 1];
+        if reader.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let entry = match tag[0] {
+            0 => read_declare(reader),
+            1 => read_sample(reader),
+            tag => return Err(invalid(format!("unknown entry tag {}", tag))),
+        };
+        match entry {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                debug!("recording ends with a partial entry");
+                Ok(None)
+            }
+            entry => entry.map(Some),
+        }
+    }
+}
+
+fn encode_sample(id: u16, sample: &Sample<Value>, buf: &mut Vec<u8>) {
+    buf.push(1);
+    buf.extend_from_slice(&id.to_le_bytes());
+    buf.extend_from_slice(&to_micros(&sample.info.time).to_le_bytes());
+    buf.push(match sample.info.quality {
+        Quality::Good => 0,
+        Quality::Stale => 1,
+        Quality::Error => 2,
+    });
+    encode_value(&sample.value, buf);
+}
+
+fn encode_value(value: &Value, buf: &mut Vec<u8>) {
+    match value {
+        Value::String(value) => {
+            buf.push(STRING);
+            encode_bytes(value.as_bytes(), buf);
+        }
+        Value::Binary(value) => {
+            buf.push(BINARY);
+            encode_bytes(value, buf);
+        }
+        Value::Float(value) => {
+            buf.push(FLOAT);
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        Value::Int(value) => {
+            buf.push(INT);
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        Value::Bool(value) => {
+            buf.push(BOOL);
+            buf.push(*value as u8);
+        }
+        Value::Timestamp(value) => {
+            buf.push(TIMESTAMP);
+            buf.extend_from_slice(&to_micros(value).to_le_bytes());
+        }
+        Value::Array(values) => {
+            buf.push(ARRAY);
+            buf.extend_from_slice(&(values.len() as u32).to_le_bytes());
+            values.iter().for_each(|value| encode_value(value, buf));
+        }
+        Value::Object(values) => {
+            buf.push(OBJECT);
+            buf.extend_from_slice(&(values.len() as u32).to_le_bytes());
+            for (key, value) in values {
+                encode_bytes(key.as_bytes(), buf);
+                encode_value(value, buf);
+            }
+        }
+    }
+}
+
+fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+fn read_declare<R: Read>(reader: &mut R) -> io::Result<Entry> {
+    let id = u16::from_le_bytes(read_array(reader)?);
+    let len = u16::from_le_bytes(read_array(reader)?);
+    let name = read_string(reader, len as usize)?;
+    Ok(Entry::Declare { id, name })
+}
+
+fn read_sample<R: Read>(reader: &mut R) -> io::Result<Entry> {
+    let id = u16::from_le_bytes(read_array(reader)?);
+    let time = from_micros(i64::from_le_bytes(read_array(reader)?));
+    let quality = match read_array::<R, 1>(reader)?[0] {
+        0 => Quality::Good,
+        1 => Quality::Stale,
+        2 => Quality::Error,
+        quality => return Err(invalid(format!("unknown quality {}", quality))),
+    };
+    let value = read_value(reader, 0)?;
+    Ok(Entry::Sample {
+        id,
+        sample: Sample {
+            value,
+            //only the system time is recorded. replays stamp samples with their own elapsed time
+            info: SampleInfo { time, elapsed: Duration::ZERO, quality },
+        },
+    })
+}
+
+fn read_value<R: Read>(reader: &mut R, depth: usize) -> io::Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(invalid(format!("value is nested more than {} levels deep", MAX_DEPTH)));
+    }
+    let value = match read_array::<R, 1>(reader)?[0] {
+        STRING => {
+            let len = u32::from_le_bytes(read_array(reader)?);
+            Value::String(read_string(reader, len as usize)?)
+        }
+        BINARY => {
+            let len = u32::from_le_bytes(read_array(reader)?);
+            Value::Binary(read_vec(reader, len as usize)?)
+        }
+        FLOAT => Value::Float(f64::from_le_bytes(read_array(reader)?)),
+        INT => Value::Int(i64::from_le_bytes(read_array(reader)?)),
+        BOOL => Value::Bool(read_array::<R, 1>(reader)?[0] != 0),
+        TIMESTAMP => Value::Timestamp(from_micros(i64::from_le_bytes(read_array(reader)?))),
+        ARRAY => {
+            let len = u32::from_le_bytes(read_array(reader)?);
+            let values = (0..len).map(|_| read_value(reader, depth + 1)).collect::<io::Result<Vec<Value>>>()?;
+            Value::Array(values)
+        }
+        OBJECT => {
+            let len = u32::from_le_bytes(read_array(reader)?);
+            let mut values = HashMap::new();
+            for _ in 0..len {
+                let key_len = u32::from_le_bytes(read_array(reader)?);
+                let key = read_string(reader, key_len as usize)?;
+                values.insert(key, read_value(reader, depth + 1)?);
+            }
+            Value::Object(values)
+        }
+        tag => return Err(invalid(format!("unknown value tag {}", tag))),
+    };
+    Ok(value)
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_vec<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    //don't trust the length to allocate up front, a corrupt one would allocate gigabytes
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_string<R: Read>(reader: &mut R, len: usize) -> io::Result<String> {
+    String::from_utf8(read_vec(reader, len)?).map_err(|err| invalid(err.to_string()))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn to_micros(time: &SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_micros() as i64,
+        Err(err) => -(err.duration().as_micros() as i64),
+    }
+}
+
+fn from_micros(micros: i64) -> SystemTime {
+    if micros >= 0 {
+        UNIX_EPOCH + Duration::from_micros(micros as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_micros(micros.unsigned_abs())
+    }
+}
+
+///Reads the magic number at the start of a recording. Returns an error if it's not a recording this version can read.
+pub fn read_magic<R: Read>(reader: &mut R) -> io::Result<()> {
+    let magic: [u8; 8] = read_array(reader)?;
+    if &magic == MAGIC {
+        Ok(())
+    } else {
+        Err(invalid("not an IOC recording, or recorded by an incompatible version".to_string()))
+    }
+}
+
+///Types an `Input` or `Output` can carry, i.e. the types a `Value` can hold. These can be recorded and replayed.
+pub trait Recordable: Clone + Send + Sync + 'static {
+    fn to_value(self) -> Value;
+    ///Returns `None` if the value holds a different type.
+    fn from_value(value: Value) -> Option<Self>;
+    fn input_kind(input: Input<Self>) -> InputKind;
+    fn output_kind(output: Output<Self>) -> OutputKind;
+}
+
+macro_rules! recordable {
+    ($type:ty, $variant:ident) => {
+        impl Recordable for $type {
+            fn to_value(self) -> Value {
+                Value::$variant(self)
+            }
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    Value::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+            fn input_kind(input: Input<Self>) -> InputKind {
+                InputKind::$variant(input)
+            }
+            fn output_kind(output: Output<Self>) -> OutputKind {
+                OutputKind::$variant(output)
+            }
+        }
+    };
+}
+
+recordable!(String, String);
+recordable!(Vec<u8>, Binary);
+recordable!(f64, Float);
+recordable!(i64, Int);
+recordable!(bool, Bool);
+recordable!(SystemTime, Timestamp);
+recordable!(Vec<Value>, Array);
+recordable!(HashMap<String, Value>, Object);
+
+///When a recording is moved aside for a new file. The current file is `path`, older ones are `path.1`, `path.2`, and so on.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    ///A new file is started once the current one is larger than this.
+    pub max_bytes: u64,
+    ///How many older files are kept.
+    pub max_files: usize,
+}
+
+///Records samples of `Input`s to a file.
+pub struct Recorder;
+
+impl Recorder {
+    ///Starts recording the given inputs to the file at path, which is rotated first if it already exists.
+    /// Returns the handle of the task writing the file, which finishes once the cancel token is cancelled or all inputs closed.
+    pub fn spawn(
+        path: PathBuf,
+        inputs: Vec<(String, &InputKind)>,
+        rotation: Rotation,
+        cancel_token: CancellationToken,
+    ) -> Result<JoinHandle<()>, IocBuildError> {
+        if inputs.len() > u16::MAX as usize {
+            return Err(IocBuildError::from_string(format!("can't record more than {} inputs", u16::MAX)));
+        }
+        let (tx, mut rx) = mpsc::channel(1024);
+        let mut names = Vec::with_capacity(inputs.len());
+        let mut last = HashMap::with_capacity(inputs.len());
+        for (id, (name, input)) in inputs.into_iter().enumerate() {
+            let id = id as u16;
+            let sample = match input {
+                InputKind::String(input) => tap(id, input, tx.clone(), cancel_token.clone()),
+                InputKind::Binary(input) => tap(id, input, tx.clone(), cancel_token.clone()),
+                InputKind::Float(input) => tap(id, input, tx.clone(), cancel_token.clone()),
+                InputKind::Int(input) => tap(id, input, tx.clone(), cancel_token.clone()),
+                InputKind::Bool(input) => tap(id, input, tx.clone(), cancel_token.clone()),
+                InputKind::Timestamp(input) => tap(id, input, tx.clone(), cancel_token.clone()),
+                InputKind::Array(input) => tap(id, input, tx.clone(), cancel_token.clone()),
+                InputKind::Object(input) => tap(id, input, tx.clone(), cancel_token.clone()),
+            };
+            names.push(name);
+            last.insert(id, sample);
+        }
+        drop(tx);
+
+        let mut writer = Writer {
+            path,
+            rotation,
+            names,
+            last,
+            file: None,
+            written: 0,
+            buf: Vec::new(),
+        };
+        writer.open().map_err(|err| {
+            IocBuildError::from_string(format!("unable to start recording to {}: {}", writer.path.display(), err))
+        })?;
+        info!("recording {} inputs to {}", writer.names.len(), writer.path.display());
+
+        Ok(tokio::task::spawn_blocking(move || {
+            while let Some((id, sample)) = rx.blocking_recv() {
+                let mut result = writer.write(id, sample);
+                //write whatever else is waiting before flushing
+                while let (Ok(()), Ok((id, sample))) = (&result, rx.try_recv()) {
+                    result = writer.write(id, sample);
+                }
+                if let Err(err) = result.and_then(|_| writer.flush()) {
+                    error!("stopped recording to {}: {}", writer.path.display(), err);
+                    return;
+                }
+            }
+            debug!("recorder shut down");
+        }))
+    }
+}
+
+//sends every sample of the input to the recorder. returns the current sample
+fn tap<T: Recordable>(
+    id: u16,
+    input: &Input<T>,
+    tx: mpsc::Sender<(u16, Sample<Value>)>,
+    cancel_token: CancellationToken,
+) -> Sample<Value> {
+    let mut source = input.sample_source();
+    let current = source.borrow_and_update().map(T::to_value);
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => break,
+                changed = source.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+            }
+            let sample = source.borrow_and_update().map(T::to_value);
+            if tx.send((id, sample)).await.is_err() {
+                break;
+            }
+        }
+    });
+    current
+}
+
+struct Writer {
+    path: PathBuf,
+    rotation: Rotation,
+    names: Vec<String>,
+    //the last sample of each input, written at the start of each file
+    last: HashMap<u16, Sample<Value>>,
+    file: Option<BufWriter<File>>,
+    written: u64,
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    //rotates the current file and starts a new one
+    fn open(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        rotate(&self.path, self.rotation.max_files)?;
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        self.buf.clear();
+        self.buf.extend_from_slice(MAGIC);
+        for (id, name) in self.names.iter().enumerate() {
+            Entry::Declare { id: id as u16, name: name.clone() }.encode(&mut self.buf);
+        }
+        for id in 0..self.names.len() as u16 {
+            if let Some(sample) = self.last.get(&id) {
+                encode_sample(id, sample, &mut self.buf);
+            }
+        }
+        file.write_all(&self.buf)?;
+        file.flush()?;
+        self.written = self.buf.len() as u64;
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn write(&mut self, id: u16, sample: Sample<Value>) -> io::Result<()> {
+        if self.written > self.rotation.max_bytes {
+            self.open()?;
+        }
+        self.buf.clear();
+        encode_sample(id, &sample, &mut self.buf);
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(&self.buf)?;
+        }
+        self.written += self.buf.len() as u64;
+        self.last.insert(id, sample);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+//moves path to path.1, path.1 to path.2 and so on, removing the oldest file
+fn rotate(path: &Path, max_files: usize) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    if max_files == 0 {
+        return fs::remove_file(path);
+    }
+    for n in (1..max_files).rev() {
+        let older = rotated(path, n);
+        if older.exists() {
+            fs::rename(&older, rotated(path, n + 1))?;
+        }
+    }
+    fs::rename(path, rotated(path, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Recorder, Rotation, MAGIC};
+    use crate::{
+        sample::{Quality, Sample, SampleInfo},
+        Input, InputKind, Value,
+    };
+    use std::{collections::HashMap, io::Cursor, time::{Duration, UNIX_EPOCH}};
+    use tokio_util::sync::CancellationToken;
+
+    #[test]
+    fn test_encode_decode() {
+        let entries = vec![
+            Entry::Declare { id: 3, name: "imu.gyro".to_string() },
+            Entry::Sample {
+                id: 3,
+                sample: Sample {
+                    value: Value::Object(HashMap::from([
+                        ("x".to_string(), Value::Float(1.5)),
+                        ("tags".to_string(), Value::Array(vec![Value::String("a".to_string()), Value::Bool(true)])),
+                        ("raw".to_string(), Value::Binary(vec![1, 2, 3])),
+                    ])),
+                    info: SampleInfo {
+                        time: UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456),
+                        elapsed: Duration::ZERO,
+                        quality: Quality::Stale,
+                    },
+                },
+            },
+        ];
+        let mut buf = Vec::new();
+        entries.iter().for_each(|entry| entry.encode(&mut buf));
+        //a cut off entry at the end is ignored
+        let full_len = buf.len();
+        Entry::Declare { id: 4, name: "cut.off".to_string() }.encode(&mut buf);
+        buf.truncate(full_len + 4);
+
+        let mut reader = Cursor::new(buf);
+        let mut decoded = Vec::new();
+        while let Some(entry) = Entry::decode(&mut reader).unwrap() {
+            decoded.push(entry);
+        }
+        assert_eq!(decoded, entries);
+    }
+
+    #[test]
+    fn test_decode_too_deep() {
+        let nested = |depth: usize| {
+            let mut value = Value::Int(1);
+            for _ in 0..depth {
+                value = Value::Array(vec![value]);
+            }
+            let mut buf = Vec::new();
+            Entry::Sample { id: 0, sample: Sample::now(value) }.encode(&mut buf);
+            buf
+        };
+        assert!(Entry::decode(&mut Cursor::new(nested(100))).unwrap().is_some());
+
+        //a crafted sample of arrays nested a million levels deep, after the header of an int sample
+        let mut buf = nested(0);
+        buf.truncate(buf.len() - 9);
+        for _ in 0..1_000_000 {
+            buf.extend_from_slice(&[6, 1, 0, 0, 0]);
+        }
+        let err = Entry::decode(&mut Cursor::new(buf)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_recorder_rotation() {
+        let dir = std::env::temp_dir().join(format!("ioc_record_{}", std::process::id()));
+        let path = dir.join("test.rec");
+        let (input, tx) = Input::new(0.0);
+        let input = InputKind::Float(input);
+        let cancel_token = CancellationToken::new();
+        let rotation = Rotation { max_bytes: 64, max_files: 2 };
+        let handle = Recorder::spawn(path.clone(), vec![("x.value".to_string(), &input)], rotation, cancel_token.clone()).unwrap();
+        for i in 1..=10 {
+            tx.send(i as f64).unwrap();
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        drop(tx);
+        handle.await.unwrap();
+
+        //every file starts with the declarations and the last value of each input
+        let mut last = Vec::new();
+        for file in [path.with_extension("rec.2"), path.with_extension("rec.1"), path.clone()] {
+            let mut reader = Cursor::new(std::fs::read(&file).unwrap());
+            assert_eq!(&reader.get_ref()[..8], MAGIC);
+            reader.set_position(8);
+            assert_eq!(Entry::decode(&mut reader).unwrap(), Some(Entry::Declare { id: 0, name: "x.value".to_string() }));
+            while let Some(entry) = Entry::decode(&mut reader).unwrap() {
+                if let Entry::Sample { sample, .. } = entry {
+                    last.push(sample.value);
+                }
+            }
+        }
+        assert!(!path.with_extension("rec.3").exists());
+        assert_eq!(last.last(), Some(&Value::Float(10.0)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//! Includes `Registry`, which maps the names used in config files to module and transformer factories.
+//!
+//! Crates that provide modules or transformers expose a function that registers them, e.g. `pub fn register(registry: &mut Registry)`.
+//! The `ioc` binary builds its registry at startup from every crate it is compiled with, so new node types don't need changes to `ioc` itself.
+
+use crate::{clock::Clock, error::IocBuildError, InputKind, ModuleIO, TransformerI};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, ObjectValidation, Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
+use serde::{de::DeserializeOwned, de::Error, Deserialize, Deserializer};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+mod de;
+
+type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+///This trait should be implemented by all transformer config objects.
+pub trait TransformerConfig {
+    ///Attempts to build this transformer given a map of all named upstream inputs, and the clock it keeps time with.
+    fn try_build(
+        &self,
+        upstream_inputs: &HashMap<String, InputKind>,
+        clock: &Clock,
+    ) -> impl Future<Output = Result<TransformerI, IocBuildError>>;
+    ///Returns the names of upstream inputs this transformer will require to be built.
+    fn needs_inputs(&self) -> HashSet<&String>;
+}
+
+///Object safe version of `TransformerConfig`, so configs of different types can be held together.
+pub trait DynTransformerConfig: Debug {
+    fn try_build<'a>(
+        &'a self,
+        upstream_inputs: &'a HashMap<String, InputKind>,
+        clock: &'a Clock,
+    ) -> LocalBoxFuture<'a, Result<TransformerI, IocBuildError>>;
+    fn needs_inputs(&self) -> HashSet<&String>;
+}
+
+impl<C: TransformerConfig + Debug> DynTransformerConfig for C {
+    fn try_build<'a>(
+        &'a self,
+        upstream_inputs: &'a HashMap<String, InputKind>,
+        clock: &'a Clock,
+    ) -> LocalBoxFuture<'a, Result<TransformerI, IocBuildError>> {
+        Box::pin(TransformerConfig::try_build(self, upstream_inputs, clock))
+    }
+    fn needs_inputs(&self) -> HashSet<&String> {
+        TransformerConfig::needs_inputs(self)
+    }
+}
+
+///A module or transformer entry in a config file, i.e. a single key naming its type and a value holding its config.
+/// The config is deserialized by the registered factory for that type when it is built.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub kind: String,
+    pub config: serde_json::Value,
+}
+
+impl<'de> Deserialize<'de> for NodeConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let map = HashMap::<String, serde_json::Value>::deserialize(deserializer)?;
+        if map.len() != 1 {
+            return Err(D::Error::custom(format!(
+                "expected a single key naming the type, but got {} keys",
+                map.len()
+            )));
+        }
+        let (kind, config) = map.into_iter().next().unwrap();
+        Ok(NodeConfig { kind, config })
+    }
+}
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+type ModuleFactory = Box<dyn Fn(serde_json::Value, CancellationToken) -> LocalBoxFuture<'static, Result<ModuleIO, IocBuildError>>>;
+type TransformerFactory = fn(serde_json::Value) -> Result<Box<dyn DynTransformerConfig>, serde_json::Error>;
+
+struct ModuleEntry {
+    schema: SchemaFn,
+    factory: ModuleFactory,
+}
+
+struct TransformerEntry {
+    schema: SchemaFn,
+    ports: Vec<String>,
+    factory: TransformerFactory,
+}
+
+///Named module and transformer factories. Names are matched ignoring case, since the config crate (0.14) lowercases every key
+/// it reads from a file, including the keys that name the type of a node.
+#[derive(Default)]
+pub struct Registry {
+    modules: BTreeMap<String, ModuleEntry>,
+    transformers: BTreeMap<String, TransformerEntry>,
+}
+
+impl Registry {
+    ///Registers a module type. `build` is called with the deserialized config when a module of this type is built.
+    /// Modules don't declare ports, since they typically depend on the config.
+    pub fn register_module<C, F, Fut>(&mut self, name: &str, build: F) -> &mut Self
+    where
+        C: DeserializeOwned + JsonSchema + 'static,
+        F: Fn(C, CancellationToken) -> Fut + 'static,
+        Fut: Future<Output = Result<ModuleIO, IocBuildError>> + 'static,
+    {
+        let kind = name.to_string();
+        let factory: ModuleFactory = Box::new(move |config, cancel_token| {
+            match de::from_value::<C>(config) {
+                Ok(config) => Box::pin(build(config, cancel_token)),
+                Err(err) => {
+                    let err = IocBuildError::from_string(format!("Invalid config for module type {}: {}", kind, err));
+                    Box::pin(async move { Err(err) })
+                }
+            }
+        });
+        let entry = ModuleEntry { schema: C::json_schema, factory };
+        if self.modules.insert(name.to_string(), entry).is_some() {
+            warn!("module type {} was registered more than once. Using the last one.", name);
+        }
+        self
+    }
+
+    ///Registers a transformer type with the names of the inputs it provides. Pass no names if they depend on the config.
+    pub fn register_transformer<C>(&mut self, name: &str, ports: &[&str]) -> &mut Self
+    where
+        C: TransformerConfig + DeserializeOwned + JsonSchema + Debug + 'static,
+    {
+        let entry = TransformerEntry {
+            schema: C::json_schema,
+            ports: ports.iter().map(|port| port.to_string()).collect(),
+            factory: |config| {
+                de::from_value::<C>(config)
+                    .map(|config| Box::new(config) as Box<dyn DynTransformerConfig>)
+            },
+        };
+        if self.transformers.insert(name.to_string(), entry).is_some() {
+            warn!("transformer type {} was registered more than once. Using the last one.", name);
+        }
+        self
+    }
+
+    ///Builds a module from its config.
+    pub async fn build_module(&self, node: NodeConfig, cancel_token: CancellationToken) -> Result<ModuleIO, IocBuildError> {
+        match find(&self.modules, &node.kind) {
+            Some((_, entry)) => (entry.factory)(node.config, cancel_token).await,
+            None => Err(IocBuildError::from_string(format!(
+                "Unknown module type {}. Known types are: {:?}",
+                node.kind,
+                self.modules.keys().collect::<Vec<_>>()
+            ))),
+        }
+    }
+
+    ///Deserializes a transformer's config so it can be built once its inputs are available.
+    pub fn transformer_config(&self, node: NodeConfig) -> Result<Box<dyn DynTransformerConfig>, IocBuildError> {
+        match find(&self.transformers, &node.kind) {
+            Some((kind, entry)) => (entry.factory)(node.config).map_err(|err| {
+                IocBuildError::from_string(format!("Invalid config for transformer type {}: {}", kind, err))
+            }),
+            None => Err(IocBuildError::from_string(format!(
+                "Unknown transformer type {}. Known types are: {:?}",
+                node.kind,
+                self.transformers.keys().collect::<Vec<_>>()
+            ))),
+        }
+    }
+
+    ///Returns the names of the inputs a transformer type provides, if the type is registered.
+    pub fn transformer_ports(&self, kind: &str) -> Option<&[String]> {
+        find(&self.transformers, kind).map(|(_, entry)| entry.ports.as_slice())
+    }
+
+    ///Returns the names of all registered module types.
+    pub fn module_kinds(&self) -> impl Iterator<Item = &String> {
+        self.modules.keys()
+    }
+
+    ///Returns the names of all registered transformer types.
+    pub fn transformer_kinds(&self) -> impl Iterator<Item = &String> {
+        self.transformers.keys()
+    }
+
+    ///JSON Schema matching any registered module config.
+    pub fn module_schema(&self, gen: &mut SchemaGenerator) -> Schema {
+        one_of(self.modules.iter().map(|(name, entry)| (name, entry.schema)), gen)
+    }
+
+    ///JSON Schema matching any registered transformer config.
+    pub fn transformer_schema(&self, gen: &mut SchemaGenerator) -> Schema {
+        one_of(self.transformers.iter().map(|(name, entry)| (name, entry.schema)), gen)
+    }
+}
+
+//returns the registered name and entry
+fn find<'a, E>(entries: &'a BTreeMap<String, E>, kind: &str) -> Option<(&'a String, &'a E)> {
+    entries
+        .get_key_value(kind)
+        .or_else(|| entries.iter().find(|(name, _)| name.eq_ignore_ascii_case(kind)))
+}
+
+//the same shape as the schema derived for an externally tagged enum
+fn one_of<'a>(entries: impl Iterator<Item = (&'a String, SchemaFn)>, gen: &mut SchemaGenerator) -> Schema {
+    let variants = entries
+        .map(|(name, schema)| {
+            let mut object = ObjectValidation {
+                additional_properties: Some(Box::new(Schema::Bool(false))),
+                ..Default::default()
+            };
+            object.required.insert(name.to_string());
+            object.properties.insert(name.to_string(), schema(gen));
+            Schema::Object(SchemaObject {
+                instance_type: Some(InstanceType::Object.into()),
+                object: Some(Box::new(object)),
+                ..Default::default()
+            })
+        })
+        .collect();
+    Schema::Object(SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            one_of: Some(variants),
+            ..Default::default()
+        })),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NodeConfig, Registry};
+    use crate::feedback::{Feedback, FeedbackConfig};
+    use crate::Module;
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn test_build_module() {
+        let mut registry = Registry::default();
+        registry.register_module("Feedback", |cfg: FeedbackConfig, cancel_token| async move {
+            Feedback::try_build(&cfg, cancel_token).await.map(|feedback| feedback.into())
+        });
+
+        //the config crate lowercases keys read from config files
+        let node: NodeConfig = serde_json::from_str(r#"{"feedback": {"items": {"x": {"float": {"start": 1.0}}}}}"#).unwrap();
+        let module = registry.build_module(node, CancellationToken::new()).await.unwrap();
+        assert!(module.inputs.contains_key("x"));
+        assert!(module.outputs.contains_key("x"));
+
+        let node: NodeConfig = serde_json::from_str(r#"{"Unknown": {}}"#).unwrap();
+        assert!(registry.build_module(node, CancellationToken::new()).await.is_err());
+    }
+}
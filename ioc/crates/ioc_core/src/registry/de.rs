@@ -0,0 +1,141 @@
+//! Deserializes registered configs from the `serde_json::Value`s they are stored as.
+//!
+//! The config crate (0.14) lowercases every key it reads from a file, and matches enum variants ignoring case when it deserializes them.
+//! Configs are stored as `serde_json::Value`s with those lowercase keys, so enum variants are matched ignoring case here too.
+
+use serde::de::{
+    self,
+    value::{MapDeserializer, SeqDeserializer},
+    DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, Unexpected, VariantAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use serde_json::Value;
+
+///Deserializes a config of type `T` from a value.
+pub(crate) fn from_value<T: de::DeserializeOwned>(value: Value) -> Result<T, serde_json::Error> {
+    T::deserialize(ConfigDeserializer(value))
+}
+
+struct ConfigDeserializer(Value);
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for ConfigDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for ConfigDeserializer {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Array(values) => {
+                visitor.visit_seq(SeqDeserializer::new(values.into_iter().map(ConfigDeserializer)))
+            }
+            Value::Object(map) => visitor.visit_map(MapDeserializer::new(
+                map.into_iter().map(|(key, value)| (key, ConfigDeserializer(value))),
+            )),
+            other => other.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        //either "Variant" or { Variant: value }
+        let (variant, value) = match self.0 {
+            Value::String(variant) => (variant, None),
+            Value::Object(map) if map.len() == 1 => {
+                let (variant, value) = map.into_iter().next().unwrap();
+                (variant, Some(value))
+            }
+            other => return other.deserialize_enum(name, variants, visitor),
+        };
+        let variant = variants
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(&variant))
+            .map(|known| known.to_string())
+            .unwrap_or(variant);
+        visitor.visit_enum(ConfigEnumAccess { variant, value })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct ConfigEnumAccess {
+    variant: String,
+    value: Option<Value>,
+}
+
+impl<'de> EnumAccess<'de> for ConfigEnumAccess {
+    type Error = serde_json::Error;
+    type Variant = ConfigVariantAccess;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let variant = seed.deserialize(IntoDeserializer::<Self::Error>::into_deserializer(self.variant))?;
+        Ok((variant, ConfigVariantAccess { value: self.value }))
+    }
+}
+
+struct ConfigVariantAccess {
+    value: Option<Value>,
+}
+
+impl<'de> VariantAccess<'de> for ConfigVariantAccess {
+    type Error = serde_json::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        match self.value {
+            None | Some(Value::Null) => Ok(()),
+            Some(_) => Err(de::Error::invalid_type(Unexpected::Map, &"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Self::Error> {
+        match self.value {
+            Some(value) => seed.deserialize(ConfigDeserializer(value)),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, &"newtype variant")),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Some(value) => ConfigDeserializer(value).deserialize_seq(visitor),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, &"tuple variant")),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Some(value) => ConfigDeserializer(value).deserialize_map(visitor),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, &"struct variant")),
+        }
+    }
+}
@@ -0,0 +1,342 @@
+//! Relays keep a node's `Input`s and `Output`s connected while the node is rebuilt, for example when it is restarted after a panic.
+//!
+//! Consumers hold the `Input`s and `Output`s of the relay, which stay open. The relay forwards from or to whichever instance of the node is connected.
+
+use crate::{
+    error::IocBuildError,
+    sample::{Quality, Sample},
+    Input, InputKind, Output, OutputKind, Value,
+};
+use std::{collections::HashMap, time::SystemTime};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+///Forwards values and samples from the connected `Input` to an `Input` that outlives it.
+///
+/// While nothing is connected, the last sample is marked `Quality::Stale`. The relayed `Input` closes when the relay is dropped.
+pub struct InputRelay<T> {
+    sources: mpsc::UnboundedSender<Input<T>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> InputRelay<T> {
+    ///Creates a relay connected to the given `Input`. Returns the relay and the relayed `Input`, which always provides samples.
+    pub fn new(input: Input<T>) -> (Self, Input<T>) {
+        let mut first = input.sample_source();
+        let (relayed, tx) = Input::new_sampled(first.borrow_and_update());
+        let mut source = Some(first);
+        let (sources, mut sources_rx) = mpsc::unbounded_channel::<Input<T>>();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    next = sources_rx.recv() => match next {
+                        Some(input) => {
+                            let mut next_source = input.sample_source();
+                            if tx.send(next_source.borrow_and_update()).is_err() {
+                                break;
+                            }
+                            source = Some(next_source);
+                        }
+                        None => break,
+                    },
+                    changed = async {
+                        match source.as_mut() {
+                            Some(source) => source.changed().await,
+                            None => std::future::pending().await,
+                        }
+                    } => {
+                        let sent = match (changed, source.as_mut()) {
+                            (Ok(()), Some(source)) => tx.send(source.borrow_and_update()).is_ok(),
+                            //the connected node stopped
+                            _ => {
+                                tx.mark(Quality::Stale);
+                                source = None;
+                                true
+                            }
+                        };
+                        if !sent {
+                            break;
+                        }
+                    },
+                }
+            }
+            debug!("input relay shutting down");
+        });
+        (Self { sources }, relayed)
+    }
+
+    ///Connects a new instance of the `Input`, replacing the previous one.
+    pub fn connect(&self, input: Input<T>) {
+        //the relay task only stops when this is dropped or the relayed input has no consumers
+        let _ = self.sources.send(input);
+    }
+}
+
+//the last thing written to an output relay, so it can be written again to a new instance
+pub(crate) enum Written<T> {
+    Value(T),
+    Sample(Sample<T>),
+}
+
+///Forwards values and samples written to an `Output` that outlives the connected `Output`.
+///
+/// The last value written is written again when a new `Output` is connected. Values written while nothing is connected are only kept for that.
+pub struct OutputRelay<T> {
+    targets: mpsc::UnboundedSender<Output<T>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> OutputRelay<T> {
+    ///Creates a relay connected to the given `Output`. Returns the relay and the relayed `Output`, which always accepts samples.
+    pub fn new(output: Output<T>) -> (Self, Output<T>) {
+        let (relayed, mut rx, mut samples_rx) = Output::new_sampled();
+        let (targets, mut targets_rx) = mpsc::unbounded_channel::<Output<T>>();
+
+        tokio::spawn(async move {
+            let mut target = Some(output);
+            let mut last: Option<Written<T>> = None;
+            loop {
+                tokio::select! {
+                    next = targets_rx.recv() => match next {
+                        Some(output) => {
+                            target = Some(output);
+                            if let Some(written) = &last {
+                                write(&mut target, written).await;
+                            }
+                        }
+                        None => break,
+                    },
+                    value = rx.recv() => match value {
+                        Some(value) => {
+                            let written = Written::Value(value);
+                            write(&mut target, &written).await;
+                            last = Some(written);
+                        }
+                        None => break,
+                    },
+                    sample = samples_rx.recv() => match sample {
+                        Some(sample) => {
+                            let written = Written::Sample(sample);
+                            write(&mut target, &written).await;
+                            last = Some(written);
+                        }
+                        None => break,
+                    },
+                }
+            }
+            debug!("output relay shutting down");
+        });
+        (Self { targets }, relayed)
+    }
+
+    ///Connects a new instance of the `Output`, replacing the previous one.
+    pub fn connect(&self, output: Output<T>) {
+        let _ = self.targets.send(output);
+    }
+}
+
+//disconnects the target if it's closed
+pub(crate) async fn write<T: Clone>(target: &mut Option<Output<T>>, written: &Written<T>) {
+    let sent = match (target.as_ref(), written) {
+        (None, _) => return,
+        (Some(output), Written::Sample(sample)) => match output.sample_sink() {
+            Some(sink) => sink.send(sample.clone()).await.is_ok(),
+            None => output.tx.send(sample.value.clone()).await.is_ok(),
+        },
+        (Some(output), Written::Value(value)) => output.tx.send(value.clone()).await.is_ok(),
+    };
+    if !sent {
+        *target = None;
+    }
+}
+
+///An `InputRelay` for any `InputKind`.
+pub enum InputKindRelay {
+    String(InputRelay<String>),
+    Binary(InputRelay<Vec<u8>>),
+    Float(InputRelay<f64>),
+    Int(InputRelay<i64>),
+    Bool(InputRelay<bool>),
+    Timestamp(InputRelay<SystemTime>),
+    Array(InputRelay<Vec<Value>>),
+    Object(InputRelay<HashMap<String, Value>>),
+}
+
+impl InputKindRelay {
+    ///Creates a relay connected to the given input. Returns the relay and the relayed input.
+    pub fn new(input: InputKind) -> (Self, InputKind) {
+        match input {
+            InputKind::String(input) => {
+                let (relay, input) = InputRelay::new(input);
+                (Self::String(relay), InputKind::String(input))
+            }
+            InputKind::Binary(input) => {
+                let (relay, input) = InputRelay::new(input);
+                (Self::Binary(relay), InputKind::Binary(input))
+            }
+            InputKind::Float(input) => {
+                let (relay, input) = InputRelay::new(input);
+                (Self::Float(relay), InputKind::Float(input))
+            }
+            InputKind::Int(input) => {
+                let (relay, input) = InputRelay::new(input);
+                (Self::Int(relay), InputKind::Int(input))
+            }
+            InputKind::Bool(input) => {
+                let (relay, input) = InputRelay::new(input);
+                (Self::Bool(relay), InputKind::Bool(input))
+            }
+            InputKind::Timestamp(input) => {
+                let (relay, input) = InputRelay::new(input);
+                (Self::Timestamp(relay), InputKind::Timestamp(input))
+            }
+            InputKind::Array(input) => {
+                let (relay, input) = InputRelay::new(input);
+                (Self::Array(relay), InputKind::Array(input))
+            }
+            InputKind::Object(input) => {
+                let (relay, input) = InputRelay::new(input);
+                (Self::Object(relay), InputKind::Object(input))
+            }
+        }
+    }
+
+    ///Connects a new instance of the input. Returns an error if it is a different kind.
+    pub fn connect(&self, input: InputKind) -> Result<(), IocBuildError> {
+        match (self, input) {
+            (Self::String(relay), InputKind::String(input)) => relay.connect(input),
+            (Self::Binary(relay), InputKind::Binary(input)) => relay.connect(input),
+            (Self::Float(relay), InputKind::Float(input)) => relay.connect(input),
+            (Self::Int(relay), InputKind::Int(input)) => relay.connect(input),
+            (Self::Bool(relay), InputKind::Bool(input)) => relay.connect(input),
+            (Self::Timestamp(relay), InputKind::Timestamp(input)) => relay.connect(input),
+            (Self::Array(relay), InputKind::Array(input)) => relay.connect(input),
+            (Self::Object(relay), InputKind::Object(input)) => relay.connect(input),
+            (_, input) => {
+                return Err(IocBuildError::from_string(format!(
+                    "unable to relay input of type {:?} to an input of a different type",
+                    input
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+///An `OutputRelay` for any `OutputKind`.
+pub enum OutputKindRelay {
+    String(OutputRelay<String>),
+    Binary(OutputRelay<Vec<u8>>),
+    Float(OutputRelay<f64>),
+    Int(OutputRelay<i64>),
+    Bool(OutputRelay<bool>),
+    Timestamp(OutputRelay<SystemTime>),
+    Array(OutputRelay<Vec<Value>>),
+    Object(OutputRelay<HashMap<String, Value>>),
+}
+
+impl OutputKindRelay {
+    ///Creates a relay connected to the given output. Returns the relay and the relayed output.
+    pub fn new(output: OutputKind) -> (Self, OutputKind) {
+        match output {
+            OutputKind::String(output) => {
+                let (relay, output) = OutputRelay::new(output);
+                (Self::String(relay), OutputKind::String(output))
+            }
+            OutputKind::Binary(output) => {
+                let (relay, output) = OutputRelay::new(output);
+                (Self::Binary(relay), OutputKind::Binary(output))
+            }
+            OutputKind::Float(output) => {
+                let (relay, output) = OutputRelay::new(output);
+                (Self::Float(relay), OutputKind::Float(output))
+            }
+            OutputKind::Int(output) => {
+                let (relay, output) = OutputRelay::new(output);
+                (Self::Int(relay), OutputKind::Int(output))
+            }
+            OutputKind::Bool(output) => {
+                let (relay, output) = OutputRelay::new(output);
+                (Self::Bool(relay), OutputKind::Bool(output))
+            }
+            OutputKind::Timestamp(output) => {
+                let (relay, output) = OutputRelay::new(output);
+                (Self::Timestamp(relay), OutputKind::Timestamp(output))
+            }
+            OutputKind::Array(output) => {
+                let (relay, output) = OutputRelay::new(output);
+                (Self::Array(relay), OutputKind::Array(output))
+            }
+            OutputKind::Object(output) => {
+                let (relay, output) = OutputRelay::new(output);
+                (Self::Object(relay), OutputKind::Object(output))
+            }
+        }
+    }
+
+    ///Connects a new instance of the output. Returns an error if it is a different kind.
+    pub fn connect(&self, output: OutputKind) -> Result<(), IocBuildError> {
+        match (self, output) {
+            (Self::String(relay), OutputKind::String(output)) => relay.connect(output),
+            (Self::Binary(relay), OutputKind::Binary(output)) => relay.connect(output),
+            (Self::Float(relay), OutputKind::Float(output)) => relay.connect(output),
+            (Self::Int(relay), OutputKind::Int(output)) => relay.connect(output),
+            (Self::Bool(relay), OutputKind::Bool(output)) => relay.connect(output),
+            (Self::Timestamp(relay), OutputKind::Timestamp(output)) => relay.connect(output),
+            (Self::Array(relay), OutputKind::Array(output)) => relay.connect(output),
+            (Self::Object(relay), OutputKind::Object(output)) => relay.connect(output),
+            (_, output) => {
+                return Err(IocBuildError::from_string(format!(
+                    "unable to relay output of type {:?} to an output of a different type",
+                    output
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputRelay, OutputRelay};
+    use crate::{sample::Quality, Input, Output};
+
+    #[tokio::test]
+    async fn test_input_relay() {
+        let (input, tx) = Input::new(1.0);
+        let (relay, relayed) = InputRelay::new(input);
+        let mut samples = relayed.sample_source();
+        assert_eq!(samples.borrow_and_update().value, 1.0);
+
+        //the relayed input stays open and goes stale when the connected input closes
+        drop(tx);
+        samples.changed().await.unwrap();
+        let sample = samples.borrow_and_update();
+        assert_eq!(sample.value, 1.0);
+        assert_eq!(sample.info.quality, Quality::Stale);
+
+        let (input, tx) = Input::new(2.0);
+        relay.connect(input);
+        samples.changed().await.unwrap();
+        assert_eq!(samples.borrow_and_update().value, 2.0);
+        tx.send(3.0).unwrap();
+        samples.changed().await.unwrap();
+        let sample = samples.borrow_and_update();
+        assert_eq!(sample.value, 3.0);
+        assert_eq!(sample.info.quality, Quality::Good);
+    }
+
+    #[tokio::test]
+    async fn test_output_relay() {
+        let (output, mut rx) = Output::new();
+        let (relay, relayed) = OutputRelay::new(output);
+        relayed.tx.send(1.0).await.unwrap();
+        assert_eq!(rx.recv().await, Some(1.0));
+
+        //the last value is written again to a new output
+        let (output, mut rx) = Output::new();
+        relay.connect(output);
+        assert_eq!(rx.recv().await, Some(1.0));
+        relayed.tx.send(2.0).await.unwrap();
+        assert_eq!(rx.recv().await, Some(2.0));
+    }
+}
@@ -0,0 +1,304 @@
+//! The `Replay` module plays back a recording made by a `Recorder`, see `record`.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    time::{Instant, SystemTime},
+};
+
+use crate::{
+    error::IocBuildError,
+    record::{read_magic, Entry, Recordable},
+    sample::{Sample, SampleInfo, SampleSender},
+    timestamp, Input, InputKind, Module, ModuleIO, Value,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{sleep_until, Duration},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+///Configuration for a `Replay` module.
+/// path is a file written by the recorder. If node is given, only the inputs recorded from that module or transformer are replayed, without its key.
+/// That way a replay module with the same key can stand in for the node it replays.
+/// speed scales the original timing, e.g. 2.0 replays twice as fast. Defaults to 1.0.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct ReplayConfig {
+    pub path: String,
+    pub node: Option<String>,
+    pub speed: Option<f64>,
+}
+
+///Replays recorded samples as `Input`s, named like the inputs they were recorded from.
+///
+/// Each input starts with the first value recorded for it. Samples are stamped with the time they are replayed at, keeping their quality.
+/// Once the recording ends the inputs keep their last values.
+pub struct Replay {
+    pub join_handle: JoinHandle<()>,
+    pub inputs: HashMap<String, InputKind>,
+}
+
+impl From<Replay> for ModuleIO {
+    fn from(replay: Replay) -> Self {
+        ModuleIO {
+            join_handle: replay.join_handle,
+            inputs: replay.inputs,
+            outputs: HashMap::new(),
+        }
+    }
+}
+
+//sends a recorded value to a replayed input. returns false if it's closed
+type Emit = Box<dyn Fn(Sample<Value>) -> bool + Send + Sync>;
+
+fn replay_input<T: Recordable>(first: Sample<Value>) -> Option<(InputKind, Emit)> {
+    let first = Sample {
+        value: T::from_value(first.value)?,
+        info: first.info,
+    };
+    let (input, tx): (Input<T>, SampleSender<T>) = Input::new_sampled(first);
+    let emit: Emit = Box::new(move |sample: Sample<Value>| match T::from_value(sample.value) {
+        Some(value) => tx.send(Sample { value, info: sample.info }).is_ok(),
+        //an input doesn't change type during a recording, unless the file is corrupt
+        None => true,
+    });
+    Some((T::input_kind(input), emit))
+}
+
+fn replay_kind(first: Sample<Value>) -> Option<(InputKind, Emit)> {
+    match first.value {
+        Value::String(_) => replay_input::<String>(first),
+        Value::Binary(_) => replay_input::<Vec<u8>>(first),
+        Value::Float(_) => replay_input::<f64>(first),
+        Value::Int(_) => replay_input::<i64>(first),
+        Value::Bool(_) => replay_input::<bool>(first),
+        Value::Timestamp(_) => replay_input::<SystemTime>(first),
+        Value::Array(_) => replay_input::<Vec<Value>>(first),
+        Value::Object(_) => replay_input::<HashMap<String, Value>>(first),
+    }
+}
+
+//the names of the recorded inputs and their first samples, by id
+type Header = (HashMap<u16, String>, HashMap<u16, Sample<Value>>);
+
+//reads the declarations and the first sample of each input at the start of a recording
+fn read_header<R: Read>(reader: &mut R) -> Result<Header, String> {
+    read_magic(reader).map_err(|err| err.to_string())?;
+    let mut names = HashMap::new();
+    let mut first = HashMap::new();
+    while first.len() < names.len() || names.is_empty() {
+        match Entry::decode(reader).map_err(|err| err.to_string())? {
+            Some(Entry::Declare { id, name }) => {
+                names.insert(id, name);
+            }
+            Some(Entry::Sample { id, sample }) => {
+                first.insert(id, sample);
+            }
+            None => break,
+        }
+    }
+    Ok((names, first))
+}
+
+impl Module for Replay {
+    type Config = ReplayConfig;
+
+    async fn try_build(cfg: &ReplayConfig, cancel_token: CancellationToken) -> Result<Self, IocBuildError> {
+        let speed = cfg.speed.unwrap_or(1.0);
+        if !(speed.is_finite() && speed > 0.0) {
+            return Err(IocBuildError::from_string(format!("replay speed must be greater than 0, got {}", speed)));
+        }
+        let mut reader = File::open(&cfg.path)
+            .map(BufReader::new)
+            .map_err(|err| IocBuildError::from_string(format!("unable to open recording {}: {}", cfg.path, err)))?;
+        let (names, mut first) = read_header(&mut reader)
+            .map_err(|err| IocBuildError::from_string(format!("unable to read recording {}: {}", cfg.path, err)))?;
+
+        let now = SampleInfo::now();
+        let mut inputs = HashMap::new();
+        let mut emitters = HashMap::new();
+        for (id, name) in names {
+            let name = match &cfg.node {
+                Some(node) => match name.split_once('.') {
+                    Some((prefix, port)) if prefix == node => port.to_string(),
+                    _ => continue,
+                },
+                None => name,
+            };
+            let Some(sample) = first.remove(&id) else {
+                warn!("recording {} has no samples of {}", cfg.path, name);
+                continue;
+            };
+            let sample = Sample {
+                value: sample.value,
+                info: SampleInfo { quality: sample.info.quality, ..now },
+            };
+            if let Some((input, emit)) = replay_kind(sample) {
+                inputs.insert(name, input);
+                emitters.insert(id, emit);
+            }
+        }
+        if inputs.is_empty() {
+            return Err(IocBuildError::from_string(format!(
+                "recording {} has no inputs to replay{}",
+                cfg.path,
+                cfg.node.as_ref().map(|node| format!(" from {}", node)).unwrap_or_default()
+            )));
+        }
+
+        //read the rest of the file in the background, ahead of replaying it
+        let (tx, mut rx) = mpsc::channel(256);
+        let path = cfg.path.clone();
+        tokio::task::spawn_blocking(move || loop {
+            match Entry::decode(&mut reader) {
+                Ok(Some(Entry::Sample { id, sample })) => {
+                    //fails once the replay is shut down
+                    if tx.blocking_send((id, sample)).is_err() {
+                        break;
+                    }
+                }
+                Ok(Some(Entry::Declare { .. })) => {}
+                Ok(None) => break,
+                Err(err) => {
+                    error!("error reading recording {}: {}", path, err);
+                    break;
+                }
+            }
+        });
+
+        let path = cfg.path.clone();
+        let join_handle = tokio::spawn(async move {
+            let start = Instant::now();
+            let start_info = SampleInfo::now();
+            let mut origin = None;
+            loop {
+                let (id, sample) = tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    next = rx.recv() => match next {
+                        Some(next) => next,
+                        None => {
+                            info!("done replaying {}", path);
+                            cancel_token.cancelled().await;
+                            break;
+                        }
+                    },
+                };
+                let Some(emit) = emitters.get(&id) else {
+                    continue;
+                };
+                let origin = *origin.get_or_insert(sample.info.time);
+                let secs = timestamp::secs_between(&origin, &sample.info.time) / speed;
+                let Ok(offset) = Duration::try_from_secs_f64(secs) else {
+                    error!("unable to replay {} at speed {}: offset of {}s is out of range", path, speed, secs);
+                    break;
+                };
+                tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    _ = sleep_until((start + offset).into()) => {}
+                }
+                let sample = Sample {
+                    value: sample.value,
+                    info: SampleInfo {
+                        time: start_info.time + offset,
+                        elapsed: start_info.elapsed + offset,
+                        quality: sample.info.quality,
+                    },
+                };
+                if !emit(sample) {
+                    emitters.remove(&id);
+                }
+            }
+            debug!("replay of {} shut down", path);
+        });
+
+        Ok(Replay { join_handle, inputs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Replay, ReplayConfig};
+    use crate::{
+        record::{Recorder, Rotation},
+        sample::Quality,
+        Input, InputKind, Module,
+    };
+    use std::time::{Duration, Instant};
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn test_record_replay() {
+        let dir = std::env::temp_dir().join(format!("ioc_replay_{}", std::process::id()));
+        let path = dir.join("drive.rec");
+        let (x, x_tx) = Input::new(0.0);
+        let (name, name_tx) = Input::new("a".to_string());
+        let (x, name) = (InputKind::Float(x), InputKind::String(name));
+        let inputs = vec![("imu.x".to_string(), &x), ("server.name".to_string(), &name)];
+        let rotation = Rotation { max_bytes: 1 << 20, max_files: 1 };
+        let recorder = Recorder::spawn(path.clone(), inputs, rotation, CancellationToken::new()).unwrap();
+        for i in 1..=3 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            x_tx.send(i as f64).unwrap();
+        }
+        drop((x_tx, name_tx));
+        recorder.await.unwrap();
+
+        //only the inputs of imu are replayed, without its key
+        let cfg = ReplayConfig {
+            path: path.to_string_lossy().to_string(),
+            node: Some("imu".to_string()),
+            speed: Some(2.0),
+        };
+        let cancel_token = CancellationToken::new();
+        let replay = Replay::try_build(&cfg, cancel_token.clone()).await.unwrap();
+        assert_eq!(replay.inputs.len(), 1);
+        let mut x = match replay.inputs.get("x") {
+            Some(InputKind::Float(x)) => x.sample_source(),
+            _ => panic!("expected a float input"),
+        };
+        assert_eq!(x.borrow_and_update().value, 0.0);
+
+        //values are replayed with their original spacing, twice as fast
+        let start = Instant::now();
+        for i in 1..=3 {
+            x.changed().await.unwrap();
+            let sample = x.borrow_and_update();
+            assert_eq!(sample.value, i as f64);
+            assert_eq!(sample.info.quality, Quality::Good);
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(15) && elapsed < Duration::from_millis(60), "{:?}", elapsed);
+
+        cancel_token.cancel();
+        replay.join_handle.await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_tiny_speed() {
+        let dir = std::env::temp_dir().join(format!("ioc_replay_tiny_{}", std::process::id()));
+        let path = dir.join("drive.rec");
+        let (x, x_tx) = Input::new(0.0);
+        let x = InputKind::Float(x);
+        let rotation = Rotation { max_bytes: 1 << 20, max_files: 1 };
+        let recorder = Recorder::spawn(path.clone(), vec![("x".to_string(), &x)], rotation, CancellationToken::new());
+        let recorder = recorder.unwrap();
+        for i in 1..=2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            x_tx.send(i as f64).unwrap();
+        }
+        drop(x_tx);
+        recorder.await.unwrap();
+
+        //the offsets at this speed overflow a Duration, which stops the replay instead of panicking
+        let cfg = ReplayConfig { path: path.to_string_lossy().to_string(), node: None, speed: Some(1e-300) };
+        let replay = Replay::try_build(&cfg, CancellationToken::new()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), replay.join_handle).await.unwrap().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
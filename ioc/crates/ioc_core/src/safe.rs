@@ -0,0 +1,203 @@
+//! `SafeOutput` drives an `Output` to a safe value, for example when the IOC shuts down or a node upstream of the output stops.
+
+use crate::{
+    error::IocBuildError,
+    relay::{write, Written},
+    sample::Sample,
+    Output, OutputKind, Value,
+};
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::sleep,
+};
+use tracing::debug;
+
+enum Command {
+    Engage,
+    Release,
+    Shutdown(oneshot::Sender<()>),
+}
+
+///Guards an `Output`. Values written to the guarded `Output` are forwarded until the guard is engaged.
+///
+/// While engaged, the safe value is written instead and values written are held back. The last one is forwarded once the guard is released.
+/// Engaging is counted, so the guard is only released once it has been released as many times as it was engaged.
+#[derive(Clone)]
+pub struct SafeOutput {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl SafeOutput {
+    ///Creates a guard for the given `Output`. Returns the guard and the guarded `Output`, which accepts samples if the given `Output` does.
+    pub fn new<T: Clone + Send + Sync + 'static>(output: Output<T>, safe_value: T) -> (Self, Output<T>) {
+        let (guarded, mut rx, mut samples_rx) = match output.samples_tx {
+            Some(_) => {
+                let (guarded, rx, samples_rx) = Output::new_sampled();
+                (guarded, rx, Some(samples_rx))
+            }
+            None => {
+                let (guarded, rx) = Output::new();
+                (guarded, rx, None)
+            }
+        };
+        let (commands, mut commands_rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let safe = Written::Value(safe_value);
+            let mut target = Some(output);
+            let mut engaged = 0usize;
+            let mut held: Option<Written<T>> = None;
+            loop {
+                let written = tokio::select! {
+                    biased;
+                    command = commands_rx.recv() => {
+                        match command {
+                            Some(Command::Engage) => {
+                                engaged += 1;
+                                if engaged == 1 {
+                                    write(&mut target, &safe).await;
+                                }
+                            }
+                            Some(Command::Release) => {
+                                engaged = engaged.saturating_sub(1);
+                                if let (0, Some(written)) = (engaged, held.take()) {
+                                    write(&mut target, &written).await;
+                                }
+                            }
+                            Some(Command::Shutdown(done)) => {
+                                write(&mut target, &safe).await;
+                                if let Some(output) = &target {
+                                    drained(output).await;
+                                }
+                                let _ = done.send(());
+                                break;
+                            }
+                            None => break,
+                        }
+                        continue;
+                    }
+                    //once the guarded output closes, only commands are handled
+                    Some(value) = rx.recv() => Written::Value(value),
+                    Some(sample) = recv_sample(&mut samples_rx) => Written::Sample(sample),
+                };
+                if engaged == 0 {
+                    write(&mut target, &written).await;
+                } else {
+                    held = Some(written);
+                }
+            }
+            debug!("safe output shutting down");
+        });
+        (Self { commands }, guarded)
+    }
+
+    ///Creates a guard for an output of any kind. Returns an error if the safe value doesn't match the output's type.
+    /// An `Int` safe value is accepted for a `Float` output.
+    pub fn for_kind(output: OutputKind, safe_value: &Value) -> Result<(Self, OutputKind), IocBuildError> {
+        let guarded = match (output, safe_value) {
+            (OutputKind::String(output), Value::String(value)) => {
+                let (guard, output) = Self::new(output, value.clone());
+                (guard, OutputKind::String(output))
+            }
+            (OutputKind::Binary(output), Value::Binary(value)) => {
+                let (guard, output) = Self::new(output, value.clone());
+                (guard, OutputKind::Binary(output))
+            }
+            (OutputKind::Float(output), Value::Float(value)) => {
+                let (guard, output) = Self::new(output, *value);
+                (guard, OutputKind::Float(output))
+            }
+            (OutputKind::Float(output), Value::Int(value)) => {
+                let (guard, output) = Self::new(output, *value as f64);
+                (guard, OutputKind::Float(output))
+            }
+            (OutputKind::Int(output), Value::Int(value)) => {
+                let (guard, output) = Self::new(output, *value);
+                (guard, OutputKind::Int(output))
+            }
+            (OutputKind::Bool(output), Value::Bool(value)) => {
+                let (guard, output) = Self::new(output, *value);
+                (guard, OutputKind::Bool(output))
+            }
+            (OutputKind::Timestamp(output), Value::Timestamp(value)) => {
+                let (guard, output) = Self::new(output, *value);
+                (guard, OutputKind::Timestamp(output))
+            }
+            (OutputKind::Array(output), Value::Array(value)) => {
+                let (guard, output) = Self::new(output, value.clone());
+                (guard, OutputKind::Array(output))
+            }
+            (OutputKind::Object(output), Value::Object(value)) => {
+                let (guard, output) = Self::new(output, value.clone());
+                (guard, OutputKind::Object(output))
+            }
+            (output, value) => {
+                return Err(IocBuildError::from_string(format!(
+                    "safe value {:?} doesn't match output of type {:?}",
+                    value, output
+                )))
+            }
+        };
+        Ok(guarded)
+    }
+
+    ///Writes the safe value and holds back anything else written until `release` is called.
+    pub fn engage(&self) {
+        let _ = self.commands.send(Command::Engage);
+    }
+
+    ///Undoes one call to `engage`.
+    pub fn release(&self) {
+        let _ = self.commands.send(Command::Release);
+    }
+
+    ///Writes the safe value, waits for it to be taken from the channel, and disconnects the output.
+    /// Returns early if the output is already disconnected.
+    pub async fn shutdown(&self) {
+        let (done, done_rx) = oneshot::channel();
+        if self.commands.send(Command::Shutdown(done)).is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+}
+
+async fn recv_sample<T>(samples_rx: &mut Option<mpsc::Receiver<Sample<T>>>) -> Option<Sample<T>> {
+    match samples_rx {
+        Some(samples_rx) => samples_rx.recv().await,
+        None => None,
+    }
+}
+
+//waits until whatever reads the output took the last value written
+async fn drained<T>(output: &Output<T>) {
+    while output.tx.capacity() < output.tx.max_capacity() && !output.tx.is_closed() {
+        sleep(Duration::from_millis(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SafeOutput;
+    use crate::Output;
+
+    #[tokio::test]
+    async fn test_safe_output() {
+        let (output, mut rx) = Output::new();
+        let (guard, guarded) = SafeOutput::new(output, 0.0);
+        guarded.tx.send(1.0).await.unwrap();
+        assert_eq!(rx.recv().await, Some(1.0));
+
+        //values are held back while engaged, and written on release
+        guard.engage();
+        assert_eq!(rx.recv().await, Some(0.0));
+        guarded.tx.send(2.0).await.unwrap();
+        guard.release();
+        assert_eq!(rx.recv().await, Some(2.0));
+
+        //shutdown returns once the safe value was taken
+        let (_, value) = tokio::join!(guard.shutdown(), rx.recv());
+        assert_eq!(value, Some(0.0));
+        assert_eq!(rx.recv().await, None);
+    }
+}
@@ -0,0 +1,169 @@
+//! Optional envelopes for values that carry the time they were sampled and a `Quality`.
+//!
+//! An `Input` created with `Input::new_sampled` emits plain values like any other `Input`, but also emits `Sample`s.
+//! Consumers that care about when a value was measured or whether it is any good can read samples with `Input::sample_source`.
+
+use crate::clock::Clock;
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch::{self, error::{RecvError, SendError}};
+
+///How trustworthy a sampled value is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Quality {
+    ///The value was read successfully.
+    Good,
+    ///The value was not refreshed when expected, or no value has been read yet. The value is the last one known.
+    Stale,
+    ///Reading the value failed. The value is the last one known.
+    Error,
+}
+
+///When a value was sampled and its quality.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleInfo {
+    pub time: SystemTime,
+    ///when it was sampled on the monotonic time of the clock it was sampled with (see `Clock::elapsed`), to measure the time between samples
+    pub elapsed: Duration,
+    pub quality: Quality,
+}
+
+impl SampleInfo {
+    ///A `Good` sample taken now.
+    pub fn now() -> Self {
+        Self::at(&Clock::real())
+    }
+
+    ///A `Good` sample taken now on the given clock.
+    pub fn at(clock: &Clock) -> Self {
+        Self {
+            time: clock.now(),
+            elapsed: clock.elapsed(),
+            quality: Quality::Good,
+        }
+    }
+}
+
+///A value with its `SampleInfo`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample<T> {
+    pub value: T,
+    pub info: SampleInfo,
+}
+
+impl<T> Sample<T> {
+    ///A `Good` sample of the given value, taken now.
+    pub fn now(value: T) -> Self {
+        Self {
+            value,
+            info: SampleInfo::now(),
+        }
+    }
+
+    ///Applies a function to the value, keeping the same `SampleInfo`.
+    pub fn map<O, F: FnOnce(T) -> O>(self, function: F) -> Sample<O> {
+        Sample {
+            value: function(self.value),
+            info: self.info,
+        }
+    }
+}
+
+///Sends values and samples to an `Input` created with `Input::new_sampled`.
+pub struct SampleSender<T> {
+    pub(crate) value_tx: watch::Sender<T>,
+    pub(crate) sample_tx: watch::Sender<Sample<T>>,
+}
+
+impl<T: Clone> SampleSender<T> {
+    ///Sends a new sample. The value is also sent to consumers of plain values.
+    pub fn send(&self, sample: Sample<T>) -> Result<(), SendError<Sample<T>>> {
+        let value = sample.value.clone();
+        self.sample_tx.send(sample)?;
+        self.value_tx.send(value).map_err(|SendError(value)| SendError(Sample::now(value)))
+    }
+
+    ///Changes the quality of the last sample without changing its value, for example when a read fails.
+    /// Consumers of plain values are not notified.
+    pub fn mark(&self, quality: Quality) {
+        self.sample_tx.send_modify(|sample| sample.info = SampleInfo { quality, ..SampleInfo::now() });
+    }
+}
+
+///Reads `Sample`s from an `Input`. If the `Input` does not provide samples, values are stamped with the time they were read and `Quality::Good`.
+pub struct SampleSource<T> {
+    inner: SampleSourceInner<T>,
+    clock: Clock,
+}
+
+enum SampleSourceInner<T> {
+    Sampled(watch::Receiver<Sample<T>>),
+    Plain(watch::Receiver<T>),
+}
+
+impl<T: Clone> SampleSource<T> {
+    pub(crate) fn sampled(rx: watch::Receiver<Sample<T>>) -> Self {
+        Self {
+            inner: SampleSourceInner::Sampled(rx),
+            clock: Clock::real(),
+        }
+    }
+
+    pub(crate) fn plain(rx: watch::Receiver<T>) -> Self {
+        Self {
+            inner: SampleSourceInner::Plain(rx),
+            clock: Clock::real(),
+        }
+    }
+
+    ///Stamps values of an `Input` that doesn't provide samples with the time on the given clock instead of the system time.
+    pub fn clocked(mut self, clock: &Clock) -> Self {
+        self.clock = clock.clone();
+        self
+    }
+
+    ///Waits for a new sample. See `watch::Receiver::changed`.
+    pub async fn changed(&mut self) -> Result<(), RecvError> {
+        match &mut self.inner {
+            SampleSourceInner::Sampled(rx) => rx.changed().await,
+            SampleSourceInner::Plain(rx) => rx.changed().await,
+        }
+    }
+
+    ///Returns the latest sample and marks it as seen.
+    pub fn borrow_and_update(&mut self) -> Sample<T> {
+        match &mut self.inner {
+            SampleSourceInner::Sampled(rx) => rx.borrow_and_update().clone(),
+            SampleSourceInner::Plain(rx) => Sample {
+                value: rx.borrow_and_update().clone(),
+                info: SampleInfo::at(&self.clock),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Quality, Sample};
+    use crate::Input;
+
+    #[test]
+    fn test_sampled_input() {
+        let (input, tx) = Input::new_sampled(Sample::now(1.0));
+        let mut values = input.source();
+        let mut samples = input.sample_source();
+
+        tx.send(Sample::now(2.0)).unwrap();
+        assert_eq!(*values.borrow_and_update(), 2.0);
+        let sample = samples.borrow_and_update();
+        assert_eq!(sample.value, 2.0);
+        assert_eq!(sample.info.quality, Quality::Good);
+
+        //marking keeps the value and doesn't notify plain consumers
+        tx.mark(Quality::Error);
+        assert!(!values.has_changed().unwrap());
+        let sample = samples.borrow_and_update();
+        assert_eq!(sample.value, 2.0);
+        assert_eq!(sample.info.quality, Quality::Error);
+    }
+}
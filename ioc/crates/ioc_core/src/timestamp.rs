@@ -0,0 +1,61 @@
+//! Helpers for `Timestamp` values. Timestamps are represented as `SystemTime` and are exchanged as (fractional) seconds since the unix epoch.
+
+use serde::{de::Error, Deserialize, Deserializer};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+///Converts a timestamp to seconds since the unix epoch. Times before the epoch are negative.
+pub fn to_secs(timestamp: &SystemTime) -> f64 {
+    match timestamp.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs_f64(),
+        Err(err) => -err.duration().as_secs_f64(),
+    }
+}
+
+///Converts seconds since the unix epoch to a timestamp. None for values that aren't finite or are too far from the epoch for a `SystemTime`.
+pub fn from_secs(secs: f64) -> Option<SystemTime> {
+    if secs >= 0.0 {
+        UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(secs).ok()?)
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::try_from_secs_f64(-secs).ok()?)
+    }
+}
+
+///Seconds from `earlier` to `later`. Zero if `later` is before `earlier`, e.g. for samples that arrive out of order.
+pub fn secs_between(earlier: &SystemTime, later: &SystemTime) -> f64 {
+    later
+        .duration_since(*earlier)
+        .map(|since| since.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+///For use with `#[serde(deserialize_with = "...")]`. Reads a timestamp from seconds since the unix epoch.
+pub fn deserialize_secs<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let secs = f64::deserialize(deserializer)?;
+    from_secs(secs).ok_or_else(|| D::Error::custom(format!("{} is not a valid timestamp", secs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deserialize_secs, from_secs, to_secs};
+
+    #[test]
+    fn test_secs_round_trip() {
+        for secs in [0.0, 1.5, -2.25, 1_700_000_000.125] {
+            assert_eq!(to_secs(&from_secs(secs).unwrap()), secs);
+        }
+        for secs in [f64::NAN, f64::INFINITY, 1.0e300, -1.0e300] {
+            assert_eq!(from_secs(secs), None);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_secs() {
+        let mut deserializer = serde_json::Deserializer::from_str("1.5");
+        assert_eq!(to_secs(&deserialize_secs(&mut deserializer).unwrap()), 1.5);
+        let mut deserializer = serde_json::Deserializer::from_str("1.0e300");
+        assert!(deserialize_secs(&mut deserializer).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Sharing I2C buses between devices.
+//!
+//! Several devices are usually connected to the same bus. An `I2cBusManager` opens each bus once and hands out
+//! `SharedI2c` handles to it, which take turns using the bus one transaction at a time.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use embedded_hal::i2c::{self, Operation};
+use embedded_hal_0::blocking::i2c as i2c_0;
+
+use crate::error::DeviceConfigError;
+
+///The bus devices use if their config doesn't name one. On a Raspberry Pi that's the bus on the GPIO header.
+pub const DEFAULT_I2C_BUS: u8 = 1;
+
+pub fn default_i2c_bus() -> u8 {
+    DEFAULT_I2C_BUS
+}
+
+///A handle to a bus that other devices may use too. Each transaction has the bus to itself.
+pub struct SharedI2c<B> {
+    bus: Arc<Mutex<B>>,
+}
+
+impl<B> Clone for SharedI2c<B> {
+    fn clone(&self) -> Self {
+        Self { bus: self.bus.clone() }
+    }
+}
+
+impl<B> SharedI2c<B> {
+    pub fn new(bus: B) -> Self {
+        Self {
+            bus: Arc::new(Mutex::new(bus)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, B> {
+        match self.bus.lock() {
+            Ok(bus) => bus,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl<B: i2c::I2c> i2c::ErrorType for SharedI2c<B> {
+    type Error = B::Error;
+}
+
+impl<B: i2c::I2c> i2c::I2c for SharedI2c<B> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), B::Error> {
+        self.lock().transaction(address, operations)
+    }
+}
+
+impl<B: i2c_0::Write> i2c_0::Write for SharedI2c<B> {
+    type Error = B::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), B::Error> {
+        self.lock().write(address, bytes)
+    }
+}
+
+impl<B: i2c_0::WriteRead> i2c_0::WriteRead for SharedI2c<B> {
+    type Error = B::Error;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), B::Error> {
+        self.lock().write_read(address, bytes, buffer)
+    }
+}
+
+type Open<B> = Box<dyn Fn(u8) -> Result<B, String> + Send>;
+
+///Opens each bus the first time a device asks for it, and shares it with every device that asks for it after.
+pub struct I2cBusManager<B> {
+    open: Open<B>,
+    buses: HashMap<u8, SharedI2c<B>>,
+}
+
+impl<B> I2cBusManager<B> {
+    ///Opens buses with the given function, which returns an error message if a bus isn't available.
+    pub fn new(open: impl Fn(u8) -> Result<B, String> + Send + 'static) -> Self {
+        Self {
+            open: Box::new(open),
+            buses: HashMap::new(),
+        }
+    }
+
+    ///A handle to the bus with the given number. Fails if the bus can't be opened; the next call tries again.
+    pub fn bus(&mut self, bus: u8) -> Result<SharedI2c<B>, DeviceConfigError> {
+        if let Some(shared) = self.buses.get(&bus) {
+            return Ok(shared.clone());
+        }
+        let shared = (self.open)(bus).map(SharedI2c::new).map_err(DeviceConfigError::new)?;
+        self.buses.insert(bus, shared.clone());
+        Ok(shared)
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::I2cBusManager;
+    use crate::mock::{pca9685::Pca9685Model, MockI2c};
+    use embedded_hal::i2c::I2c;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn test_bus_manager() {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let mut manager = I2cBusManager::new(move |bus| match bus {
+            1 => {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(MockI2c::new())
+            }
+            _ => Err(format!("no i2c bus {}", bus)),
+        });
+
+        //both handles use the bus that was opened once
+        let mut first = manager.bus(1).unwrap();
+        let mut second = manager.bus(1).unwrap();
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+        let pwm = first.lock().attach(&[0x40], Pca9685Model::default());
+        second.write(0x40, &[0x00, 0x01]).unwrap();
+        assert!(!pwm.lock().unwrap().is_sleeping());
+        let mut buffer = [0u8];
+        first.write_read(0x40, &[0x00], &mut buffer).unwrap();
+        assert_eq!(buffer, [0x01]);
+
+        let err = manager.bus(0).err().unwrap();
+        assert_eq!(err.message, "no i2c bus 0");
+    }
+}
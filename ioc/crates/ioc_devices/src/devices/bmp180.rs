@@ -1,13 +1,17 @@
 use std::{collections::HashMap, time::Duration};
 
 use embedded_hal::i2c;
+use ioc_core::sample::{Quality, Sample, SampleInfo, SampleSender};
 use ioc_core::{error::IocBuildError, Input, InputKind, ModuleBuilder, ModuleIO};
 use serde::Deserialize;
-use tokio::{sync::watch, task::JoinHandle, time::sleep};
+use schemars::JsonSchema;
+use tokio::{task::JoinHandle, time::sleep};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
-#[derive(Deserialize, Clone, Copy, Debug)]
+use crate::bus::{default_i2c_bus, DEFAULT_I2C_BUS};
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug)]
 pub enum PressurePrecision {
     UltraLowPower,
     Standard,
@@ -15,8 +19,10 @@ pub enum PressurePrecision {
     UltraHighResolution,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct Bmp180DeviceConfig {
+    #[serde(default = "default_i2c_bus")]
+    pub i2c_bus: u8,
     pressure_precision: PressurePrecision,
     period_ms: u64,
 }
@@ -24,6 +30,7 @@ pub struct Bmp180DeviceConfig {
 impl Default for Bmp180DeviceConfig {
     fn default() -> Self {
         Self {
+            i2c_bus: DEFAULT_I2C_BUS,
             pressure_precision: PressurePrecision::Standard,
             period_ms: 1000,
         }
@@ -58,7 +65,7 @@ impl From<Bmp180Device> for ModuleIO {
 pub struct Bmp180DeviceBuilder<I2C, F>
 where
     I2C: i2c::I2c + Send + 'static,
-    F: Fn(u8) -> I2C,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     i2c_bus_provider: F,
 }
@@ -66,7 +73,7 @@ where
 impl<I2C, F> Bmp180DeviceBuilder<I2C, F>
 where
     I2C: i2c::I2c + Send + 'static,
-    F: Fn(u8) -> I2C,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     pub fn new(i2c_bus_provider: F) -> Self {
         Bmp180DeviceBuilder { i2c_bus_provider }
@@ -76,13 +83,13 @@ where
 impl<I2C, F> ModuleBuilder for Bmp180DeviceBuilder<I2C, F>
 where
     I2C: i2c::I2c + Send + 'static,
-    F: Fn(u8) -> I2C,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     type Config = Bmp180DeviceConfig;
     type Module = Bmp180Device;
 
     async fn try_build(&self, cfg: &Bmp180DeviceConfig, cancel_token: CancellationToken) -> Result<Bmp180Device, IocBuildError> {
-        Bmp180Device::build(cfg, (self.i2c_bus_provider)(1), cancel_token)
+        Bmp180Device::build(cfg, (self.i2c_bus_provider)(cfg.i2c_bus)?, cancel_token)
     }
 }
 
@@ -160,7 +167,7 @@ impl Bmp180CalibrationData {
         let t_tenths = (b5 + 8) / (1 << 4);
         TempResult {
             temp_c: (t_tenths as f64) / 10.0,
-            b5: b5,
+            b5,
         }
     }
 
@@ -176,12 +183,11 @@ impl Bmp180CalibrationData {
         let x3 = ((x1 + x2) + 2) / (1 << 2);
         let b4 = (self.ac4 as u64) * ((x3 + 32768) as u64) / (1 << 15);
         let b7 = ((up - b3) as u64) * (50000 >> oss);
-        let p: i64;
-        if b7 < 0x80000000 {
-            p = (b7 * 2 / b4) as i64;
+        let p: i64 = if b7 < 0x80000000 {
+            (b7 * 2 / b4) as i64
         } else {
-            p = (b7 / b4 * 2) as i64;
-        }
+            (b7 / b4 * 2) as i64
+        };
         let x1 = p / (1 << 8);
         let x1 = x1 * x1;
         let x1 = (x1 * 3038) / (1 << 16);
@@ -192,9 +198,30 @@ impl Bmp180CalibrationData {
     }
 }
 
+///Reads the uncompensated temperature.
+async fn read_ut<I2C: i2c::I2c>(i2c: &mut I2C) -> Result<i32, I2C::Error> {
+    let mut buffer: [u8; 2] = [0u8; 2];
+    i2c.write(I2C_ADDRESS, &[CONTROL_REGISTER, MEASURE_TEMPERATURE])?;
+    sleep(TEMP_WAIT).await;
+    i2c.write_read(I2C_ADDRESS, &[OUT_MSB_REGISTER], &mut buffer)?;
+    Ok(((buffer[0] as i32) << 8) | (buffer[1] as i32))
+}
+
+///Reads the uncompensated pressure.
+async fn read_up<I2C: i2c::I2c>(i2c: &mut I2C, oss: u8, press_cmd: u8, press_wait: Duration) -> Result<i32, I2C::Error> {
+    i2c.write(I2C_ADDRESS, &[CONTROL_REGISTER, press_cmd])?;
+    sleep(press_wait).await;
+
+    //the xlsb is only read at the highest oversampling
+    let mut buffer = [0u8; 3];
+    let len = if oss == 3 { 3 } else { 2 };
+    i2c.write_read(I2C_ADDRESS, &[OUT_MSB_REGISTER], &mut buffer[..len])?;
+    Ok((((buffer[0] as i32) << 16) | ((buffer[1] as i32) << 8) | (buffer[2] as i32)) >> (8 - oss))
+}
+
 fn spawn_sensor_read_task<I2C>(
-    temp_tx: watch::Sender<f64>,
-    press_tx: watch::Sender<f64>,
+    temp_tx: SampleSender<f64>,
+    press_tx: SampleSender<f64>,
     mut i2c: I2C,
     calib: Bmp180CalibrationData,
     pressure_precision: PressurePrecision,
@@ -212,50 +239,47 @@ where
     };
     let task = tokio::spawn(async move {
         loop {
-            let mut buffer: [u8; 2] = [0u8; 2];
-
-            i2c.write(I2C_ADDRESS, &[CONTROL_REGISTER, MEASURE_TEMPERATURE])
-                .unwrap();
-            sleep(TEMP_WAIT).await;
-            i2c.write_read(I2C_ADDRESS, &[OUT_MSB_REGISTER], &mut buffer)
-                .unwrap();
-            let ut = ((buffer[0] as i32) << 8) | (buffer[1] as i32);
-
-            let temp_res = calib.calc_temperature_c(ut);
-            if let Err(err) = temp_tx.send(temp_res.temp_c) {
-                error!(
-                    "error sending temperature data. shutting down bmp180 task. {:?}",
-                    err
-                );
-                break;
-            }
-
-            i2c.write(I2C_ADDRESS, &[CONTROL_REGISTER, press_cmd])
-                .unwrap();
-            sleep(press_wait).await;
-
-            if oss == 3 {
-                buffer = [0u8, 3];
-            } else {
-                buffer = [0u8, 2];
-            }
-            i2c.write_read(I2C_ADDRESS, &[OUT_MSB_REGISTER], &mut buffer)
-                .unwrap();
-            let up: i32;
-            if oss == 3 {
-                up = (((buffer[0] as i32) << 16) | ((buffer[1] as i32) << 8) | (buffer[2] as i32))
-                    >> (8 - oss);
-            } else {
-                up = (((buffer[0] as i32) << 16) | ((buffer[1] as i32) << 8)) >> (8 - oss);
-            }
+            //on read errors keep the last values, but flag them so consumers can tell
+            let ut = match read_ut(&mut i2c).await {
+                Ok(ut) => Some(ut),
+                Err(err) => {
+                    warn!("error reading bmp180 temperature! {:?}", err);
+                    temp_tx.mark(Quality::Error);
+                    //pressure compensation depends on the temperature
+                    press_tx.mark(Quality::Error);
+                    None
+                }
+            };
 
-            let press = calib.calc_pressure_h_pa(temp_res.b5, oss, up);
-            if let Err(err) = press_tx.send(press) {
-                error!(
-                    "error sending pressure data. shutting down bmp180 task. {:?}",
-                    err
-                );
-                break;
+            if let Some(ut) = ut {
+                let temp_res = calib.calc_temperature_c(ut);
+                if let Err(err) = temp_tx.send(Sample::now(temp_res.temp_c)) {
+                    error!(
+                        "error sending temperature data. shutting down bmp180 task. {:?}",
+                        err
+                    );
+                    break;
+                }
+
+                let up = match read_up(&mut i2c, oss, press_cmd, press_wait).await {
+                    Ok(up) => Some(up),
+                    Err(err) => {
+                        warn!("error reading bmp180 pressure! {:?}", err);
+                        press_tx.mark(Quality::Error);
+                        None
+                    }
+                };
+
+                if let Some(up) = up {
+                    let press = calib.calc_pressure_h_pa(temp_res.b5, oss, up);
+                    if let Err(err) = press_tx.send(Sample::now(press)) {
+                        error!(
+                            "error sending pressure data. shutting down bmp180 task. {:?}",
+                            err
+                        );
+                        break;
+                    }
+                }
             }
 
             let delay: tokio::time::Sleep = sleep(Duration::from_millis(period_ms));
@@ -291,8 +315,13 @@ impl Bmp180Device {
                 .unwrap();
             let calib = Bmp180CalibrationData::from_bytes(&buffer)?;
 
-            let (temp, temp_tx) = Input::new(f64::NAN);
-            let (press, press_tx) = Input::new(f64::NAN);
+            //no reading yet
+            let start = Sample {
+                value: f64::NAN,
+                info: SampleInfo { quality: Quality::Stale, ..SampleInfo::now() },
+            };
+            let (temp, temp_tx) = Input::new_sampled(start.clone());
+            let (press, press_tx) = Input::new_sampled(start);
 
             let join_handle = spawn_sensor_read_task(
                 temp_tx,
@@ -312,3 +341,43 @@ impl Bmp180Device {
         }
     }
 }
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::{Bmp180Device, Bmp180DeviceConfig, PressurePrecision};
+    use crate::mock::{bmp180::{Bmp180Model, ADDRESS, DATASHEET_CALIBRATION}, MockI2c};
+    use ioc_core::sample::Quality;
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn test_bmp180_datasheet_example() {
+        //the worked example from the datasheet, read with oversampling 0
+        let bus = MockI2c::new();
+        bus.attach(&[ADDRESS], Bmp180Model::new(DATASHEET_CALIBRATION, 27898, 23843 << 3));
+        let config = Bmp180DeviceConfig {
+            pressure_precision: PressurePrecision::UltraLowPower,
+            period_ms: 10,
+            ..Default::default()
+        };
+        let cancel_token = CancellationToken::new();
+        let device = Bmp180Device::build(&config, bus, cancel_token.clone()).unwrap();
+
+        let mut pressure = device.pressure_h_pa.sample_source();
+        pressure.changed().await.unwrap();
+        assert_eq!(device.temperature_c.sample_source().borrow_and_update().value, 15.0);
+        let sample = pressure.borrow_and_update();
+        assert!((sample.value - 699.64).abs() <= 0.01, "{}", sample.value);
+        assert_eq!(sample.info.quality, Quality::Good);
+
+        cancel_token.cancel();
+        device.join_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bmp180_wrong_id() {
+        //a chip that answers at the address, but isn't a BMP180
+        let bus = MockI2c::new();
+        bus.attach(&[ADDRESS], crate::mock::l3gd20::L3gd20Model::default());
+        assert!(Bmp180Device::build(&Bmp180DeviceConfig::default(), bus, CancellationToken::new()).is_err());
+    }
+}
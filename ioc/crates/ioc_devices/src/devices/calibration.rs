@@ -0,0 +1,121 @@
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+
+use crate::error::DeviceConfigError;
+
+fn identity() -> [[f64; 3]; 3] {
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+}
+
+///Corrects the x, y and z readings of a 3 axis sensor: the offset is subtracted, then the result is multiplied by the matrix.
+/// The offset is in the units of the outputs. The matrix corrects the scale of each axis and the misalignment between them.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct AxisCalibration {
+    #[serde(default)]
+    pub offset: [f64; 3],
+    ///Rows of the matrix. Defaults to the identity.
+    #[serde(default = "identity")]
+    pub matrix: [[f64; 3]; 3],
+}
+
+impl Default for AxisCalibration {
+    fn default() -> Self {
+        Self {
+            offset: [0.0; 3],
+            matrix: identity(),
+        }
+    }
+}
+
+impl AxisCalibration {
+    pub fn apply(&self, reading: [f64; 3]) -> [f64; 3] {
+        let centered = [
+            reading[0] - self.offset[0],
+            reading[1] - self.offset[1],
+            reading[2] - self.offset[2],
+        ];
+        self.matrix.map(|row| row[0] * centered[0] + row[1] * centered[1] + row[2] * centered[2])
+    }
+}
+
+///The calibrations of an IMU's sensors, as the `ImuCalibration` transformer saves them. Devices given the file use
+/// the calibrations in it in place of the ones in their config.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CalibrationFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gyro: Option<AxisCalibration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accel: Option<AxisCalibration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mag: Option<AxisCalibration>,
+}
+
+impl CalibrationFile {
+    ///Reads the file at the path, which is JSON. Returns None if there is no file yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<CalibrationFile>, DeviceConfigError> {
+        let path = path.as_ref();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(DeviceConfigError::new(format!("unable to read calibration file {}: {}", path.display(), err)))
+            }
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|err| DeviceConfigError::new(format!("invalid calibration file {}: {}", path.display(), err)))
+    }
+
+    ///Writes the file to the path, replacing what was there. The old file stays intact if writing fails.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temp, path)
+    }
+}
+
+///Returns the calibration for a sensor, from the calibration file if one is given and has it, otherwise from the config.
+pub(crate) fn choose(
+    file: Option<&String>,
+    sensor: fn(CalibrationFile) -> Option<AxisCalibration>,
+    configured: &AxisCalibration,
+) -> Result<AxisCalibration, DeviceConfigError> {
+    let from_file = match file {
+        Some(path) => CalibrationFile::load(path)?.and_then(sensor),
+        None => None,
+    };
+    Ok(from_file.unwrap_or_else(|| configured.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AxisCalibration, CalibrationFile};
+
+    #[test]
+    fn test_calibration_file() {
+        let path = std::env::temp_dir().join(format!("ioc_calibration_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(CalibrationFile::load(&path).unwrap(), None);
+
+        let mag = AxisCalibration {
+            offset: [10.0, -4.0, 0.5],
+            matrix: [[1.1, 0.0, 0.0], [0.0, 0.9, 0.0], [0.0, 0.0, 1.0]],
+        };
+        let file = CalibrationFile {
+            mag: Some(mag.clone()),
+            ..Default::default()
+        };
+        file.save(&path).unwrap();
+        assert_eq!(CalibrationFile::load(&path).unwrap(), Some(file));
+        let corrected = mag.apply([20.0, 6.0, 0.5]);
+        assert!((corrected[0] - 11.0).abs() < 1e-9 && (corrected[1] - 9.0).abs() < 1e-9 && corrected[2] == 0.0);
+
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(CalibrationFile::load(&path).unwrap_err().message.contains("invalid calibration file"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
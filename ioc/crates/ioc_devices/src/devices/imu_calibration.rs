@@ -0,0 +1,344 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use ioc_core::{error::IocBuildError, Input, InputKind, Transformer, TransformerI, Value};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+use super::calibration::{AxisCalibration, CalibrationFile};
+
+pub const DEFAULT_GYRO_SAMPLES: usize = 200;
+pub const DEFAULT_MAG_SAMPLES: usize = 500;
+//how much the gyro may wobble while it's held still, in radians per second. about 3 degrees per second
+const MAX_GYRO_STD_DEV: f64 = 0.05;
+//magnetometer axes that turned through less than this part of the widest one are left uncorrected
+const MIN_MAG_SPAN: f64 = 0.25;
+
+///A sensor's raw readings, and the input that starts calibrating it.
+pub type SensorInputs<'a> = (&'a Input<Vec<Value>>, &'a Input<bool>);
+
+pub struct ImuCalibrationConfig<'a> {
+    ///Where the calibrations are saved. Calibrations of sensors that weren't calibrated are kept.
+    pub file: PathBuf,
+    ///The gyro's raw readings, collected while it's held still once start_gyro turns true.
+    pub gyro: Option<SensorInputs<'a>>,
+    pub gyro_samples: usize,
+    ///The magnetometer's raw readings, collected while it's turned through every orientation once start_mag turns true.
+    pub magnetometer: Option<SensorInputs<'a>>,
+    pub mag_samples: usize,
+}
+
+///Collects readings of an IMU's sensors, computes the gyro's bias and the magnetometer's hard and soft iron corrections,
+/// and saves them to a calibration file. Tells what it's doing with the `state` input, and how far along it is with `progress`.
+pub struct ImuCalibration {
+    pub join_handle: JoinHandle<()>,
+    pub state: Input<String>,
+    pub progress: Input<f64>,
+}
+
+impl From<ImuCalibration> for TransformerI {
+    fn from(calibration: ImuCalibration) -> Self {
+        TransformerI {
+            join_handle: calibration.join_handle,
+            inputs: HashMap::from([
+                ("state".to_string(), InputKind::String(calibration.state)),
+                ("progress".to_string(), InputKind::Float(calibration.progress)),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sensor {
+    Gyro,
+    Mag,
+}
+
+impl Sensor {
+    fn name(&self) -> &'static str {
+        match self {
+            Sensor::Gyro => "gyro",
+            Sensor::Mag => "magnetometer",
+        }
+    }
+}
+
+struct Calibrator {
+    file: PathBuf,
+    gyro_samples: usize,
+    mag_samples: usize,
+    collecting: Option<(Sensor, Vec<[f64; 3]>)>,
+    state_tx: watch::Sender<String>,
+    progress_tx: watch::Sender<f64>,
+}
+
+impl Calibrator {
+    fn start(&mut self, sensor: Sensor) {
+        let state = match sensor {
+            Sensor::Gyro => "collecting gyro readings. hold the imu still",
+            Sensor::Mag => "collecting magnetometer readings. turn the imu through every orientation",
+        };
+        info!("imu calibration: {}", state);
+        self.collecting = Some((sensor, Vec::new()));
+        let _ = self.state_tx.send(state.to_string());
+        let _ = self.progress_tx.send(0.0);
+    }
+
+    fn add(&mut self, sensor: Sensor, reading: &[Value]) {
+        let Some((collecting, samples)) = &mut self.collecting else {
+            return;
+        };
+        let (Some(reading), true) = (xyz(reading), *collecting == sensor) else {
+            return;
+        };
+        samples.push(reading);
+        let wanted = match sensor {
+            Sensor::Gyro => self.gyro_samples,
+            Sensor::Mag => self.mag_samples,
+        };
+        let _ = self.progress_tx.send(samples.len() as f64 / wanted as f64);
+        if samples.len() >= wanted {
+            let result = match sensor {
+                Sensor::Gyro => gyro_bias(samples),
+                Sensor::Mag => mag_iron(samples),
+            };
+            let state = match result.and_then(|calibration| self.save(sensor, calibration)) {
+                Ok(_) => format!("saved the {} calibration to {}. restart to use it", sensor.name(), self.file.display()),
+                Err(err) => format!("{} calibration failed: {}", sensor.name(), err),
+            };
+            info!("imu calibration: {}", state);
+            self.collecting = None;
+            let _ = self.state_tx.send(state);
+        }
+    }
+
+    //replaces the sensor's calibration in the file, keeping the others
+    fn save(&self, sensor: Sensor, calibration: AxisCalibration) -> Result<(), String> {
+        let mut file = CalibrationFile::load(&self.file).map_err(|err| err.message)?.unwrap_or_default();
+        match sensor {
+            Sensor::Gyro => file.gyro = Some(calibration),
+            Sensor::Mag => file.mag = Some(calibration),
+        }
+        file.save(&self.file)
+            .map_err(|err| format!("unable to write {}: {}", self.file.display(), err))
+    }
+}
+
+fn xyz(reading: &[Value]) -> Option<[f64; 3]> {
+    match reading {
+        [x, y, z] => Some([x.as_f64()?, y.as_f64()?, z.as_f64()?]),
+        _ => None,
+    }
+}
+
+//waits for the next value of an input that may not be configured, forever if it isn't. None once it's closed
+async fn next<T: Clone>(rx: &mut Option<watch::Receiver<T>>) -> Option<T> {
+    match rx {
+        Some(rx) => match rx.changed().await {
+            Ok(_) => Some(rx.borrow_and_update().clone()),
+            Err(_) => None,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+impl<'a> Transformer<'a> for ImuCalibration {
+    type Config = ImuCalibrationConfig<'a>;
+
+    async fn try_build(cfg: &ImuCalibrationConfig<'a>) -> Result<ImuCalibration, IocBuildError> {
+        if cfg.gyro_samples < 2 || cfg.mag_samples < 2 {
+            return Err(IocBuildError::message("imu calibration needs at least 2 samples"));
+        }
+        let mut gyro = cfg.gyro.map(|(gyro, _)| gyro.source());
+        let mut start_gyro = cfg.gyro.map(|(_, start)| start.source());
+        let mut mag = cfg.magnetometer.map(|(mag, _)| mag.source());
+        let mut start_mag = cfg.magnetometer.map(|(_, start)| start.source());
+
+        let (state, state_tx) = Input::new("idle".to_string());
+        let (progress, progress_tx) = Input::new(0.0);
+        let mut calibrator = Calibrator {
+            file: cfg.file.clone(),
+            gyro_samples: cfg.gyro_samples,
+            mag_samples: cfg.mag_samples,
+            collecting: None,
+            state_tx,
+            progress_tx,
+        };
+
+        let join_handle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    start = next(&mut start_gyro) => match start {
+                        Some(true) => calibrator.start(Sensor::Gyro),
+                        Some(false) => {}
+                        None => break,
+                    },
+                    start = next(&mut start_mag) => match start {
+                        Some(true) => calibrator.start(Sensor::Mag),
+                        Some(false) => {}
+                        None => break,
+                    },
+                    reading = next(&mut gyro) => match reading {
+                        Some(reading) => calibrator.add(Sensor::Gyro, &reading),
+                        None => break,
+                    },
+                    reading = next(&mut mag) => match reading {
+                        Some(reading) => calibrator.add(Sensor::Mag, &reading),
+                        None => break,
+                    },
+                }
+            }
+            if calibrator.collecting.is_some() {
+                warn!("imu calibration stopped before it collected enough readings");
+            }
+            debug!("imu calibration shut down");
+        });
+
+        Ok(ImuCalibration {
+            join_handle,
+            state,
+            progress,
+        })
+    }
+}
+
+///The gyro's bias, the average of readings taken while it was held still. Fails if it moved.
+pub fn gyro_bias(samples: &[[f64; 3]]) -> Result<AxisCalibration, String> {
+    let n = samples.len() as f64;
+    let mean = [0, 1, 2].map(|axis| samples.iter().map(|sample| sample[axis]).sum::<f64>() / n);
+    let std_dev = [0, 1, 2].map(|axis| {
+        (samples.iter().map(|sample| (sample[axis] - mean[axis]).powi(2)).sum::<f64>() / n).sqrt()
+    });
+    if std_dev.iter().any(|std_dev| *std_dev > MAX_GYRO_STD_DEV) {
+        return Err("the gyro moved while it was calibrated. hold it still".to_string());
+    }
+    Ok(AxisCalibration {
+        offset: mean,
+        ..Default::default()
+    })
+}
+
+///Hard and soft iron corrections for readings taken while the magnetometer was turned through every orientation.
+/// The offset centers each axis between its extremes, and the matrix scales the axes to the same range.
+/// Axes that barely turned, like the vertical one of a robot that was only turned around, are left uncorrected.
+pub fn mag_iron(samples: &[[f64; 3]]) -> Result<AxisCalibration, String> {
+    let extremes = [0, 1, 2].map(|axis| {
+        samples
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), sample| (min.min(sample[axis]), max.max(sample[axis])))
+    });
+    let spans = extremes.map(|(min, max)| max - min);
+    let widest = spans.iter().cloned().fold(0.0, f64::max);
+    if widest <= 0.0 {
+        return Err("the magnetometer readings didn't change. turn it through every orientation".to_string());
+    }
+    let turned = spans.map(|span| span >= MIN_MAG_SPAN * widest);
+    let turned_spans: Vec<f64> = spans.iter().zip(turned).filter(|(_, turned)| *turned).map(|(span, _)| *span).collect();
+    let mean_span = turned_spans.iter().sum::<f64>() / turned_spans.len() as f64;
+
+    let mut calibration = AxisCalibration::default();
+    for axis in 0..3 {
+        if turned[axis] {
+            let (min, max) = extremes[axis];
+            calibration.offset[axis] = (min + max) / 2.0;
+            calibration.matrix[axis][axis] = mean_span / spans[axis];
+        }
+    }
+    Ok(calibration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gyro_bias, mag_iron, ImuCalibration, ImuCalibrationConfig};
+    use crate::devices::calibration::CalibrationFile;
+    use ioc_core::{Input, Transformer, Value};
+
+    //points on a sphere of the radius, every 15 degrees of latitude and longitude
+    fn sphere(radius: f64) -> Vec<[f64; 3]> {
+        let mut points = Vec::new();
+        for lat in (-90..=90).step_by(15) {
+            for lon in (0..360).step_by(15) {
+                let (lat, lon) = ((lat as f64).to_radians(), (lon as f64).to_radians());
+                points.push([radius * lat.cos() * lon.cos(), radius * lat.cos() * lon.sin(), radius * lat.sin()]);
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn test_mag_iron() {
+        //a field of 50uT, stretched and shifted by the robot's iron
+        let readings: Vec<[f64; 3]> = sphere(50.0)
+            .iter()
+            .map(|p| [1.2 * p[0] + 10.0, 0.8 * p[1] - 20.0, p[2] + 5.0])
+            .collect();
+        let calibration = mag_iron(&readings).unwrap();
+        assert_eq!(calibration.offset, [10.0, -20.0, 5.0]);
+        //every corrected reading is the same distance from the center
+        let radii: Vec<f64> = readings
+            .iter()
+            .map(|reading| calibration.apply(*reading).iter().map(|v| v * v).sum::<f64>().sqrt())
+            .collect();
+        assert!(radii.iter().all(|radius| (radius - radii[0]).abs() < 1e-9), "{:?}", radii);
+
+        //turned around the vertical only, z is left alone
+        let flat: Vec<[f64; 3]> = sphere(50.0).iter().filter(|p| p[2].abs() < 1e-9).map(|p| [p[0] + 3.0, p[1], -40.0]).collect();
+        let calibration = mag_iron(&flat).unwrap();
+        assert_eq!(calibration.offset, [3.0, 0.0, 0.0]);
+        assert_eq!(calibration.matrix[2][2], 1.0);
+
+        assert!(mag_iron(&[[1.0, 2.0, 3.0]; 10]).is_err());
+    }
+
+    #[test]
+    fn test_gyro_bias() {
+        let still: Vec<[f64; 3]> = (0..100).map(|i| [0.01 + if i % 2 == 0 { 0.001 } else { -0.001 }, -0.02, 0.0]).collect();
+        let calibration = gyro_bias(&still).unwrap();
+        assert!(calibration.offset.iter().zip([0.01, -0.02, 0.0]).all(|(offset, bias)| (offset - bias).abs() < 1e-12));
+
+        let moved: Vec<[f64; 3]> = (0..100).map(|i| [i as f64 * 0.01, 0.0, 0.0]).collect();
+        assert!(gyro_bias(&moved).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_imu_calibration() {
+        let path = std::env::temp_dir().join(format!("ioc_imu_calibration_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        //the magnetometer was calibrated before
+        let mag = mag_iron(&sphere(50.0)).unwrap();
+        CalibrationFile { mag: Some(mag.clone()), ..Default::default() }.save(&path).unwrap();
+
+        //the reading from before the start isn't collected
+        let (gyro, gyro_tx) = Input::new(vec![Value::Float(1.0), Value::Float(1.0), Value::Float(1.0)]);
+        let (start_gyro, start_gyro_tx) = Input::new(false);
+        let calibration = ImuCalibration::try_build(&ImuCalibrationConfig {
+            file: path.clone(),
+            gyro: Some((&gyro, &start_gyro)),
+            gyro_samples: 10,
+            magnetometer: None,
+            mag_samples: 10,
+        })
+        .await
+        .unwrap();
+        let mut state = calibration.state.source();
+        let mut progress = calibration.progress.source();
+
+        start_gyro_tx.send(true).unwrap();
+        state.changed().await.unwrap();
+        assert!(state.borrow_and_update().contains("hold the imu still"));
+        assert_eq!(*progress.borrow_and_update(), 0.0);
+        for i in 0..10 {
+            gyro_tx.send(vec![Value::Float(0.02), Value::Float(-0.01), Value::Float(0.0)]).unwrap();
+            progress.changed().await.unwrap();
+            assert_eq!(*progress.borrow_and_update(), (i + 1) as f64 / 10.0);
+        }
+        state.changed().await.unwrap();
+        assert!(state.borrow().starts_with("saved the gyro calibration"), "{}", *state.borrow());
+
+        let file = CalibrationFile::load(&path).unwrap().unwrap();
+        let offset = file.gyro.unwrap().offset;
+        assert!(offset.iter().zip([0.02, -0.01, 0.0]).all(|(offset, bias)| (offset - bias).abs() < 1e-12), "{:?}", offset);
+        assert_eq!(file.mag, Some(mag));
+        std::fs::remove_file(&path).unwrap();
+        calibration.join_handle.abort();
+    }
+}
@@ -3,80 +3,131 @@ use std::{collections::HashMap, time::Duration};
 use embedded_hal::i2c;
 use ioc_core::{error::IocBuildError, Input, InputKind, ModuleBuilder, ModuleIO, Value};
 use serde::Deserialize;
+use schemars::JsonSchema;
 use tokio::{sync::watch, task::JoinHandle, time::sleep};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, warn};
 
+use crate::{
+    bus::{default_i2c_bus, DEFAULT_I2C_BUS},
+    devices::calibration::{self, AxisCalibration},
+};
+
+///Output data rates. The cutoffs of the filters scale with the data rate.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
 pub enum DataRate {
-    Low0,
-    Low1,
-    Low2,
-    High0,
-    High1,
-    High2,
-    High3,
+    Hz12_5,
+    Hz25,
+    Hz50,
+    #[default]
+    Hz100,
+    Hz200,
+    Hz400,
+    Hz800,
 }
 
 impl DataRate {
     fn odr_hertz(&self) -> f64 {
         match *self {
-            Self::Low0 => 12.5,
-            Self::Low1 => 25.0,
-            Self::Low2 => 50.0,
-            Self::High0 => 100.0,
-            Self::High1 => 200.0,
-            Self::High2 => 400.0,
-            Self::High3 => 800.0,
+            Self::Hz12_5 => 12.5,
+            Self::Hz25 => 25.0,
+            Self::Hz50 => 50.0,
+            Self::Hz100 => 100.0,
+            Self::Hz200 => 200.0,
+            Self::Hz400 => 400.0,
+            Self::Hz800 => 800.0,
         }
     }
 
     fn dr_bits(&self) -> u8 {
         match *self {
-            Self::Low0 | Self::High0 => 0,
-            Self::Low1 | Self::High1 => 1,
-            Self::Low2 | Self::High2 => 2,
-            Self::High3 => 3,
+            Self::Hz12_5 | Self::Hz100 => 0,
+            Self::Hz25 | Self::Hz200 => 1,
+            Self::Hz50 | Self::Hz400 => 2,
+            Self::Hz800 => 3,
         }
     }
 
-    fn _low_odr_bit(&self) -> u8 {
+    fn low_odr_bit(&self) -> u8 {
         match *self {
-            Self::Low0 | Self::Low1 | Self::Low2 => 1,
-            Self::High0 | Self::High1 | Self::High2 | Self::High3 => 0,
+            Self::Hz12_5 | Self::Hz25 | Self::Hz50 => 1,
+            Self::Hz100 | Self::Hz200 | Self::Hz400 | Self::Hz800 => 0,
         }
     }
 }
 
+///Full scale ranges, in degrees per second. A smaller range has a finer resolution.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
 pub enum DataScale {
-    _245DegPerSec,
-    _500DegPerSec,
-    _2000DegPerSec,
+    #[default]
+    Dps245,
+    Dps500,
+    Dps2000,
 }
 
 impl DataScale {
-    fn scale_output_dps(&self, data: i16) -> f64 {
+    fn fs_bits(&self) -> u8 {
         match *self {
-            Self::_245DegPerSec => (data as f64) / ((2 << 15) as f64) * 245.0,
-            Self::_500DegPerSec => (data as f64) / ((2 << 15) as f64) * 500.0,
-            Self::_2000DegPerSec => (data as f64) / ((2 << 15) as f64) * 2000.0,
+            Self::Dps245 => 0b00,
+            Self::Dps500 => 0b01,
+            Self::Dps2000 => 0b10,
         }
     }
+
+    //the sensitivities in the datasheet, in degrees per second per count
+    fn scale_output_rad_per_sec(&self, data: i16) -> f64 {
+        let dps_per_count = match *self {
+            Self::Dps245 => 0.00875,
+            Self::Dps500 => 0.0175,
+            Self::Dps2000 => 0.07,
+        };
+        (data as f64 * dps_per_count).to_radians()
+    }
 }
 
-#[derive(Deserialize, Debug)]
+///Configures the gyroscope. Its output `value` is the angular rate around x, y and z in radians per second,
+/// corrected by the calibration. `raw` is the same before the correction, for calibrating.
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct L3gd20DeviceConfig {
+    #[serde(default = "default_i2c_bus")]
+    pub i2c_bus: u8,
     pub i2c_address: u8,
+    #[serde(default)]
+    pub data_rate: DataRate,
+    #[serde(default)]
+    pub full_scale: DataScale,
+    ///Turns on the second low pass filter, with a cutoff selection from 0 (lowest) to 3. See the BW bits in the datasheet.
+    pub low_pass: Option<u8>,
+    ///Turns on the high pass filter, with a cutoff selection from 0 (highest) to 9. See the HPCF bits in the datasheet.
+    pub high_pass: Option<u8>,
+    ///How often the outputs are read. Defaults to the data rate.
+    pub period_ms: Option<u64>,
+    #[serde(default)]
+    pub calibration: AxisCalibration,
+    ///A file saved by the `ImuCalibration` transformer. Its gyro calibration is used in place of `calibration`.
+    pub calibration_file: Option<String>,
 }
 
 impl Default for L3gd20DeviceConfig {
     fn default() -> Self {
-        Self { i2c_address: 0x6B }
+        Self {
+            i2c_bus: DEFAULT_I2C_BUS,
+            i2c_address: 0x6B,
+            data_rate: DataRate::default(),
+            full_scale: DataScale::default(),
+            low_pass: None,
+            high_pass: None,
+            period_ms: None,
+            calibration: AxisCalibration::default(),
+            calibration_file: None,
+        }
     }
 }
 
 pub struct L3gd20Device {
     pub join_handle: JoinHandle<()>,
     pub gyroscope: Input<Vec<Value>>,
+    pub raw: Input<Vec<Value>>,
 }
 
 impl L3gd20Device {
@@ -92,9 +143,7 @@ impl L3gd20Device {
             i += 1;
         }
         if !has_valid_address {
-            return Err(IocBuildError::from_string(format!(
-                "Invalid I2C address for L3gd20."
-            )));
+            return Err(IocBuildError::from_string("Invalid I2C address for L3gd20.".to_string()));
         }
 
         //read the id register from the device and make sure it's what we expect
@@ -111,13 +160,33 @@ impl L3gd20Device {
             );
         }
 
+        if config.low_pass.is_some_and(|bw| bw > 3) || config.high_pass.is_some_and(|hpcf| hpcf > 9) {
+            return Err(IocBuildError::message(
+                "L3gd20 low_pass must be between 0 and 3 and high_pass between 0 and 9",
+            ));
+        }
+
+        let calibration = calibration::choose(config.calibration_file.as_ref(), |file| file.gyro, &config.calibration)?;
+
+        for (register, value) in control_register_values(config) {
+            if let Err(err) = i2c.write(config.i2c_address, &[register, value]) {
+                return Err(IocBuildError::from_string(format!("Error configuring L3gd20. {:?}", err)));
+            }
+        }
+
         let (gyro, tx) = Input::new(Vec::new());
+        let (raw, raw_tx) = Input::new(Vec::new());
 
-        let join_handle = spawn_gyro_task(config.i2c_address, tx, i2c, cancel_token);
+        let period = match config.period_ms {
+            Some(period_ms) => Duration::from_millis(period_ms),
+            None => Duration::from_secs_f64(1.0 / config.data_rate.odr_hertz()),
+        };
+        let join_handle = spawn_gyro_task(config.i2c_address, config.full_scale, calibration, period, (tx, raw_tx), i2c, cancel_token);
 
         Ok(Self {
-            join_handle: join_handle,
+            join_handle,
             gyroscope: gyro,
+            raw,
         })
     }
 }
@@ -126,7 +195,10 @@ impl From<L3gd20Device> for ModuleIO {
     fn from(dev: L3gd20Device) -> ModuleIO {
         ModuleIO {
             join_handle: dev.join_handle,
-            inputs: HashMap::from([("value".to_string(), InputKind::Array(dev.gyroscope))]),
+            inputs: HashMap::from([
+                ("value".to_string(), InputKind::Array(dev.gyroscope)),
+                ("raw".to_string(), InputKind::Array(dev.raw)),
+            ]),
             outputs: HashMap::new(),
         }
     }
@@ -135,7 +207,7 @@ impl From<L3gd20Device> for ModuleIO {
 pub struct L3gd20DeviceBuilder<I2C, F>
 where
     I2C: i2c::I2c + Send + 'static,
-    F: Fn(u8) -> I2C,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     i2c_bus_provider: F,
 }
@@ -143,7 +215,7 @@ where
 impl<I2C, F> L3gd20DeviceBuilder<I2C, F>
 where
     I2C: i2c::I2c + Send + 'static,
-    F: Fn(u8) -> I2C,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     pub fn new(i2c_bus_provider: F) -> Self {
         L3gd20DeviceBuilder { i2c_bus_provider }
@@ -153,13 +225,13 @@ where
 impl<I2C, F> ModuleBuilder for L3gd20DeviceBuilder<I2C, F>
 where
     I2C: i2c::I2c + Send + 'static,
-    F: Fn(u8) -> I2C,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     type Config = L3gd20DeviceConfig;
     type Module = L3gd20Device;
 
     async fn try_build(&self, cfg: &L3gd20DeviceConfig, cancel_token: CancellationToken) -> Result<L3gd20Device, IocBuildError> {
-        L3gd20Device::try_build(cfg, (self.i2c_bus_provider)(1), cancel_token)
+        L3gd20Device::try_build(cfg, (self.i2c_bus_provider)(cfg.i2c_bus)?, cancel_token)
     }
 }
 
@@ -169,42 +241,51 @@ const MULTI_READ_MASK: u8 = 0x80;
 
 const ID_REGISTER: u8 = 0x0F;
 const CTRL1_REGISTER: u8 = 0x20;
-const _OUT_X_LSB_REGISTER: u8 = 0x28;
+const CTRL2_REGISTER: u8 = 0x21;
+const CTRL4_REGISTER: u8 = 0x23;
+const CTRL5_REGISTER: u8 = 0x24;
+const LOW_ODR_REGISTER: u8 = 0x39;
 const OUT_TEMP_REGISTER: u8 = 0x26;
 
+//keeps the high and low bytes of an output from different samples
+const BLOCK_DATA_UPDATE: u8 = 0x80;
+const HIGH_PASS_ENABLE: u8 = 0x10;
+
 fn ctrl1_register_value(dr: &DataRate, bw: u8, enabled: bool) -> u8 {
     (dr.dr_bits() << 6) | ((bw & 0b11) << 4) | (if enabled { 0b1111 } else { 0b0 })
 }
 
+//the registers to write to set the gyro up, in order. it's powered on last
+fn control_register_values(config: &L3gd20DeviceConfig) -> [(u8, u8); 5] {
+    //the output goes through the high pass filter if it's on, then the second low pass filter if it's on
+    let out_sel = match (config.low_pass, config.high_pass) {
+        (Some(_), _) => 0b10,
+        (None, Some(_)) => 0b01,
+        (None, None) => 0b00,
+    };
+    let hp_enable = if config.high_pass.is_some() { HIGH_PASS_ENABLE } else { 0 };
+    [
+        (LOW_ODR_REGISTER, config.data_rate.low_odr_bit()),
+        (CTRL2_REGISTER, config.high_pass.unwrap_or(0)),
+        (CTRL4_REGISTER, BLOCK_DATA_UPDATE | (config.full_scale.fs_bits() << 4)),
+        (CTRL5_REGISTER, hp_enable | out_sel),
+        (CTRL1_REGISTER, ctrl1_register_value(&config.data_rate, config.low_pass.unwrap_or(0), true)),
+    ]
+}
+
 fn spawn_gyro_task<I2C>(
     i2c_address: u8,
-    tx: watch::Sender<Vec<Value>>,
+    scale: DataScale,
+    calibration: AxisCalibration,
+    period: Duration,
+    (tx, raw_tx): (watch::Sender<Vec<Value>>, watch::Sender<Vec<Value>>),
     mut i2c: I2C,
     cancel_token: CancellationToken
 ) -> JoinHandle<()>
 where
     I2C: i2c::I2c + Send + 'static,
 {
-    let dr = DataRate::High0;
-    let bw: u8 = 0;
-    let scale = DataScale::_245DegPerSec;
-
     tokio::spawn(async move {
-        let ctrl1 = ctrl1_register_value(&dr, bw, true);
-
-        if let Err(err) = i2c.write(i2c_address, &[CTRL1_REGISTER, ctrl1]) {
-            warn!("error setting control! {:?}", err);
-            panic!("gyro not working");
-        }
-
-        let mut buffer = [0u8; 5];
-        i2c.write_read(
-            i2c_address,
-            &[CTRL1_REGISTER | MULTI_READ_MASK],
-            &mut buffer,
-        )
-        .unwrap();
-
         loop {
             if cancel_token.is_cancelled() {
                 break;
@@ -219,21 +300,24 @@ where
                 break;
             }
 
-            let x = scale.scale_output_dps(((buffer[3] as i16) << 8) | (buffer[2] as i16));
-            let y = scale.scale_output_dps(((buffer[5] as i16) << 8) | (buffer[4] as i16));
-            let z = scale.scale_output_dps(((buffer[7] as i16) << 8) | (buffer[6] as i16));
+            let x = scale.scale_output_rad_per_sec(i16::from_le_bytes([buffer[2], buffer[3]]));
+            let y = scale.scale_output_rad_per_sec(i16::from_le_bytes([buffer[4], buffer[5]]));
+            let z = scale.scale_output_rad_per_sec(i16::from_le_bytes([buffer[6], buffer[7]]));
+            let rate = calibration.apply([x, y, z]);
 
-            if let Err(err) = tx.send(vec![Value::Float(x), Value::Float(y), Value::Float(z)]) {
+            //nobody may be calibrating, so only the corrected output has to be read
+            let _ = raw_tx.send(vec![Value::Float(x), Value::Float(y), Value::Float(z)]);
+            if let Err(err) = tx.send(rate.into_iter().map(Value::Float).collect()) {
                 warn!("error sending gyroscope data! {:?}", err);
                 break;
             }
 
-            sleep(Duration::from_millis((1000.0 / dr.odr_hertz()) as u64)).await;
+            sleep(period).await;
         }
 
         debug!("shutting down gyro!");
 
-        let ctrl1 = ctrl1_register_value(&dr, 0, false);
+        let ctrl1 = ctrl1_register_value(&DataRate::default(), 0, false);
         match i2c.write(i2c_address, &[CTRL1_REGISTER, ctrl1]) {
             Ok(_) => debug!("successfully disabled gyro on shutdown"),
             Err(err) => warn!("error disabling gyro on shutdown! {:?}", err),
@@ -241,3 +325,59 @@ where
 
     })
 }
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::{DataScale, L3gd20Device, L3gd20DeviceConfig};
+    use crate::{devices::calibration::AxisCalibration, mock::{l3gd20::{L3gd20Model, DEFAULT_ADDRESS}, MockI2c}};
+    use ioc_core::Value;
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn test_l3gd20_mock() {
+        let bus = MockI2c::new();
+        let gyro = bus.attach(&[DEFAULT_ADDRESS], L3gd20Model::default());
+        gyro.lock().unwrap().set_rate_dps([10.0, -20.0, 0.0]);
+        let config = L3gd20DeviceConfig {
+            full_scale: DataScale::Dps500,
+            calibration: AxisCalibration {
+                offset: [0.0, 0.0, -0.1],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let cancel_token = CancellationToken::new();
+        let device = L3gd20Device::try_build(&config, bus, cancel_token.clone()).unwrap();
+
+        let mut rate = device.gyroscope.source();
+        rate.changed().await.unwrap();
+        assert!(gyro.lock().unwrap().is_powered_on());
+        let rate: Vec<f64> = rate
+            .borrow()
+            .iter()
+            .map(|value| match value {
+                Value::Float(value) => *value,
+                _ => panic!("expected floats"),
+            })
+            .collect();
+        //radians per second, within a count of 17.5 millidegrees per second
+        let expected = [10f64.to_radians(), -20f64.to_radians(), 0.1];
+        assert!(rate.iter().zip(expected).all(|(rate, expected)| (rate - expected).abs() < 0.0175f64.to_radians()), "{:?}", rate);
+        //the raw output isn't corrected
+        assert_eq!(device.raw.source().borrow()[2], Value::Float(0.0));
+
+        //the gyro is powered down on shutdown
+        cancel_token.cancel();
+        device.join_handle.await.unwrap();
+        assert!(!gyro.lock().unwrap().is_powered_on());
+    }
+
+    #[test]
+    fn test_l3gd20_invalid_filter() {
+        let config = L3gd20DeviceConfig {
+            high_pass: Some(10),
+            ..Default::default()
+        };
+        assert!(L3gd20Device::try_build(&config, MockI2c::with_all_chips(), CancellationToken::new()).is_err());
+    }
+}
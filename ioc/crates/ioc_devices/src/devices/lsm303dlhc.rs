@@ -1,95 +1,323 @@
 use std::{collections::HashMap, time::Duration};
 
-use embedded_hal_0::blocking::i2c;
+use embedded_hal::i2c;
 use ioc_core::{error::IocBuildError, Input, InputKind, ModuleBuilder, ModuleIO, Value};
 
-use lsm303dlhc::Lsm303dlhc;
 use serde::Deserialize;
-use tokio::{task::JoinHandle, time::sleep};
+use schemars::JsonSchema;
+use tokio::{sync::watch, task::JoinHandle, time::sleep};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, warn};
 
-#[derive(Deserialize, Debug)]
-pub struct Lsm303dlhcDeviceConfig {}
+use crate::{
+    bus::default_i2c_bus,
+    devices::calibration::{self, AxisCalibration},
+};
+
+///Accelerometer output data rates.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
+pub enum AccelDataRate {
+    Hz1,
+    Hz10,
+    #[default]
+    Hz25,
+    Hz50,
+    Hz100,
+    Hz200,
+    Hz400,
+}
+
+impl AccelDataRate {
+    fn odr_bits(&self) -> u8 {
+        match *self {
+            Self::Hz1 => 0b0001,
+            Self::Hz10 => 0b0010,
+            Self::Hz25 => 0b0011,
+            Self::Hz50 => 0b0100,
+            Self::Hz100 => 0b0101,
+            Self::Hz200 => 0b0110,
+            Self::Hz400 => 0b0111,
+        }
+    }
+}
+
+///Accelerometer full scale ranges, in g. A smaller range has a finer resolution.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
+pub enum AccelScale {
+    #[default]
+    G2,
+    G4,
+    G8,
+    G16,
+}
+
+impl AccelScale {
+    fn fs_bits(&self) -> u8 {
+        match *self {
+            Self::G2 => 0b00,
+            Self::G4 => 0b01,
+            Self::G8 => 0b10,
+            Self::G16 => 0b11,
+        }
+    }
+
+    //the sensitivities in the datasheet, in g per count of the 12 bit outputs
+    fn scale_output_m_per_sec2(&self, data: i16) -> f64 {
+        let g_per_count = match *self {
+            Self::G2 => 0.001,
+            Self::G4 => 0.002,
+            Self::G8 => 0.004,
+            Self::G16 => 0.012,
+        };
+        //the outputs are left aligned in 16 bits
+        (data >> 4) as f64 * g_per_count * STANDARD_GRAVITY
+    }
+}
+
+///Magnetometer output data rates.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
+pub enum MagDataRate {
+    Hz0_75,
+    Hz1_5,
+    Hz3,
+    Hz7_5,
+    Hz15,
+    #[default]
+    Hz30,
+    Hz75,
+    Hz220,
+}
+
+impl MagDataRate {
+    fn do_bits(&self) -> u8 {
+        *self as u8
+    }
+}
+
+///Magnetometer full scale ranges, in gauss. The earth's field is around half a gauss.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
+pub enum MagScale {
+    #[default]
+    Gauss1_3,
+    Gauss1_9,
+    Gauss2_5,
+    Gauss4_0,
+    Gauss4_7,
+    Gauss5_6,
+    Gauss8_1,
+}
+
+impl MagScale {
+    fn gn_bits(&self) -> u8 {
+        *self as u8 + 1
+    }
+
+    //the gains in the datasheet, in counts per gauss for x and y, and for z
+    fn gain(&self) -> (f64, f64) {
+        match *self {
+            Self::Gauss1_3 => (1100.0, 980.0),
+            Self::Gauss1_9 => (855.0, 760.0),
+            Self::Gauss2_5 => (670.0, 600.0),
+            Self::Gauss4_0 => (450.0, 400.0),
+            Self::Gauss4_7 => (400.0, 355.0),
+            Self::Gauss5_6 => (330.0, 295.0),
+            Self::Gauss8_1 => (230.0, 205.0),
+        }
+    }
+}
+
+///Configures the accelerometer and magnetometer. The `accelerometer` output is in meters per second squared,
+/// and the `magnetometer` output in microtesla, both corrected by their calibrations.
+/// `accelerometer_raw` and `magnetometer_raw` are the same before the corrections, for calibrating.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct Lsm303dlhcDeviceConfig {
+    #[serde(default = "default_i2c_bus")]
+    pub i2c_bus: u8,
+    #[serde(default)]
+    pub accel_data_rate: AccelDataRate,
+    #[serde(default)]
+    pub accel_scale: AccelScale,
+    ///Turns on the accelerometer's high pass filter, with a cutoff selection from 0 (highest) to 3.
+    /// See the HPCF bits in the datasheet. The accelerometer's low pass filtering is set by its data rate.
+    pub accel_high_pass: Option<u8>,
+    #[serde(default)]
+    pub mag_data_rate: MagDataRate,
+    #[serde(default)]
+    pub mag_scale: MagScale,
+    ///How often the outputs are read. Defaults to 100ms.
+    pub period_ms: Option<u64>,
+    #[serde(default)]
+    pub accel_calibration: AxisCalibration,
+    #[serde(default)]
+    pub mag_calibration: AxisCalibration,
+    ///A file saved by the `ImuCalibration` transformer. Its calibrations are used in place of the ones above.
+    pub calibration_file: Option<String>,
+}
+
+impl Default for Lsm303dlhcDeviceConfig {
+    fn default() -> Self {
+        Self {
+            i2c_bus: default_i2c_bus(),
+            accel_data_rate: AccelDataRate::default(),
+            accel_scale: AccelScale::default(),
+            accel_high_pass: None,
+            mag_data_rate: MagDataRate::default(),
+            mag_scale: MagScale::default(),
+            period_ms: None,
+            accel_calibration: AxisCalibration::default(),
+            mag_calibration: AxisCalibration::default(),
+            calibration_file: None,
+        }
+    }
+}
 
 pub struct Lsm303dlhcDevice {
     pub join_handle: JoinHandle<()>,
     pub accelerometer: Input<Vec<Value>>,
     pub magnetometer: Input<Vec<Value>>,
+    pub accelerometer_raw: Input<Vec<Value>>,
+    pub magnetometer_raw: Input<Vec<Value>>,
 }
 
 impl Lsm303dlhcDevice {
-    pub fn try_build<I2C, E>(_cfg: &Lsm303dlhcDeviceConfig, i2c: I2C, cancel_token: CancellationToken) -> Result<Self, E>
+    pub fn try_build<I2C>(cfg: &Lsm303dlhcDeviceConfig, mut i2c: I2C, cancel_token: CancellationToken) -> Result<Self, IocBuildError>
     where
-        E: std::fmt::Debug,
-        I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E> + Send + 'static,
+        I2C: i2c::I2c + Send + 'static,
     {
-        let mut device = Lsm303dlhc::new(i2c)?;
+        if cfg.accel_high_pass.is_some_and(|hpcf| hpcf > 3) {
+            return Err(IocBuildError::message("Lsm303dlhc accel_high_pass must be between 0 and 3"));
+        }
 
-        device.mag_odr(lsm303dlhc::MagOdr::Hz30)?;
-        device.accel_odr(lsm303dlhc::AccelOdr::Hz25)?;
+        let accel_calibration = calibration::choose(cfg.calibration_file.as_ref(), |file| file.accel, &cfg.accel_calibration)?;
+        let mag_calibration = calibration::choose(cfg.calibration_file.as_ref(), |file| file.mag, &cfg.mag_calibration)?;
 
-        let accel_scale = 2.0 / (1 << 15) as f64; //to scale outputs to gs
-        let mag_scale = 1.3 / (1 << 15) as f64; //to scale to milligaus
+        //the filtered data selection sends the outputs through the high pass filter
+        let ctrl2 = match cfg.accel_high_pass {
+            Some(hpcf) => (hpcf << 4) | FILTERED_DATA_SELECTION,
+            None => 0,
+        };
+        let accel_registers = [
+            (CTRL_REG2_A, ctrl2),
+            (CTRL_REG4_A, BLOCK_DATA_UPDATE | (cfg.accel_scale.fs_bits() << 4) | HIGH_RESOLUTION),
+            (CTRL_REG1_A, (cfg.accel_data_rate.odr_bits() << 4) | XYZ_ENABLE),
+        ];
+        let mag_registers = [
+            (CRA_REG_M, cfg.mag_data_rate.do_bits() << 2),
+            (CRB_REG_M, cfg.mag_scale.gn_bits() << 5),
+            (MR_REG_M, MAG_CONTINUOUS),
+        ];
+        let writes = accel_registers
+            .iter()
+            .map(|(register, value)| (ACCEL_ADDRESS, *register, *value))
+            .chain(mag_registers.iter().map(|(register, value)| (MAG_ADDRESS, *register, *value)));
+        for (address, register, value) in writes {
+            if let Err(err) = i2c.write(address, &[register, value]) {
+                return Err(IocBuildError::from_string(format!("Error configuring Lsm303dlhc. {:?}", err)));
+            }
+        }
 
         let (accelerometer, accel_tx) = Input::new(Vec::new());
         let (magnetometer, mag_tx) = Input::new(Vec::new());
+        let (accelerometer_raw, accel_raw_tx) = Input::new(Vec::new());
+        let (magnetometer_raw, mag_raw_tx) = Input::new(Vec::new());
 
+        let reader = Reader {
+            accel_scale: cfg.accel_scale,
+            mag_scale: cfg.mag_scale,
+            accel: Outputs { calibration: accel_calibration, tx: accel_tx, raw_tx: accel_raw_tx },
+            mag: Outputs { calibration: mag_calibration, tx: mag_tx, raw_tx: mag_raw_tx },
+        };
+        let period = Duration::from_millis(cfg.period_ms.unwrap_or(100));
         let join_handle = tokio::spawn(async move {
             loop {
                 if cancel_token.is_cancelled() {
                     break;
                 }
-                match device.accel() {
-                    Ok(accel) => {
-                        let vector = (
-                            accel.x as f64 * accel_scale,
-                            accel.y as f64 * accel_scale,
-                            accel.z as f64 * accel_scale,
-                        );
-                        let vector = vec![
-                            Value::Float(vector.0),
-                            Value::Float(vector.1),
-                            Value::Float(vector.2),
-                        ];
-                        accel_tx.send(vector).unwrap();
-                    }
-                    Err(err) => {
-                        warn!("device error! {:?}", err)
-                    }
-                }
-                match device.mag() {
-                    Ok(mag) => {
-                        let vector = (
-                            mag.x as f64 * mag_scale,
-                            mag.y as f64 * mag_scale,
-                            mag.z as f64 * mag_scale,
-                        );
-                        let vector = vec![
-                            Value::Float(vector.0),
-                            Value::Float(vector.1),
-                            Value::Float(vector.2),
-                        ];
-                        mag_tx.send(vector).unwrap();
-                    }
-                    Err(err) => {
-                        warn!("device error! {:?}", err)
-                    }
+                if !reader.read(&mut i2c) {
+                    break;
                 }
-                sleep(Duration::from_millis(100)).await;
+                sleep(period).await;
             }
 
             debug!("shutting down lsm303dlhc!");
+
+            let power_down = i2c
+                .write(ACCEL_ADDRESS, &[CTRL_REG1_A, 0])
+                .and_then(|_| i2c.write(MAG_ADDRESS, &[MR_REG_M, MAG_SLEEP]));
+            if let Err(err) = power_down {
+                warn!("error powering down lsm303dlhc on shutdown! {:?}", err);
+            }
         });
 
         Ok(Self {
             join_handle,
             accelerometer,
             magnetometer,
+            accelerometer_raw,
+            magnetometer_raw,
         })
     }
 }
 
+//corrects a sensor's readings, and sends them with and without the correction
+struct Outputs {
+    calibration: AxisCalibration,
+    tx: watch::Sender<Vec<Value>>,
+    raw_tx: watch::Sender<Vec<Value>>,
+}
+
+impl Outputs {
+    //returns false once nobody is listening
+    fn send(&self, reading: [f64; 3]) -> bool {
+        let _ = self.raw_tx.send(reading.into_iter().map(Value::Float).collect());
+        self.tx.send(self.calibration.apply(reading).into_iter().map(Value::Float).collect()).is_ok()
+    }
+}
+
+//scales the outputs
+struct Reader {
+    accel_scale: AccelScale,
+    mag_scale: MagScale,
+    accel: Outputs,
+    mag: Outputs,
+}
+
+impl Reader {
+    //reads both sensors and sends what it read. returns false once nobody is listening
+    fn read<I2C: i2c::I2c>(&self, i2c: &mut I2C) -> bool {
+        let mut buffer = [0u8; 6];
+        match i2c.write_read(ACCEL_ADDRESS, &[OUT_X_L_A | MULTI_READ_MASK], &mut buffer) {
+            Ok(_) => {
+                let accel = [0, 2, 4].map(|i| {
+                    self.accel_scale.scale_output_m_per_sec2(i16::from_le_bytes([buffer[i], buffer[i + 1]]))
+                });
+                if !self.accel.send(accel) {
+                    return false;
+                }
+            }
+            Err(err) => {
+                warn!("device error! {:?}", err)
+            }
+        }
+        match i2c.write_read(MAG_ADDRESS, &[OUT_X_H_M], &mut buffer) {
+            Ok(_) => {
+                //the outputs are big endian, in the order x, z, y
+                let (gain_xy, gain_z) = self.mag_scale.gain();
+                let x = i16::from_be_bytes([buffer[0], buffer[1]]) as f64 / gain_xy;
+                let z = i16::from_be_bytes([buffer[2], buffer[3]]) as f64 / gain_z;
+                let y = i16::from_be_bytes([buffer[4], buffer[5]]) as f64 / gain_xy;
+                if !self.mag.send([x, y, z].map(|gauss| gauss * MICROTESLA_PER_GAUSS)) {
+                    return false;
+                }
+            }
+            Err(err) => {
+                warn!("device error! {:?}", err)
+            }
+        }
+        true
+    }
+}
+
 impl From<Lsm303dlhcDevice> for ModuleIO {
     fn from(dev: Lsm303dlhcDevice) -> Self {
         ModuleIO {
@@ -103,44 +331,125 @@ impl From<Lsm303dlhcDevice> for ModuleIO {
                     "magnetometer".to_string(),
                     InputKind::Array(dev.magnetometer),
                 ),
+                (
+                    "accelerometer_raw".to_string(),
+                    InputKind::Array(dev.accelerometer_raw),
+                ),
+                (
+                    "magnetometer_raw".to_string(),
+                    InputKind::Array(dev.magnetometer_raw),
+                ),
             ]),
             outputs: HashMap::new(),
         }
     }
 }
 
-pub struct Lsm303dlhcDeviceBuilder<E, I2C, F>
+pub struct Lsm303dlhcDeviceBuilder<I2C, F>
 where
-    E: std::fmt::Debug,
-    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E> + Send + 'static,
-    F: Fn(u8) -> I2C,
+    I2C: i2c::I2c + Send + 'static,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     i2c_bus_provider: F,
 }
 
-impl<E, I2C, F> Lsm303dlhcDeviceBuilder<E, I2C, F>
+impl<I2C, F> Lsm303dlhcDeviceBuilder<I2C, F>
 where
-    E: std::fmt::Debug,
-    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E> + Send + 'static,
-    F: Fn(u8) -> I2C,
+    I2C: i2c::I2c + Send + 'static,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     pub fn new(i2c_bus_provider: F) -> Self {
         Lsm303dlhcDeviceBuilder { i2c_bus_provider }
     }
 }
 
-impl<E, I2C, F> ModuleBuilder for Lsm303dlhcDeviceBuilder<E, I2C, F>
+impl<I2C, F> ModuleBuilder for Lsm303dlhcDeviceBuilder<I2C, F>
 where
-    E: std::fmt::Debug,
-    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E> + Send + 'static,
-    F: Fn(u8) -> I2C,
+    I2C: i2c::I2c + Send + 'static,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     type Config = Lsm303dlhcDeviceConfig;
     type Module = Lsm303dlhcDevice;
 
     async fn try_build(&self, cfg: &Self::Config, cancel_token: CancellationToken) -> Result<Self::Module, IocBuildError> {
-        Lsm303dlhcDevice::try_build(cfg, (self.i2c_bus_provider)(1), cancel_token).map_err(|err| {
-            IocBuildError::from_string(format!("Error building Lsm303dlhc device: {:?}", err))
-        })
+        Lsm303dlhcDevice::try_build(cfg, (self.i2c_bus_provider)(cfg.i2c_bus)?, cancel_token)
+    }
+}
+
+const STANDARD_GRAVITY: f64 = 9.80665;
+const MICROTESLA_PER_GAUSS: f64 = 100.0;
+
+const ACCEL_ADDRESS: u8 = 0x19;
+const MAG_ADDRESS: u8 = 0x1E;
+//the msb of an accelerometer register address turns on auto increment. the magnetometer always increments
+const MULTI_READ_MASK: u8 = 0x80;
+
+const CTRL_REG1_A: u8 = 0x20;
+const CTRL_REG2_A: u8 = 0x21;
+const CTRL_REG4_A: u8 = 0x23;
+const OUT_X_L_A: u8 = 0x28;
+
+const CRA_REG_M: u8 = 0x00;
+const CRB_REG_M: u8 = 0x01;
+const MR_REG_M: u8 = 0x02;
+const OUT_X_H_M: u8 = 0x03;
+
+const XYZ_ENABLE: u8 = 0b111;
+const FILTERED_DATA_SELECTION: u8 = 0x08;
+//keeps the high and low bytes of an output from different samples
+const BLOCK_DATA_UPDATE: u8 = 0x80;
+const HIGH_RESOLUTION: u8 = 0x08;
+const MAG_CONTINUOUS: u8 = 0b00;
+const MAG_SLEEP: u8 = 0b11;
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::{AccelScale, Lsm303dlhcDevice, Lsm303dlhcDeviceConfig, MagScale, STANDARD_GRAVITY};
+    use crate::{devices::calibration::AxisCalibration, mock::{lsm303dlhc::{Lsm303dlhcModel, ACCEL_ADDRESS, MAG_ADDRESS}, MockI2c}};
+    use ioc_core::Value;
+    use tokio_util::sync::CancellationToken;
+
+    fn floats(values: &[Value]) -> Vec<f64> {
+        values
+            .iter()
+            .map(|value| match value {
+                Value::Float(value) => *value,
+                _ => panic!("expected floats"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_lsm303dlhc_mock() {
+        let bus = MockI2c::new();
+        let chip = bus.attach(&[ACCEL_ADDRESS, MAG_ADDRESS], Lsm303dlhcModel::default());
+        chip.lock().unwrap().set_accel_g([0.5, -3.0, 1.0]);
+        let config = Lsm303dlhcDeviceConfig {
+            accel_scale: AccelScale::G4,
+            mag_scale: MagScale::Gauss2_5,
+            mag_calibration: AxisCalibration {
+                offset: [5.0, 0.0, 0.0],
+                matrix: [[2.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            },
+            ..Default::default()
+        };
+        let cancel_token = CancellationToken::new();
+        let device = Lsm303dlhcDevice::try_build(&config, bus, cancel_token.clone()).unwrap();
+        let mut accel = device.accelerometer.source();
+        accel.changed().await.unwrap();
+        //meters per second squared, within a count of 2mg
+        let accel = floats(&accel.borrow());
+        let expected = [0.5, -3.0, 1.0].map(|g| g * STANDARD_GRAVITY);
+        assert!(accel.iter().zip(expected).all(|(accel, expected)| (accel - expected).abs() < 0.002 * STANDARD_GRAVITY), "{:?}", accel);
+
+        //the model's field of 0.2, 0 and -0.4 gauss, in microtesla and corrected
+        let mag = floats(&device.magnetometer.source().borrow());
+        let expected = [2.0 * (20.0 - 5.0), 0.0, -40.0];
+        assert!(mag.iter().zip(expected).all(|(mag, expected)| (mag - expected).abs() < 0.5), "{:?}", mag);
+
+        //both sensors are powered down on shutdown
+        cancel_token.cancel();
+        device.join_handle.await.unwrap();
+        assert!(chip.lock().unwrap().is_powered_down());
     }
 }
@@ -21,3 +21,11 @@ pub mod l3gd20;
 /// It is discontinued at the time of writing, but they still exist and can be useful.
 #[cfg(feature = "bmp180")]
 pub mod bmp180;
+
+///Corrections for the offset, scale and alignment of the axes of the gyroscope, accelerometer and magnetometer.
+#[cfg(any(feature = "l3gd20", feature = "lsm303dlhc"))]
+pub mod calibration;
+
+///Calibrates the gyroscope and magnetometer from their raw readings, and saves the results for the devices to load.
+#[cfg(any(feature = "l3gd20", feature = "lsm303dlhc"))]
+pub mod imu_calibration;
@@ -8,18 +8,167 @@ use futures::future::join_all;
 use ioc_core::{error::IocBuildError, ModuleBuilder, ModuleIO, Output, OutputKind};
 use pwm_pca9685::{Address, Channel, Pca9685};
 use serde::Deserialize;
+use schemars::JsonSchema;
 use tokio::{sync::mpsc, task::JoinHandle};
 use tokio_util::sync::CancellationToken;
 
-use crate::error::DeviceConfigError;
+use crate::{bus::default_i2c_bus, error::DeviceConfigError};
 
 use tracing::{debug, error};
 
+//the internal oscillator, which sets the pwm frequency with the prescaler
+const OSCILLATOR_HZ: f64 = 25_000_000.0;
+//counts per pwm period
+const PERIOD_COUNTS: f64 = 4096.0;
+
+fn default_frequency_hz() -> f64 {
+    60.0
+}
+
+///How a channel turns the values sent to it into pulses.
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pca9685ChannelMode {
+    ///Values are the duty cycle, from 0 to 1.
+    #[default]
+    Duty,
+    ///Values are the pulse width in microseconds, limited to min_us..max_us.
+    PulseWidth,
+    ///Values are an angle from min_angle to max_angle, which moves the pulse width from min_us to max_us.
+    Angle,
+}
+
+///A channel with its mode and calibration. Values out of the mode's range are clamped to it.
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+pub struct Pca9685ChannelSettings {
+    pub channel: u8,
+    #[serde(default)]
+    pub mode: Pca9685ChannelMode,
+    ///Shortest pulse in microseconds. Defaults to 1000, the end of a typical servo's travel.
+    pub min_us: Option<f64>,
+    ///Longest pulse in microseconds. Defaults to 2000.
+    pub max_us: Option<f64>,
+    ///The angle at min_us in Angle mode. Any unit works, as long as values are in the same. Defaults to 0.
+    pub min_angle: Option<f64>,
+    ///The angle at max_us in Angle mode. Defaults to 180.
+    pub max_angle: Option<f64>,
+    ///Reverses the range, so the low end of the values gives the longest pulse or the highest duty.
+    #[serde(default)]
+    pub invert: bool,
+    ///Added to every value before it's clamped, in the units of the mode. Centers a servo without moving its horn.
+    #[serde(default)]
+    pub trim: f64,
+}
+
+///Either just the channel number, which is driven by duty cycle, or a channel with its settings.
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum Pca9685ChannelConfig {
+    Channel(u8),
+    Settings(Pca9685ChannelSettings),
+}
+
+impl Pca9685ChannelConfig {
+    fn settings(&self) -> Pca9685ChannelSettings {
+        match self {
+            Pca9685ChannelConfig::Channel(channel) => Pca9685ChannelSettings {
+                channel: *channel,
+                mode: Pca9685ChannelMode::Duty,
+                min_us: None,
+                max_us: None,
+                min_angle: None,
+                max_angle: None,
+                invert: false,
+                trim: 0.0,
+            },
+            Pca9685ChannelConfig::Settings(settings) => settings.clone(),
+        }
+    }
+}
+
 //system level config -- corresponds to 1 pwm chip instance
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Pca9685DeviceConfig {
+    #[serde(default = "default_i2c_bus")]
+    pub i2c_bus: u8,
     pub i2c_address: u8,
-    pub channels: HashMap<String, u8>,
+    ///The pwm frequency of every channel, from 24 to 1526Hz. Defaults to 60Hz. Analog servos usually want 50 or 60Hz.
+    #[serde(default = "default_frequency_hz")]
+    pub frequency_hz: f64,
+    pub channels: HashMap<String, Pca9685ChannelConfig>,
+}
+
+//the prescaler value closest to the frequency, as the datasheet computes it
+fn prescale(frequency_hz: f64) -> Result<u8, DeviceConfigError> {
+    let prescale = (OSCILLATOR_HZ / (PERIOD_COUNTS * frequency_hz)).round() - 1.0;
+    //the chip doesn't accept prescale values under 3
+    if (3.0..=255.0).contains(&prescale) {
+        Ok(prescale as u8)
+    } else {
+        Err(DeviceConfigError::new(format!(
+            "PCA9685 frequency_hz must be between 24 and 1526, got {}",
+            frequency_hz
+        )))
+    }
+}
+
+//turns the values sent to a channel into the count the output goes low at
+#[derive(Debug, Clone, Copy)]
+struct ChannelScale {
+    mode: Pca9685ChannelMode,
+    //the range values are clamped to, in the units of the mode
+    range: (f64, f64),
+    pulse_us: (f64, f64),
+    invert: bool,
+    trim: f64,
+    counts_per_us: f64,
+}
+
+impl ChannelScale {
+    fn new(settings: &Pca9685ChannelSettings, prescale: u8) -> Result<ChannelScale, DeviceConfigError> {
+        let pulse_us = (settings.min_us.unwrap_or(1000.0), settings.max_us.unwrap_or(2000.0));
+        let range = match settings.mode {
+            Pca9685ChannelMode::Duty => (0.0, 1.0),
+            Pca9685ChannelMode::PulseWidth => pulse_us,
+            Pca9685ChannelMode::Angle => (settings.min_angle.unwrap_or(0.0), settings.max_angle.unwrap_or(180.0)),
+        };
+        if range.0 >= range.1 || pulse_us.0 < 0.0 || pulse_us.0 >= pulse_us.1 {
+            return Err(DeviceConfigError::new(format!(
+                "PCA9685 channel {}: min_us and min_angle must be less than max_us and max_angle",
+                settings.channel
+            )));
+        }
+        let counts_per_us = OSCILLATOR_HZ / 1_000_000.0 / (prescale as f64 + 1.0);
+        let period_us = PERIOD_COUNTS / counts_per_us;
+        if settings.mode != Pca9685ChannelMode::Duty && pulse_us.1 > period_us {
+            return Err(DeviceConfigError::new(format!(
+                "PCA9685 channel {}: max_us is longer than the pwm period of {:.0}us",
+                settings.channel, period_us
+            )));
+        }
+        Ok(ChannelScale {
+            mode: settings.mode,
+            range,
+            pulse_us,
+            invert: settings.invert,
+            trim: settings.trim,
+            counts_per_us,
+        })
+    }
+
+    fn off_count(&self, value: f64) -> u16 {
+        let (low, high) = self.range;
+        let mut fraction = ((value + self.trim - low) / (high - low)).clamp(0.0, 1.0);
+        if self.invert {
+            fraction = 1.0 - fraction;
+        }
+        match self.mode {
+            Pca9685ChannelMode::Duty => (fraction * 4095.0) as u16,
+            Pca9685ChannelMode::PulseWidth | Pca9685ChannelMode::Angle => {
+                let (min_us, max_us) = self.pulse_us;
+                ((min_us + fraction * (max_us - min_us)) * self.counts_per_us).round().min(4095.0) as u16
+            }
+        }
+    }
 }
 
 //connected pwm chip instance
@@ -59,7 +208,8 @@ where
 
 fn spawn_pca_output_task<I2C, E>(
     device: Arc<Mutex<Pca9685<I2C>>>,
-    channel: u8,
+    channel: Channel,
+    scale: ChannelScale,
     mut rx: mpsc::Receiver<f64>,
     cancel_token: CancellationToken,
 ) -> JoinHandle<()> 
@@ -68,7 +218,6 @@ where
         I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E> + Send + 'static,
 {
     tokio::spawn(async move {
-        let chann = Channel::try_from(channel).unwrap();
         loop {
             tokio::select! {
                 _ = cancel_token.cancelled() => {
@@ -77,12 +226,12 @@ where
                 rx_res = rx.recv() => { 
                     match rx_res {
                         Some(new_value) => {
-                            let off_time = (new_value.min(1.0).max(0.0) * 4095.0) as u16;
+                            let off_time = scale.off_count(new_value);
                             let mut device = match device.lock() {
                                 Ok(device) => device,
                                 Err(poisoned) => poisoned.into_inner(),
                             };
-                            if let Err(err) = device.set_channel_on_off(chann, 0, off_time) {
+                            if let Err(err) = device.set_channel_on_off(channel, 0, off_time) {
                                 error!("error setting PCA9685 device output! {:?}", err);
                                 break;
                             }
@@ -94,7 +243,7 @@ where
                 },
             }
         }
-        debug!("Pca9685Device output task for channel {} shutting down.", channel)
+        debug!("Pca9685Device output task for channel {:?} shutting down.", channel)
     })
 }
 
@@ -108,19 +257,28 @@ impl Pca9685Device {
         E: std::fmt::Debug,
         I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E> + Send + 'static,
     {
+        let prescale = prescale(config.frequency_hz)?;
+        let mut scales = Vec::with_capacity(config.channels.len());
+        for (k, c) in &config.channels {
+            let settings = c.settings();
+            let channel = Channel::try_from(settings.channel)
+                .map_err(|_| DeviceConfigError::new(format!("PCA9685 has no channel {} for {}", settings.channel, k)))?;
+            scales.push((k, channel, ChannelScale::new(&settings, prescale)?));
+        }
+
         let address = Address::from(config.i2c_address);
         let mut device = Pca9685::new(i2c, address)?;
 
-        device.set_prescale(100)?;
+        device.set_prescale(prescale)?;
         device.enable()?;
 
         let device = Arc::new(Mutex::new(device));
 
         let mut channels = HashMap::with_capacity(config.channels.len());
         let mut join_handles: Vec<JoinHandle<()>> = Vec::with_capacity(config.channels.len());
-        for (k, c) in &config.channels {
+        for (k, channel, scale) in scales {
             let (output, out_rx) = Output::new();
-            let join_handle = spawn_pca_output_task(device.clone(), *c, out_rx, cancel_token.clone());
+            let join_handle = spawn_pca_output_task(device.clone(), channel, scale, out_rx, cancel_token.clone());
             channels.insert(k.to_string(), output);
             join_handles.push(join_handle);
         }
@@ -133,7 +291,8 @@ impl Pca9685Device {
                 Ok(device) => device,
                 Err(poisoned) => poisoned.into_inner(),
             };
-            match dev.disable() {
+            //turn every output off before stopping the oscillator, so they don't hold their last level
+            match dev.set_channel_full_off(Channel::All).and_then(|_| dev.disable()) {
                 Ok(_) => debug!("PCA9685 device disabled"),
                 Err(err) => error!("Error disabling PCA9685 device: {:?}", err),
             }
@@ -150,7 +309,7 @@ pub struct Pca9685DeviceBuilder<E, I2C, F>
 where
     E: std::fmt::Debug,
     I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E> + Send + 'static,
-    F: Fn(u8) -> I2C,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     i2c_bus_provider: F,
 }
@@ -159,7 +318,7 @@ impl<E, I2C, F> Pca9685DeviceBuilder<E, I2C, F>
 where
     E: std::fmt::Debug,
     I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E> + Send + 'static,
-    F: Fn(u8) -> I2C,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     pub fn new(i2c_bus_provider: F) -> Pca9685DeviceBuilder<E, I2C, F> {
         Pca9685DeviceBuilder { i2c_bus_provider }
@@ -170,14 +329,103 @@ impl<E, I2C, F> ModuleBuilder for Pca9685DeviceBuilder<E, I2C, F>
 where
     E: std::fmt::Debug,
     I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E> + Send + 'static,
-    F: Fn(u8) -> I2C,
+    F: Fn(u8) -> Result<I2C, IocBuildError>,
 {
     type Config = Pca9685DeviceConfig;
     type Module = Pca9685Device;
 
     async fn try_build(&self, cfg: &Pca9685DeviceConfig, cancel_token: CancellationToken) -> Result<Pca9685Device, IocBuildError> {
-        let i2c = (self.i2c_bus_provider)(1);
+        let i2c = (self.i2c_bus_provider)(cfg.i2c_bus)?;
         let dev = Pca9685Device::build(cfg, i2c, cancel_token)?;
         Ok(dev)
     }
 }
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::{Pca9685ChannelConfig, Pca9685ChannelMode, Pca9685ChannelSettings, Pca9685Device, Pca9685DeviceConfig};
+    use crate::mock::{pca9685::{Pca9685Model, DEFAULT_ADDRESS}, MockI2c};
+    use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+    use tokio_util::sync::CancellationToken;
+
+    fn servo(channel: u8, invert: bool, trim: f64) -> Pca9685ChannelConfig {
+        Pca9685ChannelConfig::Settings(Pca9685ChannelSettings {
+            channel,
+            mode: Pca9685ChannelMode::Angle,
+            min_us: None,
+            max_us: None,
+            min_angle: Some(-90.0),
+            max_angle: Some(90.0),
+            invert,
+            trim,
+        })
+    }
+
+    async fn wait_for_duty(pwm: &Arc<Mutex<Pca9685Model>>, channel: u8) -> f64 {
+        while pwm.lock().unwrap().duty(channel) == 0.0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        pwm.lock().unwrap().duty(channel)
+    }
+
+    #[tokio::test]
+    async fn test_pca9685_mock() {
+        let bus = MockI2c::new();
+        let pwm = bus.attach(&[DEFAULT_ADDRESS], Pca9685Model::default());
+        let config = Pca9685DeviceConfig {
+            i2c_bus: 1,
+            i2c_address: DEFAULT_ADDRESS,
+            frequency_hz: 50.0,
+            channels: HashMap::from([
+                ("light".to_string(), Pca9685ChannelConfig::Channel(3)),
+                ("pan".to_string(), servo(4, false, 0.0)),
+                ("tilt".to_string(), servo(5, true, 10.0)),
+            ]),
+        };
+        let cancel_token = CancellationToken::new();
+        let device = Pca9685Device::build(&config, bus, cancel_token.clone()).unwrap();
+        {
+            let pwm = pwm.lock().unwrap();
+            assert!(!pwm.is_sleeping());
+            assert!((pwm.frequency_hz() - 50.03).abs() < 0.01, "{}", pwm.frequency_hz());
+        }
+
+        device.channels["light"].sink().send(0.5).await.unwrap();
+        assert_eq!(wait_for_duty(&pwm, 3).await, 2047.0 / 4096.0);
+        assert_eq!(pwm.lock().unwrap().duty(2), 0.0);
+
+        //the middle of the range is a 1.5ms pulse, 307 of the 4096 counts in 20ms
+        device.channels["pan"].sink().send(0.0).await.unwrap();
+        assert_eq!(wait_for_duty(&pwm, 4).await, 307.0 / 4096.0);
+        //trimmed by 10 degrees, then inverted, 80 degrees is a 1.06ms pulse
+        device.channels["tilt"].sink().send(70.0).await.unwrap();
+        assert_eq!(wait_for_duty(&pwm, 5).await, 216.0 / 4096.0);
+
+        //every output is turned off and the chip is put to sleep on shutdown
+        cancel_token.cancel();
+        device.join_handle.await.unwrap();
+        let pwm = pwm.lock().unwrap();
+        assert!(pwm.is_sleeping());
+        assert!((0..16).all(|channel| pwm.duty(channel) == 0.0));
+    }
+
+    #[tokio::test]
+    async fn test_pca9685_invalid_config() {
+        let build = |frequency_hz: f64, channel: Pca9685ChannelConfig| {
+            let config = Pca9685DeviceConfig {
+                i2c_bus: 1,
+                i2c_address: DEFAULT_ADDRESS,
+                frequency_hz,
+                channels: HashMap::from([("servo".to_string(), channel)]),
+            };
+            Pca9685Device::build(&config, MockI2c::with_all_chips(), CancellationToken::new())
+                .err()
+                .unwrap()
+                .message
+        };
+        assert!(build(2000.0, servo(0, false, 0.0)).contains("frequency_hz"));
+        assert!(build(60.0, Pca9685ChannelConfig::Channel(16)).contains("no channel 16"));
+        //a 2ms pulse doesn't fit in a 1ms period
+        assert!(build(1000.0, servo(0, false, 0.0)).contains("max_us"));
+    }
+}
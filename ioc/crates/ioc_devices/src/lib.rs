@@ -5,3 +5,11 @@ pub mod error;
 
 ///The devices
 pub mod devices;
+
+///Sharing i2c buses between devices
+#[cfg(feature = "bus")]
+pub mod bus;
+
+///A simulated I2C bus with models of the supported chips, to run devices without hardware
+#[cfg(feature = "mock")]
+pub mod mock;
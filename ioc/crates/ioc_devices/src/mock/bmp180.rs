@@ -0,0 +1,105 @@
+//! A model of the BMP180 temperature and pressure sensor.
+
+use super::{registers, MockChip};
+
+pub const ADDRESS: u8 = 0x77;
+
+const CALIBRATION: u8 = 0xAA;
+const ID: u8 = 0xD0;
+const SOFT_RESET: u8 = 0xE0;
+const CTRL_MEAS: u8 = 0xF4;
+const OUT_MSB: u8 = 0xF6;
+
+const CHIP_ID: u8 = 0x55;
+const RESET_COMMAND: u8 = 0xB6;
+const MEASURE_TEMPERATURE: u8 = 0x0E;
+const MEASURE_PRESSURE: u8 = 0x14;
+
+///The example calibration from the datasheet, as stored in the EEPROM.
+pub const DATASHEET_CALIBRATION: [i16; 11] = [408, -72, -14383, 32741, 32757, 23153, 6190, 4, -32768, -8711, 2868];
+
+///Keeps the registers of a BMP180, including its calibration EEPROM and ID register.
+///
+/// A conversion started through the control register finishes right away, with the raw reading the model was given.
+/// The raw readings are what the chip's ADC puts out; drivers compensate them with the calibration.
+pub struct Bmp180Model {
+    registers: [u8; 256],
+    pointer: u8,
+    ut: u16,
+    up: u32,
+}
+
+impl Default for Bmp180Model {
+    ///The datasheet's calibration, reading 15.0°C and 1013.25hPa.
+    fn default() -> Self {
+        Self::new(DATASHEET_CALIBRATION, 27898, 274640)
+    }
+}
+
+impl Bmp180Model {
+    ///A chip with the given calibration coefficients, AC1 through MD in the order of the EEPROM, and raw readings. See `set_raw`.
+    pub fn new(calibration: [i16; 11], ut: u16, up: u32) -> Self {
+        let mut registers = [0u8; 256];
+        for (i, coefficient) in calibration.iter().enumerate() {
+            let [msb, lsb] = coefficient.to_be_bytes();
+            registers[CALIBRATION as usize + 2 * i] = msb;
+            registers[CALIBRATION as usize + 2 * i + 1] = lsb;
+        }
+        registers[ID as usize] = CHIP_ID;
+        let mut model = Self { registers, pointer: 0, ut, up: 0 };
+        model.set_raw(ut, up);
+        model.reset();
+        model
+    }
+
+    ///Sets the raw readings of the next conversions. up is the 19 bit reading with the highest oversampling, lower oversampling reads fewer of its bits.
+    pub fn set_raw(&mut self, ut: u16, up: u32) {
+        self.ut = ut;
+        self.up = up & 0x7FFFF;
+    }
+
+    fn reset(&mut self) {
+        self.registers[CTRL_MEAS as usize] = 0;
+        self.registers[OUT_MSB as usize..OUT_MSB as usize + 3].copy_from_slice(&[0x80, 0x00, 0x00]);
+    }
+
+    fn convert(&mut self, command: u8) {
+        self.registers[CTRL_MEAS as usize] = command;
+        let out = OUT_MSB as usize;
+        match command & 0x1F {
+            MEASURE_TEMPERATURE => {
+                self.registers[out..out + 2].copy_from_slice(&self.ut.to_be_bytes());
+            }
+            MEASURE_PRESSURE => {
+                //msb, lsb and xlsb hold the reading left aligned in 19 bits, however many bits were sampled
+                let oss = (command >> 6) as u32;
+                let up = (self.up >> (3 - oss)) << (8 - oss);
+                self.registers[out..out + 3].copy_from_slice(&up.to_be_bytes()[1..]);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MockChip for Bmp180Model {
+    fn write(&mut self, _: u8, bytes: &[u8]) {
+        let Some((&start, data)) = bytes.split_first() else {
+            return;
+        };
+        self.pointer = start;
+        for (reg, value) in registers(start, data.len(), true).zip(data) {
+            match (reg, *value) {
+                (CTRL_MEAS, command) => self.convert(command),
+                (SOFT_RESET, RESET_COMMAND) => self.reset(),
+                //everything else, including the calibration EEPROM, is read only
+                _ => {}
+            }
+        }
+    }
+
+    fn read(&mut self, _: u8, buffer: &mut [u8]) {
+        for (reg, value) in registers(self.pointer, buffer.len(), true).zip(buffer) {
+            *value = self.registers[reg as usize];
+        }
+    }
+}
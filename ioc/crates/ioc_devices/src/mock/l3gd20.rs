@@ -0,0 +1,106 @@
+//! A model of the L3GD20 gyroscope.
+
+use super::{counts, registers, MockChip};
+
+///The address with SDO pulled high. It's 0x6A with SDO low.
+pub const DEFAULT_ADDRESS: u8 = 0x6B;
+
+const WHO_AM_I: u8 = 0x0F;
+const CTRL_REG1: u8 = 0x20;
+const CTRL_REG4: u8 = 0x23;
+const OUT_TEMP: u8 = 0x26;
+const STATUS_REG: u8 = 0x27;
+const OUT_X_L: u8 = 0x28;
+
+const CHIP_ID: u8 = 0xD7;
+//the msb of the register address turns on auto increment
+const MULTI_READ: u8 = 0x80;
+const POWER_ON: u8 = 0x08;
+//x, y and z have new data
+const XYZ_DATA_AVAILABLE: u8 = 0x0F;
+
+///Keeps the registers of an L3GD20, with outputs that read the angular rate it was given.
+/// Outputs update while the chip is powered on, scaled by the configured full scale.
+pub struct L3gd20Model {
+    registers: [u8; 256],
+    pointer: u8,
+    auto_increment: bool,
+    rate_dps: [f64; 3],
+    temperature_c: f64,
+}
+
+impl Default for L3gd20Model {
+    ///Powered down after reset, at rest at 20°C.
+    fn default() -> Self {
+        let mut registers = [0u8; 256];
+        registers[WHO_AM_I as usize] = CHIP_ID;
+        registers[CTRL_REG1 as usize] = 0x07;
+        Self {
+            registers,
+            pointer: 0,
+            auto_increment: false,
+            rate_dps: [0.0; 3],
+            temperature_c: 20.0,
+        }
+    }
+}
+
+impl L3gd20Model {
+    ///Sets the angular rate around x, y and z in degrees per second.
+    pub fn set_rate_dps(&mut self, rate_dps: [f64; 3]) {
+        self.rate_dps = rate_dps;
+    }
+
+    pub fn set_temperature_c(&mut self, temperature_c: f64) {
+        self.temperature_c = temperature_c;
+    }
+
+    pub fn is_powered_on(&self) -> bool {
+        self.registers[CTRL_REG1 as usize] & POWER_ON != 0
+    }
+
+    //degrees per second per count, depending on the full scale bits
+    fn sensitivity(&self) -> f64 {
+        match (self.registers[CTRL_REG4 as usize] >> 4) & 0b11 {
+            0b00 => 0.00875,
+            0b01 => 0.0175,
+            _ => 0.07,
+        }
+    }
+
+    fn sample(&mut self) {
+        //the temperature sensor isn't calibrated. it counts down 1 per degree
+        self.registers[OUT_TEMP as usize] = (-self.temperature_c.round() as i8) as u8;
+        self.registers[STATUS_REG as usize] = XYZ_DATA_AVAILABLE;
+        for (axis, rate) in self.rate_dps.iter().enumerate() {
+            let [lsb, msb] = counts(*rate, self.sensitivity()).to_le_bytes();
+            self.registers[OUT_X_L as usize + 2 * axis] = lsb;
+            self.registers[OUT_X_L as usize + 2 * axis + 1] = msb;
+        }
+    }
+}
+
+impl MockChip for L3gd20Model {
+    fn write(&mut self, _: u8, bytes: &[u8]) {
+        let Some((&start, data)) = bytes.split_first() else {
+            return;
+        };
+        self.pointer = start & !MULTI_READ;
+        self.auto_increment = start & MULTI_READ != 0;
+        for (reg, value) in registers(self.pointer, data.len(), self.auto_increment).zip(data) {
+            //control, reference, fifo, interrupt and low odr registers are writable
+            if matches!(reg, 0x20..=0x25 | 0x2E | 0x30 | 0x32..=0x39) {
+                self.registers[reg as usize] = *value;
+            }
+        }
+    }
+
+    fn read(&mut self, _: u8, buffer: &mut [u8]) {
+        if self.is_powered_on() {
+            self.sample();
+        }
+        for (reg, value) in registers(self.pointer, buffer.len(), self.auto_increment).zip(buffer) {
+            *value = self.registers[reg as usize];
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! A model of the LSM303DLHC accelerometer and magnetometer, which answer at separate addresses.
+
+use super::{counts, registers, MockChip};
+
+pub const ACCEL_ADDRESS: u8 = 0x19;
+pub const MAG_ADDRESS: u8 = 0x1E;
+
+const CTRL_REG1_A: u8 = 0x20;
+const CTRL_REG4_A: u8 = 0x23;
+const STATUS_REG_A: u8 = 0x27;
+const OUT_X_L_A: u8 = 0x28;
+
+const CRA_REG_M: u8 = 0x00;
+const CRB_REG_M: u8 = 0x01;
+const MR_REG_M: u8 = 0x02;
+const OUT_X_H_M: u8 = 0x03;
+const SR_REG_M: u8 = 0x09;
+const TEMP_OUT_H_M: u8 = 0x31;
+
+//the msb of an accelerometer register address turns on auto increment. the magnetometer always increments
+const MULTI_READ: u8 = 0x80;
+const XYZ_DATA_AVAILABLE: u8 = 0x0F;
+const TEMP_ENABLE: u8 = 0x80;
+const MAG_DATA_READY: u8 = 0x01;
+const MAG_CONTINUOUS: u8 = 0b00;
+const MAG_SINGLE: u8 = 0b01;
+const MAG_SLEEP: u8 = 0b11;
+
+///Keeps the registers of an LSM303DLHC, with outputs that read the acceleration and magnetic field it was given.
+/// The accelerometer updates while its data rate isn't 0, the magnetometer in continuous or single conversion mode.
+pub struct Lsm303dlhcModel {
+    accel_registers: [u8; 256],
+    accel_pointer: u8,
+    accel_auto_increment: bool,
+    mag_registers: [u8; 256],
+    mag_pointer: u8,
+    accel_g: [f64; 3],
+    field_gauss: [f64; 3],
+    temperature_c: f64,
+}
+
+impl Default for Lsm303dlhcModel {
+    ///Powered down after reset. Lying flat at 20°C, in a magnetic field like the earth's at mid latitudes.
+    fn default() -> Self {
+        let mut accel_registers = [0u8; 256];
+        accel_registers[CTRL_REG1_A as usize] = 0x07;
+        let mut mag_registers = [0u8; 256];
+        mag_registers[CRA_REG_M as usize] = 0x10;
+        mag_registers[CRB_REG_M as usize] = 0x20;
+        mag_registers[MR_REG_M as usize] = MAG_SLEEP;
+        //the identification registers read "H43"
+        mag_registers[0x0A..=0x0C].copy_from_slice(b"H43");
+        Self {
+            accel_registers,
+            accel_pointer: 0,
+            accel_auto_increment: false,
+            mag_registers,
+            mag_pointer: 0,
+            accel_g: [0.0, 0.0, 1.0],
+            field_gauss: [0.2, 0.0, -0.4],
+            temperature_c: 20.0,
+        }
+    }
+}
+
+impl Lsm303dlhcModel {
+    ///Sets the acceleration along x, y and z in g.
+    pub fn set_accel_g(&mut self, accel_g: [f64; 3]) {
+        self.accel_g = accel_g;
+    }
+
+    ///Sets the magnetic field along x, y and z in gauss.
+    pub fn set_field_gauss(&mut self, field_gauss: [f64; 3]) {
+        self.field_gauss = field_gauss;
+    }
+
+    pub fn set_temperature_c(&mut self, temperature_c: f64) {
+        self.temperature_c = temperature_c;
+    }
+
+    ///Whether the accelerometer's data rate is 0 and the magnetometer is asleep.
+    pub fn is_powered_down(&self) -> bool {
+        self.accel_registers[CTRL_REG1_A as usize] >> 4 == 0 && self.mag_registers[MR_REG_M as usize] & 0b10 != 0
+    }
+
+    fn sample_accel(&mut self) {
+        //g per count of the 12 bit outputs, depending on the full scale bits. the datasheet's is coarser than 16g would need
+        let g_per_count = match (self.accel_registers[CTRL_REG4_A as usize] >> 4) & 0b11 {
+            0b00 => 0.001,
+            0b01 => 0.002,
+            0b10 => 0.004,
+            _ => 0.012,
+        };
+        self.accel_registers[STATUS_REG_A as usize] = XYZ_DATA_AVAILABLE;
+        for (axis, accel) in self.accel_g.iter().enumerate() {
+            //left aligned in 16 bits
+            let raw = counts(*accel, g_per_count / 16.0) & !0x0F;
+            let [lsb, msb] = raw.to_le_bytes();
+            self.accel_registers[OUT_X_L_A as usize + 2 * axis] = lsb;
+            self.accel_registers[OUT_X_L_A as usize + 2 * axis + 1] = msb;
+        }
+    }
+
+    fn sample_mag(&mut self) {
+        //counts per gauss for x and y, and z, depending on the gain bits
+        let (gain_xy, gain_z) = match self.mag_registers[CRB_REG_M as usize] >> 5 {
+            0 | 1 => (1100.0, 980.0),
+            2 => (855.0, 760.0),
+            3 => (670.0, 600.0),
+            4 => (450.0, 400.0),
+            5 => (400.0, 355.0),
+            6 => (330.0, 295.0),
+            _ => (230.0, 205.0),
+        };
+        let [x, y, z] = self.field_gauss;
+        //the outputs are big endian, in the order x, z, y
+        let outputs = [counts(x, 1.0 / gain_xy), counts(z, 1.0 / gain_z), counts(y, 1.0 / gain_xy)];
+        for (i, raw) in outputs.iter().enumerate() {
+            let [msb, lsb] = raw.to_be_bytes();
+            self.mag_registers[OUT_X_H_M as usize + 2 * i] = msb;
+            self.mag_registers[OUT_X_H_M as usize + 2 * i + 1] = lsb;
+        }
+        self.mag_registers[SR_REG_M as usize] = MAG_DATA_READY;
+        if self.mag_registers[CRA_REG_M as usize] & TEMP_ENABLE != 0 {
+            //8 counts per degree, left aligned in 16 bits
+            let raw = counts(self.temperature_c, 1.0 / 8.0) << 4;
+            let [msb, lsb] = raw.to_be_bytes();
+            self.mag_registers[TEMP_OUT_H_M as usize] = msb;
+            self.mag_registers[TEMP_OUT_H_M as usize + 1] = lsb;
+        }
+    }
+}
+
+impl MockChip for Lsm303dlhcModel {
+    fn write(&mut self, address: u8, bytes: &[u8]) {
+        let Some((&start, data)) = bytes.split_first() else {
+            return;
+        };
+        if address == ACCEL_ADDRESS {
+            self.accel_pointer = start & !MULTI_READ;
+            self.accel_auto_increment = start & MULTI_READ != 0;
+            for (reg, value) in registers(self.accel_pointer, data.len(), self.accel_auto_increment).zip(data) {
+                //control, fifo, interrupt and click registers are writable
+                if matches!(reg, 0x20..=0x26 | 0x2E | 0x30 | 0x32..=0x34 | 0x36..=0x38 | 0x3A..=0x3D) {
+                    self.accel_registers[reg as usize] = *value;
+                }
+            }
+        } else {
+            self.mag_pointer = start;
+            for (reg, value) in registers(start, data.len(), true).zip(data) {
+                if reg <= MR_REG_M {
+                    self.mag_registers[reg as usize] = *value;
+                }
+            }
+        }
+    }
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) {
+        if address == ACCEL_ADDRESS {
+            if self.accel_registers[CTRL_REG1_A as usize] >> 4 != 0 {
+                self.sample_accel();
+            }
+            for (reg, value) in registers(self.accel_pointer, buffer.len(), self.accel_auto_increment).zip(buffer) {
+                *value = self.accel_registers[reg as usize];
+            }
+        } else {
+            match self.mag_registers[MR_REG_M as usize] & 0b11 {
+                MAG_CONTINUOUS => self.sample_mag(),
+                MAG_SINGLE => {
+                    self.sample_mag();
+                    self.mag_registers[MR_REG_M as usize] = MAG_SLEEP;
+                }
+                _ => {}
+            }
+            for (reg, value) in registers(self.mag_pointer, buffer.len(), true).zip(buffer) {
+                *value = self.mag_registers[reg as usize];
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! A `MockI2c` bus behaves like a real I2C bus with chips attached to it, but the chips are models that live in memory.
+//!
+//! The models work at the register level, like the chips they stand in for: drivers select registers, write
+//! configuration and read measurements the same way they would on hardware. Tests and dev boxes change what
+//! a model measures, or check what was written to it, through the handle `MockI2c::attach` returns.
+
+pub mod bmp180;
+pub mod l3gd20;
+pub mod lsm303dlhc;
+pub mod pca9685;
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt::{Debug, Formatter},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use embedded_hal::i2c::{self, ErrorKind, NoAcknowledgeSource, Operation};
+use embedded_hal_0::blocking::i2c as i2c_0;
+
+///A chip attached to a `MockI2c` bus.
+pub trait MockChip: Send {
+    ///Handles a write to one of the chip's addresses. Usually the first byte selects a register.
+    fn write(&mut self, address: u8, bytes: &[u8]);
+
+    ///Fills the buffer with what the chip sends for a read from one of its addresses.
+    fn read(&mut self, address: u8, buffer: &mut [u8]);
+}
+
+type SharedChip = Arc<Mutex<dyn MockChip>>;
+
+///A simulated I2C bus. Clones share the same chips, like handles to the same hardware bus.
+#[derive(Clone, Default)]
+pub struct MockI2c {
+    chips: Arc<Mutex<HashMap<u8, SharedChip>>>,
+}
+
+///The only way a transfer on a `MockI2c` fails: no chip acknowledged the address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockI2cError {
+    pub address: u8,
+}
+
+impl i2c::Error for MockI2cError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+    }
+}
+
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl MockI2c {
+    ///A bus with nothing attached.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///A bus with a model of each supported chip attached at its default address.
+    pub fn with_all_chips() -> Self {
+        let bus = Self::new();
+        bus.attach(&[pca9685::DEFAULT_ADDRESS], pca9685::Pca9685Model::default());
+        bus.attach(&[bmp180::ADDRESS], bmp180::Bmp180Model::default());
+        bus.attach(&[l3gd20::DEFAULT_ADDRESS], l3gd20::L3gd20Model::default());
+        bus.attach(
+            &[lsm303dlhc::ACCEL_ADDRESS, lsm303dlhc::MAG_ADDRESS],
+            lsm303dlhc::Lsm303dlhcModel::default(),
+        );
+        bus
+    }
+
+    ///Attaches a chip that answers at the given addresses, replacing whatever was attached there.
+    /// Returns a handle to the chip, to change what it measures or check what was written to it.
+    pub fn attach<C: MockChip + 'static>(&self, addresses: &[u8], chip: C) -> Arc<Mutex<C>> {
+        let chip = Arc::new(Mutex::new(chip));
+        let mut chips = lock(&self.chips);
+        for address in addresses {
+            chips.insert(*address, chip.clone());
+        }
+        chip
+    }
+
+    fn transfer(&self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), MockI2cError> {
+        let chip = lock(&self.chips).get(&address).cloned().ok_or(MockI2cError { address })?;
+        let mut chip = lock(&chip);
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => chip.write(address, bytes),
+                Operation::Read(buffer) => chip.read(address, buffer),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Debug for MockI2c {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let mut addresses: Vec<u8> = lock(&self.chips).keys().copied().collect();
+        addresses.sort();
+        fmt.debug_struct("MockI2c").field("addresses", &addresses).finish()
+    }
+}
+
+impl i2c::ErrorType for MockI2c {
+    type Error = MockI2cError;
+}
+
+impl i2c::I2c for MockI2c {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), MockI2cError> {
+        self.transfer(address, operations)
+    }
+}
+
+impl i2c_0::Write for MockI2c {
+    type Error = MockI2cError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), MockI2cError> {
+        self.transfer(address, &mut [Operation::Write(bytes)])
+    }
+}
+
+impl i2c_0::WriteRead for MockI2c {
+    type Error = MockI2cError;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), MockI2cError> {
+        self.transfer(address, &mut [Operation::Write(bytes), Operation::Read(buffer)])
+    }
+}
+
+///Either a hardware I2C bus or a `MockI2c`, so which one devices use can be chosen at runtime.
+#[derive(Debug)]
+pub enum I2cBus<B> {
+    Hardware(B),
+    Mock(MockI2c),
+}
+
+#[derive(Debug)]
+pub enum I2cBusError<E> {
+    Hardware(E),
+    Mock(MockI2cError),
+}
+
+impl<E: i2c::Error> i2c::Error for I2cBusError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Hardware(err) => err.kind(),
+            Self::Mock(err) => err.kind(),
+        }
+    }
+}
+
+impl<B: i2c::I2c> i2c::ErrorType for I2cBus<B> {
+    type Error = I2cBusError<B::Error>;
+}
+
+impl<B: i2c::I2c> i2c::I2c for I2cBus<B> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        match self {
+            Self::Hardware(bus) => bus.transaction(address, operations).map_err(I2cBusError::Hardware),
+            Self::Mock(bus) => bus.transaction(address, operations).map_err(I2cBusError::Mock),
+        }
+    }
+}
+
+impl<B: i2c_0::Write> i2c_0::Write for I2cBus<B> {
+    type Error = I2cBusError<B::Error>;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        match self {
+            Self::Hardware(bus) => bus.write(address, bytes).map_err(I2cBusError::Hardware),
+            Self::Mock(bus) => i2c_0::Write::write(bus, address, bytes).map_err(I2cBusError::Mock),
+        }
+    }
+}
+
+impl<B: i2c_0::WriteRead> i2c_0::WriteRead for I2cBus<B> {
+    type Error = I2cBusError<B::Error>;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        match self {
+            Self::Hardware(bus) => bus.write_read(address, bytes, buffer).map_err(I2cBusError::Hardware),
+            Self::Mock(bus) => i2c_0::WriteRead::write_read(bus, address, bytes, buffer).map_err(I2cBusError::Mock),
+        }
+    }
+}
+
+///The hardware bus type of an `I2cBus` in builds that can't access hardware. It can't be constructed.
+#[derive(Debug)]
+pub enum NoHardware {}
+
+impl i2c::ErrorType for NoHardware {
+    type Error = Infallible;
+}
+
+impl i2c::I2c for NoHardware {
+    fn transaction(&mut self, _: u8, _: &mut [Operation<'_>]) -> Result<(), Infallible> {
+        match *self {}
+    }
+}
+
+impl i2c_0::Write for NoHardware {
+    type Error = Infallible;
+
+    fn write(&mut self, _: u8, _: &[u8]) -> Result<(), Infallible> {
+        match *self {}
+    }
+}
+
+impl i2c_0::WriteRead for NoHardware {
+    type Error = Infallible;
+
+    fn write_read(&mut self, _: u8, _: &[u8], _: &mut [u8]) -> Result<(), Infallible> {
+        match *self {}
+    }
+}
+
+//the registers a transfer of len bytes starting at start touches, one per byte
+fn registers(start: u8, len: usize, auto_increment: bool) -> impl Iterator<Item = u8> {
+    (0..len).map(move |i| if auto_increment { start.wrapping_add(i as u8) } else { start })
+}
+
+//converts a measurement to raw counts, saturating like the chips' outputs do
+fn counts(value: f64, per_count: f64) -> i16 {
+    (value / per_count).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MockChip, MockI2c, MockI2cError};
+    use embedded_hal::i2c::I2c;
+
+    #[derive(Default)]
+    struct Echo {
+        last: Vec<u8>,
+    }
+
+    impl MockChip for Echo {
+        fn write(&mut self, _: u8, bytes: &[u8]) {
+            self.last = bytes.to_vec();
+        }
+
+        fn read(&mut self, address: u8, buffer: &mut [u8]) {
+            buffer.fill(address);
+            buffer[0] = self.last.len() as u8;
+        }
+    }
+
+    #[test]
+    fn test_mock_i2c() {
+        let mut bus = MockI2c::new();
+        let echo = bus.clone().attach(&[0x10, 0x11], Echo::default());
+
+        //both addresses reach the same chip, through any clone of the bus
+        bus.write(0x10, &[1, 2, 3]).unwrap();
+        let mut buffer = [0u8; 2];
+        bus.write_read(0x11, &[4], &mut buffer).unwrap();
+        assert_eq!(buffer, [1, 0x11]);
+        assert_eq!(echo.lock().unwrap().last, vec![4]);
+
+        assert_eq!(bus.write(0x12, &[0]), Err(MockI2cError { address: 0x12 }));
+    }
+}
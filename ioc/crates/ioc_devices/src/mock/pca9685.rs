@@ -0,0 +1,114 @@
+//! A model of the PCA9685 16 channel pwm controller.
+
+use super::{registers, MockChip};
+
+///The address with all address pins low.
+pub const DEFAULT_ADDRESS: u8 = 0x40;
+
+const MODE1: u8 = 0x00;
+const MODE2: u8 = 0x01;
+const LED0_ON_L: u8 = 0x06;
+const ALL_LED_ON_L: u8 = 0xFA;
+const PRE_SCALE: u8 = 0xFE;
+
+const MODE1_SLEEP: u8 = 0x10;
+const MODE1_AUTO_INCREMENT: u8 = 0x20;
+//bit 4 of the high byte of a channel's on or off count turns it fully on or off
+const FULL: u8 = 0x10;
+
+const OSCILLATOR_HZ: f64 = 25_000_000.0;
+
+///Keeps the registers of a PCA9685. Tells the duty cycle of each channel and the pwm frequency they were configured for.
+pub struct Pca9685Model {
+    registers: [u8; 256],
+    pointer: u8,
+}
+
+impl Default for Pca9685Model {
+    ///Registers as they are after power up: asleep, with every channel fully off.
+    fn default() -> Self {
+        let mut registers = [0u8; 256];
+        registers[MODE1 as usize] = 0x11;
+        registers[MODE2 as usize] = 0x04;
+        registers[PRE_SCALE as usize] = 0x1E;
+        for channel in 0..16 {
+            registers[channel_register(channel) as usize + 3] = FULL;
+        }
+        registers[ALL_LED_ON_L as usize + 3] = FULL;
+        Self { registers, pointer: 0 }
+    }
+}
+
+fn channel_register(channel: u8) -> u8 {
+    LED0_ON_L + 4 * channel
+}
+
+impl Pca9685Model {
+    ///Whether the oscillator is off, so no channel is putting out pwm.
+    pub fn is_sleeping(&self) -> bool {
+        self.registers[MODE1 as usize] & MODE1_SLEEP != 0
+    }
+
+    pub fn prescale(&self) -> u8 {
+        self.registers[PRE_SCALE as usize]
+    }
+
+    ///The pwm frequency with the internal oscillator.
+    pub fn frequency_hz(&self) -> f64 {
+        OSCILLATOR_HZ / (4096.0 * (self.prescale() as f64 + 1.0))
+    }
+
+    ///The fraction of each period the channel is on, from 0.0 to 1.0.
+    pub fn duty(&self, channel: u8) -> f64 {
+        let reg = channel_register(channel) as usize;
+        let count = |reg: usize| ((self.registers[reg + 1] as u16 & 0x0F) << 8) | self.registers[reg] as u16;
+        //full off takes precedence over full on
+        if self.registers[reg + 3] & FULL != 0 {
+            0.0
+        } else if self.registers[reg + 1] & FULL != 0 {
+            1.0
+        } else {
+            let (on, off) = (count(reg), count(reg + 2));
+            ((off + 4096 - on) % 4096) as f64 / 4096.0
+        }
+    }
+
+    fn write_register(&mut self, reg: u8, value: u8) {
+        match reg {
+            //the prescaler can only be set while the oscillator is off
+            PRE_SCALE if !self.is_sleeping() => {}
+            PRE_SCALE => self.registers[PRE_SCALE as usize] = value.max(3),
+            //the all channel registers write to every channel
+            ALL_LED_ON_L..=0xFD => {
+                for channel in 0..16 {
+                    self.registers[(channel_register(channel) + (reg - ALL_LED_ON_L)) as usize] = value;
+                }
+                self.registers[reg as usize] = value;
+            }
+            0x46..=0xF9 | 0xFF => {}
+            _ => self.registers[reg as usize] = value,
+        }
+    }
+
+    fn auto_increment(&self) -> bool {
+        self.registers[MODE1 as usize] & MODE1_AUTO_INCREMENT != 0
+    }
+}
+
+impl MockChip for Pca9685Model {
+    fn write(&mut self, _: u8, bytes: &[u8]) {
+        let Some((&start, data)) = bytes.split_first() else {
+            return;
+        };
+        self.pointer = start;
+        for (reg, value) in registers(start, data.len(), self.auto_increment()).zip(data) {
+            self.write_register(reg, *value);
+        }
+    }
+
+    fn read(&mut self, _: u8, buffer: &mut [u8]) {
+        for (reg, value) in registers(self.pointer, buffer.len(), self.auto_increment()).zip(buffer) {
+            *value = self.registers[reg as usize];
+        }
+    }
+}
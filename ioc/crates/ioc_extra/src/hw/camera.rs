@@ -7,6 +7,7 @@ use ioc_core::{
     error::IocBuildError, Input, InputKind, Module, ModuleIO, Output, OutputKind
 };
 use serde::Deserialize;
+use schemars::JsonSchema;
 use tokio_util::sync::CancellationToken;
 use std::collections::HashMap;
 use tokio::task::JoinHandle;
@@ -39,7 +40,7 @@ impl From<Camera> for ModuleIO {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct CameraConfig {}
 
 impl Module for Camera {
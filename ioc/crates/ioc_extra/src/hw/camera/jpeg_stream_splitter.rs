@@ -1,5 +1,5 @@
 
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::AsyncReadExt;
 use tokio::sync::watch;
 use tokio::task::JoinHandle;
 use tracing::{debug, warn};
@@ -15,7 +15,7 @@ enum SplitJpegState {
 }
 
 pub fn split_jpegs(
-    mut byte_stream: impl AsyncRead + AsyncReadExt + Send + Unpin + 'static,
+    mut byte_stream: impl AsyncReadExt + Send + Unpin + 'static,
     frame_tx: watch::Sender<Vec<u8>>,
 ) -> JoinHandle<watch::Sender<Vec<u8>>> {
 
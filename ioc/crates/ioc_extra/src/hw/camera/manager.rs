@@ -57,11 +57,8 @@ impl CameraDisabled {
     }
 
     async fn transition(&mut self, _params: &CameraParams) {
-        match self.frames.take() {
-            Some(frames) => {
-                self.camevt_tx.send(CameraEvt::StreamFinished(frames)).await.unwrap();
-            },
-            None => {}
+        if let Some(frames) = self.frames.take() {
+            self.camevt_tx.send(CameraEvt::StreamFinished(frames)).await.unwrap();
         }
     }
 }
@@ -164,7 +161,7 @@ fn spawn_watch_camera_params(
                 enable_o = enable.recv() => {
                     if let Some(enabled) = enable_o {
                         params.enabled = enabled;
-                        if let Err(_) = params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await {
+                        if params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await.is_err() {
                             break;
                         }
                     } else {
@@ -173,8 +170,8 @@ fn spawn_watch_camera_params(
                 },
                 q_o = q.recv() => {
                     if let Some(q_val) = q_o {
-                        params.q = q_val.max(0.0).min(100.0) as u8;
-                        if let Err(_) = params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await {
+                        params.q = q_val.clamp(0.0, 100.0) as u8;
+                        if params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await.is_err() {
                             break;
                         }
                     } else {
@@ -183,8 +180,8 @@ fn spawn_watch_camera_params(
                 },
                 framerate_o = framerate.recv() => {
                     if let Some(framerate_val) = framerate_o {
-                        params.framerate = framerate_val.max(1.0).min(60.0) as u8;
-                        if let Err(_) = params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await {
+                        params.framerate = framerate_val.clamp(1.0, 60.0) as u8;
+                        if params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await.is_err() {
                             break;
                         }
                     } else {
@@ -198,7 +195,7 @@ fn spawn_watch_camera_params(
                             if let (Ok(w), Ok(h)) = (parts[0].parse::<usize>(), parts[1].parse::<usize>()) {
                                 params.w = w;
                                 params.h = h;
-                                if let Err(_) = params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await {
+                                if params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await.is_err() {
                                     break;
                                 }
                             } else {
@@ -218,7 +215,7 @@ fn spawn_watch_camera_params(
                         } else {
                             params.tuning_file = Some(tuning_file_val);
                         }
-                        if let Err(_) = params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await {
+                        if params_tx.send(CameraEvt::ParamsUpdated(params.clone())).await.is_err() {
                             break;
                         }
                     } else {
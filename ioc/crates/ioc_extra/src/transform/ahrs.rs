@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use ioc_core::{clock::Clock, error::IocBuildError, Input, InputKind, Transformer, TransformerI, Value};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::debug;
+
+fn default_beta() -> f64 {
+    0.1
+}
+
+fn default_kp() -> f64 {
+    1.0
+}
+
+///How the gyro is corrected with the accelerometer and magnetometer.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+pub enum AhrsFilter {
+    ///Madgwick's gradient descent filter. beta, 0.1 by default, is how fast it corrects the gyro, in radians per second.
+    /// Larger values follow the accelerometer and magnetometer more closely, and their noise with them.
+    Madgwick {
+        #[serde(default = "default_beta")]
+        beta: f64,
+    },
+    ///Mahony's complementary filter. kp, 1 by default, corrects the gyro in proportion to the error,
+    /// and ki, 0 by default, integrates the error to remove the gyro's bias.
+    Mahony {
+        #[serde(default = "default_kp")]
+        kp: f64,
+        #[serde(default)]
+        ki: f64,
+    },
+}
+
+impl Default for AhrsFilter {
+    fn default() -> Self {
+        AhrsFilter::Madgwick { beta: default_beta() }
+    }
+}
+
+pub struct AhrsConfig<'a> {
+    ///Angular rates around x, y and z in radians per second.
+    pub gyro: &'a Input<Vec<Value>>,
+    ///Accelerations along x, y and z, in any unit. Reads up when the sensor is level.
+    pub accelerometer: &'a Input<Vec<Value>>,
+    ///Magnetic field along x, y and z, in any unit. Without it yaw drifts with the gyro and there is no heading.
+    pub magnetometer: Option<&'a Input<Vec<Value>>>,
+    pub filter: AhrsFilter,
+    pub period_ms: u64,
+    pub clock: &'a Clock,
+}
+
+///Fuses a gyro, accelerometer and magnetometer into the orientation of the sensor.
+pub struct Ahrs {
+    pub join_handle: JoinHandle<()>,
+    ///The orientation as a unit quaternion [w, x, y, z] that rotates the sensor's axes onto the earth's:
+    /// x to magnetic north, y to west and z up.
+    pub quaternion: Input<Vec<Value>>,
+    ///Rotation around x in radians, applied after pitch and yaw.
+    pub roll: Input<f64>,
+    ///Rotation around y in radians, from -pi/2 to pi/2.
+    pub pitch: Input<f64>,
+    ///Rotation around z in radians, counterclockwise from magnetic north when seen from above.
+    pub yaw: Input<f64>,
+    ///The angle of the magnetic field from the x axis in the horizontal plane, like `Heading` emits when the sensor is level,
+    /// but corrected for tilt. It's clockwise from north, NaN without a magnetometer.
+    pub heading: Input<f64>,
+}
+
+impl From<Ahrs> for TransformerI {
+    fn from(ahrs: Ahrs) -> Self {
+        TransformerI {
+            join_handle: ahrs.join_handle,
+            inputs: HashMap::from([
+                ("quaternion".to_string(), InputKind::Array(ahrs.quaternion)),
+                ("roll".to_string(), InputKind::Float(ahrs.roll)),
+                ("pitch".to_string(), InputKind::Float(ahrs.pitch)),
+                ("yaw".to_string(), InputKind::Float(ahrs.yaw)),
+                ("heading".to_string(), InputKind::Float(ahrs.heading)),
+            ]),
+        }
+    }
+}
+
+type Quaternion = [f64; 4];
+type Vector = [f64; 3];
+
+fn multiply(a: Quaternion, b: Quaternion) -> Quaternion {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+fn conjugate(q: Quaternion) -> Quaternion {
+    [q[0], -q[1], -q[2], -q[3]]
+}
+
+///rotates a vector in the sensor's frame into the earth's
+fn to_earth(q: Quaternion, v: Vector) -> Vector {
+    let r = multiply(multiply(q, [0.0, v[0], v[1], v[2]]), conjugate(q));
+    [r[1], r[2], r[3]]
+}
+
+///rotates a vector in the earth's frame into the sensor's
+fn to_sensor(q: Quaternion, v: Vector) -> Vector {
+    to_earth(conjugate(q), v)
+}
+
+fn cross(a: Vector, b: Vector) -> Vector {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+///returns None for vectors that have no direction
+fn normalize<const N: usize>(v: [f64; N]) -> Option<[f64; N]> {
+    let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm > 0.0 && norm.is_finite() {
+        Some(v.map(|x| x / norm))
+    } else {
+        None
+    }
+}
+
+fn wrap_angle(angle: f64) -> f64 {
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
+
+///The quaternion for rotations around x, then y, then z.
+pub fn from_euler(roll: f64, pitch: f64, yaw: f64) -> [f64; 4] {
+    let (sr, cr) = (roll / 2.0).sin_cos();
+    let (sp, cp) = (pitch / 2.0).sin_cos();
+    let (sy, cy) = (yaw / 2.0).sin_cos();
+    [
+        cr * cp * cy + sr * sp * sy,
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+    ]
+}
+
+///The roll, pitch and yaw of a unit quaternion.
+pub fn to_euler(q: [f64; 4]) -> [f64; 3] {
+    let [w, x, y, z] = q;
+    [
+        (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y)),
+        (2.0 * (w * y - x * z)).clamp(-1.0, 1.0).asin(),
+        (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z)),
+    ]
+}
+
+///The orientation the accelerometer and magnetometer read right now, ignoring the gyro.
+fn measured(accel: Vector, mag: Option<Vector>) -> Quaternion {
+    let roll = accel[1].atan2(accel[2]);
+    let pitch = (-accel[0]).atan2(accel[1].hypot(accel[2]));
+    let tilt = from_euler(roll, pitch, 0.0);
+    let yaw = match mag {
+        Some(mag) => {
+            let horizontal = to_earth(tilt, mag);
+            -horizontal[1].atan2(horizontal[0])
+        }
+        None => 0.0,
+    };
+    multiply(from_euler(0.0, 0.0, yaw), tilt)
+}
+
+///Internal state of the filter, stepped with readings taken dt seconds apart.
+pub struct AhrsState {
+    filter: AhrsFilter,
+    q: Quaternion,
+    //the integral of the error for mahony's ki, in radians per second
+    integral: Vector,
+}
+
+impl AhrsState {
+    pub fn new(filter: AhrsFilter, q: [f64; 4]) -> Self {
+        AhrsState {
+            filter,
+            q,
+            integral: [0.0; 3],
+        }
+    }
+
+    pub fn quaternion(&self) -> [f64; 4] {
+        self.q
+    }
+
+    ///Rotates the orientation by the gyro's rates for dt seconds, corrected towards the accelerometer and magnetometer.
+    /// Readings with no direction, like an accelerometer in free fall, are ignored.
+    pub fn step(&mut self, gyro: [f64; 3], accel: Option<[f64; 3]>, mag: Option<[f64; 3]>, dt: f64) {
+        let accel = accel.and_then(normalize);
+        let mag = mag.and_then(normalize);
+        let q_dot = match self.filter {
+            AhrsFilter::Madgwick { beta } => self.madgwick(gyro, accel, mag, beta),
+            AhrsFilter::Mahony { kp, ki } => self.mahony(gyro, accel, mag, kp, ki, dt),
+        };
+        let q = [0, 1, 2, 3].map(|i| self.q[i] + q_dot[i] * dt);
+        //a NaN reading would stick forever, so it's dropped
+        if let Some(q) = normalize(q) {
+            self.q = q;
+        }
+    }
+
+    fn madgwick(&self, gyro: Vector, accel: Option<Vector>, mag: Option<Vector>, beta: f64) -> Quaternion {
+        let q_dot = multiply(self.q, [0.0, gyro[0], gyro[1], gyro[2]]).map(|x| x * 0.5);
+        let Some(a) = accel else {
+            return q_dot;
+        };
+        let [w, x, y, z] = self.q;
+
+        //gradient of the difference between where gravity should be, straight up, and where the accelerometer says it is
+        let f1 = 2.0 * (x * z - w * y) - a[0];
+        let f2 = 2.0 * (w * x + y * z) - a[1];
+        let f3 = 2.0 * (0.5 - x * x - y * y) - a[2];
+        let mut s = [
+            -2.0 * y * f1 + 2.0 * x * f2,
+            2.0 * z * f1 + 2.0 * w * f2 - 4.0 * x * f3,
+            -2.0 * w * f1 + 2.0 * z * f2 - 4.0 * y * f3,
+            2.0 * x * f1 + 2.0 * y * f2,
+        ];
+
+        //the same for the magnetic field, which points north and down or up by the field's inclination
+        if let Some(m) = mag {
+            let h = to_earth(self.q, m);
+            let bx = 2.0 * h[0].hypot(h[1]);
+            let bz = 2.0 * h[2];
+            let f1 = bx * (0.5 - y * y - z * z) + bz * (x * z - w * y) - m[0];
+            let f2 = bx * (x * y - w * z) + bz * (w * x + y * z) - m[1];
+            let f3 = bx * (w * y + x * z) + bz * (0.5 - x * x - y * y) - m[2];
+            s[0] += -bz * y * f1 + (-bx * z + bz * x) * f2 + bx * y * f3;
+            s[1] += bz * z * f1 + (bx * y + bz * w) * f2 + (bx * z - 2.0 * bz * x) * f3;
+            s[2] += (-2.0 * bx * y - bz * w) * f1 + (bx * x + bz * z) * f2 + (bx * w - 2.0 * bz * y) * f3;
+            s[3] += (-2.0 * bx * z + bz * x) * f1 + (-bx * w + bz * y) * f2 + bx * x * f3;
+        }
+
+        match normalize(s) {
+            Some(s) => [0, 1, 2, 3].map(|i| q_dot[i] - beta * s[i]),
+            None => q_dot,
+        }
+    }
+
+    fn mahony(&mut self, gyro: Vector, accel: Option<Vector>, mag: Option<Vector>, kp: f64, ki: f64, dt: f64) -> Quaternion {
+        let mut error = [0.0; 3];
+        if let Some(a) = accel {
+            //the rotation that would turn the measured gravity onto the estimated one
+            let up = to_sensor(self.q, [0.0, 0.0, 1.0]);
+            error = cross(a, up);
+            if let Some(m) = mag {
+                let h = to_earth(self.q, m);
+                let north = to_sensor(self.q, [h[0].hypot(h[1]), 0.0, h[2]]);
+                let mag_error = cross(m, north);
+                error = [0, 1, 2].map(|i| error[i] + mag_error[i]);
+            }
+        }
+        if ki > 0.0 {
+            self.integral = [0, 1, 2].map(|i| self.integral[i] + ki * error[i] * dt);
+        }
+        let rate = [0, 1, 2].map(|i| gyro[i] + kp * error[i] + self.integral[i]);
+        multiply(self.q, [0.0, rate[0], rate[1], rate[2]]).map(|x| x * 0.5)
+    }
+
+    ///The tilt compensated heading for a magnetometer reading, see `Ahrs::heading`.
+    pub fn heading(&self, mag: [f64; 3]) -> f64 {
+        let h = to_earth(self.q, mag);
+        let yaw = to_euler(self.q)[2];
+        wrap_angle(h[1].atan2(h[0]) - yaw)
+    }
+}
+
+fn vector(values: &[Value]) -> Option<Vector> {
+    match values {
+        [x, y, z, ..] => Some([x.as_f64()?, y.as_f64()?, z.as_f64()?]),
+        _ => None,
+    }
+}
+
+//waits for the next reading of an optional input, forever if there is none
+async fn next(rx: &mut Option<watch::Receiver<Vec<Value>>>) -> Option<Option<Vector>> {
+    match rx {
+        Some(rx) => match rx.changed().await {
+            Ok(_) => Some(vector(&rx.borrow_and_update())),
+            Err(_) => None,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+impl<'a> Transformer<'a> for Ahrs {
+    type Config = AhrsConfig<'a>;
+
+    async fn try_build(cfg: &AhrsConfig<'a>) -> Result<Ahrs, IocBuildError> {
+        if cfg.period_ms == 0 {
+            return Err(IocBuildError::message("ahrs period_ms must be greater than 0"));
+        }
+        let gains_valid = match cfg.filter {
+            AhrsFilter::Madgwick { beta } => beta >= 0.0 && beta.is_finite(),
+            AhrsFilter::Mahony { kp, ki } => kp >= 0.0 && kp.is_finite() && ki >= 0.0 && ki.is_finite(),
+        };
+        if !gains_valid {
+            return Err(IocBuildError::message("ahrs gains must be positive numbers"));
+        }
+
+        let mut gyro_rx = cfg.gyro.source();
+        let mut accel_rx = cfg.accelerometer.source();
+        let mut mag_rx = cfg.magnetometer.map(|mag| mag.source());
+        let mut gyro = vector(&gyro_rx.borrow_and_update());
+        let mut accel = vector(&accel_rx.borrow_and_update());
+        let mut mag = mag_rx.as_mut().and_then(|rx| vector(&rx.borrow_and_update()));
+
+        //starts where the accelerometer and magnetometer say, so it doesn't have to converge at the gain's pace
+        let mut state = accel.map(|accel| AhrsState::new(cfg.filter, measured(accel, mag)));
+        let start = state.as_ref().map(|state| state.quaternion()).unwrap_or([1.0, 0.0, 0.0, 0.0]);
+        let start_heading = match (&state, mag) {
+            (Some(state), Some(mag)) => state.heading(mag),
+            _ => f64::NAN,
+        };
+        let [roll_start, pitch_start, yaw_start] = to_euler(start);
+        let (quaternion, quaternion_tx) = Input::new(start.iter().map(|x| Value::Float(*x)).collect());
+        let (roll, roll_tx) = Input::new(roll_start);
+        let (pitch, pitch_tx) = Input::new(pitch_start);
+        let (yaw, yaw_tx) = Input::new(yaw_start);
+        let (heading, heading_tx) = Input::new(start_heading);
+
+        let filter = cfg.filter;
+        let period = Duration::from_millis(cfg.period_ms);
+        let clock = cfg.clock.clone();
+        let join_handle = tokio::spawn(async move {
+            let mut last_step = clock.elapsed();
+            loop {
+                tokio::select! {
+                    _ = clock.sleep(period) => {
+                        let now = clock.elapsed();
+                        let dt = now.saturating_sub(last_step).as_secs_f64();
+                        last_step = now;
+                        if state.is_none() {
+                            state = accel.map(|accel| AhrsState::new(filter, measured(accel, mag)));
+                        }
+                        //nothing to start from until the accelerometer reads
+                        let Some(state) = state.as_mut() else {
+                            continue;
+                        };
+                        state.step(gyro.unwrap_or([0.0; 3]), accel, mag, dt);
+
+                        let q = state.quaternion();
+                        let [r, p, y] = to_euler(q);
+                        let h = mag.map(|mag| state.heading(mag)).unwrap_or(f64::NAN);
+                        if quaternion_tx.send(q.iter().map(|x| Value::Float(*x)).collect()).is_err()
+                            || roll_tx.send(r).is_err()
+                            || pitch_tx.send(p).is_err()
+                            || yaw_tx.send(y).is_err()
+                            || heading_tx.send(h).is_err()
+                        {
+                            break;
+                        }
+                    },
+                    gyro_res = gyro_rx.changed() => {
+                        if gyro_res.is_err() {
+                            break;
+                        }
+                        gyro = vector(&gyro_rx.borrow_and_update()).or(gyro);
+                    },
+                    accel_res = accel_rx.changed() => {
+                        if accel_res.is_err() {
+                            break;
+                        }
+                        accel = vector(&accel_rx.borrow_and_update()).or(accel);
+                    },
+                    mag_res = next(&mut mag_rx) => {
+                        match mag_res {
+                            Some(reading) => mag = reading.or(mag),
+                            None => break,
+                        }
+                    },
+                }
+            }
+            debug!("ahrs shut down");
+        });
+
+        Ok(Ahrs {
+            join_handle,
+            quaternion,
+            roll,
+            pitch,
+            yaw,
+            heading,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_euler, to_euler, to_sensor, vector, Ahrs, AhrsConfig, AhrsFilter, AhrsState, Vector};
+    use ioc_core::{clock::Clock, Input, Transformer, Value};
+    use std::time::{Duration, SystemTime};
+
+    //a field pointing north and 60 degrees down, and gravity, as a sensor with orientation q reads them
+    fn readings(q: [f64; 4]) -> (Vector, Vector) {
+        let accel = to_sensor(q, [0.0, 0.0, 9.81]);
+        let mag = to_sensor(q, [25.0, 0.0, -43.3]);
+        (accel, mag)
+    }
+
+    fn assert_angles(actual: [f64; 3], expected: [f64; 3], tolerance: f64) {
+        for axis in 0..3 {
+            assert!((actual[axis] - expected[axis]).abs() < tolerance, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_euler() {
+        let angles = [0.3, -0.2, 2.5];
+        assert_angles(to_euler(from_euler(angles[0], angles[1], angles[2])), angles, 1e-12);
+    }
+
+    #[test]
+    fn test_converges() {
+        let angles = [0.4, -0.3, 2.0];
+        let (accel, mag) = readings(from_euler(angles[0], angles[1], angles[2]));
+        //madgwick's steps are always beta * dt long, so it settles within a few of them of the orientation
+        for (filter, tolerance) in [
+            (AhrsFilter::Madgwick { beta: 0.1 }, 0.005),
+            (AhrsFilter::Mahony { kp: 2.0, ki: 0.0 }, 1e-3),
+        ] {
+            let mut state = AhrsState::new(filter, [1.0, 0.0, 0.0, 0.0]);
+            for _ in 0..4000 {
+                state.step([0.0; 3], Some(accel), Some(mag), 0.01);
+            }
+            assert_angles(to_euler(state.quaternion()), angles, tolerance);
+            assert!((state.heading(mag) + 2.0).abs() < tolerance, "{:?} {}", filter, state.heading(mag));
+        }
+    }
+
+    #[test]
+    fn test_tracks_rotation() {
+        //turning at 1 rad/s around an axis tilted from z, with a gyro that reads 0.02 rad/s high on every axis
+        let rate = [0.0, 0.6, 0.8];
+        let bias = 0.02;
+        let dt = 0.005;
+        let start = from_euler(0.1, 0.2, 0.3);
+        for (filter, tolerance) in [
+            (AhrsFilter::Madgwick { beta: 0.1 }, 0.02),
+            (AhrsFilter::Mahony { kp: 1.0, ki: 0.3 }, 0.005),
+        ] {
+            let mut truth = start;
+            let mut state = AhrsState::new(filter, start);
+            for _ in 0..4000 {
+                let half = rate.map(|w| w * dt / 2.0);
+                let turn = [(1.0 - half.iter().map(|x| x * x).sum::<f64>()).sqrt(), half[0], half[1], half[2]];
+                truth = super::multiply(truth, turn);
+                let (accel, mag) = readings(truth);
+                state.step(rate.map(|w| w + bias), Some(accel), Some(mag), dt);
+            }
+            //the angle between the estimate and the truth
+            let dot: f64 = (0..4).map(|i| state.quaternion()[i] * truth[i]).sum();
+            let error = 2.0 * dot.abs().min(1.0).acos();
+            assert!(error < tolerance, "{:?} off by {}", filter, error);
+        }
+    }
+
+    #[test]
+    fn test_vector() {
+        //whole numbers from a config file are Ints
+        assert_eq!(vector(&[Value::Int(1), Value::Float(0.5), Value::Int(-2)]), Some([1.0, 0.5, -2.0]));
+        assert_eq!(vector(&[Value::Float(1.0), Value::Float(2.0)]), None);
+        assert_eq!(vector(&[Value::Float(1.0), Value::Bool(true), Value::Float(2.0)]), None);
+    }
+
+    #[test]
+    fn test_gyro_only() {
+        let mut state = AhrsState::new(AhrsFilter::default(), [1.0, 0.0, 0.0, 0.0]);
+        for _ in 0..100 {
+            state.step([0.0, 0.0, 0.5], None, None, 0.01);
+        }
+        assert_angles(to_euler(state.quaternion()), [0.0, 0.0, 0.5], 1e-5);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_ahrs_simulated() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let floats = |v: Vector| v.iter().map(|x| Value::Float(*x)).collect::<Vec<Value>>();
+        //tilted 0.5 rad, which turns the plain angle of the field away from the true heading
+        let (accel, mag) = readings(from_euler(0.5, 0.0, -1.0));
+        let (gyro, _gyro_tx) = Input::new(floats([0.0; 3]));
+        let (accelerometer, _accel_tx) = Input::new(floats(accel));
+        let (magnetometer, _mag_tx) = Input::new(floats(mag));
+        let ahrs = Ahrs::try_build(&AhrsConfig {
+            gyro: &gyro,
+            accelerometer: &accelerometer,
+            magnetometer: Some(&magnetometer),
+            filter: AhrsFilter::default(),
+            period_ms: 10,
+            clock: &clock,
+        })
+        .await
+        .unwrap();
+        let (roll, yaw, heading) = (ahrs.roll.source(), ahrs.yaw.source(), ahrs.heading.source());
+        assert!((mag[1].atan2(mag[0]) - 1.0).abs() > 0.1);
+
+        driver.advance(Duration::from_millis(500)).await;
+        assert!((*roll.borrow() - 0.5).abs() < 0.005, "{}", *roll.borrow());
+        assert!((*yaw.borrow() + 1.0).abs() < 0.005, "{}", *yaw.borrow());
+        assert!((*heading.borrow() - 1.0).abs() < 0.005, "{}", *heading.borrow());
+        ahrs.join_handle.abort();
+    }
+}
@@ -1,15 +1,18 @@
 use std::collections::HashMap;
 
+use ioc_core::clock::Clock;
+use ioc_core::sample::{Quality, SampleSource};
 use ioc_core::{error::IocBuildError, Input, InputKind, Transformer, TransformerI};
 use tokio::sync::watch;
-use tokio::{task::JoinHandle, time::sleep};
+use tokio::task::JoinHandle;
 use tracing::{info, warn};
-use std::time::{Instant,Duration};
+use std::time::Duration;
 use std::sync::{Arc, Mutex};
 
 pub struct WindowAverageFilterConfig<'a> {
     pub input: &'a Input<f64>,
     pub period_ms: u64,
+    pub clock: &'a Clock,
 }
 
 pub struct WindowAverage {
@@ -30,30 +33,31 @@ impl From<WindowAverage> for TransformerI {
 
 struct WindowAverageState{
     last_value: f64,
-    last_append: Instant,
-    last_window: Instant,
+    //on the clock's monotonic time
+    last_append: Duration,
+    last_window: Duration,
     sum: f64
 }
 
 impl WindowAverageState {
-    fn new(start: f64) -> Self {
+    fn new(start: f64, now: Duration) -> Self {
         Self {
             last_value: start, 
-            last_append: Instant::now(),
-            last_window: Instant::now(),
+            last_append: now,
+            last_window: now,
             sum: 0.0,
         }
     }
-    fn append(&mut self, new_value: f64) {
-        let now = Instant::now();
-        let dt = now.duration_since(self.last_append).as_secs_f64();
+    ///Appends a value sampled at the given time. Late samples count from the last append.
+    fn append(&mut self, new_value: f64, time: Duration) {
+        let dt = time.saturating_sub(self.last_append).as_secs_f64();
         self.sum += dt * self.last_value;
 
         self.last_value = new_value;
-        self.last_append = now;
+        self.last_append = self.last_append.max(time);
     }
-    fn step(&mut self) -> f64 {
-        let now = Instant::now();
+    fn step(&mut self, now: Duration) -> f64 {
+        let now = now.max(self.last_append);
         if self.last_window == self.last_append {
             //only one remainn-sum, so just send the last value 
             self.last_window = now;
@@ -62,8 +66,8 @@ impl WindowAverageState {
             self.last_value
         } else {
             //add a reaimann-sum and divide by dt for average. reset.
-            let dt = now.duration_since(self.last_append).as_secs_f64();
-            let window_t = now.duration_since(self.last_window).as_secs_f64();
+            let dt = now.saturating_sub(self.last_append).as_secs_f64();
+            let window_t = now.saturating_sub(self.last_window).as_secs_f64();
             self.sum += dt * self.last_value;
             let avg = self.sum / window_t;
             self.last_window = now;
@@ -76,26 +80,28 @@ impl WindowAverageState {
 
 fn spawn_window_avg_task(
     start: f64,
-    mut in_rx: watch::Receiver<f64>,
+    mut in_rx: SampleSource<f64>,
     out_tx: watch::Sender<f64>,
     period_ms: u64,
+    clock: Clock,
 ) -> JoinHandle<()> {
 
 
-    let state = Arc::new(Mutex::new(WindowAverageState::new(start)));
+    let state = Arc::new(Mutex::new(WindowAverageState::new(start, clock.elapsed())));
 
     let wt_state = state.clone();
+    let wt_clock = clock.clone();
     let write_task = tokio::spawn(async move {
         loop{
             let step = match wt_state.lock() {
-                Ok(mut state) => state.step(),
-                Err(poisoned) => poisoned.into_inner().step(),
+                Ok(mut state) => state.step(wt_clock.elapsed()),
+                Err(poisoned) => poisoned.into_inner().step(wt_clock.elapsed()),
             };
             if let Err(err) = out_tx.send(step) {
                 warn!("send error in window averager: {}", err);
                 break;
             }
-            sleep(Duration::from_millis(period_ms)).await;
+            wt_clock.sleep(Duration::from_millis(period_ms)).await;
         }
         info!("write task done in window averager!");
     });
@@ -103,12 +109,16 @@ fn spawn_window_avg_task(
     //read task 
     tokio::spawn(async move {
         while in_rx.changed().await.is_ok() {
-            let new_in = *in_rx.borrow_and_update();
+            let new_in = in_rx.borrow_and_update();
+            //failed reads keep the last value
+            if new_in.info.quality == Quality::Error {
+                continue;
+            }
             let mut state = match state.lock() {
                 Ok(state) => state,
                 Err(poisoned) => poisoned.into_inner(),
             };
-            state.append(new_in);
+            state.append(new_in.value, new_in.info.elapsed);
         }
         info!("shutting down window averager!");
         write_task.abort();
@@ -119,15 +129,33 @@ impl <'a> Transformer<'a> for WindowAverage {
     type Config = WindowAverageFilterConfig<'a>;
 
     async fn try_build(cfg: &WindowAverageFilterConfig<'a>) -> Result<WindowAverage, IocBuildError> {
-        let mut in_rx = cfg.input.source();
-        let start = *in_rx.borrow_and_update();
+        let mut in_rx = cfg.input.sample_source().clocked(cfg.clock);
+        let start = in_rx.borrow_and_update().value;
 
         let (value, out_tx) = Input::new(start);
 
-        let join_handle = spawn_window_avg_task(start, in_rx, out_tx, cfg.period_ms);
+        let join_handle = spawn_window_avg_task(start, in_rx, out_tx, cfg.period_ms, cfg.clock.clone());
         Ok(WindowAverage{
             join_handle,
             value
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WindowAverageState;
+    use std::time::Duration;
+
+    #[test]
+    fn test_window_average_sample_time() {
+        let ms = Duration::from_millis;
+        let mut state = WindowAverageState::new(0.0, ms(0));
+        //a 2 sampled at 500ms counts from then, however late it came in
+        state.append(2.0, ms(500));
+        assert!((state.step(ms(1000)) - 1.0).abs() < 1e-9);
+        //a sample from before the last window counts from its start
+        state.append(4.0, ms(800));
+        assert!((state.step(ms(2000)) - 4.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use ioc_core::clock::Clock;
+use ioc_core::sample::{Quality, SampleSource};
+use ioc_core::{error::IocBuildError, Input, InputKind, Transformer, TransformerI};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::debug;
+
+use super::pid::{self, PidLimits, PidState};
+
+///One of the two loops of a cascade.
+pub struct CascadeLoop<'a> {
+    pub process_var: &'a Input<f64>,
+    pub p: &'a Input<f64>,
+    pub i: &'a Input<f64>,
+    pub d: &'a Input<f64>,
+    ///added to the loop's output as is
+    pub feed_forward: Option<&'a Input<f64>>,
+    pub limits: PidLimits,
+    ///time constant of the low-pass filter on the derivative. 0 doesn't filter it
+    pub derivative_filter_ms: f64,
+}
+
+pub struct CascadeConfig<'a> {
+    pub set_point: &'a Input<f64>,
+    ///its output is the inner loop's set point
+    pub outer: CascadeLoop<'a>,
+    pub inner: CascadeLoop<'a>,
+    ///both loops run while this is true. always enabled if there is none
+    pub enable: Option<&'a Input<bool>>,
+    ///the output while disabled. 0 if there is none
+    pub manual: Option<&'a Input<f64>>,
+    ///the outer loop's period
+    pub period_ms: u64,
+    ///how many times the inner loop runs each period of the outer loop
+    pub inner_rate: u32,
+    pub clock: &'a Clock,
+}
+
+///Two PID controllers where the outer one sets the set point of the inner one, e.g. a velocity loop inside a position loop.
+/// Both run from the same clock, the inner one inner_rate times as often as the outer one.
+pub struct Cascade {
+    pub join_handle: JoinHandle<()>,
+    pub value: Input<f64>,
+    pub inner_set_point: Input<f64>,
+}
+
+impl From<Cascade> for TransformerI {
+    fn from(cascade: Cascade) -> Self {
+        TransformerI {
+            join_handle: cascade.join_handle,
+            inputs: HashMap::from([
+                ("value".to_owned(), InputKind::Float(cascade.value)),
+                ("inner_set_point".to_owned(), InputKind::Float(cascade.inner_set_point)),
+            ]),
+        }
+    }
+}
+
+//the inputs of one loop
+struct LoopReader {
+    process_var: SampleSource<f64>,
+    gains: [watch::Receiver<f64>; 3],
+    feed_forward: Option<watch::Receiver<f64>>,
+}
+
+impl LoopReader {
+    fn new(cfg: &CascadeLoop, clock: &Clock) -> Self {
+        LoopReader {
+            process_var: cfg.process_var.sample_source().clocked(clock),
+            gains: [cfg.p.source(), cfg.i.source(), cfg.d.source()],
+            feed_forward: cfg.feed_forward.map(Input::source),
+        }
+    }
+
+    fn state(&mut self, set_point: f64, cfg: &CascadeLoop, clock: &Clock) -> PidState {
+        let [p, i, d] = &mut self.gains;
+        let mut state = PidState::new(
+            *p.borrow_and_update(),
+            *i.borrow_and_update(),
+            *d.borrow_and_update(),
+            set_point,
+            self.process_var.borrow_and_update().value,
+            clock.elapsed(),
+        );
+        state.limit(cfg.limits, cfg.derivative_filter_ms);
+        self.read(&mut state);
+        state
+    }
+
+    ///Copies the latest values into the loop's state. Returns false once a gain's input is gone.
+    fn read(&mut self, state: &mut PidState) -> bool {
+        if self.gains.iter().any(|gain| gain.has_changed().is_err()) {
+            return false;
+        }
+        let [p, i, d] = &mut self.gains;
+        state.p = *p.borrow_and_update();
+        state.i = *i.borrow_and_update();
+        state.d = *d.borrow_and_update();
+        //keep the last good value if the measurement failed
+        let sample = self.process_var.borrow_and_update();
+        if sample.info.quality == Quality::Good {
+            state.process_var = sample.value;
+        }
+        if let Some(feed_forward) = self.feed_forward.as_mut() {
+            state.feed_forward = *feed_forward.borrow_and_update();
+        }
+        true
+    }
+}
+
+impl<'a> Transformer<'a> for Cascade {
+    type Config = CascadeConfig<'a>;
+
+    async fn try_build(cfg: &CascadeConfig<'a>) -> Result<Cascade, IocBuildError> {
+        pid::validate(&cfg.outer.limits, cfg.outer.derivative_filter_ms)?;
+        pid::validate(&cfg.inner.limits, cfg.inner.derivative_filter_ms)?;
+        if cfg.period_ms == 0 || cfg.inner_rate == 0 {
+            return Err(IocBuildError::message("cascade period_ms and inner_rate must be greater than 0"));
+        }
+
+        let clock = cfg.clock.clone();
+        let mut set_point = cfg.set_point.source();
+        let mut enable = cfg.enable.map(Input::source);
+        let mut manual = cfg.manual.map(Input::source);
+        let mut outer_reader = LoopReader::new(&cfg.outer, &clock);
+        let mut inner_reader = LoopReader::new(&cfg.inner, &clock);
+        let mut outer = outer_reader.state(*set_point.borrow_and_update(), &cfg.outer, &clock);
+        outer.step(clock.elapsed());
+        let mut inner = inner_reader.state(outer.value, &cfg.inner, &clock);
+        inner.step(clock.elapsed());
+
+        let (value, value_tx) = Input::new(inner.value);
+        let (inner_set_point, inner_set_point_tx) = Input::new(inner.set_point);
+        let inner_rate = cfg.inner_rate;
+        let inner_limits = cfg.inner.limits;
+        let inner_period = Duration::from_millis(cfg.period_ms) / inner_rate;
+        let mut next_step = clock.elapsed() + inner_period;
+        let join_handle = tokio::spawn(async move {
+            //both loops stepped when they were built
+            let mut step = 1 % inner_rate;
+            loop {
+                //both loops keep to the inner period however long a step takes
+                clock.sleep(next_step.saturating_sub(clock.elapsed())).await;
+                next_step += inner_period;
+                let now = clock.elapsed();
+
+                if set_point.has_changed().is_err() || !outer_reader.read(&mut outer) || !inner_reader.read(&mut inner) {
+                    break;
+                }
+                outer.set_point = *set_point.borrow_and_update();
+                let enabled = enable.as_mut().map(|enable| *enable.borrow_and_update()).unwrap_or(true);
+                outer.enabled = enabled;
+                inner.enabled = enabled;
+                //while disabled the outer loop follows the inner process var, so the inner loop picks up from where it is
+                outer.manual = inner.process_var;
+                inner.manual = manual.as_mut().map(|manual| *manual.borrow_and_update()).unwrap_or(0.0);
+
+                if step == 0 {
+                    //while the inner loop is saturated, raising its set point further in that direction only winds up the outer integral
+                    let outer_err = outer.set_point - outer.process_var;
+                    outer.hold_integral = (inner.value >= inner_limits.max && outer_err > 0.0)
+                        || (inner.value <= inner_limits.min && outer_err < 0.0);
+                    outer.step(now);
+                    inner.set_point = outer.value;
+                }
+                step = (step + 1) % inner_rate;
+                inner.step(now);
+
+                if inner_set_point_tx.send(inner.set_point).is_err() || value_tx.send(inner.value).is_err() {
+                    break;
+                }
+            }
+            debug!("cascade controller shut down")
+        });
+
+        Ok(Cascade {
+            join_handle,
+            value,
+            inner_set_point,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cascade, CascadeConfig, CascadeLoop};
+    use crate::transform::pid::PidLimits;
+    use ioc_core::{clock::Clock, Input, Transformer};
+    use std::time::{Duration, SystemTime};
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_cascade_rates() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let (set_point, _set_point_tx) = Input::new(1.0);
+        let (position, _position_tx) = Input::new(0.0);
+        let (velocity, _velocity_tx) = Input::new(0.0);
+        let (one, _one_tx) = Input::new(1.0);
+        let (zero, _zero_tx) = Input::new(0.0);
+        let cascade_loop = |process_var| CascadeLoop {
+            process_var,
+            p: &zero,
+            i: &one,
+            d: &zero,
+            feed_forward: None,
+            limits: PidLimits::default(),
+            derivative_filter_ms: 0.0,
+        };
+        let cascade = Cascade::try_build(&CascadeConfig {
+            set_point: &set_point,
+            outer: cascade_loop(&position),
+            inner: cascade_loop(&velocity),
+            enable: None,
+            manual: None,
+            period_ms: 100,
+            inner_rate: 5,
+            clock: &clock,
+        })
+        .await
+        .unwrap();
+        let inner_set_point = cascade.inner_set_point.source();
+        let value = cascade.value.source();
+
+        //the outer loop only moves every 100ms, integrating its error of 1
+        driver.advance(Duration::from_millis(90)).await;
+        assert_eq!(*inner_set_point.borrow(), 0.0);
+        driver.advance(Duration::from_millis(20)).await;
+        assert!((*inner_set_point.borrow() - 0.1).abs() < 1e-9, "{}", *inner_set_point.borrow());
+        driver.advance(Duration::from_millis(100)).await;
+        assert!((*inner_set_point.borrow() - 0.2).abs() < 1e-9, "{}", *inner_set_point.borrow());
+        //while the inner loop integrates its set point every 20ms, 0.1 from 100ms and 0.2 from 200ms
+        assert!((*value.borrow() - 0.014).abs() < 1e-9, "{}", *value.borrow());
+        cascade.join_handle.abort();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_cascade_inner_saturation() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let (set_point, set_point_tx) = Input::new(1.0);
+        let (position, _position_tx) = Input::new(0.0);
+        let (velocity, _velocity_tx) = Input::new(0.0);
+        let (one, _one_tx) = Input::new(1.0);
+        let (zero, _zero_tx) = Input::new(0.0);
+        let cascade = Cascade::try_build(&CascadeConfig {
+            set_point: &set_point,
+            outer: CascadeLoop {
+                process_var: &position,
+                p: &zero,
+                i: &one,
+                d: &zero,
+                feed_forward: None,
+                limits: PidLimits::default(),
+                derivative_filter_ms: 0.0,
+            },
+            //the inner loop saturates as soon as its set point passes 0.05
+            inner: CascadeLoop {
+                process_var: &velocity,
+                p: &one,
+                i: &zero,
+                d: &zero,
+                feed_forward: None,
+                limits: PidLimits { min: -0.05, max: 0.05, ..Default::default() },
+                derivative_filter_ms: 0.0,
+            },
+            enable: None,
+            manual: None,
+            period_ms: 100,
+            inner_rate: 5,
+            clock: &clock,
+        })
+        .await
+        .unwrap();
+        let inner_set_point = cascade.inner_set_point.source();
+        let value = cascade.value.source();
+
+        //the outer integral holds at its first step once the inner loop saturated, instead of winding up to 1
+        driver.advance(Duration::from_millis(1010)).await;
+        assert!((*inner_set_point.borrow() - 0.1).abs() < 1e-9, "{}", *inner_set_point.borrow());
+        assert_eq!(*value.borrow(), 0.05);
+
+        //it integrates again as soon as the error turns around
+        set_point_tx.send(-1.0).unwrap();
+        driver.advance(Duration::from_millis(100)).await;
+        assert!((*inner_set_point.borrow() - 0.0).abs() < 1e-9, "{}", *inner_set_point.borrow());
+        cascade.join_handle.abort();
+    }
+}
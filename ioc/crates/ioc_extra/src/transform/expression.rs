@@ -0,0 +1,692 @@
+use std::collections::HashMap;
+
+use futures::future::select_all;
+use ioc_core::{error::IocBuildError, Input, InputKind, Transformer, TransformerI};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{debug, error};
+
+///An input that can be used in an expression. Ints are read as Floats.
+pub enum ExpressionInput<'a> {
+    Float(&'a Input<f64>),
+    Int(&'a Input<i64>),
+    Bool(&'a Input<bool>),
+}
+
+pub struct ExpressionConfig<'a> {
+    pub expression: &'a str,
+    ///inputs by the names used in the expression
+    pub inputs: HashMap<String, ExpressionInput<'a>>,
+}
+
+///Evaluates an arithmetic or boolean expression over named inputs whenever one of them changes.
+///
+/// The expression is type checked when it is built. The type of the emitted input (Float or Bool) is the type of the expression.
+///
+/// Supports:
+/// - numbers, `true` and `false`
+/// - `+ - * / % ^` on Floats
+/// - `< <= > >=` on Floats, `== !=` on Floats or Bools
+/// - `&& || !` on Bools
+/// - `condition ? a : b`
+/// - functions: abs, sign, sqrt, exp, ln, log10, floor, ceil, round, sin, cos, tan, asin, acos, atan, atan2, pow, min, max, clamp
+pub struct Expression {
+    pub join_handle: JoinHandle<()>,
+    pub value: InputKind,
+}
+
+impl From<Expression> for TransformerI {
+    fn from(expression: Expression) -> Self {
+        TransformerI {
+            join_handle: expression.join_handle,
+            inputs: HashMap::from([("value".to_owned(), expression.value)]),
+        }
+    }
+}
+
+impl<'a> Transformer<'a> for Expression {
+    type Config = ExpressionConfig<'a>;
+
+    async fn try_build(cfg: &ExpressionConfig<'a>) -> Result<Expression, IocBuildError> {
+        //float and bool variables are kept in separate slots, so evaluation doesn't need to check types
+        let mut variables = HashMap::with_capacity(cfg.inputs.len());
+        let mut readers = Vec::with_capacity(cfg.inputs.len());
+        let mut values = Values { floats: Vec::new(), bools: Vec::new() };
+        for (name, input) in &cfg.inputs {
+            let (reader, variable) = match input {
+                ExpressionInput::Float(input) => {
+                    values.floats.push(f64::NAN);
+                    (Reader::Float(input.source()), Variable::Float(values.floats.len() - 1))
+                }
+                ExpressionInput::Int(input) => {
+                    values.floats.push(f64::NAN);
+                    (Reader::Int(input.source()), Variable::Float(values.floats.len() - 1))
+                }
+                ExpressionInput::Bool(input) => {
+                    values.bools.push(false);
+                    (Reader::Bool(input.source()), Variable::Bool(values.bools.len() - 1))
+                }
+            };
+            variables.insert(name.as_str(), variable);
+            readers.push((reader, variable));
+        }
+
+        let compiled = compile(cfg.expression, &variables)
+            .map_err(|err| IocBuildError::from_string(format!("error in expression '{}': {}", cfg.expression, err)))?;
+
+        for (reader, variable) in readers.iter_mut() {
+            reader.read(*variable, &mut values);
+        }
+
+        match compiled {
+            Compiled::Float(expr) => {
+                let (value, tx) = Input::new(expr.eval(&values));
+                let join_handle = spawn_eval_task(readers, values, move |values| expr.eval(values), tx);
+                Ok(Expression { join_handle, value: InputKind::Float(value) })
+            }
+            Compiled::Bool(expr) => {
+                let (value, tx) = Input::new(expr.eval(&values));
+                let join_handle = spawn_eval_task(readers, values, move |values| expr.eval(values), tx);
+                Ok(Expression { join_handle, value: InputKind::Bool(value) })
+            }
+        }
+    }
+}
+
+fn spawn_eval_task<T, F>(
+    mut readers: Vec<(Reader, Variable)>,
+    mut values: Values,
+    eval: F,
+    tx: watch::Sender<T>,
+) -> JoinHandle<()>
+where
+    T: Send + Sync + 'static,
+    F: Fn(&Values) -> T + Send + 'static,
+{
+    tokio::spawn(async move {
+        //an expression without inputs is a constant
+        while !readers.is_empty() {
+            let changes = readers.iter_mut().map(|(reader, _)| Box::pin(reader.changed()));
+            let (changed, _, _) = select_all(changes).await;
+            if !changed {
+                break;
+            }
+            for (reader, variable) in readers.iter_mut() {
+                reader.read(*variable, &mut values);
+            }
+            if let Err(err) = tx.send(eval(&values)) {
+                error!("send error in expression transformer {}", err);
+                break;
+            }
+        }
+        debug!("shutting down expression transformer!");
+    })
+}
+
+enum Reader {
+    Float(watch::Receiver<f64>),
+    Int(watch::Receiver<i64>),
+    Bool(watch::Receiver<bool>),
+}
+
+impl Reader {
+    fn read(&mut self, variable: Variable, values: &mut Values) {
+        match (self, variable) {
+            (Self::Float(rx), Variable::Float(index)) => values.floats[index] = *rx.borrow_and_update(),
+            (Self::Int(rx), Variable::Float(index)) => values.floats[index] = *rx.borrow_and_update() as f64,
+            (Self::Bool(rx), Variable::Bool(index)) => values.bools[index] = *rx.borrow_and_update(),
+            _ => unreachable!("reader and variable types always match"),
+        }
+    }
+    async fn changed(&mut self) -> bool {
+        match self {
+            Self::Float(rx) => rx.changed().await.is_ok(),
+            Self::Int(rx) => rx.changed().await.is_ok(),
+            Self::Bool(rx) => rx.changed().await.is_ok(),
+        }
+    }
+}
+
+///current values of all variables
+struct Values {
+    floats: Vec<f64>,
+    bools: Vec<bool>,
+}
+
+#[derive(Clone, Copy)]
+enum Variable {
+    Float(usize),
+    Bool(usize),
+}
+
+enum Compiled {
+    Float(FloatExpr),
+    Bool(BoolExpr),
+}
+
+enum FloatExpr {
+    Const(f64),
+    Var(usize),
+    Neg(Box<FloatExpr>),
+    Binary(fn(f64, f64) -> f64, Box<FloatExpr>, Box<FloatExpr>),
+    Call1(fn(f64) -> f64, Box<FloatExpr>),
+    Call2(fn(f64, f64) -> f64, Box<FloatExpr>, Box<FloatExpr>),
+    Clamp(Box<FloatExpr>, Box<FloatExpr>, Box<FloatExpr>),
+    Cond(Box<BoolExpr>, Box<FloatExpr>, Box<FloatExpr>),
+}
+
+impl FloatExpr {
+    fn eval(&self, values: &Values) -> f64 {
+        match self {
+            Self::Const(value) => *value,
+            Self::Var(index) => values.floats[*index],
+            Self::Neg(x) => -x.eval(values),
+            Self::Binary(op, a, b) | Self::Call2(op, a, b) => op(a.eval(values), b.eval(values)),
+            Self::Call1(function, x) => function(x.eval(values)),
+            //unlike f64::clamp, this doesn't panic if min > max
+            Self::Clamp(x, min, max) => x.eval(values).max(min.eval(values)).min(max.eval(values)),
+            Self::Cond(condition, a, b) => {
+                if condition.eval(values) {
+                    a.eval(values)
+                } else {
+                    b.eval(values)
+                }
+            }
+        }
+    }
+}
+
+enum BoolExpr {
+    Const(bool),
+    Var(usize),
+    Not(Box<BoolExpr>),
+    And(Box<BoolExpr>, Box<BoolExpr>),
+    Or(Box<BoolExpr>, Box<BoolExpr>),
+    Compare(fn(&f64, &f64) -> bool, Box<FloatExpr>, Box<FloatExpr>),
+    Equal(bool, Box<BoolExpr>, Box<BoolExpr>),
+    Cond(Box<BoolExpr>, Box<BoolExpr>, Box<BoolExpr>),
+}
+
+impl BoolExpr {
+    fn eval(&self, values: &Values) -> bool {
+        match self {
+            Self::Const(value) => *value,
+            Self::Var(index) => values.bools[*index],
+            Self::Not(x) => !x.eval(values),
+            Self::And(a, b) => a.eval(values) && b.eval(values),
+            Self::Or(a, b) => a.eval(values) || b.eval(values),
+            Self::Compare(op, a, b) => op(&a.eval(values), &b.eval(values)),
+            Self::Equal(equal, a, b) => (a.eval(values) == b.eval(values)) == *equal,
+            Self::Cond(condition, a, b) => {
+                if condition.eval(values) {
+                    a.eval(values)
+                } else {
+                    b.eval(values)
+                }
+            }
+        }
+    }
+}
+
+///Parses and type checks an expression.
+fn compile(expression: &str, variables: &HashMap<&str, Variable>) -> Result<Compiled, String> {
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser { tokens: &tokens, position: 0, depth: 0 };
+    let ast = parser.parse_expr()?;
+    if let Some((token, column)) = parser.peek() {
+        return Err(format!("unexpected {:?} at column {}", token, column));
+    }
+    check(&ast, variables)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 22] = [
+    "<=", ">=", "==", "!=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "^", "!", "(", ")", ",", "?", ":", "&", "|",
+];
+
+//tokens with the column they start at
+fn tokenize(expression: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = expression.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            //exponents, e.g. 1e-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = text
+                .parse()
+                .map_err(|_| format!("invalid number {} at column {}", text, column))?;
+            tokens.push((Token::Number(number), column));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), column));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(&"&") | Some(&"|") => {
+                    return Err(format!("unexpected '{}' at column {}. Use '{}{}'", c, column, c, c))
+                }
+                Some(op) => {
+                    tokens.push((Token::Op(op), column));
+                    i += op.len();
+                }
+                None => return Err(format!("unexpected '{}' at column {}", c, column)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+//untyped syntax tree
+enum Ast {
+    Number(f64),
+    Ident(String, usize),
+    Unary(&'static str, Box<Ast>, usize),
+    Binary(&'static str, Box<Ast>, Box<Ast>, usize),
+    Call(String, Vec<Ast>, usize),
+    Cond(Box<Ast>, Box<Ast>, Box<Ast>, usize),
+}
+
+///How deeply an expression may nest. Parsing, checking and evaluating recurse once per level.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'t> {
+    tokens: &'t [(Token, usize)],
+    position: usize,
+    depth: usize,
+}
+
+impl<'t> Parser<'t> {
+    //enters a nested expression, or fails if it is nested too deeply to parse without running out of stack
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("the expression is nested more than {} levels deep", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&'t (Token, usize)> {
+        self.tokens.get(self.position)
+    }
+
+    //consumes the next token if it is one of the given operators
+    fn take_op(&mut self, ops: &[&'static str]) -> Option<(&'static str, usize)> {
+        match self.peek() {
+            Some((Token::Op(op), column)) if ops.contains(op) => {
+                self.position += 1;
+                Some((op, *column))
+            }
+            _ => None,
+        }
+    }
+
+    fn expect_op(&mut self, op: &'static str) -> Result<(), String> {
+        match self.take_op(&[op]) {
+            Some(_) => Ok(()),
+            None => match self.peek() {
+                Some((token, column)) => Err(format!("expected '{}' but got {:?} at column {}", op, token, column)),
+                None => Err(format!("expected '{}' but the expression ended", op)),
+            },
+        }
+    }
+
+    //condition ? a : b
+    fn parse_expr(&mut self) -> Result<Ast, String> {
+        self.nest()?;
+        let condition = self.parse_binary(0)?;
+        let expr = match self.take_op(&["?"]) {
+            Some((_, column)) => {
+                let a = self.parse_expr()?;
+                self.expect_op(":")?;
+                let b = self.parse_expr()?;
+                Ast::Cond(Box::new(condition), Box::new(a), Box::new(b), column)
+            }
+            None => condition,
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    //binary operators from lowest to highest precedence
+    const LEVELS: [&'static [&'static str]; 5] = [
+        &["||"],
+        &["&&"],
+        &["<", "<=", ">", ">=", "==", "!="],
+        &["+", "-"],
+        &["*", "/", "%"],
+    ];
+
+    fn parse_binary(&mut self, level: usize) -> Result<Ast, String> {
+        if level == Self::LEVELS.len() {
+            return self.parse_unary();
+        }
+        let mut lhs = self.parse_binary(level + 1)?;
+        //each operator in a chain nests the operators before it one level deeper
+        let mut chain = 0;
+        while let Some((op, column)) = self.take_op(Self::LEVELS[level]) {
+            self.nest()?;
+            chain += 1;
+            let rhs = self.parse_binary(level + 1)?;
+            lhs = Ast::Binary(op, Box::new(lhs), Box::new(rhs), column);
+        }
+        self.depth -= chain;
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Ast, String> {
+        match self.take_op(&["-", "!"]) {
+            Some((op, column)) => {
+                self.nest()?;
+                let operand = self.parse_unary()?;
+                self.depth -= 1;
+                Ok(Ast::Unary(op, Box::new(operand), column))
+            }
+            None => self.parse_power(),
+        }
+    }
+
+    //right associative, and binds tighter than unary minus on its left: -x^2 == -(x^2)
+    fn parse_power(&mut self) -> Result<Ast, String> {
+        let base = self.parse_primary()?;
+        match self.take_op(&["^"]) {
+            Some((op, column)) => {
+                self.nest()?;
+                let exponent = self.parse_unary()?;
+                self.depth -= 1;
+                Ok(Ast::Binary(op, Box::new(base), Box::new(exponent), column))
+            }
+            None => Ok(base),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Ast, String> {
+        let (token, column) = match self.peek() {
+            Some(next) => next.clone(),
+            None => return Err("the expression ended unexpectedly".to_string()),
+        };
+        self.position += 1;
+        match token {
+            Token::Number(number) => Ok(Ast::Number(number)),
+            Token::Ident(name) => {
+                if self.take_op(&["("]).is_some() {
+                    let mut args = Vec::new();
+                    if self.take_op(&[")"]).is_none() {
+                        loop {
+                            args.push(self.parse_expr()?);
+                            if self.take_op(&[")"]).is_some() {
+                                break;
+                            }
+                            self.expect_op(",")?;
+                        }
+                    }
+                    Ok(Ast::Call(name, args, column))
+                } else {
+                    Ok(Ast::Ident(name, column))
+                }
+            }
+            Token::Op("(") => {
+                let inner = self.parse_expr()?;
+                self.expect_op(")")?;
+                Ok(inner)
+            }
+            Token::Op(op) => Err(format!("unexpected '{}' at column {}", op, column)),
+        }
+    }
+}
+
+fn float(ast: &Ast, variables: &HashMap<&str, Variable>) -> Result<Box<FloatExpr>, String> {
+    match check(ast, variables)? {
+        Compiled::Float(expr) => Ok(Box::new(expr)),
+        Compiled::Bool(_) => Err(format!("expected a Float but got a Bool at column {}", column(ast))),
+    }
+}
+
+fn boolean(ast: &Ast, variables: &HashMap<&str, Variable>) -> Result<Box<BoolExpr>, String> {
+    match check(ast, variables)? {
+        Compiled::Bool(expr) => Ok(Box::new(expr)),
+        Compiled::Float(_) => Err(format!("expected a Bool but got a Float at column {}", column(ast))),
+    }
+}
+
+fn column(ast: &Ast) -> usize {
+    match ast {
+        Ast::Number(_) => 0,
+        Ast::Ident(_, column)
+        | Ast::Unary(_, _, column)
+        | Ast::Binary(_, _, _, column)
+        | Ast::Call(_, _, column)
+        | Ast::Cond(_, _, _, column) => *column,
+    }
+}
+
+//type checks the syntax tree
+fn check(ast: &Ast, variables: &HashMap<&str, Variable>) -> Result<Compiled, String> {
+    let compiled = match ast {
+        Ast::Number(number) => Compiled::Float(FloatExpr::Const(*number)),
+        Ast::Ident(name, column) => match (variables.get(name.as_str()), name.as_str()) {
+            (Some(Variable::Float(index)), _) => Compiled::Float(FloatExpr::Var(*index)),
+            (Some(Variable::Bool(index)), _) => Compiled::Bool(BoolExpr::Var(*index)),
+            (None, "true") => Compiled::Bool(BoolExpr::Const(true)),
+            (None, "false") => Compiled::Bool(BoolExpr::Const(false)),
+            (None, "pi") => Compiled::Float(FloatExpr::Const(std::f64::consts::PI)),
+            (None, _) => return Err(format!("unknown input '{}' at column {}", name, column)),
+        },
+        Ast::Unary("-", x, _) => Compiled::Float(FloatExpr::Neg(float(x, variables)?)),
+        Ast::Unary(_, x, _) => Compiled::Bool(BoolExpr::Not(boolean(x, variables)?)),
+        Ast::Binary(op @ ("==" | "!="), a, b, column) => {
+            let equal = *op == "==";
+            match (check(a, variables)?, check(b, variables)?) {
+                (Compiled::Float(a), Compiled::Float(b)) => Compiled::Bool(BoolExpr::Compare(
+                    if equal { f64::eq } else { f64::ne },
+                    Box::new(a),
+                    Box::new(b),
+                )),
+                (Compiled::Bool(a), Compiled::Bool(b)) => Compiled::Bool(BoolExpr::Equal(equal, Box::new(a), Box::new(b))),
+                _ => return Err(format!("can't compare a Float and a Bool with '{}' at column {}", op, column)),
+            }
+        }
+        Ast::Binary(op @ ("<" | "<=" | ">" | ">="), a, b, _) => {
+            let compare: fn(&f64, &f64) -> bool = match *op {
+                "<" => f64::lt,
+                "<=" => f64::le,
+                ">" => f64::gt,
+                _ => f64::ge,
+            };
+            Compiled::Bool(BoolExpr::Compare(compare, float(a, variables)?, float(b, variables)?))
+        }
+        Ast::Binary("&&", a, b, _) => Compiled::Bool(BoolExpr::And(boolean(a, variables)?, boolean(b, variables)?)),
+        Ast::Binary("||", a, b, _) => Compiled::Bool(BoolExpr::Or(boolean(a, variables)?, boolean(b, variables)?)),
+        Ast::Binary(op, a, b, _) => {
+            let function: fn(f64, f64) -> f64 = match *op {
+                "+" => |a, b| a + b,
+                "-" => |a, b| a - b,
+                "*" => |a, b| a * b,
+                "/" => |a, b| a / b,
+                "%" => |a, b| a % b,
+                _ => f64::powf,
+            };
+            Compiled::Float(FloatExpr::Binary(function, float(a, variables)?, float(b, variables)?))
+        }
+        Ast::Cond(condition, a, b, column) => {
+            let condition = boolean(condition, variables)?;
+            match (check(a, variables)?, check(b, variables)?) {
+                (Compiled::Float(a), Compiled::Float(b)) => Compiled::Float(FloatExpr::Cond(condition, Box::new(a), Box::new(b))),
+                (Compiled::Bool(a), Compiled::Bool(b)) => Compiled::Bool(BoolExpr::Cond(condition, Box::new(a), Box::new(b))),
+                _ => return Err(format!("both branches of '?' at column {} must have the same type", column)),
+            }
+        }
+        Ast::Call(name, args, column) => check_call(name, args, *column, variables)?,
+    };
+    Ok(compiled)
+}
+
+fn check_call(name: &str, args: &[Ast], column: usize, variables: &HashMap<&str, Variable>) -> Result<Compiled, String> {
+    let one: Option<fn(f64) -> f64> = match name {
+        "abs" => Some(f64::abs),
+        "sign" => Some(|x: f64| if x == 0.0 { 0.0 } else { x.signum() }),
+        "sqrt" => Some(f64::sqrt),
+        "exp" => Some(f64::exp),
+        "ln" => Some(f64::ln),
+        "log10" => Some(f64::log10),
+        "floor" => Some(f64::floor),
+        "ceil" => Some(f64::ceil),
+        "round" => Some(f64::round),
+        "sin" => Some(f64::sin),
+        "cos" => Some(f64::cos),
+        "tan" => Some(f64::tan),
+        "asin" => Some(f64::asin),
+        "acos" => Some(f64::acos),
+        "atan" => Some(f64::atan),
+        _ => None,
+    };
+    let two: Option<fn(f64, f64) -> f64> = match name {
+        "atan2" => Some(f64::atan2),
+        "pow" => Some(f64::powf),
+        "min" => Some(f64::min),
+        "max" => Some(f64::max),
+        _ => None,
+    };
+    let arity = match (one, two, name) {
+        (Some(_), _, _) => 1,
+        (_, Some(_), _) => 2,
+        (_, _, "clamp") => 3,
+        _ => return Err(format!("unknown function '{}' at column {}", name, column)),
+    };
+    if args.len() != arity {
+        return Err(format!(
+            "{} takes {} arguments but got {} at column {}",
+            name,
+            arity,
+            args.len(),
+            column
+        ));
+    }
+    let expr = match (one, two) {
+        (Some(function), _) => FloatExpr::Call1(function, float(&args[0], variables)?),
+        (_, Some(function)) => FloatExpr::Call2(function, float(&args[0], variables)?, float(&args[1], variables)?),
+        _ => FloatExpr::Clamp(
+            float(&args[0], variables)?,
+            float(&args[1], variables)?,
+            float(&args[2], variables)?,
+        ),
+    };
+    Ok(Compiled::Float(expr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, Compiled, Expression, ExpressionConfig, ExpressionInput, Values, Variable};
+    use ioc_core::{Input, InputKind, Transformer};
+    use std::collections::HashMap;
+
+    fn eval(expression: &str) -> Result<String, String> {
+        let variables = HashMap::from([("a", Variable::Float(0)), ("b", Variable::Float(1)), ("enabled", Variable::Bool(0))]);
+        let values = Values {
+            floats: vec![3.0, 1.0],
+            bools: vec![true],
+        };
+        Ok(match compile(expression, &variables)? {
+            Compiled::Float(expr) => expr.eval(&values).to_string(),
+            Compiled::Bool(expr) => expr.eval(&values).to_string(),
+        })
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval("(a - b) * 0.5"), Ok("1".to_string()));
+        assert_eq!(eval("a > 0.2 && enabled"), Ok("true".to_string()));
+        assert_eq!(eval("-a^2 + 2 * 3 % 4"), Ok("-7".to_string()));
+        assert_eq!(eval("2^3^2"), Ok("512".to_string()));
+        assert_eq!(eval("clamp(a, 0, 2) + max(b, 1.5e0) + abs(-1)"), Ok("4.5".to_string()));
+        assert_eq!(eval("atan2(0, 1) == 0 && sqrt(a * a) == a"), Ok("true".to_string()));
+        assert_eq!(eval("!enabled || b >= 1 ? a : b"), Ok("3".to_string()));
+        assert_eq!(eval("enabled != false"), Ok("true".to_string()));
+    }
+
+    #[test]
+    fn test_type_errors() {
+        assert!(eval("a + enabled").is_err());
+        assert!(eval("a && enabled").is_err());
+        assert!(eval("a == enabled").is_err());
+        assert!(eval("enabled ? a : enabled").is_err());
+        assert!(eval("min(a)").is_err());
+        assert!(eval("unknown + 1").is_err());
+        assert!(eval("a & b").is_err());
+        assert!(eval("(a + b").is_err());
+        assert!(eval("a b").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_depth_limit() {
+        let (a, _a_tx) = Input::new(1.0);
+        let build = |expression: String| {
+            let inputs = HashMap::from([("a".to_string(), ExpressionInput::Float(&a))]);
+            async move { Expression::try_build(&ExpressionConfig { expression: &expression, inputs }).await.map(|_| ()) }
+        };
+
+        assert!(build(format!("{}a{}", "(".repeat(100), ")".repeat(100))).await.is_ok());
+        assert!(build(format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000))).await.is_err());
+        assert!(build(format!("{}a", "-".repeat(100_000))).await.is_err());
+        assert!(build(format!("a{}", "^a".repeat(100_000))).await.is_err());
+        assert!(build(format!("a{}", " + a".repeat(100_000))).await.is_err());
+        assert!(build(format!("{}a", "max(a, ".repeat(100_000))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expression() {
+        let (a, a_tx) = Input::new(0.5);
+        let (n, _n_tx) = Input::new(2i64);
+        let (enabled, enabled_tx) = Input::new(false);
+        let expression = Expression::try_build(&ExpressionConfig {
+            expression: "enabled ? a * n : 0",
+            inputs: HashMap::from([
+                ("a".to_string(), ExpressionInput::Float(&a)),
+                ("n".to_string(), ExpressionInput::Int(&n)),
+                ("enabled".to_string(), ExpressionInput::Bool(&enabled)),
+            ]),
+        })
+        .await
+        .unwrap();
+        let mut value = match expression.value {
+            InputKind::Float(value) => value.source(),
+            _ => panic!("expected a float input"),
+        };
+
+        assert_eq!(*value.borrow_and_update(), 0.0);
+        enabled_tx.send(true).unwrap();
+        value.changed().await.unwrap();
+        assert_eq!(*value.borrow_and_update(), 1.0);
+        a_tx.send(2.0).unwrap();
+        value.changed().await.unwrap();
+        assert_eq!(*value.borrow_and_update(), 4.0);
+    }
+}
@@ -0,0 +1,468 @@
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::{PI, SQRT_2};
+use std::time::Duration;
+
+use ioc_core::clock::Clock;
+use ioc_core::sample::Quality;
+use ioc_core::{error::IocBuildError, Input, InputKind, Transformer, TransformerI};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+///A filter and its parameters.
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub enum FilterKind {
+    ///Exponential moving average, which covers 63% of a step in the input in time_constant_ms, however unevenly the input is updated.
+    /// Emits every period_ms, a tenth of the time constant by default, so it keeps moving when the input stops changing.
+    Ema {
+        time_constant_ms: f64,
+        period_ms: Option<u64>,
+    },
+    ///Second order Butterworth low-pass filter. Samples the input sample_rate_hz times a second, and emits as often.
+    LowPass { cutoff_hz: f64, sample_rate_hz: f64 },
+    ///Second order Butterworth high-pass filter. Samples the input sample_rate_hz times a second, and emits as often.
+    HighPass { cutoff_hz: f64, sample_rate_hz: f64 },
+    ///Second order band-pass filter that passes low_hz to high_hz. Samples the input sample_rate_hz times a second, and emits as often.
+    BandPass {
+        low_hz: f64,
+        high_hz: f64,
+        sample_rate_hz: f64,
+    },
+    ///Median of the values the input had in the last window_ms milliseconds, which rejects spikes.
+    /// Emits whenever the input changes, and when old values drop out of the window.
+    Median { window_ms: u64 },
+    ///Values within width of center, 0 by default, become center. Emits whenever the input changes.
+    Deadband {
+        #[serde(default)]
+        center: f64,
+        width: f64,
+    },
+}
+
+pub struct FilterConfig<'a> {
+    pub input: &'a Input<f64>,
+    pub kind: FilterKind,
+    pub clock: &'a Clock,
+}
+
+///Filters a Float input.
+pub struct Filter {
+    pub join_handle: JoinHandle<()>,
+    pub value: Input<f64>,
+}
+
+impl From<Filter> for TransformerI {
+    fn from(filter: Filter) -> Self {
+        TransformerI {
+            join_handle: filter.join_handle,
+            inputs: HashMap::from([("value".to_owned(), InputKind::Float(filter.value))]),
+        }
+    }
+}
+
+///An exponential moving average of an input that holds its value between samples.
+struct Ema {
+    time_constant: f64,
+    input: f64,
+    value: f64,
+    last_time: Duration,
+}
+
+impl Ema {
+    ///Moves towards the input for the time since the last step. Late times don't move it.
+    fn step(&mut self, now: Duration) -> f64 {
+        let dt = now.saturating_sub(self.last_time).as_secs_f64();
+        self.value = self.input + (self.value - self.input) * (-dt / self.time_constant).exp();
+        self.last_time = self.last_time.max(now);
+        self.value
+    }
+
+    fn sample(&mut self, value: f64, time: Duration) {
+        //the old input held until the new one came in
+        self.step(time);
+        self.input = value;
+    }
+}
+
+///A second order IIR filter, direct form 1. Coefficients are normalized so a0 is 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    inputs: [f64; 2],
+    outputs: [f64; 2],
+}
+
+impl Biquad {
+    //the RBJ audio eq cookbook's designs, with the bilinear transform
+    fn design(center_hz: f64, sample_rate_hz: f64, q: f64, b: fn(f64, f64) -> [f64; 3]) -> Self {
+        let w0 = 2.0 * PI * center_hz / sample_rate_hz;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b: b(cos, alpha).map(|b| b / a0),
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            inputs: [0.0; 2],
+            outputs: [0.0; 2],
+        }
+    }
+
+    pub fn low_pass(cutoff_hz: f64, sample_rate_hz: f64) -> Self {
+        Self::design(cutoff_hz, sample_rate_hz, 1.0 / SQRT_2, |cos, _| [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0])
+    }
+
+    pub fn high_pass(cutoff_hz: f64, sample_rate_hz: f64) -> Self {
+        Self::design(cutoff_hz, sample_rate_hz, 1.0 / SQRT_2, |cos, _| [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0])
+    }
+
+    ///Unity gain at the geometric mean of low_hz and high_hz.
+    pub fn band_pass(low_hz: f64, high_hz: f64, sample_rate_hz: f64) -> Self {
+        let center_hz = (low_hz * high_hz).sqrt();
+        Self::design(center_hz, sample_rate_hz, center_hz / (high_hz - low_hz), |_, alpha| [alpha, 0.0, -alpha])
+    }
+
+    ///Starts as if the input had always been the given value, so there's no jump at the start.
+    pub fn settled(mut self, input: f64) -> Self {
+        let dc_gain = self.b.iter().sum::<f64>() / (1.0 + self.a[0] + self.a[1]);
+        self.inputs = [input; 2];
+        self.outputs = [input * dc_gain; 2];
+        self
+    }
+
+    pub fn step(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.inputs[0] + self.b[2] * self.inputs[1]
+            - self.a[0] * self.outputs[0]
+            - self.a[1] * self.outputs[1];
+        self.inputs = [input, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+///The median of the values the input held within a window of time. Values count until the next one came in.
+struct Median {
+    window: Duration,
+    samples: VecDeque<(Duration, f64)>,
+    value: f64,
+}
+
+impl Median {
+    fn sample(&mut self, value: f64, time: Duration) -> f64 {
+        //late samples count as if they came in with the latest one
+        let time = self.samples.back().map(|(latest, _)| (*latest).max(time)).unwrap_or(time);
+        self.samples.push_back((time, value));
+        self.evaluate(time)
+    }
+
+    fn evaluate(&mut self, now: Duration) -> f64 {
+        //drops values that were replaced before the window started
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|(next, _)| now.saturating_sub(*next) > self.window)
+        {
+            self.samples.pop_front();
+        }
+        let mut values: Vec<f64> = self.samples.iter().map(|(_, value)| *value).collect();
+        values.sort_by(f64::total_cmp);
+        let middle = values.len() / 2;
+        self.value = if values.len() % 2 == 0 {
+            (values[middle - 1] + values[middle]) / 2.0
+        } else {
+            values[middle]
+        };
+        self.value
+    }
+}
+
+enum FilterState {
+    Ema(Ema),
+    //the filter and the input it holds until the next sample
+    Biquad(Biquad, f64),
+    Median(Median),
+    Deadband { center: f64, width: f64 },
+}
+
+impl FilterState {
+    fn new(kind: &FilterKind, start: f64, now: Duration) -> Result<(FilterState, Option<Duration>), IocBuildError> {
+        let sample_period = |sample_rate_hz: f64, highest_hz: f64| {
+            //written so NaNs fail too
+            let below_nyquist = highest_hz < sample_rate_hz / 2.0;
+            if !below_nyquist {
+                return Err(IocBuildError::from_string(format!(
+                    "filter frequencies must be below half of sample_rate_hz, {}",
+                    sample_rate_hz / 2.0
+                )));
+            }
+            Ok(Some(Duration::from_secs_f64(1.0 / sample_rate_hz)))
+        };
+        match *kind {
+            FilterKind::Ema { time_constant_ms, period_ms } => {
+                if !(time_constant_ms > 0.0 && time_constant_ms.is_finite()) || period_ms == Some(0) {
+                    return Err(IocBuildError::message("ema time_constant_ms and period_ms must be greater than 0"));
+                }
+                let period = match period_ms {
+                    Some(period_ms) => Duration::from_millis(period_ms),
+                    None => Duration::from_secs_f64(time_constant_ms / 10_000.0).max(Duration::from_millis(1)),
+                };
+                let ema = Ema {
+                    time_constant: time_constant_ms / 1000.0,
+                    input: start,
+                    value: start,
+                    last_time: now,
+                };
+                Ok((FilterState::Ema(ema), Some(period)))
+            }
+            FilterKind::LowPass { cutoff_hz, sample_rate_hz } | FilterKind::HighPass { cutoff_hz, sample_rate_hz } => {
+                let positive = cutoff_hz > 0.0;
+                if !positive {
+                    return Err(IocBuildError::message("filter cutoff_hz must be greater than 0"));
+                }
+                let period = sample_period(sample_rate_hz, cutoff_hz)?;
+                let biquad = match kind {
+                    FilterKind::LowPass { .. } => Biquad::low_pass(cutoff_hz, sample_rate_hz),
+                    _ => Biquad::high_pass(cutoff_hz, sample_rate_hz),
+                };
+                Ok((FilterState::Biquad(biquad.settled(start), start), period))
+            }
+            FilterKind::BandPass { low_hz, high_hz, sample_rate_hz } => {
+                if !(low_hz > 0.0 && low_hz < high_hz) {
+                    return Err(IocBuildError::message("band-pass filter must have 0 < low_hz < high_hz"));
+                }
+                let period = sample_period(sample_rate_hz, high_hz)?;
+                Ok((FilterState::Biquad(Biquad::band_pass(low_hz, high_hz, sample_rate_hz).settled(start), start), period))
+            }
+            FilterKind::Median { window_ms } => {
+                if window_ms == 0 {
+                    return Err(IocBuildError::message("median filter window_ms must be greater than 0"));
+                }
+                let median = Median {
+                    window: Duration::from_millis(window_ms),
+                    samples: VecDeque::from([(now, start)]),
+                    value: start,
+                };
+                let period = Duration::from_millis((window_ms + 9) / 10);
+                Ok((FilterState::Median(median), Some(period)))
+            }
+            FilterKind::Deadband { center, width } => {
+                let valid = width >= 0.0 && center.is_finite();
+                if !valid {
+                    return Err(IocBuildError::message("deadband must have a width of 0 or more and a finite center"));
+                }
+                Ok((FilterState::Deadband { center, width }, None))
+            }
+        }
+    }
+
+    fn value(&self, start: f64) -> f64 {
+        match self {
+            FilterState::Ema(ema) => ema.value,
+            FilterState::Biquad(biquad, _) => biquad.outputs[0],
+            FilterState::Median(median) => median.value,
+            FilterState::Deadband { center, width } => deadband(start, *center, *width),
+        }
+    }
+
+    ///Takes a new input value. Returns the value to emit, for filters that emit on every input.
+    fn sample(&mut self, value: f64, time: Duration) -> Option<f64> {
+        match self {
+            FilterState::Ema(ema) => {
+                ema.sample(value, time);
+                None
+            }
+            FilterState::Biquad(_, input) => {
+                *input = value;
+                None
+            }
+            FilterState::Median(median) => Some(median.sample(value, time)),
+            FilterState::Deadband { center, width } => Some(deadband(value, *center, *width)),
+        }
+    }
+
+    ///Called every period, for filters that have one. Returns the value to emit, if there's a new one.
+    fn tick(&mut self, now: Duration) -> Option<f64> {
+        match self {
+            FilterState::Ema(ema) => Some(ema.step(now)),
+            FilterState::Biquad(biquad, input) => Some(biquad.step(*input)),
+            FilterState::Median(median) => {
+                let last = median.value;
+                Some(median.evaluate(now)).filter(|value| *value != last)
+            }
+            FilterState::Deadband { .. } => unreachable!("deadbands have no period"),
+        }
+    }
+}
+
+fn deadband(value: f64, center: f64, width: f64) -> f64 {
+    if (value - center).abs() <= width {
+        center
+    } else {
+        value
+    }
+}
+
+impl<'a> Transformer<'a> for Filter {
+    type Config = FilterConfig<'a>;
+
+    async fn try_build(cfg: &FilterConfig<'a>) -> Result<Filter, IocBuildError> {
+        let mut input = cfg.input.sample_source().clocked(cfg.clock);
+        let start = input.borrow_and_update().value;
+        let (mut state, period) = FilterState::new(&cfg.kind, start, cfg.clock.elapsed())?;
+        let (value, value_tx) = Input::new(state.value(start));
+
+        let clock = cfg.clock.clone();
+        let mut next_tick = period.map(|period| clock.elapsed() + period);
+        let join_handle = tokio::spawn(async move {
+            loop {
+                //periods are kept no matter how often the input changes
+                let wait = next_tick.map(|next_tick| next_tick.saturating_sub(clock.elapsed()));
+                let output = tokio::select! {
+                    _ = clock.sleep(wait.unwrap_or_default()), if wait.is_some() => {
+                        let tick = next_tick.unwrap_or_else(|| clock.elapsed());
+                        next_tick = period.map(|period| tick + period);
+                        state.tick(tick)
+                    },
+                    input_res = input.changed() => {
+                        if input_res.is_err() {
+                            break;
+                        }
+                        //failed reads keep the last value
+                        let sample = input.borrow_and_update();
+                        if sample.info.quality == Quality::Error {
+                            None
+                        } else {
+                            state.sample(sample.value, clock.elapsed())
+                        }
+                    },
+                };
+                if let Some(output) = output {
+                    if value_tx.send(output).is_err() {
+                        break;
+                    }
+                }
+            }
+            debug!("filter shut down");
+        });
+
+        Ok(Filter { join_handle, value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Biquad, Filter, FilterConfig, FilterKind};
+    use ioc_core::{clock::Clock, Input, Transformer};
+    use std::f64::consts::PI;
+    use std::time::{Duration, SystemTime};
+
+    //the gain once it settled, for a sine of the given frequency sampled at 1kHz
+    fn gain(mut biquad: Biquad, hz: f64) -> f64 {
+        let sine = |i: usize| (2.0 * PI * hz * i as f64 / 1000.0).sin();
+        let mean_square = (0..5000).map(|i| biquad.step(sine(i))).skip(4000).map(|y| y * y).sum::<f64>() / 1000.0;
+        (mean_square / 0.5).sqrt()
+    }
+
+    #[test]
+    fn test_butterworth() {
+        //-3dB at the cutoff, and -12dB per octave past it
+        assert!((gain(Biquad::low_pass(10.0, 1000.0), 1.0) - 1.0).abs() < 0.01);
+        assert!((gain(Biquad::low_pass(10.0, 1000.0), 10.0) - 0.5f64.sqrt()).abs() < 0.01);
+        assert!(gain(Biquad::low_pass(10.0, 1000.0), 100.0) < 0.011);
+        assert!((gain(Biquad::high_pass(10.0, 1000.0), 10.0) - 0.5f64.sqrt()).abs() < 0.01);
+        assert!(gain(Biquad::high_pass(10.0, 1000.0), 1.0) < 0.011);
+        assert!((gain(Biquad::high_pass(10.0, 1000.0), 100.0) - 1.0).abs() < 0.01);
+        assert!((gain(Biquad::band_pass(5.0, 20.0, 1000.0), 10.0) - 1.0).abs() < 0.01);
+        assert!(gain(Biquad::band_pass(5.0, 20.0, 1000.0), 200.0) < 0.1);
+
+        //a settled low-pass filter passes a constant through, and a high-pass filter blocks it
+        let mut low_pass = Biquad::low_pass(10.0, 1000.0).settled(3.0);
+        assert!((low_pass.step(3.0) - 3.0).abs() < 1e-9);
+        let mut high_pass = Biquad::high_pass(10.0, 1000.0).settled(3.0);
+        assert!(high_pass.step(3.0).abs() < 1e-9);
+    }
+
+    async fn build(kind: FilterKind, clock: &Clock) -> (Filter, tokio::sync::watch::Sender<f64>) {
+        let (input, input_tx) = Input::new(0.0);
+        let filter = Filter::try_build(&FilterConfig { input: &input, kind, clock }).await.unwrap();
+        (filter, input_tx)
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_ema_uneven_updates() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let kind = FilterKind::Ema { time_constant_ms: 1000.0, period_ms: Some(100) };
+        let (filter, input_tx) = build(kind, &clock).await;
+        let value = filter.value.source();
+
+        //a step held for a time constant covers 63% of it, no matter that the input was sent again at odd times
+        input_tx.send(1.0).unwrap();
+        tokio::task::yield_now().await;
+        driver.advance(Duration::from_millis(30)).await;
+        input_tx.send(1.0).unwrap();
+        driver.advance(Duration::from_millis(470)).await;
+        input_tx.send(1.0).unwrap();
+        driver.advance(Duration::from_millis(500)).await;
+        assert!((*value.borrow() - (1.0 - (-1.0f64).exp())).abs() < 1e-9, "{}", *value.borrow());
+
+        //and keeps moving after the input stopped changing
+        driver.advance(Duration::from_millis(4000)).await;
+        assert!((*value.borrow() - (1.0 - (-5.0f64).exp())).abs() < 1e-9, "{}", *value.borrow());
+        filter.join_handle.abort();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_low_pass_holds_input() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(1));
+        let kind = FilterKind::LowPass { cutoff_hz: 5.0, sample_rate_hz: 100.0 };
+        let (filter, input_tx) = build(kind, &clock).await;
+        let value = filter.value.source();
+
+        //a single update is held, so the output settles at it
+        input_tx.send(2.0).unwrap();
+        driver.advance(Duration::from_millis(2000)).await;
+        assert!((*value.borrow() - 2.0).abs() < 1e-6, "{}", *value.borrow());
+        filter.join_handle.abort();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_median_and_deadband() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let (median, median_tx) = build(FilterKind::Median { window_ms: 50 }, &clock).await;
+        let median_value = median.value.source();
+        for input in [1.0, 1.1, 9.0, 1.2, 1.0] {
+            median_tx.send(input).unwrap();
+            driver.tick().await;
+            assert!(*median_value.borrow() < 1.2, "spike passed: {}", *median_value.borrow());
+        }
+        //a value counts until the window passed since it was replaced, even if the input doesn't change again
+        driver.advance(Duration::from_millis(100)).await;
+        assert_eq!(*median_value.borrow(), 1.0);
+        median_tx.send(5.0).unwrap();
+        driver.tick().await;
+        assert_eq!(*median_value.borrow(), 3.0);
+        driver.advance(Duration::from_millis(60)).await;
+        assert_eq!(*median_value.borrow(), 5.0);
+
+        let (deadband, deadband_tx) = build(FilterKind::Deadband { center: 0.0, width: 0.1 }, &clock).await;
+        let deadband_value = deadband.value.source();
+        deadband_tx.send(-0.05).unwrap();
+        driver.tick().await;
+        assert_eq!(*deadband_value.borrow(), 0.0);
+        deadband_tx.send(0.3).unwrap();
+        driver.tick().await;
+        assert_eq!(*deadband_value.borrow(), 0.3);
+        median.join_handle.abort();
+        deadband.join_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_filter_invalid_config() {
+        let clock = Clock::real();
+        let (input, _input_tx) = Input::new(0.0);
+        let kind = FilterKind::LowPass { cutoff_hz: 60.0, sample_rate_hz: 100.0 };
+        let err = Filter::try_build(&FilterConfig { input: &input, kind, clock: &clock }).await.err().unwrap();
+        assert!(format!("{:?}", err).contains("below half of sample_rate_hz"), "{:?}", err);
+    }
+}
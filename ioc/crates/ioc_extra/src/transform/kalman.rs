@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+use std::ops::{Add, Index, IndexMut, Mul, Sub};
+use std::time::Duration;
+
+use futures::future::select_all;
+use ioc_core::{clock::Clock, error::IocBuildError, Input, InputKind, Transformer, TransformerI, Value};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{debug, warn};
+
+///A measurement or control input. Floats are vectors of one value.
+pub enum KalmanInput<'a> {
+    Float(&'a Input<f64>),
+    Array(&'a Input<Vec<Value>>),
+}
+
+pub struct KalmanMeasurement<'a> {
+    pub input: KalmanInput<'a>,
+    ///Maps the state to what the input measures, one row per value of the input.
+    pub h: Vec<Vec<f64>>,
+    ///Covariance of the measurement's noise.
+    pub r: Vec<Vec<f64>>,
+}
+
+pub struct KalmanControl<'a> {
+    pub input: KalmanInput<'a>,
+    ///Maps the control input to its effect on the state over one period, one column per value of the input.
+    pub b: Vec<Vec<f64>>,
+}
+
+pub struct KalmanConfig<'a> {
+    ///Moves the state ahead by one period. Its size is the size of the state.
+    pub f: Vec<Vec<f64>>,
+    ///Covariance of the noise added to the state each period.
+    pub q: Vec<Vec<f64>>,
+    pub control: Option<KalmanControl<'a>>,
+    pub measurements: Vec<KalmanMeasurement<'a>>,
+    ///All zeros if not given.
+    pub initial_state: Option<Vec<f64>>,
+    ///The identity if not given.
+    pub initial_covariance: Option<Vec<Vec<f64>>>,
+    ///Names for the values of the state, each emitted as a Float input too. Can be empty.
+    pub names: Vec<String>,
+    pub period_ms: u64,
+    pub clock: &'a Clock,
+}
+
+///A linear Kalman filter. Predicts the state every period, and corrects it whenever one of its measurements changes.
+pub struct Kalman {
+    pub join_handle: JoinHandle<()>,
+    ///The estimated state.
+    pub state: Input<Vec<Value>>,
+    ///The variance of each value of the state, the diagonal of its covariance.
+    pub variance: Input<Vec<Value>>,
+    ///The values of the state by their names.
+    pub values: HashMap<String, Input<f64>>,
+}
+
+impl From<Kalman> for TransformerI {
+    fn from(kalman: Kalman) -> Self {
+        let mut inputs: HashMap<String, InputKind> = kalman
+            .values
+            .into_iter()
+            .map(|(name, value)| (name, InputKind::Float(value)))
+            .collect();
+        inputs.insert("state".to_string(), InputKind::Array(kalman.state));
+        inputs.insert("variance".to_string(), InputKind::Array(kalman.variance));
+        TransformerI {
+            join_handle: kalman.join_handle,
+            inputs,
+        }
+    }
+}
+
+///A dense matrix, just big enough for the filter's needs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Matrix { rows, cols, data: vec![0.0; rows * cols] }
+    }
+
+    pub fn identity(size: usize) -> Self {
+        let mut identity = Matrix::zeros(size, size);
+        for i in 0..size {
+            identity[(i, i)] = 1.0;
+        }
+        identity
+    }
+
+    pub fn column(values: &[f64]) -> Self {
+        Matrix { rows: values.len(), cols: 1, data: values.to_vec() }
+    }
+
+    ///Checks that the rows are all as long, and that the matrix is rows x cols where those are given.
+    pub fn from_rows(name: &str, rows: &[Vec<f64>], size: (Option<usize>, Option<usize>)) -> Result<Self, IocBuildError> {
+        let cols = rows.first().map(|row| row.len()).unwrap_or(0);
+        if cols == 0 || rows.iter().any(|row| row.len() != cols) {
+            return Err(IocBuildError::from_string(format!(
+                "kalman matrix {} must have rows of the same, non zero length",
+                name
+            )));
+        }
+        if size.0.is_some_and(|expected| expected != rows.len()) || size.1.is_some_and(|expected| expected != cols) {
+            let dim = |dim: Option<usize>| dim.map(|dim| dim.to_string()).unwrap_or("any".to_string());
+            return Err(IocBuildError::from_string(format!(
+                "kalman matrix {} is {}x{} but must be {}x{}",
+                name,
+                rows.len(),
+                cols,
+                dim(size.0),
+                dim(size.1)
+            )));
+        }
+        Ok(Matrix { rows: rows.len(), cols, data: rows.concat() })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut transposed = Matrix::zeros(self.cols, self.rows);
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                transposed[(c, r)] = self[(r, c)];
+            }
+        }
+        transposed
+    }
+
+    pub fn diagonal(&self) -> Vec<f64> {
+        (0..self.rows.min(self.cols)).map(|i| self[(i, i)]).collect()
+    }
+
+    ///Gauss-Jordan elimination with partial pivoting. None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        assert_eq!(self.rows, self.cols, "only square matrices have an inverse");
+        let n = self.rows;
+        let mut a = self.clone();
+        let mut inverse = Matrix::identity(n);
+        for col in 0..n {
+            let pivot = (col..n).max_by(|x, y| a[(*x, col)].abs().total_cmp(&a[(*y, col)].abs()))?;
+            let value = a[(pivot, col)];
+            if value.abs() < f64::EPSILON || !value.is_finite() {
+                return None;
+            }
+            for c in 0..n {
+                a.data.swap(col * n + c, pivot * n + c);
+                inverse.data.swap(col * n + c, pivot * n + c);
+            }
+            for c in 0..n {
+                a[(col, c)] /= value;
+                inverse[(col, c)] /= value;
+            }
+            for r in (0..n).filter(|r| *r != col) {
+                let factor = a[(r, col)];
+                for c in 0..n {
+                    a[(r, c)] -= factor * a[(col, c)];
+                    inverse[(r, c)] -= factor * inverse[(col, c)];
+                }
+            }
+        }
+        Some(inverse)
+    }
+}
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = f64;
+    fn index(&self, (r, c): (usize, usize)) -> &f64 {
+        &self.data[r * self.cols + c]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (r, c): (usize, usize)) -> &mut f64 {
+        &mut self.data[r * self.cols + c]
+    }
+}
+
+impl Mul for &Matrix {
+    type Output = Matrix;
+    fn mul(self, other: &Matrix) -> Matrix {
+        assert_eq!(self.cols, other.rows, "matrix sizes don't match");
+        let mut product = Matrix::zeros(self.rows, other.cols);
+        for r in 0..self.rows {
+            for c in 0..other.cols {
+                product[(r, c)] = (0..self.cols).map(|i| self[(r, i)] * other[(i, c)]).sum();
+            }
+        }
+        product
+    }
+}
+
+impl Add for &Matrix {
+    type Output = Matrix;
+    fn add(self, other: &Matrix) -> Matrix {
+        assert!(self.rows == other.rows && self.cols == other.cols, "matrix sizes don't match");
+        Matrix {
+            rows: self.rows,
+            cols: self.cols,
+            data: self.data.iter().zip(&other.data).map(|(a, b)| a + b).collect(),
+        }
+    }
+}
+
+impl Sub for &Matrix {
+    type Output = Matrix;
+    fn sub(self, other: &Matrix) -> Matrix {
+        assert!(self.rows == other.rows && self.cols == other.cols, "matrix sizes don't match");
+        Matrix {
+            rows: self.rows,
+            cols: self.cols,
+            data: self.data.iter().zip(&other.data).map(|(a, b)| a - b).collect(),
+        }
+    }
+}
+
+///The state estimate and its covariance, and the model that moves them.
+pub struct KalmanState {
+    x: Matrix,
+    p: Matrix,
+    f: Matrix,
+    q: Matrix,
+}
+
+impl KalmanState {
+    pub fn new(x: Matrix, p: Matrix, f: Matrix, q: Matrix) -> Self {
+        KalmanState { x, p, f, q }
+    }
+
+    pub fn state(&self) -> Vec<f64> {
+        self.x.data.clone()
+    }
+
+    pub fn variance(&self) -> Vec<f64> {
+        self.p.diagonal()
+    }
+
+    ///Moves the estimate ahead by one period, with the effect of the control if there is one.
+    pub fn predict(&mut self, control: Option<(&Matrix, &Matrix)>) {
+        self.x = &self.f * &self.x;
+        if let Some((b, u)) = control {
+            self.x = &self.x + &(b * u);
+        }
+        self.p = &(&(&self.f * &self.p) * &self.f.transpose()) + &self.q;
+    }
+
+    ///Corrects the estimate with a measurement z. Returns false if it couldn't, because its covariance is singular.
+    pub fn update(&mut self, h: &Matrix, r: &Matrix, z: &Matrix) -> bool {
+        let y = z - &(h * &self.x);
+        let p_ht = &self.p * &h.transpose();
+        let s = &(h * &p_ht) + r;
+        let Some(s_inv) = s.inverse() else {
+            return false;
+        };
+        let k = &p_ht * &s_inv;
+        self.x = &self.x + &(&k * &y);
+        //the joseph form keeps the covariance symmetric and positive
+        let i_kh = &Matrix::identity(self.x.rows) - &(&k * h);
+        self.p = &(&(&i_kh * &self.p) * &i_kh.transpose()) + &(&(&k * r) * &k.transpose());
+        true
+    }
+}
+
+enum Reader {
+    Float(watch::Receiver<f64>),
+    Array(watch::Receiver<Vec<Value>>),
+}
+
+impl Reader {
+    fn new(input: &KalmanInput) -> Self {
+        match input {
+            KalmanInput::Float(input) => Reader::Float(input.source()),
+            KalmanInput::Array(input) => Reader::Array(input.source()),
+        }
+    }
+
+    ///the current value as a column, None if it isn't all finite numbers
+    fn read(&mut self) -> Option<Matrix> {
+        let values = match self {
+            Reader::Float(rx) => vec![*rx.borrow_and_update()],
+            Reader::Array(rx) => rx
+                .borrow_and_update()
+                .iter()
+                .map(Value::as_f64)
+                .collect::<Option<Vec<f64>>>()?,
+        };
+        values.iter().all(|x| x.is_finite()).then(|| Matrix::column(&values))
+    }
+
+    async fn changed(&mut self) -> bool {
+        match self {
+            Reader::Float(rx) => rx.changed().await.is_ok(),
+            Reader::Array(rx) => rx.changed().await.is_ok(),
+        }
+    }
+}
+
+fn values(values: Vec<f64>) -> Vec<Value> {
+    values.into_iter().map(Value::Float).collect()
+}
+
+impl<'a> Transformer<'a> for Kalman {
+    type Config = KalmanConfig<'a>;
+
+    async fn try_build(cfg: &KalmanConfig<'a>) -> Result<Kalman, IocBuildError> {
+        if cfg.period_ms == 0 {
+            return Err(IocBuildError::message("kalman filter period_ms must be greater than 0"));
+        }
+        if cfg.measurements.is_empty() {
+            return Err(IocBuildError::message("kalman filter needs at least one measurement"));
+        }
+        let f = Matrix::from_rows("f", &cfg.f, (None, None))?;
+        let n = f.rows();
+        if f.cols() != n {
+            return Err(IocBuildError::message("kalman matrix f must be square"));
+        }
+        let q = Matrix::from_rows("q", &cfg.q, (Some(n), Some(n)))?;
+        let x = match &cfg.initial_state {
+            Some(x) if x.len() != n => {
+                return Err(IocBuildError::from_string(format!("kalman initial_state must have {} values", n)))
+            }
+            Some(x) => Matrix::column(x),
+            None => Matrix::zeros(n, 1),
+        };
+        if !cfg.names.is_empty() && cfg.names.len() != n {
+            return Err(IocBuildError::from_string(format!("kalman filter needs {} names for its state, or none", n)));
+        }
+        for (index, name) in cfg.names.iter().enumerate() {
+            if name == "state" || name == "variance" || cfg.names[..index].contains(name) {
+                return Err(IocBuildError::from_string(format!("kalman filter can't name two inputs {}", name)));
+            }
+        }
+        let p = match &cfg.initial_covariance {
+            Some(p) => Matrix::from_rows("initial_covariance", p, (Some(n), Some(n)))?,
+            None => Matrix::identity(n),
+        };
+        let mut control = match &cfg.control {
+            Some(control) => Some((Matrix::from_rows("b", &control.b, (Some(n), None))?, Reader::new(&control.input))),
+            None => None,
+        };
+        let mut measurements = Vec::with_capacity(cfg.measurements.len());
+        for (index, measurement) in cfg.measurements.iter().enumerate() {
+            let h = Matrix::from_rows(&format!("h of measurement {}", index), &measurement.h, (None, Some(n)))?;
+            let r = Matrix::from_rows(&format!("r of measurement {}", index), &measurement.r, (Some(h.rows()), Some(h.rows())))?;
+            measurements.push((Reader::new(&measurement.input), h, r));
+        }
+
+        let mut state = KalmanState::new(x, p, f, q);
+        let (state_input, state_tx) = Input::new(values(state.state()));
+        let (variance, variance_tx) = Input::new(values(state.variance()));
+        let (named, named_txs): (HashMap<_, _>, Vec<_>) = cfg
+            .names
+            .iter()
+            .zip(state.state())
+            .map(|(name, start)| {
+                let (value, tx) = Input::new(start);
+                ((name.clone(), value), tx)
+            })
+            .unzip();
+        //values from before the filter started are old news
+        for (reader, _, _) in measurements.iter_mut() {
+            reader.read();
+        }
+
+        let period = Duration::from_millis(cfg.period_ms);
+        let clock = cfg.clock.clone();
+        let mut next_predict = clock.elapsed() + period;
+        let join_handle = tokio::spawn(async move {
+            loop {
+                //predictions keep to the period no matter how often measurements come in
+                let wait = next_predict.saturating_sub(clock.elapsed());
+                let changes = measurements.iter_mut().map(|(reader, _, _)| Box::pin(reader.changed()));
+                let measured = tokio::select! {
+                    _ = clock.sleep(wait) => None,
+                    (changed, index, _) = select_all(changes) => {
+                        if !changed {
+                            break;
+                        }
+                        Some(index)
+                    },
+                };
+
+                match measured {
+                    None => {
+                        next_predict += period;
+                        let u = control.as_mut().and_then(|(b, reader)| reader.read().filter(|u| u.rows() == b.cols()));
+                        let b = control.as_ref().map(|(b, _)| b);
+                        state.predict(b.zip(u.as_ref()));
+                    }
+                    Some(index) => {
+                        let (reader, h, r) = &mut measurements[index];
+                        match reader.read() {
+                            Some(z) if z.rows() == h.rows() => {
+                                if !state.update(h, r, &z) {
+                                    warn!("kalman filter skipped measurement {}, its covariance is singular", index);
+                                }
+                            }
+                            _ => warn!("kalman filter skipped measurement {}, it must be {} finite floats", index, h.rows()),
+                        }
+                    }
+                }
+
+                let estimate = state.state();
+                if named_txs.iter().zip(&estimate).any(|(tx, value)| tx.send(*value).is_err())
+                    || state_tx.send(values(estimate)).is_err()
+                    || variance_tx.send(values(state.variance())).is_err()
+                {
+                    break;
+                }
+            }
+            debug!("kalman filter shut down");
+        });
+
+        Ok(Kalman {
+            join_handle,
+            state: state_input,
+            variance,
+            values: named,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Kalman, KalmanConfig, KalmanControl, KalmanInput, KalmanMeasurement, KalmanState, Matrix};
+    use ioc_core::{clock::Clock, Input, Transformer, Value};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_inverse() {
+        let a = Matrix::from_rows("a", &[vec![0.0, 2.0, 1.0], vec![1.0, 1.0, 0.0], vec![3.0, 0.0, 1.0]], (None, None)).unwrap();
+        let product = &a * &a.inverse().unwrap();
+        for r in 0..3 {
+            for c in 0..3 {
+                assert!((product[(r, c)] - if r == c { 1.0 } else { 0.0 }).abs() < 1e-12, "{:?}", product);
+            }
+        }
+        let singular = Matrix::from_rows("s", &[vec![1.0, 2.0], vec![2.0, 4.0]], (None, None)).unwrap();
+        assert_eq!(singular.inverse(), None);
+        let err = Matrix::from_rows("h", &[vec![1.0, 0.0]], (None, Some(3))).unwrap_err();
+        assert!(format!("{:?}", err).contains("1x2 but must be anyx3"), "{:?}", err);
+    }
+
+    #[test]
+    fn test_constant_velocity() {
+        //position and velocity, with noisy positions of something moving at 2 units per second
+        let dt = 0.1;
+        let f = Matrix::from_rows("f", &[vec![1.0, dt], vec![0.0, 1.0]], (None, None)).unwrap();
+        let q = Matrix::from_rows("q", &[vec![1e-6, 0.0], vec![0.0, 1e-6]], (None, None)).unwrap();
+        let h = Matrix::from_rows("h", &[vec![1.0, 0.0]], (None, None)).unwrap();
+        let r = Matrix::column(&[0.25]);
+        let mut state = KalmanState::new(Matrix::zeros(2, 1), Matrix::identity(2), f, q);
+        for step in 1..=200 {
+            state.predict(None);
+            //noise that averages out, from -0.5 to 0.5
+            let noise = ((step * 7919) % 101) as f64 / 100.0 - 0.5;
+            state.update(&h, &r, &Matrix::column(&[2.0 * dt * step as f64 + noise]));
+        }
+        let [position, velocity] = state.state()[..] else { panic!() };
+        assert!((position - 40.0).abs() < 0.2, "{}", position);
+        assert!((velocity - 2.0).abs() < 0.05, "{}", velocity);
+        assert!(state.variance().iter().all(|v| *v > 0.0 && *v < 0.05), "{:?}", state.variance());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_kalman_simulated() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let (position, position_tx) = Input::new(0.0);
+        let (accel, _accel_tx) = Input::new(vec![Value::Float(1.0)]);
+        //pushed along at 1 unit per second squared, with the position measured
+        let kalman = Kalman::try_build(&KalmanConfig {
+            f: vec![vec![1.0, 0.1], vec![0.0, 1.0]],
+            q: vec![vec![1e-4, 0.0], vec![0.0, 1e-4]],
+            control: Some(KalmanControl {
+                input: KalmanInput::Array(&accel),
+                b: vec![vec![0.005], vec![0.1]],
+            }),
+            measurements: vec![KalmanMeasurement {
+                input: KalmanInput::Float(&position),
+                h: vec![vec![1.0, 0.0]],
+                r: vec![vec![0.01]],
+            }],
+            initial_state: None,
+            initial_covariance: Some(vec![vec![100.0, 0.0], vec![0.0, 100.0]]),
+            names: vec!["position".to_string(), "velocity".to_string()],
+            period_ms: 100,
+            clock: &clock,
+        })
+        .await
+        .unwrap();
+        let state = kalman.state.source();
+        let variance = kalman.variance.source();
+        let velocity = kalman.values["velocity"].source();
+
+        //predictions alone follow the control
+        driver.advance(Duration::from_millis(1000)).await;
+        let floats = |values: &Vec<Value>| values.iter().map(|v| if let Value::Float(x) = v { *x } else { f64::NAN }).collect::<Vec<_>>();
+        let predicted = floats(&state.borrow());
+        assert!((predicted[0] - 0.5).abs() < 1e-9 && (predicted[1] - 1.0).abs() < 1e-9, "{:?}", predicted);
+        assert!(floats(&variance.borrow())[0] > 100.0);
+        assert_eq!(*velocity.borrow(), predicted[1]);
+
+        //a measurement pulls the position to it, and makes it certain
+        position_tx.send(3.0).unwrap();
+        driver.tick().await;
+        let corrected = floats(&state.borrow());
+        assert!((corrected[0] - 3.0).abs() < 0.01, "{:?}", corrected);
+        assert!(floats(&variance.borrow())[0] < 0.011, "{:?}", floats(&variance.borrow()));
+        kalman.join_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_kalman_invalid_config() {
+        let clock = Clock::real();
+        let (position, _position_tx) = Input::new(0.0);
+        let result = Kalman::try_build(&KalmanConfig {
+            f: vec![vec![1.0, 0.1], vec![0.0, 1.0]],
+            q: vec![vec![1e-4]],
+            control: None,
+            measurements: vec![KalmanMeasurement {
+                input: KalmanInput::Float(&position),
+                h: vec![vec![1.0, 0.0]],
+                r: vec![vec![0.01]],
+            }],
+            initial_state: None,
+            initial_covariance: None,
+            names: Vec::new(),
+            period_ms: 100,
+            clock: &clock,
+        })
+        .await;
+        let err = result.err().unwrap();
+        assert!(format!("{:?}", err).contains("kalman matrix q is 1x1 but must be 2x2"), "{:?}", err);
+    }
+}
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 
-use ioc_core::{error::IocBuildError, Input, InputKind, Transformer, TransformerI};
-use tokio::{sync::watch, task::JoinHandle, time::sleep};
+use ioc_core::{clock::Clock, error::IocBuildError, Input, InputKind, Transformer, TransformerI};
+use tokio::{sync::watch, task::JoinHandle};
 use tracing::debug;
-use std::time::{Instant,Duration};
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct LimiterParams {
@@ -33,6 +33,7 @@ impl Default for LimiterParams {
 pub struct LimiterFilterConfig<'a> {
     pub input: &'a Input<f64>,
     pub params: LimiterParams,
+    pub clock: &'a Clock,
 }
 
 pub struct Limiter {
@@ -56,11 +57,11 @@ struct State {
     x: f64, //current value of x
     dx: f64, //current derivative of x
     ddx: f64, //current acceleration of x
-    last_time: Instant, //timestamp of the last step
+    last_time: Duration, //clock.elapsed() at the last step
 }
 
 impl State {
-    fn initial(start: f64, params: &LimiterParams) -> Result<State, IocBuildError> {
+    fn initial(start: f64, params: &LimiterParams, now: Duration) -> Result<State, IocBuildError> {
         if params.min > params.max || params.min.is_nan() || params.max.is_nan() {
             return Err(IocBuildError::message("must have min < max and non NaN values in limiter"));
         }
@@ -71,17 +72,15 @@ impl State {
             return Err(IocBuildError::message("must have ddmin < ddmax and non NaN values in limiter"));
         }
         let x = start.max(params.min).min(params.max);
-        Ok(State{target: x, x, dx: 0.0, ddx: 0.0, last_time: Instant::now()})
+        Ok(State{target: x, x, dx: 0.0, ddx: 0.0, last_time: now})
     }
 
-    fn step(&mut self, params: &LimiterParams) -> f64 {
-        let now = Instant::now();
-
+    fn step(&mut self, params: &LimiterParams, now: Duration) -> f64 {
         if self.x == self.target && self.dx == 0.0 && self.ddx == 0.0 {
             self.last_time = now;
             self.x
         } else {
-            let dt = now.duration_since(self.last_time).as_secs_f64();
+            let dt = now.saturating_sub(self.last_time).as_secs_f64();
             //move with whatever velocity we had last frame
             self.x = (self.x + self.dx * dt).min(params.max).max(params.min);
             self.last_time = now; 
@@ -154,41 +153,18 @@ fn calc_accel_decel_times(deccel: f64, accel: f64, x0: f64, v0: f64, xf: f64) ->
 }
 
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn test_calc_accel_decel_times() {
-        //x0=0, v0=0, xf=5. accelerate at 1 for sqrt(5) secs, decelerate at -1 for sqrt(5) secs
-        let times = super::calc_accel_decel_times(-1.0, 1.0, 0.0, 0.0, 5.0);
-        assert_eq!(times, Some(((5.0_f64).sqrt(), (5.0_f64).sqrt())));
-
-        //x0=0, v0=0, xf=-5. accelerate at -1 for sqrt(5) secs, decelerate at 1 for sqrt(5) secs
-        let times = super::calc_accel_decel_times(1.0, -1.0, 0.0, 0.0, -5.0);
-        assert_eq!(times, Some(((5.0_f64).sqrt(), (5.0_f64).sqrt())));
-
-        //x0=0, v0=1, xf=5. accelerate at 1 for t1=(sqrt(34)-4)/5 secs, decelerate at -1 for t1 + 1 secs
-        let times = super::calc_accel_decel_times(-1.0, 1.0, 0.0, 1.0, 5.0);
-        let expected_t1 = ((34.0_f64).sqrt()-4.0)/2.0;
-        assert_eq!(times, Some((expected_t1, expected_t1+1.0)));
-
-        //overshooting test (same as the first but v0 = 100)
-        let times = super::calc_accel_decel_times(-1.0, 1.0, 0.0, 100.0, 5.0);
-        assert_eq!(times, None);
-    }
-}
-
-
 fn spawn_limiter_task(
     mut state: State, 
     params: &LimiterParams, 
     mut rx: watch::Receiver<f64>,
-    tx: watch::Sender<f64>
+    tx: watch::Sender<f64>,
+    clock: Clock,
 ) -> JoinHandle<()> {
     let params = params.clone();
     tokio::spawn(async move {
         loop {
             tokio::select! {
-                _ = sleep(Duration::from_millis(params.period_ms)) => {},
+                _ = clock.sleep(Duration::from_millis(params.period_ms)) => {},
                 in_res = rx.changed() => {
                     if in_res.is_ok() {
                         let new_in = *rx.borrow_and_update();
@@ -198,7 +174,7 @@ fn spawn_limiter_task(
                     }
                 }
             }
-            if tx.send(state.step(&params)).is_err() {
+            if tx.send(state.step(&params, clock.elapsed())).is_err() {
                 break;
             }
         }
@@ -214,14 +190,38 @@ impl<'a> Transformer<'a> for Limiter {
         let params = &cfg.params;
         let mut in_rx = cfg.input.source();
         let start = *in_rx.borrow_and_update();
-        let state = State::initial(start, params)?;
+        let state = State::initial(start, params, cfg.clock.elapsed())?;
         let (value, out_tx) = Input::new(start);
 
-        let join_handle = spawn_limiter_task(state, params, in_rx, out_tx);
+        let join_handle = spawn_limiter_task(state, params, in_rx, out_tx, cfg.clock.clone());
 
         Ok(Limiter{
             join_handle,
             value,
         })
     }
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_calc_accel_decel_times() {
+        //x0=0, v0=0, xf=5. accelerate at 1 for sqrt(5) secs, decelerate at -1 for sqrt(5) secs
+        let times = super::calc_accel_decel_times(-1.0, 1.0, 0.0, 0.0, 5.0);
+        assert_eq!(times, Some(((5.0_f64).sqrt(), (5.0_f64).sqrt())));
+
+        //x0=0, v0=0, xf=-5. accelerate at -1 for sqrt(5) secs, decelerate at 1 for sqrt(5) secs
+        let times = super::calc_accel_decel_times(1.0, -1.0, 0.0, 0.0, -5.0);
+        assert_eq!(times, Some(((5.0_f64).sqrt(), (5.0_f64).sqrt())));
+
+        //x0=0, v0=1, xf=5. accelerate at 1 for t1=(sqrt(34)-4)/5 secs, decelerate at -1 for t1 + 1 secs
+        let times = super::calc_accel_decel_times(-1.0, 1.0, 0.0, 1.0, 5.0);
+        let expected_t1 = ((34.0_f64).sqrt()-4.0)/2.0;
+        assert_eq!(times, Some((expected_t1, expected_t1+1.0)));
+
+        //overshooting test (same as the first but v0 = 100)
+        let times = super::calc_accel_decel_times(-1.0, 1.0, 0.0, 100.0, 5.0);
+        assert_eq!(times, None);
+    }
 }
\ No newline at end of file
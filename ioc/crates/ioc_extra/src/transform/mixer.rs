@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use futures::future::select_all;
+use ioc_core::{error::IocBuildError, Input, InputKind, Transformer, TransformerI};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+pub struct MixerConfig<'a> {
+    ///the inputs and their weights
+    pub inputs: Vec<(&'a Input<f64>, f64)>,
+    pub min: f64,
+    pub max: f64,
+}
+
+///Emits the weighted sum of its inputs, clamped to min and max, whenever one of them changes.
+pub struct Mixer {
+    pub join_handle: JoinHandle<()>,
+    pub value: Input<f64>,
+    ///true while the sum is beyond min or max
+    pub saturated: Input<bool>,
+}
+
+impl From<Mixer> for TransformerI {
+    fn from(mixer: Mixer) -> Self {
+        TransformerI {
+            join_handle: mixer.join_handle,
+            inputs: HashMap::from([
+                ("value".to_owned(), InputKind::Float(mixer.value)),
+                ("saturated".to_owned(), InputKind::Bool(mixer.saturated)),
+            ]),
+        }
+    }
+}
+
+fn mix(values: impl Iterator<Item = (f64, f64)>, min: f64, max: f64) -> (f64, bool) {
+    let sum: f64 = values.map(|(value, weight)| value * weight).sum();
+    let value = sum.clamp(min, max);
+    (value, value != sum && !sum.is_nan())
+}
+
+impl<'a> Transformer<'a> for Mixer {
+    type Config = MixerConfig<'a>;
+
+    async fn try_build(cfg: &MixerConfig<'a>) -> Result<Mixer, IocBuildError> {
+        if cfg.inputs.is_empty() {
+            return Err(IocBuildError::message("mixer needs at least one input"));
+        }
+        //written so NaNs fail too
+        let limits_valid = cfg.min <= cfg.max;
+        if !limits_valid {
+            return Err(IocBuildError::message("mixer must have min <= max"));
+        }
+        let mut readers: Vec<_> = cfg.inputs.iter().map(|(input, weight)| (input.source(), *weight)).collect();
+        let (min, max) = (cfg.min, cfg.max);
+        let (start, start_saturated) = mix(readers.iter_mut().map(|(rx, weight)| (*rx.borrow_and_update(), *weight)), min, max);
+        let (value, value_tx) = Input::new(start);
+        let (saturated, saturated_tx) = Input::new(start_saturated);
+
+        let join_handle = tokio::spawn(async move {
+            loop {
+                let changes = readers.iter_mut().map(|(rx, _)| Box::pin(rx.changed()));
+                let (changed, _, _) = select_all(changes).await;
+                if changed.is_err() {
+                    break;
+                }
+                let (mixed, is_saturated) = mix(readers.iter_mut().map(|(rx, weight)| (*rx.borrow_and_update(), *weight)), min, max);
+                saturated_tx.send_if_modified(|was| std::mem::replace(was, is_saturated) != is_saturated);
+                if value_tx.send(mixed).is_err() {
+                    break;
+                }
+            }
+            debug!("shutting down mixer!");
+        });
+
+        Ok(Mixer {
+            join_handle,
+            value,
+            saturated,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mixer, MixerConfig};
+    use ioc_core::{Input, Transformer};
+
+    #[tokio::test]
+    async fn test_mixer() {
+        let (a, a_tx) = Input::new(1.0);
+        let (b, _b_tx) = Input::new(2.0);
+        let mixer = Mixer::try_build(&MixerConfig {
+            inputs: vec![(&a, 1.0), (&b, -0.5)],
+            min: -1.0,
+            max: 1.0,
+        })
+        .await
+        .unwrap();
+        let mut value = mixer.value.source();
+        let saturated = mixer.saturated.source();
+        assert_eq!(*value.borrow_and_update(), 0.0);
+        assert!(!*saturated.borrow());
+
+        //the sum saturates
+        a_tx.send(4.0).unwrap();
+        value.changed().await.unwrap();
+        assert_eq!(*value.borrow_and_update(), 1.0);
+        assert!(*saturated.borrow());
+
+        assert!(Mixer::try_build(&MixerConfig { inputs: vec![], min: 0.0, max: 1.0 }).await.is_err());
+        mixer.join_handle.abort();
+    }
+}
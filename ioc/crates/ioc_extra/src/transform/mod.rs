@@ -5,9 +5,15 @@ pub mod hbridge;
 ///Takes a Float input (x) and applies a simple linear transform, providing an input that emits m*x+b for some m and b.
 pub mod linear;
 
-///An implementation of a PID controller. Takes a set_point, process_var, P, I and D parameters. Provides a single input that emits an output value.    
+///An implementation of a PID controller. Takes a set_point, process_var, P, I and D parameters, with optional output limits, feed forward and enable. Provides an input that emits an output value, and its P, I and D terms.
 pub mod pid;
 
+///Two PID controllers in a cascade, the inner one running at a multiple of the outer one's rate
+pub mod cascade;
+
+///Combines several Float inputs with weights, and saturates the sum
+pub mod mixer;
+
 ///Various simple 1-1 functions 
 pub mod function;
 
@@ -15,4 +21,24 @@ pub mod function;
 pub mod limiter;
 
 ///Use to average the value over fixed windows
-pub mod average;
\ No newline at end of file
+pub mod average;
+
+///Smoothing and spike rejection filters: moving average, butterworth, median and deadband
+pub mod filter;
+
+///Fuses a gyro, accelerometer and magnetometer into an orientation and a tilt compensated heading
+pub mod ahrs;
+
+///A linear Kalman filter over Float and Array measurements
+pub mod kalman;
+
+///Runs a user-provided script with typed input and output ports
+#[cfg(feature = "script")]
+pub mod script;
+
+///Evaluates a type checked arithmetic or boolean expression over named inputs
+pub mod expression;
+
+///Runs transformers compiled to WebAssembly that implement a small ABI
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,8 +1,87 @@
+use ioc_core::clock::Clock;
+use ioc_core::sample::Quality;
 use ioc_core::{error::IocBuildError, Input, InputKind, Transformer, TransformerI};
-use tokio::{time::sleep, task::JoinHandle};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use std::collections::HashMap;
-use std::time::{Instant,Duration};
-use tracing::debug;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+///How the integral is kept from winding up while the output is limited.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Default)]
+pub enum AntiWindup {
+    ///Stops integrating while the output is limited and the error would push it further past the limit.
+    #[default]
+    Clamp,
+    ///Bleeds the part of the output beyond the limit back out of the integral, with a time constant of tracking_ms.
+    BackCalculation { tracking_ms: f64 },
+}
+
+///Limits on the output of a pid controller. Unlimited by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PidLimits {
+    pub min: f64,
+    pub max: f64,
+    pub anti_windup: AntiWindup,
+}
+
+impl Default for PidLimits {
+    fn default() -> Self {
+        PidLimits {
+            min: f64::NEG_INFINITY,
+            max: f64::INFINITY,
+            anti_windup: AntiWindup::Clamp,
+        }
+    }
+}
+
+///A rule that turns the ultimate gain and period found by autotuning into P, I and D gains.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Default)]
+pub enum TuningRule {
+    ///Ziegler-Nichols' classic PID rule. Fast, with a quarter amplitude decay.
+    #[default]
+    ZieglerNichols,
+    ///Ziegler-Nichols' rule for a PI controller.
+    ZieglerNicholsPi,
+    ///Tyreus-Luyben's rule, slower and more robust than Ziegler-Nichols.
+    TyreusLuyben,
+    ///Ziegler-Nichols with some overshoot.
+    SomeOvershoot,
+    ///Ziegler-Nichols with no overshoot.
+    NoOvershoot,
+}
+
+impl TuningRule {
+    ///The P, I and D gains for a process with the given ultimate gain and period in seconds.
+    pub fn gains(&self, ultimate_gain: f64, ultimate_period: f64) -> [f64; 3] {
+        //proportional gain, and integral and derivative times in periods
+        let (p, ti, td) = match self {
+            TuningRule::ZieglerNichols => (0.6, 0.5, 0.125),
+            TuningRule::ZieglerNicholsPi => (0.45, 1.0 / 1.2, 0.0),
+            TuningRule::TyreusLuyben => (1.0 / 2.2, 2.2, 1.0 / 6.3),
+            TuningRule::SomeOvershoot => (1.0 / 3.0, 0.5, 1.0 / 3.0),
+            TuningRule::NoOvershoot => (0.2, 0.5, 1.0 / 3.0),
+        };
+        let p = p * ultimate_gain;
+        [p, p / (ti * ultimate_period), p * td * ultimate_period]
+    }
+}
+
+///Autotuning with a relay experiment. While it runs, the output switches between amplitude above and below what it was when it started as the process var crosses the set point, which makes the process oscillate. The size and period of the oscillation give the ultimate gain and period, and a rule turns those into gains.
+pub struct PidAutotune<'a> {
+    ///starts the experiment when it turns true. turning it false stops it
+    pub start: &'a Input<bool>,
+    pub amplitude: f64,
+    ///how far the process var has to cross the set point to switch the output, so noise doesn't
+    pub hysteresis: f64,
+    ///how many oscillations to measure, after the first
+    pub cycles: u32,
+    pub rule: TuningRule,
+    ///experiments that take longer are stopped without results, e.g. if the output can't move the process var across the set point
+    pub max_duration: Duration,
+}
 
 pub struct PidConfig<'a> {
     pub set_point: &'a Input<f64>,
@@ -10,53 +89,211 @@ pub struct PidConfig<'a> {
     pub p: &'a Input<f64>,
     pub i: &'a Input<f64>,
     pub d: &'a Input<f64>,
+    ///added to the output as is
+    pub feed_forward: Option<&'a Input<f64>>,
+    ///the controller runs while this is true. always enabled if there is none
+    pub enable: Option<&'a Input<bool>>,
+    ///the output while disabled. 0 if there is none
+    pub manual: Option<&'a Input<f64>>,
+    pub limits: PidLimits,
+    ///time constant of the low-pass filter on the derivative. 0 doesn't filter it
+    pub derivative_filter_ms: f64,
+    pub autotune: Option<PidAutotune<'a>>,
     pub period_ms: u64,
+    pub clock: &'a Clock,
 }
 
 pub struct Pid {
     pub join_handle: JoinHandle<()>,
     pub value: Input<f64>,
+    pub p_term: Input<f64>,
+    pub i_term: Input<f64>,
+    pub d_term: Input<f64>,
+    pub autotune: Option<PidAutotuneOutputs>,
+}
+
+///What autotuning found. NaN until an experiment finishes.
+pub struct PidAutotuneOutputs {
+    ///true while the relay experiment runs
+    pub tuning: Input<bool>,
+    pub ultimate_gain: Input<f64>,
+    ///in seconds
+    pub ultimate_period: Input<f64>,
+    pub suggested_p: Input<f64>,
+    pub suggested_i: Input<f64>,
+    pub suggested_d: Input<f64>,
+}
+
+//the senders for autotuning's outputs
+struct AutotuneTx {
+    tuning: watch::Sender<bool>,
+    ultimate_gain: watch::Sender<f64>,
+    ultimate_period: watch::Sender<f64>,
+    suggested: [watch::Sender<f64>; 3],
+}
+
+impl AutotuneTx {
+    fn send(&self, tuning: bool, results: Option<TuningResults>) -> bool {
+        self.tuning.send_if_modified(|was| std::mem::replace(was, tuning) != tuning);
+        match results {
+            Some(results) => self.ultimate_gain.send(results.ultimate_gain).is_ok()
+                && self.ultimate_period.send(results.ultimate_period).is_ok()
+                && self.suggested.iter().zip(results.gains).all(|(tx, gain)| tx.send(gain).is_ok()),
+            None => !self.tuning.is_closed(),
+        }
+    }
 }
 
 impl From<Pid> for TransformerI {
     fn from(pid: Pid) -> Self {
+        let mut inputs = HashMap::from([
+            ("value".to_string(), InputKind::Float(pid.value)),
+            ("p_term".to_string(), InputKind::Float(pid.p_term)),
+            ("i_term".to_string(), InputKind::Float(pid.i_term)),
+            ("d_term".to_string(), InputKind::Float(pid.d_term)),
+        ]);
+        if let Some(autotune) = pid.autotune {
+            inputs.extend([
+                ("tuning".to_string(), InputKind::Bool(autotune.tuning)),
+                ("ultimate_gain".to_string(), InputKind::Float(autotune.ultimate_gain)),
+                ("ultimate_period".to_string(), InputKind::Float(autotune.ultimate_period)),
+                ("suggested_p".to_string(), InputKind::Float(autotune.suggested_p)),
+                ("suggested_i".to_string(), InputKind::Float(autotune.suggested_i)),
+                ("suggested_d".to_string(), InputKind::Float(autotune.suggested_d)),
+            ]);
+        }
         TransformerI{
             join_handle: pid.join_handle,
-            inputs: HashMap::from([
-                ("value".to_string(), InputKind::Float(pid.value)),
-            ]),
+            inputs,
         }
     }
 }
 
+//waits for the next value of an optional input, forever if there is none
+async fn next<T: Clone>(rx: &mut Option<watch::Receiver<T>>) -> Option<T> {
+    match rx {
+        Some(rx) => match rx.changed().await {
+            Ok(_) => Some(rx.borrow_and_update().clone()),
+            Err(_) => None,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+///Checks the output limits and derivative filter of a controller.
+pub(crate) fn validate(limits: &PidLimits, derivative_filter_ms: f64) -> Result<(), IocBuildError> {
+    //written so NaNs fail too
+    let limits_valid = limits.min <= limits.max;
+    if !limits_valid {
+        return Err(IocBuildError::message("pid controller must have min <= max"));
+    }
+    if let AntiWindup::BackCalculation { tracking_ms } = limits.anti_windup {
+        let tracking_valid = tracking_ms > 0.0 && tracking_ms.is_finite();
+        if !tracking_valid {
+            return Err(IocBuildError::message("pid controller tracking_ms must be greater than 0"));
+        }
+    }
+    let filter_valid = derivative_filter_ms >= 0.0 && derivative_filter_ms.is_finite();
+    if !filter_valid {
+        return Err(IocBuildError::message("pid controller derivative_filter_ms must not be negative"));
+    }
+    Ok(())
+}
+
 impl<'a> Transformer<'a> for Pid {
     type Config = PidConfig<'a>;
 
     async fn try_build(cfg: &PidConfig<'a>) -> Result<Pid, IocBuildError> {
+        validate(&cfg.limits, cfg.derivative_filter_ms)?;
+        if let Some(autotune) = &cfg.autotune {
+            let autotune_valid = autotune.amplitude > 0.0 && autotune.amplitude.is_finite()
+                && autotune.hysteresis >= 0.0 && autotune.hysteresis.is_finite()
+                && autotune.cycles > 0 && !autotune.max_duration.is_zero();
+            if !autotune_valid {
+                return Err(IocBuildError::message(
+                    "pid autotune must have amplitude > 0, hysteresis >= 0, cycles > 0 and a max duration"
+                ));
+            }
+        }
+
         let mut set_point = cfg.set_point.source();
-        let mut process_var = cfg.process_var.source();
+        //process var samples carry the time they were measured, and plain values the time on the clock they were read at
+        let mut process_var = cfg.process_var.sample_source().clocked(cfg.clock);
         let mut p = cfg.p.source();
         let mut i = cfg.i.source();
         let mut d = cfg.d.source();
+        let mut feed_forward = cfg.feed_forward.map(Input::source);
+        let mut enable = cfg.enable.map(Input::source);
+        let mut manual = cfg.manual.map(Input::source);
+        let mut autotune_start = cfg.autotune.as_ref().map(|autotune| autotune.start.source());
 
         let mut state = PidState::new(
             *p.borrow_and_update(),
             *i.borrow_and_update(),
             *d.borrow_and_update(),
             *set_point.borrow_and_update(),
-            *process_var.borrow_and_update(),
+            process_var.borrow_and_update().value,
+            cfg.clock.elapsed(),
         );
-        //start with just a p component for the output
-        let start_value = state.last_err * *p.borrow_and_update();
-        let (value, value_tx) = Input::new(start_value);
+        state.limit(cfg.limits, cfg.derivative_filter_ms);
+        if let Some(feed_forward) = feed_forward.as_mut() {
+            state.feed_forward = *feed_forward.borrow_and_update();
+        }
+        if let Some(enable) = enable.as_mut() {
+            state.enabled = *enable.borrow_and_update();
+        }
+        if let Some(manual) = manual.as_mut() {
+            state.manual = *manual.borrow_and_update();
+        }
+        state.autotune = cfg.autotune.as_ref().map(|autotune| AutotuneParams {
+            amplitude: autotune.amplitude,
+            hysteresis: autotune.hysteresis,
+            cycles: autotune.cycles as usize,
+            rule: autotune.rule,
+            max_duration: autotune.max_duration,
+        });
+        //only a change to true starts autotuning, so it doesn't start with the ioc
+        if let Some(autotune_start) = autotune_start.as_mut() {
+            autotune_start.borrow_and_update();
+        }
+        state.step(cfg.clock.elapsed());
+
+        let (value, value_tx) = Input::new(state.value);
+        let (p_term, p_term_tx) = Input::new(state.p_term);
+        let (i_term, i_term_tx) = Input::new(state.i_term);
+        let (d_term, d_term_tx) = Input::new(state.d_term);
+        let (autotune, autotune_tx) = match cfg.autotune {
+            Some(_) => {
+                let (tuning, tuning_tx) = Input::new(false);
+                let (ultimate_gain, ultimate_gain_tx) = Input::new(f64::NAN);
+                let (ultimate_period, ultimate_period_tx) = Input::new(f64::NAN);
+                let (suggested_p, suggested_p_tx) = Input::new(f64::NAN);
+                let (suggested_i, suggested_i_tx) = Input::new(f64::NAN);
+                let (suggested_d, suggested_d_tx) = Input::new(f64::NAN);
+                (
+                    Some(PidAutotuneOutputs { tuning, ultimate_gain, ultimate_period, suggested_p, suggested_i, suggested_d }),
+                    Some(AutotuneTx {
+                        tuning: tuning_tx,
+                        ultimate_gain: ultimate_gain_tx,
+                        ultimate_period: ultimate_period_tx,
+                        suggested: [suggested_p_tx, suggested_i_tx, suggested_d_tx],
+                    }),
+                )
+            }
+            None => (None, None),
+        };
         let period_ms = cfg.period_ms;
+        let clock = cfg.clock.clone();
         let join_handle = tokio::spawn(async move {
             loop {
                 tokio::select! {
-                    _ = sleep(Duration::from_millis(period_ms)) => {},
+                    _ = clock.sleep(Duration::from_millis(period_ms)) => {
+                        state.step(clock.elapsed());
+                    },
                     p_res = p.changed() => {
                         if p_res.is_ok() {
                             state.p= *p.borrow_and_update();
+                            state.step(clock.elapsed());
                         } else {
                             break;
                         }
@@ -64,6 +301,7 @@ impl<'a> Transformer<'a> for Pid {
                     i_res = i.changed() => {
                         if i_res.is_ok() {
                             state.i= *i.borrow_and_update();
+                            state.step(clock.elapsed());
                         } else {
                             break;
                         }
@@ -71,6 +309,7 @@ impl<'a> Transformer<'a> for Pid {
                     d_res = d.changed() => {
                         if d_res.is_ok() {
                             state.d= *d.borrow_and_update();
+                            state.step(clock.elapsed());
                         } else {
                             break;
                         }
@@ -78,22 +317,75 @@ impl<'a> Transformer<'a> for Pid {
                     set_point_res = set_point.changed() => {
                         if set_point_res.is_ok() {
                             state.set_point= *set_point.borrow_and_update();
+                            state.step(clock.elapsed());
                         } else {
                             break;
                         }
                     },
                     process_var_res = process_var.changed() => {
                         if process_var_res.is_ok() {
-                            state.process_var= *process_var.borrow_and_update();
+                            //keep the last good value if the measurement failed
+                            let sample = process_var.borrow_and_update();
+                            if sample.info.quality == Quality::Good {
+                                state.process_var= sample.value;
+                                state.step(sample.info.elapsed);
+                            }
                         } else {
                             break;
                         }
                     },
+                    feed_forward_res = next(&mut feed_forward) => {
+                        match feed_forward_res {
+                            Some(feed_forward) => {
+                                state.feed_forward= feed_forward;
+                                state.step(clock.elapsed());
+                            },
+                            None => break,
+                        }
+                    },
+                    enable_res = next(&mut enable) => {
+                        match enable_res {
+                            Some(enabled) => {
+                                state.enabled= enabled;
+                                state.step(clock.elapsed());
+                            },
+                            None => break,
+                        }
+                    },
+                    manual_res = next(&mut manual) => {
+                        match manual_res {
+                            Some(manual) => {
+                                state.manual= manual;
+                                state.step(clock.elapsed());
+                            },
+                            None => break,
+                        }
+                    },
+                    autotune_res = next(&mut autotune_start) => {
+                        match autotune_res {
+                            Some(true) => {
+                                state.start_autotune(clock.elapsed());
+                                state.step(clock.elapsed());
+                            },
+                            Some(false) => {
+                                state.relay = None;
+                                state.step(clock.elapsed());
+                            },
+                            None => break,
+                        }
+                    },
                 };
 
-                let value = state.step();
+                if let Some(autotune_tx) = autotune_tx.as_ref() {
+                    if !autotune_tx.send(state.relay.is_some(), state.tuned.take()) {
+                        break;
+                    }
+                }
 
-                if value_tx.send(value).is_err() {
+                if p_term_tx.send(state.p_term).is_err()
+                    || i_term_tx.send(state.i_term).is_err()
+                    || d_term_tx.send(state.d_term).is_err()
+                    || value_tx.send(state.value).is_err() {
                     break;
                 }
             }
@@ -103,60 +395,511 @@ impl<'a> Transformer<'a> for Pid {
         Ok(Pid{
             join_handle,
             value,
+            p_term,
+            i_term,
+            d_term,
+            autotune,
         })
     }
 }
 
+struct AutotuneParams {
+    amplitude: f64,
+    hysteresis: f64,
+    cycles: usize,
+    rule: TuningRule,
+    max_duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TuningResults {
+    ultimate_gain: f64,
+    ultimate_period: f64,
+    gains: [f64; 3],
+}
+
+///A running relay experiment.
+struct Relay {
+    //the output it switches around, and when it started
+    bias: f64,
+    started: Duration,
+    high: bool,
+    //when the output last switched high, and the extremes of the process var since
+    last_rise: Option<Duration>,
+    min: f64,
+    max: f64,
+    //the period and amplitude of each oscillation
+    cycles: Vec<(f64, f64)>,
+}
+
 ///internal state of pid controller
-struct PidState {
-    p: f64,
-    i: f64,
-    d: f64,
-    set_point: f64,
-    process_var: f64,
-    last_update: Instant,
-    last_err: f64,
-    integral_sum: f64,
+pub(crate) struct PidState {
+    pub(crate) p: f64,
+    pub(crate) i: f64,
+    pub(crate) d: f64,
+    pub(crate) set_point: f64,
+    pub(crate) process_var: f64,
+    pub(crate) feed_forward: f64,
+    pub(crate) enabled: bool,
+    pub(crate) manual: f64,
+    //the integral doesn't change while this is set, e.g. while a cascade's inner loop can't follow the output
+    pub(crate) hold_integral: bool,
+    limits: PidLimits,
+    //seconds
+    derivative_filter: f64,
+    autotune: Option<AutotuneParams>,
+    relay: Option<Relay>,
+    //results of an experiment that finished and weren't sent yet
+    tuned: Option<TuningResults>,
+    //on the clock's monotonic time
+    last_update: Duration,
+    last_process_var: f64,
+    //filtered derivative of the error, taken from the process var alone so set point changes don't kick it
+    derivative: f64,
+    p_term: f64,
+    i_term: f64,
+    d_term: f64,
+    pub(crate) value: f64,
 }
 
 impl PidState {
-    fn new(p: f64, i: f64, d: f64, set_point: f64, process_var: f64) -> Self {
+    pub(crate) fn new(p: f64, i: f64, d: f64, set_point: f64, process_var: f64, now: Duration) -> Self {
         PidState {
             p, i, d, set_point, process_var,
-            last_update: Instant::now(),
-            last_err: set_point-process_var,
-            integral_sum: 0.0 
+            feed_forward: 0.0,
+            enabled: true,
+            manual: 0.0,
+            hold_integral: false,
+            limits: PidLimits::default(),
+            derivative_filter: 0.0,
+            autotune: None,
+            relay: None,
+            tuned: None,
+            last_update: now,
+            last_process_var: process_var,
+            derivative: 0.0,
+            //start with just a p component for the output
+            p_term: p * (set_point-process_var),
+            i_term: 0.0,
+            d_term: 0.0,
+            value: p * (set_point-process_var),
         }
     }
 }
 
 impl PidState {
-    fn step(&mut self) -> f64 {
+    pub(crate) fn limit(&mut self, limits: PidLimits, derivative_filter_ms: f64) {
+        self.limits = limits;
+        self.derivative_filter = derivative_filter_ms / 1000.0;
+    }
+
+    fn start_autotune(&mut self, now: Duration) {
+        if self.autotune.is_none() || self.relay.is_some() || !self.enabled {
+            return;
+        }
+        self.relay = Some(Relay {
+            bias: self.value,
+            started: now,
+            high: self.set_point > self.process_var,
+            last_rise: None,
+            min: self.process_var,
+            max: self.process_var,
+            cycles: Vec::new(),
+        });
+    }
+
+    ///Switches the relay as the process var crosses the set point and measures the oscillation. Returns the output.
+    fn step_relay(&mut self, err: f64, now: Duration) -> f64 {
+        let (Some(params), Some(relay)) = (self.autotune.as_ref(), self.relay.as_mut()) else {
+            return self.value;
+        };
+        if now.saturating_sub(relay.started) > params.max_duration {
+            warn!("pid autotune stopped after {:?} without finishing", params.max_duration);
+            let bias = relay.bias;
+            self.relay = None;
+            return bias;
+        }
+        let PidLimits { min, max, .. } = self.limits;
+        relay.min = relay.min.min(self.process_var);
+        relay.max = relay.max.max(self.process_var);
+        if relay.high && err < -params.hysteresis {
+            relay.high = false;
+        } else if !relay.high && err > params.hysteresis {
+            relay.high = true;
+            if let Some(last_rise) = relay.last_rise {
+                let period = now.saturating_sub(last_rise).as_secs_f64();
+                relay.cycles.push((period, (relay.max - relay.min) / 2.0));
+            }
+            relay.last_rise = Some(now);
+            relay.min = self.process_var;
+            relay.max = self.process_var;
+        }
+        let high = (relay.bias + params.amplitude).clamp(min, max);
+        let low = (relay.bias - params.amplitude).clamp(min, max);
+        let output = if relay.high { high } else { low };
+
+        //the first oscillation is still settling
+        if relay.cycles.len() > params.cycles {
+            let measured = &relay.cycles[1..];
+            let period = measured.iter().map(|(period, _)| period).sum::<f64>() / measured.len() as f64;
+            let amplitude = measured.iter().map(|(_, amplitude)| amplitude).sum::<f64>() / measured.len() as f64;
+            //describing function of a relay with hysteresis
+            let swing = (amplitude.powi(2) - params.hysteresis.powi(2)).max(0.0).sqrt();
+            let ultimate_gain = 4.0 * (high - low) / 2.0 / (std::f64::consts::PI * swing);
+            let bias = relay.bias;
+            self.relay = None;
+            //an oscillation that doesn't get past the hysteresis has no gain to measure
+            let measured = swing > 0.0 && ultimate_gain.is_finite() && period.is_finite();
+            if !measured {
+                warn!("pid autotune failed: the oscillation of {} didn't exceed the hysteresis of {}", amplitude, params.hysteresis);
+                return bias;
+            }
+            debug!("pid autotune found an ultimate gain of {} and period of {}s", ultimate_gain, period);
+            self.tuned = Some(TuningResults {
+                ultimate_gain,
+                ultimate_period: period,
+                gains: params.rule.gains(ultimate_gain, period),
+            });
+        }
+        output
+    }
+
+    ///Updates the output at now, the clock's `elapsed` time. Earlier times than the last update don't advance it.
+    pub(crate) fn step(&mut self, now: Duration) {
         //calculate new error and how long since last update
-        let now = Instant::now();
         let new_err = self.set_point - self.process_var;
-        let dt = now.duration_since(self.last_update).as_secs_f64();
+        let dt = now.saturating_sub(self.last_update).as_secs_f64();
 
-        //calculate simple numerical derivative
-        let drv = if dt > 0.0 {
-            (new_err - self.last_err) / dt
-        } else {
-            0.0
-        };
+        //derivative on measurement, which is the derivative of the error while the set point holds still
+        if dt > 0.0 && self.process_var.is_finite() {
+            let raw = if self.last_process_var.is_finite() {
+                -(self.process_var - self.last_process_var) / dt
+            } else {
+                0.0
+            };
+            let alpha = if self.derivative_filter > 0.0 {
+                1.0 - (-dt / self.derivative_filter).exp()
+            } else {
+                1.0
+            };
+            self.derivative += (raw - self.derivative) * alpha;
+            self.last_process_var = self.process_var;
+        }
+        self.last_update = self.last_update.max(now);
 
-        //add a new area to the reimann sum to calculate numerical integral
-        let int = if new_err.is_finite() {
-            self.integral_sum + dt * new_err
+        self.p_term = self.p * new_err;
+        self.d_term = self.d * self.derivative;
+        let PidLimits { min, max, anti_windup } = self.limits;
+        let rest = self.p_term + self.d_term + self.feed_forward;
+
+        if !self.enabled {
+            self.relay = None;
+        }
+        if self.relay.is_some() || !self.enabled {
+            //track the relay or manual output so the integral picks up from it afterwards
+            self.value = if self.relay.is_some() {
+                self.step_relay(new_err, now)
+            } else {
+                self.manual.clamp(min, max)
+            };
+            self.i_term = self.value - rest;
+            if !self.i_term.is_finite() {
+                self.i_term = 0.0;
+            }
+            return;
+        }
+
+        //the integral is kept as a term, so changing i doesn't bump the output
+        let integrated = if new_err.is_finite() && dt > 0.0 && !self.hold_integral {
+            self.i_term + self.i * new_err * dt
         } else {
-            self.integral_sum
+            self.i_term
         };
+        let unlimited = rest + integrated;
+        let limited = unlimited.clamp(min, max);
+        self.i_term = match anti_windup {
+            AntiWindup::Clamp => {
+                let winding_up = (unlimited > max && integrated > self.i_term)
+                    || (unlimited < min && integrated < self.i_term);
+                if winding_up {
+                    self.i_term
+                } else {
+                    integrated
+                }
+            }
+            AntiWindup::BackCalculation { tracking_ms } => {
+                if unlimited.is_finite() && dt > 0.0 {
+                    integrated + (limited - unlimited) * (1.0 - (-dt * 1000.0 / tracking_ms).exp())
+                } else {
+                    integrated
+                }
+            }
+        };
+
+        //calculate pid output: p * err + integral(i * err, dt) + d * derivative(err, t) + feed forward
+        self.value = (rest + self.i_term).clamp(min, max);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::{AntiWindup, AutotuneParams, Pid, PidConfig, PidLimits, PidState, TuningRule};
+    use ioc_core::{
+        clock::Clock,
+        sample::{Sample, SampleInfo},
+        Input, Transformer,
+    };
+    use std::time::{Duration, SystemTime};
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_pid_simulated() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let (set_point, _set_point_tx) = Input::new(1.0);
+        let (process_var, _process_var_tx) = Input::new(0.0);
+        let (p, _p_tx) = Input::new(0.0);
+        let (i, _i_tx) = Input::new(2.0);
+        let (d, _d_tx) = Input::new(0.0);
+        let pid = Pid::try_build(&PidConfig {
+            set_point: &set_point,
+            process_var: &process_var,
+            p: &p,
+            i: &i,
+            d: &d,
+            feed_forward: None,
+            enable: None,
+            manual: None,
+            limits: PidLimits::default(),
+            derivative_filter_ms: 0.0,
+            autotune: None,
+            period_ms: 10,
+            clock: &clock,
+        })
+        .await
+        .unwrap();
+        let value = pid.value.source();
 
-        //update ourself
-        self.last_update = now;
-        self.last_err = new_err;
-        self.integral_sum = int;
+        //the integral of a constant error of 1 over 1.5 simulated seconds, no matter how long that takes in real time
+        driver.advance(Duration::from_millis(1500)).await;
+        assert!((*value.borrow() - 3.0).abs() < 1e-9, "{}", *value.borrow());
+        pid.join_handle.abort();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_pid_sample_time() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let (set_point, _set_point_tx) = Input::new(1.0);
+        let (process_var, process_var_tx) = Input::new_sampled(Sample { value: 0.0, info: SampleInfo::at(&clock) });
+        let (p, _p_tx) = Input::new(0.0);
+        let (i, _i_tx) = Input::new(1.0);
+        let (d, _d_tx) = Input::new(0.0);
+        let pid = Pid::try_build(&PidConfig {
+            set_point: &set_point,
+            process_var: &process_var,
+            p: &p,
+            i: &i,
+            d: &d,
+            feed_forward: None,
+            enable: None,
+            manual: None,
+            limits: PidLimits::default(),
+            derivative_filter_ms: 0.0,
+            autotune: None,
+            period_ms: 60_000,
+            clock: &clock,
+        })
+        .await
+        .unwrap();
+        let value = pid.value.source();
+
+        //a measurement taken at 0.5s that only arrives at 1s integrates the error over 0.5s
+        driver.advance(Duration::from_secs(1)).await;
+        let taken = SampleInfo { elapsed: Duration::from_millis(500), ..SampleInfo::at(&clock) };
+        process_var_tx.send(Sample { value: 0.0, info: taken }).unwrap();
+        driver.tick().await;
+        assert!((*value.borrow() - 0.5).abs() < 1e-9, "{}", *value.borrow());
+
+        //one taken before the last step doesn't integrate again
+        process_var_tx.send(Sample { value: 0.0, info: SampleInfo { elapsed: Duration::from_millis(200), ..taken } }).unwrap();
+        driver.tick().await;
+        assert!((*value.borrow() - 0.5).abs() < 1e-9, "{}", *value.borrow());
+        pid.join_handle.abort();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_pid_anti_windup() {
+        for anti_windup in [AntiWindup::Clamp, AntiWindup::BackCalculation { tracking_ms: 50.0 }] {
+            let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+            let (set_point, set_point_tx) = Input::new(10.0);
+            let (process_var, _process_var_tx) = Input::new(0.0);
+            let (p, _p_tx) = Input::new(0.0);
+            let (i, _i_tx) = Input::new(1.0);
+            let (d, _d_tx) = Input::new(0.0);
+            let pid = Pid::try_build(&PidConfig {
+                set_point: &set_point,
+                process_var: &process_var,
+                p: &p,
+                i: &i,
+                d: &d,
+                feed_forward: None,
+                enable: None,
+                manual: None,
+                limits: PidLimits { min: -1.0, max: 1.0, anti_windup },
+                derivative_filter_ms: 0.0,
+                autotune: None,
+                period_ms: 10,
+                clock: &clock,
+            })
+            .await
+            .unwrap();
+            let value = pid.value.source();
+            let i_term = pid.i_term.source();
+
+            //an error the output can't remove for 10s doesn't wind the integral up to 100
+            driver.advance(Duration::from_secs(10)).await;
+            assert!((*value.borrow() - 1.0).abs() < 1e-9, "{:?} {}", anti_windup, *value.borrow());
+            assert!(*i_term.borrow() < 2.0, "{:?} {}", anti_windup, *i_term.borrow());
 
-        //calculate pid output: p * err + i * integral(err, dt) + d + derivative(err, t)
-        self.p * new_err + self.i * int + self.d * drv
+            //so the output comes off the limit as soon as the error changes sign
+            set_point_tx.send(-1.0).unwrap();
+            driver.advance(Duration::from_secs(1)).await;
+            assert!(*value.borrow() < 0.5, "{:?} {}", anti_windup, *value.borrow());
+            pid.join_handle.abort();
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_pid_derivative_on_measurement() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let (set_point, set_point_tx) = Input::new(0.0);
+        let (process_var, process_var_tx) = Input::new(0.0);
+        let (p, _p_tx) = Input::new(0.0);
+        let (i, _i_tx) = Input::new(0.0);
+        let (d, _d_tx) = Input::new(1.0);
+        let (feed_forward, _feed_forward_tx) = Input::new(0.5);
+        let pid = Pid::try_build(&PidConfig {
+            set_point: &set_point,
+            process_var: &process_var,
+            p: &p,
+            i: &i,
+            d: &d,
+            feed_forward: Some(&feed_forward),
+            enable: None,
+            manual: None,
+            limits: PidLimits::default(),
+            derivative_filter_ms: 100.0,
+            autotune: None,
+            period_ms: 10,
+            clock: &clock,
+        })
+        .await
+        .unwrap();
+        let value = pid.value.source();
+        let d_term = pid.d_term.source();
+        assert_eq!(*value.borrow(), 0.5);
+
+        //a set point change doesn't kick the derivative
+        set_point_tx.send(5.0).unwrap();
+        driver.advance(Duration::from_millis(100)).await;
+        assert_eq!(*d_term.borrow(), 0.0);
+
+        //a ramp in the process var is filtered into its slope
+        for _ in 0..100 {
+            let next = *process_var.source().borrow() + 0.02;
+            process_var_tx.send(next).unwrap();
+            driver.tick().await;
+        }
+        assert!((*d_term.borrow() + 2.0).abs() < 0.2, "{}", *d_term.borrow());
+        assert!((*value.borrow() - (*d_term.borrow() + 0.5)).abs() < 1e-9);
+        pid.join_handle.abort();
     }
-}
\ No newline at end of file
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_pid_bumpless_transfer() {
+        let (clock, driver) = Clock::simulated(SystemTime::UNIX_EPOCH, Duration::from_millis(10));
+        let (set_point, _set_point_tx) = Input::new(1.0);
+        let (process_var, _process_var_tx) = Input::new(0.0);
+        let (p, _p_tx) = Input::new(2.0);
+        let (i, _i_tx) = Input::new(1.0);
+        let (d, _d_tx) = Input::new(0.0);
+        let (enable, enable_tx) = Input::new(false);
+        let (manual, _manual_tx) = Input::new(0.75);
+        let pid = Pid::try_build(&PidConfig {
+            set_point: &set_point,
+            process_var: &process_var,
+            p: &p,
+            i: &i,
+            d: &d,
+            feed_forward: None,
+            enable: Some(&enable),
+            manual: Some(&manual),
+            limits: PidLimits::default(),
+            derivative_filter_ms: 0.0,
+            autotune: None,
+            period_ms: 10,
+            clock: &clock,
+        })
+        .await
+        .unwrap();
+        let value = pid.value.source();
+
+        //disabled, it puts out the manual value and doesn't integrate
+        driver.advance(Duration::from_secs(1)).await;
+        assert_eq!(*value.borrow(), 0.75);
+
+        //enabling it picks up from the manual value, then integrates the error of 1
+        enable_tx.send(true).unwrap();
+        driver.tick().await;
+        assert!((*value.borrow() - 0.75).abs() < 0.02, "{}", *value.borrow());
+        driver.advance(Duration::from_secs(1)).await;
+        assert!((*value.borrow() - 1.75).abs() < 0.02, "{}", *value.borrow());
+        pid.join_handle.abort();
+    }
+
+    //a p controller 1 below its set point, running a relay experiment with a hysteresis of 0.1
+    fn relay_state(max_duration: Duration) -> PidState {
+        let mut state = PidState::new(1.0, 0.0, 0.0, 1.0, 0.0, Duration::ZERO);
+        state.autotune = Some(AutotuneParams {
+            amplitude: 1.0,
+            hysteresis: 0.1,
+            cycles: 2,
+            rule: TuningRule::ZieglerNichols,
+            max_duration,
+        });
+        state.start_autotune(Duration::ZERO);
+        state
+    }
+
+    #[test]
+    fn test_autotune_timeout() {
+        //the process var never crosses the set point, so the output stays high until the experiment times out
+        let mut state = relay_state(Duration::from_secs(10));
+        state.step(Duration::from_secs(1));
+        assert!(state.relay.is_some());
+        assert_eq!(state.value, 2.0);
+        state.step(Duration::from_secs(11));
+        assert!(state.relay.is_none());
+        assert!(state.tuned.is_none());
+        assert_eq!(state.value, 1.0);
+    }
+
+    #[test]
+    fn test_autotune_failed() {
+        //oscillations no bigger than the hysteresis would give an infinite gain
+        let mut state = relay_state(Duration::from_secs(10));
+        if let Some(relay) = state.relay.as_mut() {
+            relay.cycles = vec![(2.0, 0.1); 3];
+        }
+        state.step(Duration::from_secs(1));
+        assert!(state.relay.is_none());
+        assert!(state.tuned.is_none());
+        assert_eq!(state.value, 1.0);
+    }
+
+    #[test]
+    fn test_tuning_rules() {
+        let near = |gains: [f64; 3], expected: [f64; 3]| gains.iter().zip(expected).all(|(gain, expected)| (gain - expected).abs() < 1e-9);
+        //p = 0.6 ku, i = p / (tu / 2), d = p * tu / 8
+        assert!(near(TuningRule::ZieglerNichols.gains(10.0, 2.0), [6.0, 6.0, 1.5]));
+        assert!(near(TuningRule::ZieglerNicholsPi.gains(10.0, 2.0), [4.5, 2.7, 0.0]));
+        assert!(near(TuningRule::NoOvershoot.gains(10.0, 2.0), [2.0, 2.0, 4.0 / 3.0]));
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::select_all;
+use ioc_core::{clock::Clock, error::IocBuildError, Input, InputKind, Transformer, TransformerI};
+use rhai::{Dynamic, Engine, ImmutableString, Map, Scope, AST};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{debug, error, info, warn};
+
+///An input port of a script. The script reads it as a variable with the port's name.
+pub enum ScriptInput<'a> {
+    Float(&'a Input<f64>),
+    Int(&'a Input<i64>),
+    Bool(&'a Input<bool>),
+    String(&'a Input<String>),
+}
+
+///An output port of a script, with its start value. The script writes it by assigning to a variable with the port's name.
+#[derive(Clone, Debug)]
+pub enum ScriptOutput {
+    Float(f64),
+    Int(i64),
+    Bool(bool),
+    String(String),
+}
+
+///Limits on a single evaluation of a script. Evaluations that exceed them are stopped and their outputs are discarded.
+#[derive(Clone, Debug)]
+pub struct ScriptLimits {
+    pub max_operations: u64,
+    pub max_time_ms: u64,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: 100_000,
+            max_time_ms: 10,
+        }
+    }
+}
+
+pub struct ScriptConfig<'a> {
+    pub inputs: HashMap<String, ScriptInput<'a>>,
+    pub outputs: HashMap<String, ScriptOutput>,
+    ///runs once before the first evaluation, e.g. to set up `state`
+    pub init: Option<&'a str>,
+    pub script: &'a str,
+    ///evaluate on a fixed period instead of whenever an input changes
+    pub period_ms: Option<u64>,
+    pub limits: ScriptLimits,
+    ///the clock periods are measured with
+    pub clock: &'a Clock,
+}
+
+///Runs a [Rhai](https://rhai.rs) script whenever its inputs change, or on a period.
+///
+/// Input and output ports are variables in the script. A map named `state` is kept between evaluations.
+pub struct Script {
+    pub join_handle: JoinHandle<()>,
+    pub outputs: HashMap<String, InputKind>,
+}
+
+impl From<Script> for TransformerI {
+    fn from(script: Script) -> Self {
+        TransformerI {
+            join_handle: script.join_handle,
+            inputs: script.outputs,
+        }
+    }
+}
+
+enum InputReader {
+    Float(watch::Receiver<f64>),
+    Int(watch::Receiver<i64>),
+    Bool(watch::Receiver<bool>),
+    String(watch::Receiver<String>),
+}
+
+impl InputReader {
+    fn read(&mut self) -> Dynamic {
+        match self {
+            Self::Float(rx) => Dynamic::from_float(*rx.borrow_and_update()),
+            Self::Int(rx) => Dynamic::from_int(*rx.borrow_and_update()),
+            Self::Bool(rx) => Dynamic::from_bool(*rx.borrow_and_update()),
+            Self::String(rx) => Dynamic::from(rx.borrow_and_update().clone()),
+        }
+    }
+    async fn changed(&mut self) -> bool {
+        match self {
+            Self::Float(rx) => rx.changed().await.is_ok(),
+            Self::Int(rx) => rx.changed().await.is_ok(),
+            Self::Bool(rx) => rx.changed().await.is_ok(),
+            Self::String(rx) => rx.changed().await.is_ok(),
+        }
+    }
+}
+
+enum OutputWriter {
+    Float(watch::Sender<f64>),
+    Int(watch::Sender<i64>),
+    Bool(watch::Sender<bool>),
+    String(watch::Sender<String>),
+}
+
+impl OutputWriter {
+    ///Sends the value if it has the port's type and is different from the last value.
+    fn write(&self, value: Dynamic) -> Result<(), String> {
+        let type_name = value.type_name();
+        match self {
+            Self::Float(tx) => {
+                //ints are accepted where floats are expected
+                let value = value
+                    .as_float()
+                    .or_else(|_| value.as_int().map(|value| value as f64))
+                    .map_err(|_| format!("expected a float but got {}", type_name))?;
+                tx.send_if_modified(|current| replace(current, value));
+            }
+            Self::Int(tx) => {
+                let value = value.as_int().map_err(|_| format!("expected an int but got {}", type_name))?;
+                tx.send_if_modified(|current| replace(current, value));
+            }
+            Self::Bool(tx) => {
+                let value = value.as_bool().map_err(|_| format!("expected a bool but got {}", type_name))?;
+                tx.send_if_modified(|current| replace(current, value));
+            }
+            Self::String(tx) => {
+                let value = value
+                    .into_immutable_string()
+                    .map_err(|_| format!("expected a string but got {}", type_name))?;
+                tx.send_if_modified(|current| replace(current, value.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn replace<T: PartialEq>(current: &mut T, value: T) -> bool {
+    if *current != value {
+        *current = value;
+        true
+    } else {
+        false
+    }
+}
+
+fn build_engine(limits: &ScriptLimits, deadline: Arc<Mutex<Instant>>) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(limits.max_operations)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(10_000)
+        .set_max_array_size(10_000)
+        .set_max_map_size(10_000)
+        .disable_symbol("eval");
+    engine.on_print(|text| info!("script: {}", text));
+    engine.on_debug(|text, _, pos| debug!("script {:?}: {}", pos, text));
+
+    //checking the clock on every operation is expensive, so only check every so often
+    engine.on_progress(move |operations| {
+        let expired = operations % 256 == 0
+            && match deadline.lock() {
+                Ok(deadline) => Instant::now() > *deadline,
+                Err(poisoned) => Instant::now() > *poisoned.into_inner(),
+            };
+        if expired {
+            Some(Dynamic::from(ImmutableString::from("time limit exceeded")))
+        } else {
+            None
+        }
+    });
+    engine
+}
+
+fn set_deadline(deadline: &Mutex<Instant>, max_time_ms: u64) {
+    let new_deadline = Instant::now() + Duration::from_millis(max_time_ms);
+    match deadline.lock() {
+        Ok(mut deadline) => *deadline = new_deadline,
+        Err(poisoned) => *poisoned.into_inner() = new_deadline,
+    }
+}
+
+///The engine and variables of a script, moved to a blocking thread for each evaluation.
+struct Evaluator {
+    engine: Engine,
+    scope: Scope<'static>,
+    deadline: Arc<Mutex<Instant>>,
+    limits: ScriptLimits,
+    ///variables kept between evaluations: `state` and the outputs
+    persistent: Vec<String>,
+    ///the number of variables in the scope before an evaluation
+    scope_len: usize,
+}
+
+impl Evaluator {
+    ///Runs the script on a blocking thread. Returns the evaluator with the result of the evaluation.
+    async fn run(mut self, ast: Arc<AST>) -> Result<(Evaluator, Result<(), String>), String> {
+        tokio::task::spawn_blocking(move || {
+            let result = self.run_blocking(&ast);
+            (self, result)
+        })
+        .await
+        .map_err(|err| err.to_string())
+    }
+
+    fn run_blocking(&mut self, ast: &AST) -> Result<(), String> {
+        let snapshot: Vec<(String, Dynamic)> = self
+            .persistent
+            .iter()
+            .filter_map(|name| self.scope.get_value::<Dynamic>(name).map(|value| (name.clone(), value)))
+            .collect();
+        set_deadline(&self.deadline, self.limits.max_time_ms);
+        let result = self.engine.run_ast_with_scope(&mut self.scope, ast);
+        //drop anything the script declared, so each evaluation starts the same way
+        self.scope.rewind(self.scope_len);
+        if let Err(err) = result {
+            //a failed evaluation leaves state and outputs as they were before it
+            for (name, value) in snapshot {
+                self.scope.set_value(name, value);
+            }
+            return Err(err.to_string());
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Transformer<'a> for Script {
+    type Config = ScriptConfig<'a>;
+
+    async fn try_build(cfg: &ScriptConfig<'a>) -> Result<Script, IocBuildError> {
+        if cfg.inputs.is_empty() && cfg.period_ms.is_none() {
+            return Err(IocBuildError::message(
+                "a script needs at least one input or a period, otherwise it would never run",
+            ));
+        }
+        if let Some(name) = cfg.inputs.keys().find(|name| cfg.outputs.contains_key(*name) || *name == "state") {
+            return Err(IocBuildError::from_string(format!(
+                "script port {} is used more than once. Ports must have unique names, and `state` is reserved.",
+                name
+            )));
+        }
+        if cfg.outputs.contains_key("state") {
+            return Err(IocBuildError::message("script port name `state` is reserved"));
+        }
+
+        let deadline = Arc::new(Mutex::new(Instant::now()));
+        let engine = build_engine(&cfg.limits, deadline.clone());
+        let ast = engine
+            .compile(cfg.script)
+            .map_err(|err| IocBuildError::from_string(format!("error compiling script: {}", err)))?;
+        let init_ast = cfg
+            .init
+            .map(|init| engine.compile(init))
+            .transpose()
+            .map_err(|err| IocBuildError::from_string(format!("error compiling script init: {}", err)))?;
+
+        let mut readers: Vec<(String, InputReader)> = cfg
+            .inputs
+            .iter()
+            .map(|(name, input)| {
+                let reader = match input {
+                    ScriptInput::Float(input) => InputReader::Float(input.source()),
+                    ScriptInput::Int(input) => InputReader::Int(input.source()),
+                    ScriptInput::Bool(input) => InputReader::Bool(input.source()),
+                    ScriptInput::String(input) => InputReader::String(input.source()),
+                };
+                (name.clone(), reader)
+            })
+            .collect();
+
+        let mut writers: Vec<(String, OutputWriter)> = Vec::with_capacity(cfg.outputs.len());
+        let mut outputs = HashMap::with_capacity(cfg.outputs.len());
+        let mut scope = Scope::new();
+        for (name, output) in &cfg.outputs {
+            let (input, writer) = match output {
+                ScriptOutput::Float(start) => {
+                    let (input, tx) = Input::new(*start);
+                    (InputKind::Float(input), OutputWriter::Float(tx))
+                }
+                ScriptOutput::Int(start) => {
+                    let (input, tx) = Input::new(*start);
+                    (InputKind::Int(input), OutputWriter::Int(tx))
+                }
+                ScriptOutput::Bool(start) => {
+                    let (input, tx) = Input::new(*start);
+                    (InputKind::Bool(input), OutputWriter::Bool(tx))
+                }
+                ScriptOutput::String(start) => {
+                    let (input, tx) = Input::new(start.clone());
+                    (InputKind::String(input), OutputWriter::String(tx))
+                }
+            };
+            //outputs keep their last values between evaluations
+            scope.push_dynamic(name.as_str(), match output {
+                ScriptOutput::Float(start) => Dynamic::from_float(*start),
+                ScriptOutput::Int(start) => Dynamic::from_int(*start),
+                ScriptOutput::Bool(start) => Dynamic::from_bool(*start),
+                ScriptOutput::String(start) => Dynamic::from(start.clone()),
+            });
+            outputs.insert(name.clone(), input);
+            writers.push((name.clone(), writer));
+        }
+        scope.push("state", Map::new());
+        for (name, reader) in readers.iter_mut() {
+            scope.push_dynamic(name.as_str(), reader.read());
+        }
+        let mut persistent: Vec<String> = cfg.outputs.keys().cloned().collect();
+        persistent.push("state".to_string());
+        let mut evaluator = Evaluator {
+            scope_len: scope.len(),
+            engine,
+            scope,
+            deadline,
+            limits: cfg.limits.clone(),
+            persistent,
+        };
+
+        if let Some(init_ast) = init_ast {
+            let result;
+            (evaluator, result) = evaluator
+                .run(Arc::new(init_ast))
+                .await
+                .map_err(|err| IocBuildError::from_string(format!("error running script init: {}", err)))?;
+            if let Err(err) = result {
+                return Err(IocBuildError::from_string(format!("error running script init: {}", err)));
+            }
+        }
+
+        let ast = Arc::new(ast);
+        let period_ms = cfg.period_ms;
+        let clock = cfg.clock.clone();
+        let join_handle = tokio::spawn(async move {
+            loop {
+                for (name, reader) in readers.iter_mut() {
+                    evaluator.scope.set_value(name.as_str(), reader.read());
+                }
+
+                let result;
+                (evaluator, result) = match evaluator.run(ast.clone()).await {
+                    Ok(evaluated) => evaluated,
+                    Err(err) => {
+                        error!("script stopped: {}", err);
+                        break;
+                    }
+                };
+                match result {
+                    Ok(()) => write_outputs(&evaluator.scope, &writers),
+                    Err(err) => warn!("error running script: {}", err),
+                }
+
+                match period_ms {
+                    Some(period_ms) => clock.sleep(Duration::from_millis(period_ms)).await,
+                    None => {
+                        let (changed, _, _) =
+                            select_all(readers.iter_mut().map(|(_, reader)| Box::pin(reader.changed()))).await;
+                        if !changed {
+                            break;
+                        }
+                    }
+                }
+            }
+            debug!("script transformer shut down");
+        });
+
+        Ok(Script { join_handle, outputs })
+    }
+}
+
+fn write_outputs(scope: &Scope, writers: &[(String, OutputWriter)]) {
+    for (name, writer) in writers {
+        if let Some(value) = scope.get_value::<Dynamic>(name) {
+            if let Err(err) = writer.write(value) {
+                warn!("script output {}: {}", name, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ioc_core::clock::Clock;
+    use super::{Script, ScriptConfig, ScriptInput, ScriptLimits, ScriptOutput};
+    use ioc_core::{Input, InputKind, Transformer};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_script() {
+        let (x, x_tx) = Input::new(1.0);
+        let script = Script::try_build(&ScriptConfig {
+            inputs: HashMap::from([("x".to_string(), ScriptInput::Float(&x))]),
+            outputs: HashMap::from([("y".to_string(), ScriptOutput::Float(0.0))]),
+            init: Some("state.count = 0;"),
+            script: "state.count += 1; y = x * state.count;",
+            period_ms: None,
+            clock: &Clock::real(),
+            limits: ScriptLimits::default(),
+        })
+        .await
+        .unwrap();
+        let mut y = match script.outputs.get("y") {
+            Some(InputKind::Float(y)) => y.source(),
+            _ => panic!("expected a float output"),
+        };
+
+        y.changed().await.unwrap();
+        assert_eq!(*y.borrow_and_update(), 1.0);
+        x_tx.send(3.0).unwrap();
+        y.changed().await.unwrap();
+        assert_eq!(*y.borrow_and_update(), 6.0);
+    }
+
+    #[tokio::test]
+    async fn test_script_time_limit() {
+        let script = Script::try_build(&ScriptConfig {
+            inputs: HashMap::new(),
+            outputs: HashMap::from([("y".to_string(), ScriptOutput::Int(0))]),
+            init: None,
+            script: "y = 1; loop {}",
+            period_ms: Some(10),
+            clock: &Clock::real(),
+            limits: ScriptLimits {
+                max_operations: 0,
+                max_time_ms: 5,
+            },
+        })
+        .await
+        .unwrap();
+        let y = match script.outputs.get("y") {
+            Some(InputKind::Int(y)) => y.source(),
+            _ => panic!("expected an int output"),
+        };
+
+        //the script is stopped before its outputs are written
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*y.borrow(), 0);
+        script.join_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_script_error_keeps_state() {
+        let (x, x_tx) = Input::new(1.0);
+        let script = Script::try_build(&ScriptConfig {
+            inputs: HashMap::from([("x".to_string(), ScriptInput::Float(&x))]),
+            outputs: HashMap::from([("y".to_string(), ScriptOutput::Float(0.0))]),
+            init: Some("state.count = 0;"),
+            script: "state.count += 1; y = state.count; if x < 0.0 { throw \"negative\"; }",
+            period_ms: None,
+            clock: &Clock::real(),
+            limits: ScriptLimits::default(),
+        })
+        .await
+        .unwrap();
+        let mut y = match script.outputs.get("y") {
+            Some(InputKind::Float(y)) => y.source(),
+            _ => panic!("expected a float output"),
+        };
+
+        y.changed().await.unwrap();
+        assert_eq!(*y.borrow_and_update(), 1.0);
+        //the failed evaluation's changes to state and y are rolled back
+        x_tx.send(-1.0).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!y.has_changed().unwrap());
+        x_tx.send(2.0).unwrap();
+        y.changed().await.unwrap();
+        assert_eq!(*y.borrow_and_update(), 2.0);
+    }
+}
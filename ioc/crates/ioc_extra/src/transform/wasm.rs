@@ -0,0 +1,648 @@
+//! Runs transformers compiled to WebAssembly, so control logic can be shipped and updated without rebuilding `ioc`.
+//!
+//! # ABI (version 1)
+//!
+//! A plugin is a wasm module that exports:
+//! - `memory`
+//! - `ioc_ports_ptr() -> i32` and `ioc_ports_len() -> i32`, the location in memory of its port manifest.
+//!   The manifest is UTF-8 text with one port per line: `input <type> <name>` or `output <type> <name>`, where type is `float`, `int` or `bool`.
+//! - `ioc_init()` (optional), called once after the ports are read
+//! - `ioc_step(dt: f64)`, called whenever an input changes, or on a period. `dt` is the number of seconds since the last step, or 0 for the first.
+//!
+//! and may import these functions from the `ioc` module. Ports are numbered from 0 in the order they appear in the manifest, inputs and outputs separately.
+//! - `input_float(index: i32) -> f64`, `input_int(index: i32) -> i64`, `input_bool(index: i32) -> i32`
+//! - `output_float(index: i32, value: f64)`, `output_int(index: i32, value: i64)`, `output_bool(index: i32, value: i32)`
+//! - `log(ptr: i32, len: i32)` logs a UTF-8 string from memory
+//!
+//! Outputs keep their values between steps and start at zero/false. They are only emitted once a step finishes within its limits.
+//!
+//! Calls into the plugin run on tokio's blocking threads, so a slow step doesn't hold up other tasks.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use futures::future::select_all;
+use ioc_core::{clock::Clock, error::IocBuildError, Input, InputKind, Transformer, TransformerI};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{debug, error, info, warn};
+use wasmi::{Caller, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+///Limits on a plugin.
+/// Calls that run out of fuel are stopped. Calls that take longer than max_time_ms can't be interrupted, but their outputs are discarded.
+/// The plugin's memory can't grow past max_memory_bytes. Modules that start out with more fail to load.
+#[derive(Clone, Debug)]
+pub struct WasmLimits {
+    pub max_fuel: u64,
+    pub max_time_ms: u64,
+    pub max_memory_bytes: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            max_fuel: 1_000_000,
+            max_time_ms: 10,
+            max_memory_bytes: 16 << 20,
+        }
+    }
+}
+
+pub struct WasmConfig<'a> {
+    ///the compiled module
+    pub wasm: &'a [u8],
+    ///upstream inputs by the names of the plugin's input ports
+    pub inputs: HashMap<String, &'a InputKind>,
+    ///step on a fixed period instead of whenever an input changes
+    pub period_ms: Option<u64>,
+    pub limits: WasmLimits,
+    ///the clock periods and the time between steps are measured with
+    pub clock: &'a Clock,
+}
+
+///A transformer loaded from a WebAssembly module. Each of the plugin's output ports is emitted as an input.
+pub struct Wasm {
+    pub join_handle: JoinHandle<()>,
+    pub outputs: HashMap<String, InputKind>,
+}
+
+impl From<Wasm> for TransformerI {
+    fn from(wasm: Wasm) -> Self {
+        TransformerI {
+            join_handle: wasm.join_handle,
+            inputs: wasm.outputs,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PortValue {
+    Float(f64),
+    Int(i64),
+    Bool(bool),
+}
+
+struct Port {
+    name: String,
+    start: PortValue,
+}
+
+struct Manifest {
+    inputs: Vec<Port>,
+    outputs: Vec<Port>,
+}
+
+fn parse_manifest(manifest: &str) -> Result<Manifest, String> {
+    let mut inputs: Vec<Port> = Vec::new();
+    let mut outputs: Vec<Port> = Vec::new();
+    for line in manifest.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (direction, kind, name) = match parts[..] {
+            [direction, kind, name] => (direction, kind, name),
+            _ => return Err(format!("expected '<input|output> <type> <name>' but got '{}'", line)),
+        };
+        let start = match kind {
+            "float" => PortValue::Float(0.0),
+            "int" => PortValue::Int(0),
+            "bool" => PortValue::Bool(false),
+            _ => return Err(format!("unknown port type '{}'. Expected float, int or bool", kind)),
+        };
+        if inputs.iter().chain(outputs.iter()).any(|port| port.name == name) {
+            return Err(format!("port {} is declared more than once", name));
+        }
+        let port = Port { name: name.to_string(), start };
+        match direction {
+            "input" => inputs.push(port),
+            "output" => outputs.push(port),
+            _ => return Err(format!("unknown port direction '{}'. Expected input or output", direction)),
+        }
+    }
+    Ok(Manifest { inputs, outputs })
+}
+
+//the values of ports as seen by the plugin, and the limits on its memory
+struct HostState {
+    inputs: Vec<PortValue>,
+    outputs: Vec<PortValue>,
+    limits: StoreLimits,
+}
+
+fn port_error(direction: &str, index: i32, expected: &str, value: Option<&PortValue>) -> wasmi::Error {
+    match value {
+        Some(value) => wasmi::Error::new(format!("{} {} is {:?}, not a {}", direction, index, value, expected)),
+        None => wasmi::Error::new(format!("no {} numbered {}", direction, index)),
+    }
+}
+
+fn set_output(caller: &mut Caller<'_, HostState>, index: i32, value: PortValue) -> Result<(), wasmi::Error> {
+    match caller.data_mut().outputs.get_mut(index as usize) {
+        Some(output) if std::mem::discriminant(output) == std::mem::discriminant(&value) => {
+            *output = value;
+            Ok(())
+        }
+        other => Err(port_error("output", index, &format!("{:?}", value), other.map(|other| &*other))),
+    }
+}
+
+fn build_linker(engine: &Engine) -> Result<Linker<HostState>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+    linker
+        .func_wrap("ioc", "input_float", |caller: Caller<'_, HostState>, index: i32| {
+            match caller.data().inputs.get(index as usize) {
+                Some(PortValue::Float(value)) => Ok(*value),
+                other => Err(port_error("input", index, "float", other)),
+            }
+        })?
+        .func_wrap("ioc", "input_int", |caller: Caller<'_, HostState>, index: i32| {
+            match caller.data().inputs.get(index as usize) {
+                Some(PortValue::Int(value)) => Ok(*value),
+                other => Err(port_error("input", index, "int", other)),
+            }
+        })?
+        .func_wrap("ioc", "input_bool", |caller: Caller<'_, HostState>, index: i32| {
+            match caller.data().inputs.get(index as usize) {
+                Some(PortValue::Bool(value)) => Ok(*value as i32),
+                other => Err(port_error("input", index, "bool", other)),
+            }
+        })?
+        .func_wrap("ioc", "output_float", |mut caller: Caller<'_, HostState>, index: i32, value: f64| {
+            set_output(&mut caller, index, PortValue::Float(value))
+        })?
+        .func_wrap("ioc", "output_int", |mut caller: Caller<'_, HostState>, index: i32, value: i64| {
+            set_output(&mut caller, index, PortValue::Int(value))
+        })?
+        .func_wrap("ioc", "output_bool", |mut caller: Caller<'_, HostState>, index: i32, value: i32| {
+            set_output(&mut caller, index, PortValue::Bool(value != 0))
+        })?
+        .func_wrap("ioc", "log", |caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            let memory = caller
+                .get_export("memory")
+                .and_then(Extern::into_memory)
+                .ok_or_else(|| wasmi::Error::new("log needs an exported memory"))?;
+            let text = read_str(memory.data(&caller), ptr, len).map_err(wasmi::Error::new)?;
+            info!("wasm: {}", text);
+            Ok(())
+        })?;
+    Ok(linker)
+}
+
+fn read_str(memory: &[u8], ptr: i32, len: i32) -> Result<String, String> {
+    let start = ptr as u32 as usize;
+    let end = start.saturating_add(len as u32 as usize);
+    memory
+        .get(start..end)
+        .map(|bytes| String::from_utf8_lossy(bytes).to_string())
+        .ok_or_else(|| format!("{}..{} is out of bounds of memory", start, end))
+}
+
+//an instantiated plugin
+struct Plugin {
+    store: Store<HostState>,
+    step: TypedFunc<f64, ()>,
+    limits: WasmLimits,
+}
+
+///Calls into the plugin within the limits.
+/// The outputs written by the plugin are reset if it fails, so a failed call has no effect.
+fn call<P: wasmi::WasmParams, R: wasmi::WasmResults>(
+    store: &mut Store<HostState>,
+    func: &TypedFunc<P, R>,
+    params: P,
+    limits: &WasmLimits,
+) -> Result<R, String> {
+    let outputs = store.data().outputs.clone();
+    store.set_fuel(limits.max_fuel).map_err(|err| err.to_string())?;
+    let start = Instant::now();
+    let result = func.call(&mut *store, params).map_err(|err| err.to_string());
+    let result = match result {
+        Ok(_) if start.elapsed() > Duration::from_millis(limits.max_time_ms) => Err(format!(
+            "took {:?}, more than the limit of {}ms",
+            start.elapsed(),
+            limits.max_time_ms
+        )),
+        result => result,
+    };
+    if result.is_err() {
+        store.data_mut().outputs = outputs;
+    }
+    result
+}
+
+enum InputReader {
+    Float(watch::Receiver<f64>),
+    Int(watch::Receiver<i64>),
+    Bool(watch::Receiver<bool>),
+}
+
+impl InputReader {
+    fn read(&mut self) -> PortValue {
+        match self {
+            Self::Float(rx) => PortValue::Float(*rx.borrow_and_update()),
+            Self::Int(rx) => PortValue::Int(*rx.borrow_and_update()),
+            Self::Bool(rx) => PortValue::Bool(*rx.borrow_and_update()),
+        }
+    }
+    async fn changed(&mut self) -> bool {
+        match self {
+            Self::Float(rx) => rx.changed().await.is_ok(),
+            Self::Int(rx) => rx.changed().await.is_ok(),
+            Self::Bool(rx) => rx.changed().await.is_ok(),
+        }
+    }
+}
+
+enum OutputWriter {
+    Float(watch::Sender<f64>),
+    Int(watch::Sender<i64>),
+    Bool(watch::Sender<bool>),
+}
+
+impl OutputWriter {
+    fn write(&self, value: PortValue) {
+        match (self, value) {
+            (Self::Float(tx), PortValue::Float(value)) => tx.send_if_modified(|current| replace(current, value)),
+            (Self::Int(tx), PortValue::Int(value)) => tx.send_if_modified(|current| replace(current, value)),
+            (Self::Bool(tx), PortValue::Bool(value)) => tx.send_if_modified(|current| replace(current, value)),
+            //output values can only be set with the type of their port
+            _ => false,
+        };
+    }
+}
+
+fn replace<T: PartialEq>(current: &mut T, value: T) -> bool {
+    if *current != value {
+        *current = value;
+        true
+    } else {
+        false
+    }
+}
+
+fn load(wasm: &[u8], limits: WasmLimits) -> Result<(Plugin, Manifest), String> {
+    let mut engine_config = wasmi::Config::default();
+    engine_config.consume_fuel(true);
+    let engine = Engine::new(&engine_config);
+    let module = Module::new(&engine, wasm).map_err(|err| format!("invalid module: {}", err))?;
+    let linker = build_linker(&engine).map_err(|err| err.to_string())?;
+    let host = HostState {
+        inputs: Vec::new(),
+        outputs: Vec::new(),
+        limits: StoreLimitsBuilder::new().memory_size(limits.max_memory_bytes).instances(1).build(),
+    };
+    let mut store = Store::new(&engine, host);
+    store.limiter(|host| &mut host.limits);
+
+    store.set_fuel(limits.max_fuel).map_err(|err| err.to_string())?;
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(|err| format!("error instantiating module: {}", err))?;
+
+    let ports_ptr = instance
+        .get_typed_func::<(), i32>(&store, "ioc_ports_ptr")
+        .map_err(|err| format!("ioc_ports_ptr: {}", err))?;
+    let ports_len = instance
+        .get_typed_func::<(), i32>(&store, "ioc_ports_len")
+        .map_err(|err| format!("ioc_ports_len: {}", err))?;
+    let step = instance
+        .get_typed_func::<f64, ()>(&store, "ioc_step")
+        .map_err(|err| format!("ioc_step: {}", err))?;
+    let init = match instance.get_export(&store, "ioc_init") {
+        Some(_) => Some(
+            instance
+                .get_typed_func::<(), ()>(&store, "ioc_init")
+                .map_err(|err| format!("ioc_init: {}", err))?,
+        ),
+        None => None,
+    };
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or_else(|| "the module must export its memory".to_string())?;
+
+    let ptr = call(&mut store, &ports_ptr, (), &limits).map_err(|err| format!("ioc_ports_ptr: {}", err))?;
+    let len = call(&mut store, &ports_len, (), &limits).map_err(|err| format!("ioc_ports_len: {}", err))?;
+    let manifest = read_str(memory.data(&store), ptr, len).and_then(|manifest| parse_manifest(&manifest))?;
+
+    let host = store.data_mut();
+    host.inputs = manifest.inputs.iter().map(|port| port.start).collect();
+    host.outputs = manifest.outputs.iter().map(|port| port.start).collect();
+    if let Some(init) = init {
+        call(&mut store, &init, (), &limits).map_err(|err| format!("ioc_init: {}", err))?;
+    }
+    Ok((Plugin { store, step, limits }, manifest))
+}
+
+impl Plugin {
+    ///Steps the plugin on a blocking thread. Returns it with its outputs, or None if the step failed.
+    async fn step(mut self, dt: f64) -> Result<(Plugin, Option<Vec<PortValue>>), String> {
+        tokio::task::spawn_blocking(move || match call(&mut self.store, &self.step, dt, &self.limits) {
+            Ok(()) => {
+                let outputs = self.store.data().outputs.clone();
+                (self, Some(outputs))
+            }
+            Err(err) => {
+                warn!("error running wasm plugin: {}", err);
+                (self, None)
+            }
+        })
+        .await
+        .map_err(|err| err.to_string())
+    }
+}
+
+impl<'a> Transformer<'a> for Wasm {
+    type Config = WasmConfig<'a>;
+
+    async fn try_build(cfg: &WasmConfig<'a>) -> Result<Wasm, IocBuildError> {
+        let (wasm, limits) = (cfg.wasm.to_vec(), cfg.limits.clone());
+        let (mut plugin, manifest) = tokio::task::spawn_blocking(move || load(&wasm, limits))
+            .await
+            .map_err(|err| err.to_string())
+            .and_then(|loaded| loaded)
+            .map_err(|err| IocBuildError::from_string(format!("error loading wasm plugin: {}", err)))?;
+
+        if manifest.inputs.is_empty() && cfg.period_ms.is_none() {
+            return Err(IocBuildError::message(
+                "a wasm plugin needs at least one input or a period, otherwise it would never run",
+            ));
+        }
+        if let Some(name) = cfg.inputs.keys().find(|name| !manifest.inputs.iter().any(|port| port.name == **name)) {
+            return Err(IocBuildError::from_string(format!(
+                "the wasm plugin has no input port named {}. Its inputs are {:?}",
+                name,
+                manifest.inputs.iter().map(|port| &port.name).collect::<Vec<_>>()
+            )));
+        }
+
+        let mut readers = Vec::with_capacity(manifest.inputs.len());
+        let mut errors = Vec::new();
+        for port in &manifest.inputs {
+            match (port.start, cfg.inputs.get(&port.name)) {
+                (PortValue::Float(_), Some(InputKind::Float(input))) => readers.push(InputReader::Float(input.source())),
+                (PortValue::Int(_), Some(InputKind::Int(input))) => readers.push(InputReader::Int(input.source())),
+                (PortValue::Bool(_), Some(InputKind::Bool(input))) => readers.push(InputReader::Bool(input.source())),
+                (start, Some(other)) => errors.push(IocBuildError::from_string(format!(
+                    "wasm input port {} expected {:?} but got {:?}",
+                    port.name, start, other
+                ))),
+                (_, None) => errors.push(IocBuildError::from_string(format!(
+                    "wasm input port {} is not connected",
+                    port.name
+                ))),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(IocBuildError::from_errs(errors));
+        }
+
+        let mut writers = Vec::with_capacity(manifest.outputs.len());
+        let mut outputs = HashMap::with_capacity(manifest.outputs.len());
+        //outputs start with the values init left them with
+        for (port, value) in manifest.outputs.iter().zip(plugin.store.data().outputs.iter()) {
+            let (input, writer) = match *value {
+                PortValue::Float(start) => {
+                    let (input, tx) = Input::new(start);
+                    (InputKind::Float(input), OutputWriter::Float(tx))
+                }
+                PortValue::Int(start) => {
+                    let (input, tx) = Input::new(start);
+                    (InputKind::Int(input), OutputWriter::Int(tx))
+                }
+                PortValue::Bool(start) => {
+                    let (input, tx) = Input::new(start);
+                    (InputKind::Bool(input), OutputWriter::Bool(tx))
+                }
+            };
+            outputs.insert(port.name.clone(), input);
+            writers.push(writer);
+        }
+
+        let period_ms = cfg.period_ms;
+        let clock = cfg.clock.clone();
+        let join_handle = tokio::spawn(async move {
+            let mut last_step: Option<Duration> = None;
+            loop {
+                let inputs = readers.iter_mut().map(|reader| reader.read()).collect();
+                plugin.store.data_mut().inputs = inputs;
+
+                let now = clock.elapsed();
+                let dt = last_step.map(|last| now.saturating_sub(last).as_secs_f64()).unwrap_or(0.0);
+                last_step = Some(now);
+                let outputs;
+                (plugin, outputs) = match plugin.step(dt).await {
+                    Ok(stepped) => stepped,
+                    Err(err) => {
+                        error!("wasm plugin stopped: {}", err);
+                        break;
+                    }
+                };
+                if let Some(outputs) = outputs {
+                    for (writer, value) in writers.iter().zip(outputs) {
+                        writer.write(value);
+                    }
+                }
+
+                match period_ms {
+                    Some(period_ms) => clock.sleep(Duration::from_millis(period_ms)).await,
+                    None => {
+                        let (changed, _, _) = select_all(readers.iter_mut().map(|reader| Box::pin(reader.changed()))).await;
+                        if !changed {
+                            break;
+                        }
+                    }
+                }
+            }
+            debug!("wasm transformer shut down");
+        });
+
+        Ok(Wasm { join_handle, outputs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ioc_core::clock::Clock;
+    use super::{Wasm, WasmConfig, WasmLimits};
+    use ioc_core::{Input, InputKind, Transformer};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    //y = gain * x, and counts the steps
+    const SCALE: &str = r#"
+        (module
+            (import "ioc" "input_float" (func $input_float (param i32) (result f64)))
+            (import "ioc" "output_float" (func $output_float (param i32 f64)))
+            (import "ioc" "output_int" (func $output_int (param i32 i64)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "input float x\ninput float gain\noutput float y\noutput int steps\n")
+            (global $steps (mut i64) (i64.const 0))
+            (func (export "ioc_ports_ptr") (result i32) (i32.const 0))
+            (func (export "ioc_ports_len") (result i32) (i32.const 63))
+            (func (export "ioc_step") (param $dt f64)
+                (call $output_float (i32.const 0) (f64.mul (call $input_float (i32.const 0)) (call $input_float (i32.const 1))))
+                (global.set $steps (i64.add (global.get $steps) (i64.const 1)))
+                (call $output_int (i32.const 1) (global.get $steps))))
+    "#;
+
+    const SPIN: &str = r#"
+        (module
+            (import "ioc" "output_float" (func $output_float (param i32 f64)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "output float y")
+            (func (export "ioc_ports_ptr") (result i32) (i32.const 0))
+            (func (export "ioc_ports_len") (result i32) (i32.const 14))
+            (func (export "ioc_step") (param $dt f64)
+                (call $output_float (i32.const 0) (f64.const 1))
+                (loop $forever (br $forever))))
+    "#;
+
+    #[tokio::test]
+    async fn test_wasm() {
+        let wasm = wat::parse_str(SCALE).unwrap();
+        let (x, x_tx) = Input::new(2.0);
+        let (gain, _gain_tx) = Input::new(1.5);
+        let (x, gain) = (InputKind::Float(x), InputKind::Float(gain));
+        let plugin = Wasm::try_build(&WasmConfig {
+            wasm: &wasm,
+            inputs: HashMap::from([("x".to_string(), &x), ("gain".to_string(), &gain)]),
+            period_ms: None,
+            clock: &Clock::real(),
+            limits: WasmLimits::default(),
+        })
+        .await
+        .unwrap();
+        let (mut y, steps) = match (plugin.outputs.get("y"), plugin.outputs.get("steps")) {
+            (Some(InputKind::Float(y)), Some(InputKind::Int(steps))) => (y.source(), steps.source()),
+            _ => panic!("expected float and int outputs"),
+        };
+
+        y.changed().await.unwrap();
+        assert_eq!(*y.borrow_and_update(), 3.0);
+        x_tx.send(4.0).unwrap();
+        y.changed().await.unwrap();
+        assert_eq!(*y.borrow_and_update(), 6.0);
+        assert_eq!(*steps.borrow(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wasm_limits() {
+        let wasm = wat::parse_str(SPIN).unwrap();
+        let plugin = Wasm::try_build(&WasmConfig {
+            wasm: &wasm,
+            inputs: HashMap::new(),
+            period_ms: Some(10),
+            clock: &Clock::real(),
+            limits: WasmLimits::default(),
+        })
+        .await
+        .unwrap();
+        let y = match plugin.outputs.get("y") {
+            Some(InputKind::Float(y)) => y.source(),
+            _ => panic!("expected a float output"),
+        };
+
+        //the step runs out of fuel, so its outputs are discarded
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*y.borrow(), 0.0);
+        plugin.join_handle.abort();
+    }
+
+    //tries to grow its memory by a page on every step. grown is the number of pages it got
+    const GROW: &str = r#"
+        (module
+            (import "ioc" "output_int" (func $output_int (param i32 i64)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "output int grown")
+            (global $grown (mut i64) (i64.const 0))
+            (func (export "ioc_ports_ptr") (result i32) (i32.const 0))
+            (func (export "ioc_ports_len") (result i32) (i32.const 16))
+            (func (export "ioc_step") (param $dt f64)
+                (if (i32.ge_s (memory.grow (i32.const 1)) (i32.const 0))
+                    (then (global.set $grown (i64.add (global.get $grown) (i64.const 1)))))
+                (call $output_int (i32.const 0) (global.get $grown))))
+    "#;
+
+    #[tokio::test]
+    async fn test_wasm_memory() {
+        let wasm = wat::parse_str(GROW).unwrap();
+        let limits = WasmLimits { max_memory_bytes: 3 << 16, ..Default::default() };
+        let plugin = Wasm::try_build(&WasmConfig {
+            wasm: &wasm,
+            inputs: HashMap::new(),
+            period_ms: Some(1),
+            clock: &Clock::real(),
+            limits,
+        })
+        .await
+        .unwrap();
+        let grown = match plugin.outputs.get("grown") {
+            Some(InputKind::Int(grown)) => grown.source(),
+            _ => panic!("expected an int output"),
+        };
+
+        //the memory stops growing at 3 pages
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*grown.borrow(), 2);
+        plugin.join_handle.abort();
+
+        //a module that starts out with more memory doesn't load
+        let limits = WasmLimits { max_memory_bytes: 1 << 15, ..Default::default() };
+        let result = Wasm::try_build(&WasmConfig {
+            wasm: &wasm,
+            inputs: HashMap::new(),
+            period_ms: Some(1),
+            clock: &Clock::real(),
+            limits,
+        })
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_example_plugin() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../example-configs/plugins");
+        let wasm = std::fs::read(format!("{}/scale.wasm", dir)).unwrap();
+        //the compiled plugin is up to date with its source
+        assert_eq!(wasm, wat::parse_file(format!("{}/scale.wat", dir)).unwrap());
+
+        let (x, x_tx) = Input::new(0.01);
+        let (gain, _gain_tx) = Input::new(2.0);
+        let (x, gain) = (InputKind::Float(x), InputKind::Float(gain));
+        let plugin = Wasm::try_build(&WasmConfig {
+            wasm: &wasm,
+            inputs: HashMap::from([("x".to_string(), &x), ("gain".to_string(), &gain)]),
+            period_ms: None,
+            clock: &Clock::real(),
+            limits: WasmLimits::default(),
+        })
+        .await
+        .unwrap();
+        let mut y = match plugin.outputs.get("y") {
+            Some(InputKind::Float(y)) => y.source(),
+            _ => panic!("expected a float output"),
+        };
+
+        //0.02 is within the deadband
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(*y.borrow_and_update(), 0.0);
+        x_tx.send(0.5).unwrap();
+        y.changed().await.unwrap();
+        assert_eq!(*y.borrow_and_update(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_wasm_ports() {
+        let wasm = wat::parse_str(SCALE).unwrap();
+        let (x, _x_tx) = Input::new(true);
+        let x = InputKind::Bool(x);
+        let result = Wasm::try_build(&WasmConfig {
+            wasm: &wasm,
+            inputs: HashMap::from([("x".to_string(), &x)]),
+            period_ms: None,
+            clock: &Clock::real(),
+            limits: WasmLimits::default(),
+        })
+        .await;
+        //x has the wrong type and gain isn't connected
+        assert!(result.is_err());
+    }
+}
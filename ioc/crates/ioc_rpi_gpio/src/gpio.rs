@@ -3,13 +3,14 @@ use std::collections::HashMap;
 use ioc_core::{error::IocBuildError, Input, InputKind, Module, ModuleIO, Output, OutputKind};
 use rppal::gpio::{Level, Trigger};
 use serde::Deserialize;
+use schemars::JsonSchema;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error};
 use futures_util::future::join_all;
 use crate::error::GpioError;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub enum PinConfig {
     //The produces an output that accepts booleans. The output will set the pin to high when true and low when false.
     DigitalOut{ pin: u8 },
@@ -20,7 +21,7 @@ pub enum PinConfig {
 }
 
 //Configuration to create a Gpio module with named pins which can be inputs or outputs.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct GpioConfig {
     pub pins: HashMap<String, PinConfig>,
 }
@@ -87,7 +88,7 @@ fn spawn_gpio_soft_pwm_out(gpio: &rppal::gpio::Gpio, pin: u8, frequency: f64) ->
 
     let handle = tokio::spawn(async move {
         while let Some(new_val) = rx.recv().await {
-            let duty_cycle = new_val.min(1.0).max(0.0);
+            let duty_cycle = new_val.clamp(0.0, 1.0);
             if let Err(err) = pin.set_pwm_frequency(frequency, duty_cycle) {
                 error!("error setting pwm output: {}", err);
             }
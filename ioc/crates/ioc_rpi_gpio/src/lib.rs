@@ -10,12 +10,14 @@ pub mod gpio;
 
 pub use rppal::i2c::I2c;
 
-//get i2c bus by id
-pub fn get_bus(bus: u8) -> I2c {
-    I2c::with_bus(bus).unwrap()
+use error::GpioError;
+
+//get i2c bus by id. fails if it isn't enabled or doesn't exist on this model
+pub fn get_bus(bus: u8) -> Result<I2c, GpioError> {
+    I2c::with_bus(bus).map_err(|err| GpioError::from(format!("unable to open i2c bus {}: {}", bus, err)))
 }
 
 //get default i2c bus
-pub fn get_default_bus() -> I2c {
-    I2c::new().unwrap()
+pub fn get_default_bus() -> Result<I2c, GpioError> {
+    I2c::new().map_err(|err| GpioError::from(format!("unable to open the default i2c bus: {}", err)))
 }
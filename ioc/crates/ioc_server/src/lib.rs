@@ -3,6 +3,7 @@ pub(crate) mod server;
 use std::collections::HashMap;
 
 use ioc_core::error::IocBuildError;
+use ioc_core::timestamp;
 use ioc_core::InputKind;
 use ioc_core::Module;
 use ioc_core::ModuleIO;
@@ -14,17 +15,19 @@ use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
 use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
 use tower_http::trace::DefaultMakeSpan;
 use tower_http::trace::TraceLayer;
 
 use serde::Deserialize;
+use schemars::JsonSchema;
 
 use crate::server::{
     endpoint::Endpoint, io::ServerIoBuilder,
     state::ServerState,
 };
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub enum ServerInputConfig {
     Float {
         start: f64,
@@ -32,9 +35,21 @@ pub enum ServerInputConfig {
         max: f64,
         step: f64,
     },
+    Int {
+        start: i64,
+        min: i64,
+        max: i64,
+        step: i64,
+    },
     Bool {
         start: bool,
     },
+    ///start is given in seconds since the unix epoch
+    Timestamp {
+        #[serde(deserialize_with = "timestamp::deserialize_secs")]
+        #[schemars(with = "f64")]
+        start: SystemTime,
+    },
     String {
         start: String,
         max_length: usize,
@@ -51,17 +66,19 @@ pub enum ServerInputConfig {
     },
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub enum ServerOutputConfig {
     Float,
+    Int,
     Bool,
+    Timestamp,
     String,
     Binary,
     Array,
     Object
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub enum EndpointConfig {
     WebSocket {
         inputs: Vec<String>,
@@ -75,13 +92,16 @@ pub enum EndpointConfig {
     },
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct ServerConfig {
     pub port: u16,
     pub root_context: String,
     pub inputs: HashMap<String, ServerInputConfig>,
     pub outputs: HashMap<String, ServerOutputConfig>,
     pub endpoints: HashMap<String, EndpointConfig>,
+    ///outputs that are reported with `Stale` quality when they aren't written for this long
+    #[serde(default)]
+    pub max_age_ms: HashMap<String, u64>,
     pub state_channel_size: Option<usize>,
     pub io_channel_size: Option<usize>,
 }
@@ -134,8 +154,12 @@ impl Module for Server {
             inputs.insert(key.to_string(), srv_input);
         }
         debug!("building server outputs ...");
+        if let Some(key) = cfg.max_age_ms.keys().find(|key| !cfg.outputs.contains_key(*key)) {
+            return Err(IocBuildError::from_string(format!("max_age_ms is given for {}, which is not a server output", key)));
+        }
         for (key, output_config) in cfg.outputs.iter() {
-            let srv_output = io_builder.try_build_output(key, output_config).await?;
+            let max_age = cfg.max_age_ms.get(key).map(|max_age_ms| Duration::from_millis(*max_age_ms));
+            let srv_output = io_builder.try_build_output(key, output_config, max_age).await?;
             outputs.insert(key.to_string(), srv_output);
         }
 
@@ -48,10 +48,14 @@ impl WebSocketConnection {
                             Message::Text(text) => {
                                 match serde_json::from_str::<HashMap<String, WsStateUpdate>>(&text)
                                 {
-                                    Ok(updates) => {
-                                        let state_cmd = StateCmd::Update(updates.into());
-                                        state_cmd_tx.send(state_cmd).await.unwrap();
-                                    }
+                                    Ok(updates) => match updates.try_into() {
+                                        Ok(update) => {
+                                            state_cmd_tx.send(StateCmd::Update(update)).await.unwrap();
+                                        }
+                                        Err(err) => {
+                                            warn!("invalid update {}", err);
+                                        }
+                                    },
                                     Err(err) => {
                                         warn!("could not parse {}", err);
                                     }
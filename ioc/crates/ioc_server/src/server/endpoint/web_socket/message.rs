@@ -1,5 +1,6 @@
 use crate::server::state::{ServerInputState, ServerOutputState, StateUpdate};
-use ioc_core::Value;
+use ioc_core::sample::{Quality, SampleInfo};
+use ioc_core::{timestamp, Value};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -30,9 +31,18 @@ pub enum WsInputStateInitial {
         max: f64,
         step: f64,
     },
+    Int {
+        value: i64,
+        min: i64,
+        max: i64,
+        step: i64,
+    },
     Bool {
         value: bool,
     },
+    Timestamp {
+        value: f64,
+    },
     String {
         value: String,
         max_length: usize,
@@ -63,8 +73,21 @@ impl From<ServerInputState> for WsInputStateInitial {
                 max,
                 step,
             },
+            ServerInputState::Int {
+                value,
+                min,
+                max,
+                step,
+            } => WsInputStateInitial::Int {
+                value,
+                min,
+                max,
+                step,
+            },
             ServerInputState::Bool { value } => 
                 WsInputStateInitial::Bool { value },
+            ServerInputState::Timestamp { value } => 
+                WsInputStateInitial::Timestamp { value: timestamp::to_secs(&value) },
             ServerInputState::String { value, max_length, choices } => {
                 WsInputStateInitial::String { value, max_length, choices }
             }
@@ -78,10 +101,32 @@ impl From<ServerInputState> for WsInputStateInitial {
     }
 }
 
+///Sample time and quality of an output value.
+#[derive(Serialize)]
+pub struct WsSampleInfo {
+    time: f64,
+    quality: Quality,
+}
+
+impl From<SampleInfo> for WsSampleInfo {
+    fn from(info: SampleInfo) -> Self {
+        WsSampleInfo {
+            time: timestamp::to_secs(&info.time),
+            quality: info.quality,
+        }
+    }
+}
+
+fn ws_samples(samples: HashMap<String, SampleInfo>) -> HashMap<String, WsSampleInfo> {
+    samples.into_iter().map(|(k, info)| (k, info.into())).collect()
+}
+
 #[derive(Serialize)]
 pub enum WsOutputStateInitial {
     Float { value: Option<f64> },
+    Int { value: Option<i64> },
     Bool { value: Option<bool> },
+    Timestamp { value: Option<f64> },
     String { value: Option<String> },
     Binary { value: Option<Vec<u8>> },
     Array { value: Option<Vec<Value>> },
@@ -92,7 +137,11 @@ impl From<ServerOutputState> for WsOutputStateInitial {
     fn from(state: ServerOutputState) -> Self {
         match state {
             ServerOutputState::Float { value } => WsOutputStateInitial::Float { value },
+            ServerOutputState::Int { value } => WsOutputStateInitial::Int { value },
             ServerOutputState::Bool { value } => WsOutputStateInitial::Bool { value },
+            ServerOutputState::Timestamp { value } => WsOutputStateInitial::Timestamp { 
+                value: value.map(|value| timestamp::to_secs(&value)) 
+            },
             ServerOutputState::String { value } => WsOutputStateInitial::String { value },
             ServerOutputState::Binary { value } => WsOutputStateInitial::Binary { value },
             ServerOutputState::Array { value } => WsOutputStateInitial::Array { value },
@@ -105,6 +154,7 @@ impl From<ServerOutputState> for WsOutputStateInitial {
 pub struct WsInitialMessage {
     inputs: HashMap<String, WsInputStateInitial>,
     outputs: HashMap<String, WsOutputStateInitial>,
+    samples: HashMap<String, WsSampleInfo>,
     time: WsTimestamp,
 }
 
@@ -120,11 +170,13 @@ impl From<StateUpdate> for WsInitialMessage {
             outputs.insert(k, i.into());
         }
 
+        let samples = ws_samples(state.samples);
         let time = WsTimestamp::now();
 
         Self {
             inputs,
             outputs,
+            samples,
             time,
         }
     }
@@ -134,22 +186,35 @@ impl From<StateUpdate> for WsInitialMessage {
 pub enum WsStateUpdate {
     Bool { value: bool },
     Float { value: f64 },
+    Int { value: i64 },
+    Timestamp { value: f64 },
     String { value: String },
     Binary { value: Vec<u8> },
     Array { value: Vec<Value> },
     Object { value: HashMap<String, Value> },
 }
 
-impl From<WsStateUpdate> for ServerInputState {
-    fn from(update: WsStateUpdate) -> Self {
-        match update {
+impl TryFrom<WsStateUpdate> for ServerInputState {
+    type Error = String;
+
+    fn try_from(update: WsStateUpdate) -> Result<Self, Self::Error> {
+        Ok(match update {
             WsStateUpdate::Float { value } => ServerInputState::Float {
                 value,
                 min: 0.0,
                 max: 0.0,
                 step: 0.0,
             },
+            WsStateUpdate::Int { value } => ServerInputState::Int {
+                value,
+                min: 0,
+                max: 0,
+                step: 0,
+            },
             WsStateUpdate::Bool { value } => ServerInputState::Bool { value },
+            WsStateUpdate::Timestamp { value } => ServerInputState::Timestamp {
+                value: timestamp::from_secs(value).ok_or_else(|| format!("{} is not a valid timestamp", value))?,
+            },
             WsStateUpdate::String { value } => ServerInputState::String {
                 value,
                 max_length: 0,
@@ -158,7 +223,7 @@ impl From<WsStateUpdate> for ServerInputState {
             WsStateUpdate::Binary { value } => ServerInputState::Binary { value },
             WsStateUpdate::Array { value } => ServerInputState::Array { value },
             WsStateUpdate::Object { value } => ServerInputState::Object { value },
-        }
+        })
     }
 }
 
@@ -166,7 +231,11 @@ impl From<ServerInputState> for WsStateUpdate {
     fn from(state: ServerInputState) -> Self {
         match state {
             ServerInputState::Float { value, .. } => WsStateUpdate::Float { value },
+            ServerInputState::Int { value, .. } => WsStateUpdate::Int { value },
             ServerInputState::Bool { value } => WsStateUpdate::Bool { value },
+            ServerInputState::Timestamp { value } => WsStateUpdate::Timestamp {
+                value: timestamp::to_secs(&value),
+            },
             ServerInputState::String { value, .. } => WsStateUpdate::String { value },
             ServerInputState::Binary { value } => WsStateUpdate::Binary { value },
             ServerInputState::Array { value } => WsStateUpdate::Array { value },
@@ -181,9 +250,15 @@ impl From<ServerOutputState> for Option<WsStateUpdate> {
             ServerOutputState::Float { value, .. } => {
                 value.map(|value| WsStateUpdate::Float { value })
             }
+            ServerOutputState::Int { value } => {
+                value.map(|value| WsStateUpdate::Int { value })
+            }
             ServerOutputState::Bool { value } => value.map(|value| 
                 WsStateUpdate::Bool { value }
             ),
+            ServerOutputState::Timestamp { value } => value.map(|value|
+                WsStateUpdate::Timestamp { value: timestamp::to_secs(&value) }
+            ),
             ServerOutputState::String { value, .. } => value.map(|value| 
                 WsStateUpdate::String { value: value.to_string() }
             ),
@@ -201,17 +276,22 @@ impl From<ServerOutputState> for Option<WsStateUpdate> {
     }
 }
 
-impl From<HashMap<String, WsStateUpdate>> for StateUpdate {
-    fn from(update: HashMap<String, WsStateUpdate>) -> Self {
+impl TryFrom<HashMap<String, WsStateUpdate>> for StateUpdate {
+    type Error = String;
+
+    ///Fails if any of the updates is invalid, so a message is applied entirely or not at all.
+    fn try_from(update: HashMap<String, WsStateUpdate>) -> Result<Self, Self::Error> {
         let mut inputs = HashMap::with_capacity(update.len());
         for (k, i) in update {
-            inputs.insert(k, i.into());
+            let input = i.try_into().map_err(|err| format!("{}: {}", k, err))?;
+            inputs.insert(k, input);
         }
 
-        StateUpdate {
+        Ok(StateUpdate {
             inputs,
             outputs: HashMap::new(),
-        }
+            samples: HashMap::new(),
+        })
     }
 }
 
@@ -219,6 +299,7 @@ impl From<HashMap<String, WsStateUpdate>> for StateUpdate {
 pub struct WsUpdateMessage {
     pub inputs: HashMap<String, WsStateUpdate>,
     pub outputs: HashMap<String, WsStateUpdate>,
+    pub samples: HashMap<String, WsSampleInfo>,
     pub time: WsTimestamp,
 }
 
@@ -236,12 +317,53 @@ impl From<StateUpdate> for WsUpdateMessage {
             }
         }
 
+        let samples = ws_samples(update.samples);
         let time = WsTimestamp::now();
 
         Self {
             inputs,
             outputs,
+            samples,
             time,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{WsStateUpdate, WsUpdateMessage};
+    use crate::server::state::{ServerInputState, ServerOutputState, StateUpdate};
+    use ioc_core::sample::{Quality, SampleInfo};
+    use std::{collections::HashMap, time::{Duration, UNIX_EPOCH}};
+
+    #[test]
+    fn test_timestamp_update() {
+        let updates: HashMap<String, WsStateUpdate> = serde_json::from_str(r#"{ "start": { "Timestamp": { "value": 1.5 } } }"#).unwrap();
+        let update = StateUpdate::try_from(updates).unwrap();
+        assert!(matches!(update.inputs["start"], ServerInputState::Timestamp { value } if value == UNIX_EPOCH + Duration::from_millis(1500)));
+
+        //out of range for a timestamp, the update is rejected instead of panicking
+        for value in ["1.0e300", "-1.0e300"] {
+            let updates: HashMap<String, WsStateUpdate> = serde_json::from_str(&format!(r#"{{ "start": {{ "Timestamp": {{ "value": {} }} }} }}"#, value)).unwrap();
+            assert!(StateUpdate::try_from(updates).is_err());
+        }
+    }
+
+    #[test]
+    fn test_update_samples() {
+        let update = StateUpdate {
+            inputs: HashMap::new(),
+            outputs: HashMap::from([("x".to_string(), ServerOutputState::Float { value: Some(2.5) })]),
+            samples: HashMap::from([(
+                "x".to_string(),
+                SampleInfo { time: UNIX_EPOCH + Duration::from_millis(1500), elapsed: Duration::ZERO, quality: Quality::Stale },
+            )]),
+        };
+        let json = serde_json::to_string(&WsUpdateMessage::from(update)).unwrap();
+
+        //samples are keyed like the outputs, with the time in seconds
+        let message: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(message["outputs"]["x"], serde_json::json!({ "Float": { "value": 2.5 } }));
+        assert_eq!(message["samples"]["x"], serde_json::json!({ "time": 1.5, "quality": "Stale" }));
+    }
+}
@@ -3,10 +3,13 @@ use crate::server::state::{ServerInputState, ServerOutputState};
 use crate::{ServerInputConfig, ServerOutputConfig};
 
 use ioc_core::error::IocBuildError;
+use ioc_core::sample::{Quality, Sample, SampleInfo};
 use ioc_core::{Input, InputKind, Output, OutputKind};
 use tracing::{debug, warn};
 use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, Instant};
 
 pub(crate) struct ServerIoBuilder {
     pub cmd_tx: mpsc::Sender<StateCmd>,
@@ -24,8 +27,9 @@ impl ServerIoBuilder {
             inputs: HashSet::from([key.to_string()]), 
             outputs: HashSet::new() 
         };
-        self.cmd_tx.send(subs_cmd ).await.unwrap();
-        let subs = subs_rx.await.unwrap();
+        let stopped = || IocBuildError::message("server state stopped while building its inputs");
+        self.cmd_tx.send(subs_cmd).await.map_err(|_| stopped())?;
+        let subs = subs_rx.await.map_err(|_| stopped())?;
         match config {
                 ServerInputConfig::Bool { .. } => {
                     let start = match subs.start.inputs.get(key) {
@@ -40,7 +44,9 @@ impl ServerIoBuilder {
                         while let Ok(update) = subs_rx.recv().await {
                             if let Some(value) = update.inputs.get(&key) {
                                 if let ServerInputState::Bool { value } = value {
-                                    tx.send(*value).unwrap();
+                                    if tx.send(*value).is_err() {
+                                        break;
+                                    }
                                 } else {
                                     warn!("Expected Bool input for key: {}", key);
                                 }
@@ -63,7 +69,9 @@ impl ServerIoBuilder {
                         while let Ok(update) = subs_rx.recv().await {
                             if let Some(value) = update.inputs.get(&key) {
                                 if let ServerInputState::Float { value, .. } = value {
-                                    tx.send(*value).unwrap();
+                                    if tx.send(*value).is_err() {
+                                        break;
+                                    }
                                 } else {
                                     warn!("Expected Float input for key: {}", key);
                                 }
@@ -73,6 +81,56 @@ impl ServerIoBuilder {
                     });
                     Ok(InputKind::Float(input))
                 },
+                ServerInputConfig::Int { .. } => {
+                    let start = match subs.start.inputs.get(key) {
+                        Some(ServerInputState::Int { value, .. }) => Ok(*value),
+                        Some(_) => Err(IocBuildError::from_string(format!("Expected Int input for key: {}", key))),
+                        None => Err(IocBuildError::from_string(format!("No input found for key: {}", key))),
+                    }?;
+                    let (input, tx) = Input::new(start);
+                    let mut subs_rx = subs.update_rx;
+                    let key = key.to_owned();
+                    tokio::spawn(async move {
+                        while let Ok(update) = subs_rx.recv().await {
+                            if let Some(value) = update.inputs.get(&key) {
+                                if let ServerInputState::Int { value, .. } = value {
+                                    if tx.send(*value).is_err() {
+                                        break;
+                                    }
+                                } else {
+                                    warn!("Expected Int input for key: {}", key);
+                                }
+                            }
+                        }
+                        debug!("Server input shutting down!");
+                    });
+                    Ok(InputKind::Int(input))
+                },
+                ServerInputConfig::Timestamp { .. } => {
+                    let start = match subs.start.inputs.get(key) {
+                        Some(ServerInputState::Timestamp { value }) => Ok(*value),
+                        Some(_) => Err(IocBuildError::from_string(format!("Expected Timestamp input for key: {}", key))),
+                        None => Err(IocBuildError::from_string(format!("No input found for key: {}", key))),
+                    }?;
+                    let (input, tx) = Input::new(start);
+                    let mut subs_rx = subs.update_rx;
+                    let key = key.to_owned();
+                    tokio::spawn(async move {
+                        while let Ok(update) = subs_rx.recv().await {
+                            if let Some(value) = update.inputs.get(&key) {
+                                if let ServerInputState::Timestamp { value } = value {
+                                    if tx.send(*value).is_err() {
+                                        break;
+                                    }
+                                } else {
+                                    warn!("Expected Timestamp input for key: {}", key);
+                                }
+                            }
+                        }
+                        debug!("Server input shutting down!");
+                    });
+                    Ok(InputKind::Timestamp(input))
+                },
                 ServerInputConfig::String { .. } => {
                     let start = match subs.start.inputs.get(key) {
                         Some(ServerInputState::String { value, .. }) => Ok(value.clone()),
@@ -86,7 +144,9 @@ impl ServerIoBuilder {
                         while let Ok(update) = subs_rx.recv().await {
                             if let Some(value) = update.inputs.get(&key) {
                                 if let ServerInputState::String { value, .. } = value {
-                                    tx.send(value.clone()).unwrap();
+                                    if tx.send(value.clone()).is_err() {
+                                        break;
+                                    }
                                 } else {
                                     warn!("Expected String input for key: {}", key);
                                 }
@@ -109,7 +169,9 @@ impl ServerIoBuilder {
                         while let Ok(update) = subs_rx.recv().await {
                             if let Some(value) = update.inputs.get(&key) {
                                 if let ServerInputState::Binary { value, .. } = value {
-                                    tx.send(value.clone()).unwrap();
+                                    if tx.send(value.clone()).is_err() {
+                                        break;
+                                    }
                                 } else {
                                     warn!("Expected Binary input for key: {}", key);
                                 }
@@ -132,7 +194,9 @@ impl ServerIoBuilder {
                         while let Ok(update) = subs_rx.recv().await {
                             if let Some(value) = update.inputs.get(&key) {
                                 if let ServerInputState::Array { value } = value {
-                                    tx.send(value.clone()).unwrap();
+                                    if tx.send(value.clone()).is_err() {
+                                        break;
+                                    }
                                 } else {
                                     warn!("Expected Array input for key: {}", key);
                                 }
@@ -155,7 +219,9 @@ impl ServerIoBuilder {
                         while let Ok(update) = subs_rx.recv().await {
                             if let Some(value) = update.inputs.get(&key) {
                                 if let ServerInputState::Object { value } = value {
-                                    tx.send(value.clone()).unwrap();
+                                    if tx.send(value.clone()).is_err() {
+                                        break;
+                                    }
                                 } else {
                                     warn!("Expected Object input for key: {}", key);
                                 }
@@ -172,99 +238,142 @@ impl ServerIoBuilder {
         &self,
         key: &str,
         config: &ServerOutputConfig,
+        max_age: Option<Duration>,
     ) -> Result<OutputKind, IocBuildError> {
         
         match config {
             ServerOutputConfig::Bool => {
-                let (output, mut rx) = Output::new();
-                let cmd_tx = self.cmd_tx.clone();
-                let key = key.to_owned();
-                tokio::spawn(async move {
-                    while let Some(value) = rx.recv().await {
-                        cmd_tx.send(StateCmd::Update(StateUpdate{
-                            inputs: HashMap::new(),
-                            outputs: HashMap::from([(key.to_string(), ServerOutputState::Bool{ value: Some(value) })]),
-                        })).await.unwrap();
-                    }
-                    debug!("Server output shutting down!");
-                });
+                let (output, rx, samples_rx) = Output::new_sampled();
+                spawn_output_task(self.cmd_tx.clone(), key, rx, samples_rx, max_age, |value| ServerOutputState::Bool{ value: Some(value) });
                 Ok(OutputKind::Bool(output))
             },
             ServerOutputConfig::Float => {
-                let (output, mut rx) = Output::new();
-                let cmd_tx = self.cmd_tx.clone();
-                let key = key.to_owned();
-                tokio::spawn(async move {
-                    while let Some(value) = rx.recv().await {
-                        cmd_tx.send(StateCmd::Update(StateUpdate{
-                            inputs: HashMap::new(),
-                            outputs: HashMap::from([(key.to_string(), ServerOutputState::Float{ value: Some(value) })]),
-                        })).await.unwrap();
-                    }
-                    debug!("Server output shutting down!");
-                });
+                let (output, rx, samples_rx) = Output::new_sampled();
+                spawn_output_task(self.cmd_tx.clone(), key, rx, samples_rx, max_age, |value| ServerOutputState::Float{ value: Some(value) });
                 Ok(OutputKind::Float(output))
             },
+            ServerOutputConfig::Int => {
+                let (output, rx, samples_rx) = Output::new_sampled();
+                spawn_output_task(self.cmd_tx.clone(), key, rx, samples_rx, max_age, |value| ServerOutputState::Int{ value: Some(value) });
+                Ok(OutputKind::Int(output))
+            },
+            ServerOutputConfig::Timestamp => {
+                let (output, rx, samples_rx) = Output::new_sampled();
+                spawn_output_task(self.cmd_tx.clone(), key, rx, samples_rx, max_age, |value| ServerOutputState::Timestamp{ value: Some(value) });
+                Ok(OutputKind::Timestamp(output))
+            },
             ServerOutputConfig::String => {
-                let (output, mut rx) = Output::new();
-                let cmd_tx = self.cmd_tx.clone();
-                let key = key.to_owned();
-                tokio::spawn(async move {
-                    while let Some(value) = rx.recv().await {
-                        cmd_tx.send(StateCmd::Update(StateUpdate{
-                            inputs: HashMap::new(),
-                            outputs: HashMap::from([(key.to_string(), ServerOutputState::String{ value: Some(value) })]),
-                        })).await.unwrap();
-                    }
-                    debug!("Server output shutting down!");
-                });
+                let (output, rx, samples_rx) = Output::new_sampled();
+                spawn_output_task(self.cmd_tx.clone(), key, rx, samples_rx, max_age, |value| ServerOutputState::String{ value: Some(value) });
                 Ok(OutputKind::String(output))
             },
             ServerOutputConfig::Binary => {
-                let (output, mut rx) = Output::new();
-                let cmd_tx = self.cmd_tx.clone();
-                let key = key.to_owned();
-                tokio::spawn(async move {
-                    while let Some(value) = rx.recv().await {
-                        cmd_tx.send(StateCmd::Update(StateUpdate{
-                            inputs: HashMap::new(),
-                            outputs: HashMap::from([(key.to_string(), ServerOutputState::Binary{ value: Some(value) })]),
-                        })).await.unwrap();
-                    }
-                    debug!("Server output shutting down!");
-                });
+                let (output, rx, samples_rx) = Output::new_sampled();
+                spawn_output_task(self.cmd_tx.clone(), key, rx, samples_rx, max_age, |value| ServerOutputState::Binary{ value: Some(value) });
                 Ok(OutputKind::Binary(output))
             },
             ServerOutputConfig::Array => {
-                let (output, mut rx) = Output::new();
-                let cmd_tx = self.cmd_tx.clone();
-                let key = key.to_owned();
-                tokio::spawn(async move {
-                    while let Some(value) = rx.recv().await {
-                        cmd_tx.send(StateCmd::Update(StateUpdate{
-                            inputs: HashMap::new(),
-                            outputs: HashMap::from([(key.to_string(), ServerOutputState::Array{ value: Some(value) })]),
-                        })).await.unwrap();
-                    }
-                    debug!("Server output shutting down!");
-                });
+                let (output, rx, samples_rx) = Output::new_sampled();
+                spawn_output_task(self.cmd_tx.clone(), key, rx, samples_rx, max_age, |value| ServerOutputState::Array{ value: Some(value) });
                 Ok(OutputKind::Array(output))
             },
             ServerOutputConfig::Object => {
-                let (output, mut rx) = Output::new();
-                let cmd_tx = self.cmd_tx.clone();
-                let key = key.to_owned();
-                tokio::spawn(async move {
-                    while let Some(value) = rx.recv().await {
-                        cmd_tx.send(StateCmd::Update(StateUpdate{
-                            inputs: HashMap::new(),
-                            outputs: HashMap::from([(key.to_string(), ServerOutputState::Object{ value: Some(value) })]),
-                        })).await.unwrap();
-                    }
-                    debug!("Server output shutting down!");
-                });
+                let (output, rx, samples_rx) = Output::new_sampled();
+                spawn_output_task(self.cmd_tx.clone(), key, rx, samples_rx, max_age, |value| ServerOutputState::Object{ value: Some(value) });
                 Ok(OutputKind::Object(output))
             },
         }
     }
 }
+
+///Forwards values and samples written to a server output to the server state.
+/// If the output isn't written for max_age, its last sample is marked `Stale` until it's written again.
+fn spawn_output_task<T: Send + 'static>(
+    cmd_tx: mpsc::Sender<StateCmd>,
+    key: &str,
+    mut rx: mpsc::Receiver<T>,
+    mut samples_rx: mpsc::Receiver<Sample<T>>,
+    max_age: Option<Duration>,
+    to_state: fn(T) -> ServerOutputState,
+) {
+    let key = key.to_owned();
+    tokio::spawn(async move {
+        let mut last = SampleInfo::now();
+        let mut stale_at = max_age.map(|max_age| Instant::now() + max_age);
+        loop {
+            let (value, info) = tokio::select! {
+                Some(value) = rx.recv() => {
+                    //plain values only need a sample to clear a stale one
+                    let was_stale = stale_at.is_none() && max_age.is_some();
+                    last = SampleInfo::now();
+                    (Some(value), was_stale.then_some(last))
+                },
+                Some(sample) = samples_rx.recv() => {
+                    last = sample.info;
+                    (Some(sample.value), Some(sample.info))
+                },
+                _ = sleep_until(stale_at.unwrap_or_else(Instant::now)), if stale_at.is_some() => {
+                    stale_at = None;
+                    (None, Some(SampleInfo { quality: Quality::Stale, ..last }))
+                },
+                else => break,
+            };
+            if value.is_some() {
+                stale_at = max_age.map(|max_age| Instant::now() + max_age);
+            }
+            let update = StateCmd::Update(StateUpdate{
+                inputs: HashMap::new(),
+                outputs: value.map(|value| HashMap::from([(key.to_string(), to_state(value))])).unwrap_or_default(),
+                samples: info.map(|info| HashMap::from([(key.to_string(), info)])).unwrap_or_default(),
+            });
+            //the server state stops first on shutdown
+            if cmd_tx.send(update).await.is_err() {
+                break;
+            }
+        }
+        debug!("Server output shutting down!");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerIoBuilder;
+    use crate::server::state::{ServerOutputState, StateCmd, StateUpdate};
+    use crate::ServerOutputConfig;
+    use ioc_core::sample::Quality;
+    use ioc_core::OutputKind;
+    use std::time::Duration;
+    use tokio::{sync::mpsc, time::timeout};
+
+    async fn next_update(cmd_rx: &mut mpsc::Receiver<StateCmd>) -> StateUpdate {
+        match timeout(Duration::from_secs(1), cmd_rx.recv()).await {
+            Ok(Some(StateCmd::Update(update))) => update,
+            _ => panic!("expected a state update"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stale_output() {
+        let (cmd_tx, mut cmd_rx) = mpsc::channel(8);
+        let builder = ServerIoBuilder { cmd_tx };
+        let output = builder.try_build_output("x", &ServerOutputConfig::Float, Some(Duration::from_millis(20))).await.unwrap();
+        let OutputKind::Float(output) = output else {
+            panic!("expected a Float output");
+        };
+
+        output.sink().send(1.0).await.unwrap();
+        let update = next_update(&mut cmd_rx).await;
+        assert!(matches!(update.outputs["x"], ServerOutputState::Float { value: Some(value) } if value == 1.0));
+        assert!(update.samples.is_empty());
+
+        //not written for max_age, the value stays but is stale
+        let update = next_update(&mut cmd_rx).await;
+        assert!(update.outputs.is_empty());
+        assert_eq!(update.samples["x"].quality, Quality::Stale);
+
+        //until it's written again
+        output.sink().send(2.0).await.unwrap();
+        let update = next_update(&mut cmd_rx).await;
+        assert_eq!(update.samples["x"].quality, Quality::Good);
+    }
+}
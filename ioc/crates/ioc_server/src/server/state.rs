@@ -1,9 +1,11 @@
 use crate::{ServerInputConfig, ServerOutputConfig};
 use ioc_core::error::IocBuildError;
+use ioc_core::sample::SampleInfo;
 use ioc_core::Value;
 use tokio_util::sync::CancellationToken;
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
 use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio::task::JoinHandle;
 use tracing::{debug, error};
@@ -16,9 +18,18 @@ pub(crate) enum ServerInputState {
         max: f64,
         step: f64,
     },
+    Int {
+        value: i64,
+        min: i64,
+        max: i64,
+        step: i64,
+    },
     Bool {
         value: bool,
     },
+    Timestamp {
+        value: SystemTime,
+    },
     String {
         value: String,
         max_length: usize,
@@ -38,7 +49,9 @@ pub(crate) enum ServerInputState {
 #[derive(Debug, Clone)]
 pub(crate) enum ServerOutputState {
     Float { value: Option<f64> },
+    Int { value: Option<i64> },
     Bool { value: Option<bool> },
+    Timestamp { value: Option<SystemTime> },
     String { value: Option<String> },
     Binary { value: Option<Vec<u8>> },
     Array { value: Option<Vec<Value>> },
@@ -49,6 +62,8 @@ pub(crate) enum ServerOutputState {
 pub(crate) struct StateUpdate {
     pub inputs: HashMap<String, ServerInputState>,
     pub outputs: HashMap<String, ServerOutputState>,
+    ///sample time and quality of outputs that were written with `Sample`s
+    pub samples: HashMap<String, SampleInfo>,
 }
 
 #[derive(Debug)]
@@ -77,8 +92,17 @@ impl From<&ServerInputConfig> for ServerInputState {
                     max: *max,
                     step: *step,
                 },
+            ServerInputConfig::Int { start, min, max, step, } => 
+                ServerInputState::Int {
+                    value: *start,
+                    min: *min,
+                    max: *max,
+                    step: *step,
+                },
             ServerInputConfig::Bool { start } => 
                 ServerInputState::Bool { value: *start },
+            ServerInputConfig::Timestamp { start } => 
+                ServerInputState::Timestamp { value: *start },
             ServerInputConfig::String { start, max_length, choices } => 
                 ServerInputState::String {
                     value: start.to_string(),
@@ -99,7 +123,9 @@ impl From<&ServerOutputConfig> for ServerOutputState {
     fn from(config: &ServerOutputConfig) -> Self {
         match config {
             ServerOutputConfig::Float => ServerOutputState::Float { value: None },
+            ServerOutputConfig::Int => ServerOutputState::Int { value: None },
             ServerOutputConfig::Bool => ServerOutputState::Bool { value: None },
+            ServerOutputConfig::Timestamp => ServerOutputState::Timestamp { value: None },
             ServerOutputConfig::String => ServerOutputState::String { value: None },
             ServerOutputConfig::Binary => ServerOutputState::Binary { value: None },
             ServerOutputConfig::Array => ServerOutputState::Array { value: None },
@@ -140,6 +166,8 @@ impl ServerState {
             output_states.insert(key.to_string(), output.into());
         }
 
+        let mut internal_samples: HashMap<String, SampleInfo> = HashMap::new();
+
         let mut state_subs = StateSubscriptions::with_capacities(100, 100);
 
         let server_state_handle = tokio::spawn(async move {
@@ -163,7 +191,12 @@ impl ServerState {
                             }
                         }
 
+                        let mut filtered_samples: HashMap<String, SampleInfo> = HashMap::new();
+
                         for k in outputs {
+                            if let Some(info) = internal_samples.get(&k) {
+                                filtered_samples.insert(k.clone(), *info);
+                            }
                             if let Some(o) = internal_outputs.get(&k) {
                                 filtered_outputs.insert(k, o.clone());
                             }
@@ -173,6 +206,7 @@ impl ServerState {
                             start: StateUpdate {
                                 inputs: filtered_inputs,
                                 outputs: filtered_outputs,
+                                samples: filtered_samples,
                             },
                             update_rx: subs_rx,
                         };
@@ -215,6 +249,49 @@ impl ServerState {
                                             );
                                         }
                                     }
+                                    (
+                                        ServerInputState::Int {
+                                            value: updated_int_value,
+                                            ..
+                                        },
+                                        ServerInputState::Int {
+                                            value: current_int_value,
+                                            min,
+                                            max,
+                                            step,
+                                        },
+                                    ) => {
+                                        if *current_int_value != updated_int_value {
+                                            *current_int_value = updated_int_value;
+                                            inputs.insert(
+                                                k,
+                                                ServerInputState::Int {
+                                                    value: updated_int_value,
+                                                    min: *min,
+                                                    max: *max,
+                                                    step: *step,
+                                                },
+                                            );
+                                        }
+                                    }
+                                    (
+                                        ServerInputState::Timestamp {
+                                            value: updated_timestamp_value,
+                                        },
+                                        ServerInputState::Timestamp {
+                                            value: current_timestamp_value,
+                                        },
+                                    ) => {
+                                        if *current_timestamp_value != updated_timestamp_value {
+                                            *current_timestamp_value = updated_timestamp_value;
+                                            inputs.insert(
+                                                k,
+                                                ServerInputState::Timestamp {
+                                                    value: updated_timestamp_value,
+                                                },
+                                            );
+                                        }
+                                    }
                                     (
                                         ServerInputState::Bool {
                                             value: updated_bool_value,
@@ -336,6 +413,38 @@ impl ServerState {
                                             },
                                         );
                                     }
+                                    (
+                                        ServerOutputState::Int {
+                                            value: updated_int_value,
+                                        },
+                                        ServerOutputState::Int {
+                                            value: current_int_value,
+                                        },
+                                    ) => {
+                                        *current_int_value = updated_int_value;
+                                        outputs.insert(
+                                            k,
+                                            ServerOutputState::Int {
+                                                value: updated_int_value,
+                                            },
+                                        );
+                                    }
+                                    (
+                                        ServerOutputState::Timestamp {
+                                            value: updated_timestamp_value,
+                                        },
+                                        ServerOutputState::Timestamp {
+                                            value: current_timestamp_value,
+                                        },
+                                    ) => {
+                                        *current_timestamp_value = updated_timestamp_value;
+                                        outputs.insert(
+                                            k,
+                                            ServerOutputState::Timestamp {
+                                                value: updated_timestamp_value,
+                                            },
+                                        );
+                                    }
                                     (
                                         ServerOutputState::Bool {
                                             value: updated_bool_value,
@@ -421,7 +530,17 @@ impl ServerState {
                             }
                         }
 
-                        state_subs.publish(StateUpdate { inputs, outputs })
+                        let mut samples: HashMap<String, SampleInfo> =
+                            HashMap::with_capacity(update.samples.len());
+
+                        for (k, info) in update.samples {
+                            if internal_outputs.contains_key(&k) {
+                                internal_samples.insert(k.clone(), info);
+                                samples.insert(k, info);
+                            }
+                        }
+
+                        state_subs.publish(StateUpdate { inputs, outputs, samples })
                     }
                 }
             }
@@ -480,8 +599,15 @@ impl StateSubscriptionKey {
             }
         }
 
-        if !outputs.is_empty() || !inputs.is_empty() {
-            Some(StateUpdate { inputs, outputs })
+        let mut samples = HashMap::new();
+        for (k, info) in &update.samples {
+            if self.outputs.contains(k) {
+                samples.insert(k.to_string(), *info);
+            }
+        }
+
+        if !outputs.is_empty() || !inputs.is_empty() || !samples.is_empty() {
+            Some(StateUpdate { inputs, outputs, samples })
         } else {
             None
         }
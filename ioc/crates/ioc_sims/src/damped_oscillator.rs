@@ -1,5 +1,5 @@
 
-use ioc_core::{error::IocBuildError, Input, InputKind, Transformer, TransformerI};
+use ioc_core::{clock::Clock, error::IocBuildError, Input, InputKind, Transformer, TransformerI};
 use tokio::{sync:: watch, task::JoinHandle};
 use tracing::warn;
 use std::{collections::HashMap, time::{Duration, Instant}};
@@ -28,13 +28,14 @@ impl From<DampedOscillator> for TransformerI {
 }
 
 fn spawn_sim_task(cfg: &DampedOscillatorConfig, x_tx: watch::Sender<f64>, v_tx: watch::Sender<f64>) -> JoinHandle<()> {
-    let mut m = InputAverager::new(cfg.m);
-    let mut c = InputAverager::new(cfg.c);
-    let mut k = InputAverager::new(cfg.k);
-    let mut f = InputAverager::new(cfg.f);
+    let mut m = InputAverager::new(cfg.m, cfg.clock);
+    let mut c = InputAverager::new(cfg.c, cfg.clock);
+    let mut k = InputAverager::new(cfg.k, cfg.clock);
+    let mut f = InputAverager::new(cfg.f, cfg.clock);
 
     let period_ms = cfg.period_ms;
     let steps_per_frame = cfg.steps_per_frame;
+    let clock = cfg.clock.clone();
 
     tokio::spawn(async move {
         //todo: make initial conditions configurable
@@ -76,8 +77,11 @@ fn spawn_sim_task(cfg: &DampedOscillatorConfig, x_tx: watch::Sender<f64>, v_tx:
             let end = Instant::now();
             let dt = end - start;
 
-            if dt.as_millis() < period_ms as u128 {
-                tokio::time::sleep(Duration::from_millis(period_ms - dt.as_millis() as u64)).await; 
+            if clock.is_simulated() {
+                //a simulated clock doesn't advance while the frame is computed
+                clock.sleep(Duration::from_millis(period_ms)).await;
+            } else if dt.as_millis() < period_ms as u128 {
+                clock.sleep(Duration::from_millis(period_ms - dt.as_millis() as u64)).await; 
             } else {
                 warn!("sim task took too long to run: {}ms", dt.as_millis());
             }
@@ -113,11 +117,18 @@ fn oscillator_fn(state: &mut State<f64>, _: &NoEnv) {
 /// - `f`: The external force applied to the oscillator.
 /// - `period_ms`: The frequency at which frames are emitted, in milliseconds.
 /// - `steps_per_frame`: The number of integration steps to take per frame.
+/// - `clock`: The clock frames are emitted on.
 ///
 /// # Example
 ///
 /// ```
-/// use ioc_sims::DampedOscillatorConfig;
+/// use ioc_core::{clock::Clock, Input};
+/// use ioc_sims::damped_oscillator::DampedOscillatorConfig;
+///
+/// let (mass_input, _) = Input::new(1.0);
+/// let (damping_input, _) = Input::new(0.5);
+/// let (spring_constant_input, _) = Input::new(1.0);
+/// let (external_force_input, _) = Input::new(0.0);
 ///
 /// let config = DampedOscillatorConfig {
 ///     m: &mass_input,
@@ -126,6 +137,7 @@ fn oscillator_fn(state: &mut State<f64>, _: &NoEnv) {
 ///     f: &external_force_input,
 ///     period_ms: 10,
 ///     steps_per_frame: 100,
+///     clock: &Clock::real(),
 /// };
 /// ```
 pub struct DampedOscillatorConfig<'a> {
@@ -135,6 +147,7 @@ pub struct DampedOscillatorConfig<'a> {
     pub f: &'a Input<f64>, //external force
     pub period_ms: u64, //how frequently to emit a frame
     pub steps_per_frame: u64, //how many integration steps to take per frame
+    pub clock: &'a Clock, //the clock frames are emitted on
 }
 
 impl<'a> Transformer<'a> for DampedOscillator {
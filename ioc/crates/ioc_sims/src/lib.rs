@@ -1,37 +1,36 @@
 extern crate peroxide;
-use std::{sync::{Arc, Mutex}, time::Instant};
+use std::{sync::{Arc, Mutex}, time::Duration};
 
-use ioc_core::Input;
+use ioc_core::{clock::Clock, Input};
 use tracing::info;
 
 pub mod damped_oscillator;
 
 struct WindowAverageState{
     last_value: f64,
-    last_append: Instant,
-    last_window: Instant,
+    //on the clock's monotonic time
+    last_append: Duration,
+    last_window: Duration,
     sum: f64
 }
 
 impl WindowAverageState {
-    fn new(start: f64) -> Self {
+    fn new(start: f64, now: Duration) -> Self {
         Self {
             last_value: start, 
-            last_append: Instant::now(),
-            last_window: Instant::now(),
+            last_append: now,
+            last_window: now,
             sum: 0.0,
         }
     }
-    fn append(&mut self, new_value: f64) {
-        let now = Instant::now();
-        let dt = now.duration_since(self.last_append).as_secs_f64();
+    fn append(&mut self, new_value: f64, now: Duration) {
+        let dt = now.saturating_sub(self.last_append).as_secs_f64();
         self.sum += dt * self.last_value;
 
         self.last_value = new_value;
         self.last_append = now;
     }
-    fn step(&mut self) -> f64 {
-        let now = Instant::now();
+    fn step(&mut self, now: Duration) -> f64 {
         if self.last_window == self.last_append {
             //only one remainn-sum, so just send the last value 
             self.last_window = now;
@@ -40,8 +39,8 @@ impl WindowAverageState {
             self.last_value
         } else {
             //add a reaimann-sum and divide by dt for average. reset.
-            let dt = now.duration_since(self.last_append).as_secs_f64();
-            let window_t = now.duration_since(self.last_window).as_secs_f64();
+            let dt = now.saturating_sub(self.last_append).as_secs_f64();
+            let window_t = now.saturating_sub(self.last_window).as_secs_f64();
             self.sum += dt * self.last_value;
             let avg = self.sum / window_t;
             self.last_window = now;
@@ -54,16 +53,19 @@ impl WindowAverageState {
 
 pub struct InputAverager {
     state: Arc<Mutex<WindowAverageState>>,
+    clock: Clock,
 }
 
 impl InputAverager {
-    pub fn new(i: &Input<f64>) -> Self {
+    ///Averages an input over time on the given clock. Each `read` returns the average since the last one.
+    pub fn new(i: &Input<f64>, clock: &Clock) -> Self {
         let mut rx = i.source();
         let state = Arc::new(Mutex::new(
-            WindowAverageState::new(*rx.borrow_and_update())
+            WindowAverageState::new(*rx.borrow_and_update(), clock.elapsed())
         ));
 
         let task_state = state.clone();
+        let task_clock = clock.clone();
         tokio::spawn(async move {
             while rx.changed().await.is_ok() {
                 let new_value = *rx.borrow_and_update();
@@ -71,12 +73,12 @@ impl InputAverager {
                     Ok(v) => v,
                     Err(poisoned) => poisoned.into_inner(),
                 };
-                state.append(new_value);
+                state.append(new_value, task_clock.elapsed());
             }
             info!("input average shut down!");
         });
 
-        Self { state }
+        Self { state, clock: clock.clone() }
     }
 
     pub fn read(&mut self) -> f64 {
@@ -84,6 +86,6 @@ impl InputAverager {
             Ok(v) => v,
             Err(poisoned) => poisoned.into_inner(),
         };
-        state.step()
+        state.step(self.clock.elapsed())
     }
 }
\ No newline at end of file
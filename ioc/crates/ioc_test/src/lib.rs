@@ -0,0 +1,301 @@
+//! A harness for testing IOC configs in-process.
+//!
+//! A `Harness` builds an `IocConfig`, replaces modules that need hardware or a network with `StandIn`s, and runs it under a simulated clock.
+//! The test sets the stand-ins' inputs, advances time, and checks the values the graph writes to the stand-ins' outputs.
+//!
+//! ```no_run
+//! use ioc_test::{Harness, StandIn};
+//! use std::time::Duration;
+//!
+//! # async fn example() {
+//! Harness::from_yaml(include_str!("../../../example-configs/linear_transform_demo.yml"))
+//!     .unwrap()
+//!     .stand_in("local_server", StandIn::new().input("celsius", 0.0).output::<f64>("fahrenheit"))
+//!     .run(|io| async move {
+//!         io.set("local_server.celsius", 100.0);
+//!         io.expect_float("local_server.fahrenheit", 212.0, 1e-9, Duration::from_millis(100)).await;
+//!     })
+//!     .await
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use ioc::config::{registry, IocConfig, IocModuleConfig};
+use ioc_core::{
+    clock::{Clock, ClockDriver},
+    error::IocBuildError,
+    record::Recordable,
+    registry::{NodeConfig, Registry},
+    Input, InputKind, ModuleIO, Output, OutputKind, Value,
+};
+use tokio::sync::{oneshot, watch};
+use tokio_util::sync::CancellationToken;
+
+//the module type stand-ins are built as. their config is their module's key
+const STAND_IN: &str = "StandIn";
+
+//sets a stand-in's input. returns false if the value has the wrong type
+type Setter = Box<dyn Fn(Value) -> bool + Send>;
+type BuildInput = Box<dyn Fn() -> (InputKind, Setter) + Send + Sync>;
+type BuildOutput = Box<dyn Fn() -> (OutputKind, watch::Receiver<Option<Value>>) + Send + Sync>;
+
+///Takes the place of a module in a config, with inputs the test sets and outputs it reads.
+/// Its ports should match those of the module it replaces that the rest of the config uses.
+#[derive(Default)]
+pub struct StandIn {
+    inputs: Vec<(String, BuildInput)>,
+    outputs: Vec<(String, BuildOutput)>,
+}
+
+impl StandIn {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Adds an input that starts at the given value. Its type is the type of the value.
+    pub fn input<T: Recordable>(mut self, name: &str, start: T) -> Self {
+        let build: BuildInput = Box::new(move || {
+            let (input, tx) = Input::new(start.clone());
+            let setter: Setter = Box::new(move |value| match T::from_value(value) {
+                Some(value) => {
+                    let _ = tx.send(value);
+                    true
+                }
+                None => false,
+            });
+            (T::input_kind(input), setter)
+        });
+        self.inputs.push((name.to_string(), build));
+        self
+    }
+
+    ///Adds an output of the given type.
+    pub fn output<T: Recordable>(mut self, name: &str) -> Self {
+        let build: BuildOutput = Box::new(|| {
+            let (output, mut rx) = Output::<T>::new();
+            let (tx, value) = watch::channel(None);
+            tokio::spawn(async move {
+                while let Some(written) = rx.recv().await {
+                    tx.send_replace(Some(written.to_value()));
+                }
+            });
+            (T::output_kind(output), value)
+        });
+        self.outputs.push((name.to_string(), build));
+        self
+    }
+}
+
+//the ports of every stand-in that was built, by their full names, e.g. local_server.x
+#[derive(Default)]
+struct Ports {
+    inputs: HashMap<String, Setter>,
+    outputs: HashMap<String, watch::Receiver<Option<Value>>>,
+}
+
+fn build_stand_in(
+    key: &str,
+    stand_ins: &HashMap<String, StandIn>,
+    ports: &Mutex<Ports>,
+    cancel_token: CancellationToken,
+) -> Result<ModuleIO, IocBuildError> {
+    let stand_in = stand_ins
+        .get(key)
+        .ok_or_else(|| IocBuildError::from_string(format!("no stand-in for module {}", key)))?;
+    let mut ports = lock(ports);
+    let mut inputs = HashMap::new();
+    for (name, build) in &stand_in.inputs {
+        let (input, setter) = build();
+        ports.inputs.insert(format!("{}.{}", key, name), setter);
+        inputs.insert(name.clone(), input);
+    }
+    let mut outputs = HashMap::new();
+    for (name, build) in &stand_in.outputs {
+        let (output, value) = build();
+        ports.outputs.insert(format!("{}.{}", key, name), value);
+        outputs.insert(name.clone(), output);
+    }
+    Ok(ModuleIO {
+        join_handle: tokio::spawn(async move { cancel_token.cancelled().await }),
+        inputs,
+        outputs,
+    })
+}
+
+fn lock(ports: &Mutex<Ports>) -> MutexGuard<'_, Ports> {
+    match ports.lock() {
+        Ok(ports) => ports,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+///Builds and runs a config under a simulated clock. See the crate docs for an example.
+pub struct Harness {
+    config: IocConfig,
+    registry: Registry,
+    stand_ins: HashMap<String, StandIn>,
+    step: Duration,
+}
+
+impl Harness {
+    ///Runs the given config with every module and transformer the `ioc` binary was built with. The clock advances 1ms per tick.
+    pub fn new(config: IocConfig) -> Self {
+        Self {
+            config,
+            registry: registry(),
+            stand_ins: HashMap::new(),
+            step: Duration::from_millis(1),
+        }
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self, IocBuildError> {
+        IocConfig::from_yaml(yaml)
+            .map(Self::new)
+            .map_err(|err| IocBuildError::from_string(format!("failed to parse config: {}", err)))
+    }
+
+    ///Reads a config file. See `IocConfig::load`.
+    pub fn load(path: &str) -> Result<Self, IocBuildError> {
+        IocConfig::load(path)
+            .map(Self::new)
+            .map_err(|err| IocBuildError::from_string(format!("failed to read config {}: {}", path, err)))
+    }
+
+    ///Sets how far the clock advances per tick. Transformers with shorter periods run once per tick.
+    pub fn step(mut self, step: Duration) -> Self {
+        self.step = step;
+        self
+    }
+
+    ///Replaces the module with the given key. Panics if the config has no such module.
+    pub fn stand_in(mut self, module_key: &str, stand_in: StandIn) -> Self {
+        let module = self
+            .config
+            .modules
+            .get_mut(module_key)
+            .unwrap_or_else(|| panic!("no module {} to stand in for", module_key));
+        *module = IocModuleConfig(NodeConfig {
+            kind: STAND_IN.to_string(),
+            config: serde_json::Value::String(module_key.to_string()),
+        });
+        self.stand_ins.insert(module_key.to_string(), stand_in);
+        self
+    }
+
+    ///The registry the config is built with, e.g. to register node types only used in tests.
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        &mut self.registry
+    }
+
+    ///Builds the config and runs the test once everything is running. Shuts down once the test returns, and returns its result.
+    /// Returns an error if the config can't be built, or if it stops with an error.
+    pub async fn run<F, Fut, R>(self, test: F) -> Result<R, IocBuildError>
+    where
+        F: FnOnce(TestIo) -> Fut,
+        Fut: Future<Output = R>,
+    {
+        let Harness {
+            config,
+            mut registry,
+            stand_ins,
+            step,
+        } = self;
+        let ports = Arc::new(Mutex::new(Ports::default()));
+        let factory_ports = ports.clone();
+        registry.register_module(STAND_IN, move |key: String, cancel_token: CancellationToken| {
+            let module = build_stand_in(&key, &stand_ins, &factory_ports, cancel_token);
+            async move { module }
+        });
+
+        let (clock, driver) = Clock::simulated(SystemTime::now(), step);
+        let (ready_tx, ready) = oneshot::channel();
+        let cancel_token = CancellationToken::new();
+        let app = config.start_with(&registry, clock, Some(ready_tx), cancel_token.clone());
+        tokio::pin!(app);
+        tokio::select! {
+            result = &mut app => {
+                return Err(result.err().unwrap_or_else(|| IocBuildError::message("the IOC stopped before it was running")));
+            }
+            _ = ready => {}
+        }
+
+        let io = TestIo { driver, ports };
+        let test = async {
+            let result = test(io).await;
+            cancel_token.cancel();
+            result
+        };
+        let (app_result, result) = tokio::join!(app, test);
+        app_result.map(|_| result)
+    }
+}
+
+///Sets inputs of stand-ins, reads their outputs and advances time while a `Harness` runs. Ports are named by their module's key and their own, e.g. local_server.x.
+pub struct TestIo {
+    driver: ClockDriver,
+    ports: Arc<Mutex<Ports>>,
+}
+
+impl TestIo {
+    ///Sets a stand-in's input. Panics if there is no such input or it has a different type.
+    pub fn set<T: Recordable>(&self, input: &str, value: T) {
+        let ports = lock(&self.ports);
+        let setter = ports.inputs.get(input).unwrap_or_else(|| panic!("no stand-in input {}", input));
+        assert!(setter(value.to_value()), "value has the wrong type for input {}", input);
+    }
+
+    ///The last value written to a stand-in's output, if any. Panics if there is no such output.
+    pub fn output(&self, output: &str) -> Option<Value> {
+        let ports = lock(&self.ports);
+        let value = ports.outputs.get(output).unwrap_or_else(|| panic!("no stand-in output {}", output));
+        let last = value.borrow().clone();
+        last
+    }
+
+    ///How much simulated time passed since the config was started.
+    pub fn elapsed(&self) -> Duration {
+        self.driver.elapsed()
+    }
+
+    ///Advances the simulated clock, one step at a time.
+    pub async fn advance(&self, duration: Duration) {
+        self.driver.advance(duration).await;
+    }
+
+    ///Advances the clock until the value written to an output matches the predicate, for at most timeout.
+    /// Returns the matching value, or the last value if none matched in time.
+    pub async fn wait_for(&self, output: &str, timeout: Duration, predicate: impl Fn(&Value) -> bool) -> Result<Value, Option<Value>> {
+        let until = self.driver.elapsed() + timeout;
+        loop {
+            match self.output(output) {
+                Some(value) if predicate(&value) => return Ok(value),
+                last if self.driver.elapsed() >= until => return Err(last),
+                _ => self.driver.tick().await,
+            }
+        }
+    }
+
+    ///Waits for an output to be written the expected value. Panics if it isn't within timeout.
+    pub async fn expect<T: Recordable + PartialEq + Debug>(&self, output: &str, expected: T, timeout: Duration) {
+        let expected_value = expected.clone().to_value();
+        if let Err(last) = self.wait_for(output, timeout, |value| *value == expected_value).await {
+            panic!("expected {} to be {:?} within {:?}, last value was {:?}", output, expected, timeout, last);
+        }
+    }
+
+    ///Waits for a Float or Int output to be written a value within tolerance of the expected value. Panics if it isn't within timeout.
+    pub async fn expect_float(&self, output: &str, expected: f64, tolerance: f64, timeout: Duration) {
+        let near = |value: &Value| matches!(value.as_f64(), Some(value) if (value - expected).abs() <= tolerance);
+        if let Err(last) = self.wait_for(output, timeout, near).await {
+            panic!("expected {} to be {} ± {} within {:?}, last value was {:?}", output, expected, tolerance, timeout, last);
+        }
+    }
+}
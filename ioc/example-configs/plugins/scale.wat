@@ -0,0 +1,22 @@
+;; An example wasm transformer for wasm_demo.yml: y = gain * x, with a deadband of 0.05 around zero.
+;; scale.wasm is compiled from this file, e.g. with `wasm-tools parse scale.wat -o scale.wasm`.
+;; The ABI is documented in `ioc_extra::transform::wasm`.
+(module
+    (import "ioc" "input_float" (func $input_float (param i32) (result f64)))
+    (import "ioc" "output_float" (func $output_float (param i32 f64)))
+    (import "ioc" "log" (func $log (param i32 i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 0) "input float x\ninput float gain\noutput float y\n")
+    (data (i32.const 64) "scale plugin loaded")
+    (func (export "ioc_ports_ptr") (result i32) (i32.const 0))
+    (func (export "ioc_ports_len") (result i32) (i32.const 46))
+    (func (export "ioc_init")
+        (call $log (i32.const 64) (i32.const 19)))
+    (func (export "ioc_step") (param $dt f64)
+        (local $y f64)
+        (local.set $y (f64.mul (call $input_float (i32.const 0)) (call $input_float (i32.const 1))))
+        (call $output_float (i32.const 0)
+            (select
+                (f64.const 0)
+                (local.get $y)
+                (f64.lt (f64.abs (local.get $y)) (f64.const 0.05))))))